@@ -0,0 +1,59 @@
+-- the base schema from before migrations, written so it can run against an instance that was set
+-- up by hand from the old database/ directory
+DO $$ BEGIN
+	CREATE TYPE UserData AS (
+		public_profile BOOL,
+		display_name TEXT,
+		share_status BOOL,
+		now_playing TEXT,
+		public_status TEXT,
+		recent_plays TEXT[],
+		followers NUMERIC[],
+		following NUMERIC[]
+	);
+EXCEPTION
+	WHEN duplicate_object THEN NULL;
+END $$;
+
+CREATE TABLE IF NOT EXISTS auth (
+	username NUMERIC NOT NULL,
+	password NUMERIC NOT NULL,
+	admin BOOL NOT NULL,
+	last_login NUMERIC,
+	userdata UserData
+);
+
+CREATE TABLE IF NOT EXISTS PlaylistData (
+	username NUMERIC NOT NULL,
+	playlist_name TEXT NOT NULL,
+	song_hash NUMERIC NOT NULL,
+	song_name TEXT NOT NULL,
+	date_added NUMERIC NOT NULL,
+	custom_name TEXT
+);
+
+CREATE TABLE IF NOT EXISTS Playlist (
+	username NUMERIC NOT NULL,
+	name TEXT NOT NULL,
+	creation_timestamp NUMERIC NOT NULL,
+	description TEXT,
+	public_playlist BOOL NOT NULL,
+	last_update NUMERIC NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS songs (
+	id NUMERIC NOT NULL,
+	title TEXT NOT NULL,
+	upload_date TEXT,
+	uploader TEXT,
+	url TEXT,
+	genre TEXT,
+	thumbnail TEXT,
+	album TEXT,
+	album_artist TEXT,
+	artist TEXT,
+	creator TEXT,
+	filesize BIGINT,
+	downloaded_timestamp NUMERIC,	
+	downloaded BOOL NOT NULL
+);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS plays (
+	username NUMERIC NOT NULL,
+	song_hash NUMERIC NOT NULL,
+	played_at NUMERIC NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS plays_username_played_at ON plays (username, played_at);
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS activity (
+	id BIGSERIAL PRIMARY KEY,
+	username NUMERIC NOT NULL,
+	kind TEXT NOT NULL,
+	playlist TEXT,
+	song_hash NUMERIC,
+	detail TEXT,
+	created_at NUMERIC NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS activity_username_id ON activity (username, id);
@@ -0,0 +1,6 @@
+-- put the follows back into the userdata arrays
+UPDATE auth SET
+	userdata.following = (SELECT array_agg(followee) FROM follows WHERE follower = auth.username),
+	userdata.followers = (SELECT array_agg(follower) FROM follows WHERE followee = auth.username);
+
+DROP TABLE IF EXISTS follows;
@@ -0,0 +1,30 @@
+CREATE TABLE IF NOT EXISTS follows (
+	follower NUMERIC NOT NULL,
+	followee NUMERIC NOT NULL,
+	created_at NUMERIC NOT NULL,
+	UNIQUE (follower, followee),
+	CHECK (follower <> followee)
+);
+
+CREATE INDEX IF NOT EXISTS follows_followee ON follows (followee);
+
+-- move follows out of the old userdata arrays, both arrays are used since the old updates were not
+-- transactional and could leave a follow on only one side
+INSERT INTO follows(follower, followee, created_at)
+SELECT 
+	old.follower, 
+	old.followee, 
+	floor(EXTRACT(EPOCH FROM now()))::NUMERIC
+FROM (
+	SELECT username AS follower, unnest((userdata).following) AS followee FROM auth
+	UNION
+	SELECT unnest((userdata).followers) AS follower, username AS followee FROM auth
+) old
+WHERE 
+	old.follower <> old.followee
+	AND old.follower IN (SELECT username FROM auth)
+	AND old.followee IN (SELECT username FROM auth)
+ON CONFLICT DO NOTHING;
+
+UPDATE auth SET userdata.followers = NULL, userdata.following = NULL
+WHERE (userdata).followers IS NOT NULL OR (userdata).following IS NOT NULL;
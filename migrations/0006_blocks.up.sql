@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS blocks (
+	blocker NUMERIC NOT NULL,
+	blocked NUMERIC NOT NULL,
+	created_at NUMERIC NOT NULL,
+	UNIQUE (blocker, blocked)
+);
+
+CREATE INDEX IF NOT EXISTS blocks_blocked ON blocks (blocked);
+
+CREATE TABLE IF NOT EXISTS mutes (
+	muter NUMERIC NOT NULL,
+	muted NUMERIC NOT NULL,
+	created_at NUMERIC NOT NULL,
+	UNIQUE (muter, muted)
+);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS follow_requests (
+	requester NUMERIC NOT NULL,
+	target NUMERIC NOT NULL,
+	created_at NUMERIC NOT NULL,
+	UNIQUE (requester, target)
+);
+
+CREATE INDEX IF NOT EXISTS follow_requests_target ON follow_requests (target);
@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS admin_log (
+	id BIGSERIAL PRIMARY KEY,
+	admin NUMERIC NOT NULL,
+	command TEXT NOT NULL,
+	args TEXT NOT NULL,
+	response TEXT NOT NULL,
+	created_at NUMERIC NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS bans (
+	username NUMERIC PRIMARY KEY,
+	banned_by NUMERIC NOT NULL,
+	expires_at NUMERIC,
+	created_at NUMERIC NOT NULL
+);
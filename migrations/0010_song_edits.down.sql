@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS song_edits;
+ALTER TABLE songs DROP COLUMN IF EXISTS edited_timestamp;
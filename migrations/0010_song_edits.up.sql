@@ -0,0 +1,12 @@
+-- set when the metadata of a song is edited so SYNC_LIB sends it again
+ALTER TABLE songs ADD COLUMN IF NOT EXISTS edited_timestamp NUMERIC;
+
+-- every EDIT_SONG, old_values and new_values are the edited fields as json
+CREATE TABLE IF NOT EXISTS song_edits (
+	id BIGSERIAL PRIMARY KEY,
+	song_hash NUMERIC NOT NULL,
+	edited_by NUMERIC NOT NULL,
+	old_values TEXT NOT NULL,
+	new_values TEXT NOT NULL,
+	created_at NUMERIC NOT NULL
+);
@@ -0,0 +1,3 @@
+-- set once the enrichment pass has looked at a song, songs from before it are left as they are
+ALTER TABLE songs ADD COLUMN IF NOT EXISTS enriched BOOL NOT NULL DEFAULT FALSE;
+UPDATE songs SET enriched = TRUE;
@@ -0,0 +1,3 @@
+ALTER TABLE songs DROP COLUMN IF EXISTS original_title;
+ALTER TABLE songs DROP COLUMN IF EXISTS original_creator;
+ALTER TABLE songs DROP COLUMN IF EXISTS original_upload_date;
@@ -0,0 +1,20 @@
+-- the title, creator and upload date yt-dlp gave the song, FIND_SONG, ADD_SONG and REMOVE_SONG
+-- still find it by these after it is edited or enriched
+ALTER TABLE songs ADD COLUMN IF NOT EXISTS original_title TEXT;
+ALTER TABLE songs ADD COLUMN IF NOT EXISTS original_creator TEXT;
+ALTER TABLE songs ADD COLUMN IF NOT EXISTS original_upload_date TEXT;
+
+-- the title from before the first edit, enrichment only takes the dashes out of the date
+UPDATE songs SET
+	original_title = COALESCE(
+		(
+			SELECT old_values::json->>'title'
+			FROM song_edits
+			WHERE song_edits.song_hash = songs.id
+			ORDER BY id
+			LIMIT 1
+		),
+		title
+	),
+	original_creator = creator,
+	original_upload_date = REPLACE(upload_date, '-', '');
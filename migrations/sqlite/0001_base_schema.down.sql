@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS songs;
+DROP TABLE IF EXISTS Playlist;
+DROP TABLE IF EXISTS PlaylistData;
+DROP TABLE IF EXISTS auth;
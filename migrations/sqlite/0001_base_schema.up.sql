@@ -0,0 +1,49 @@
+-- the same tables as the postgres schema, sqlite has no unsigned 64 bit integers so the username
+-- and song hashes are stored as INTEGER with the same bits, and the UserData type is split into
+-- columns of auth
+CREATE TABLE IF NOT EXISTS auth (
+	username INTEGER NOT NULL,
+	password INTEGER NOT NULL,
+	admin BOOLEAN NOT NULL,
+	last_login INTEGER,
+	public_profile BOOLEAN,
+	display_name TEXT,
+	share_status BOOLEAN,
+	now_playing TEXT,
+	public_status TEXT
+);
+
+CREATE TABLE IF NOT EXISTS PlaylistData (
+	username INTEGER NOT NULL,
+	playlist_name TEXT NOT NULL,
+	song_hash INTEGER NOT NULL,
+	song_name TEXT NOT NULL,
+	date_added INTEGER NOT NULL,
+	custom_name TEXT
+);
+
+CREATE TABLE IF NOT EXISTS Playlist (
+	username INTEGER NOT NULL,
+	name TEXT NOT NULL,
+	creation_timestamp INTEGER NOT NULL,
+	description TEXT,
+	public_playlist BOOLEAN NOT NULL,
+	last_update INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS songs (
+	id INTEGER NOT NULL,
+	title TEXT NOT NULL,
+	upload_date TEXT,
+	uploader TEXT,
+	url TEXT,
+	genre TEXT,
+	thumbnail TEXT,
+	album TEXT,
+	album_artist TEXT,
+	artist TEXT,
+	creator TEXT,
+	filesize BIGINT,
+	downloaded_timestamp INTEGER,
+	downloaded BOOLEAN NOT NULL
+);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS plays (
+	username INTEGER NOT NULL,
+	song_hash INTEGER NOT NULL,
+	played_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS plays_username_played_at ON plays (username, played_at);
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS activity (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	username INTEGER NOT NULL,
+	kind TEXT NOT NULL,
+	playlist TEXT,
+	song_hash INTEGER,
+	detail TEXT,
+	created_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS activity_username_id ON activity (username, id);
@@ -0,0 +1,10 @@
+-- sqlite instances never had the userdata arrays so there is nothing to move over
+CREATE TABLE IF NOT EXISTS follows (
+	follower INTEGER NOT NULL,
+	followee INTEGER NOT NULL,
+	created_at INTEGER NOT NULL,
+	UNIQUE (follower, followee),
+	CHECK (follower <> followee)
+);
+
+CREATE INDEX IF NOT EXISTS follows_followee ON follows (followee);
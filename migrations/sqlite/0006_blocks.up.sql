@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS blocks (
+	blocker INTEGER NOT NULL,
+	blocked INTEGER NOT NULL,
+	created_at INTEGER NOT NULL,
+	UNIQUE (blocker, blocked)
+);
+
+CREATE INDEX IF NOT EXISTS blocks_blocked ON blocks (blocked);
+
+CREATE TABLE IF NOT EXISTS mutes (
+	muter INTEGER NOT NULL,
+	muted INTEGER NOT NULL,
+	created_at INTEGER NOT NULL,
+	UNIQUE (muter, muted)
+);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS follow_requests (
+	requester INTEGER NOT NULL,
+	target INTEGER NOT NULL,
+	created_at INTEGER NOT NULL,
+	UNIQUE (requester, target)
+);
+
+CREATE INDEX IF NOT EXISTS follow_requests_target ON follow_requests (target);
@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS admin_log (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	admin INTEGER NOT NULL,
+	command TEXT NOT NULL,
+	args TEXT NOT NULL,
+	response TEXT NOT NULL,
+	created_at INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS bans (
+	username INTEGER PRIMARY KEY,
+	banned_by INTEGER NOT NULL,
+	expires_at INTEGER,
+	created_at INTEGER NOT NULL
+);
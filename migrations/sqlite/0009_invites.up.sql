@@ -0,0 +1,19 @@
+ALTER TABLE auth ADD COLUMN invites_left INT NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS invites (
+	code TEXT PRIMARY KEY,
+	created_by INTEGER NOT NULL,
+	uses INT NOT NULL DEFAULT 0,
+	max_uses INT NOT NULL,
+	expires_at INTEGER,
+	created_at INTEGER NOT NULL,
+	CHECK (uses <= max_uses)
+);
+
+-- who invited whom, kept after the invite is revoked
+CREATE TABLE IF NOT EXISTS invite_uses (
+	code TEXT NOT NULL,
+	invited INTEGER NOT NULL UNIQUE,
+	invited_by INTEGER NOT NULL,
+	created_at INTEGER NOT NULL
+);
@@ -0,0 +1,12 @@
+-- set when the metadata of a song is edited so SYNC_LIB sends it again
+ALTER TABLE songs ADD COLUMN edited_timestamp INTEGER;
+
+-- every EDIT_SONG, old_values and new_values are the edited fields as json
+CREATE TABLE IF NOT EXISTS song_edits (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	song_hash INTEGER NOT NULL,
+	edited_by INTEGER NOT NULL,
+	old_values TEXT NOT NULL,
+	new_values TEXT NOT NULL,
+	created_at INTEGER NOT NULL
+);
@@ -0,0 +1 @@
+ALTER TABLE playlist DROP COLUMN custom_image;
@@ -0,0 +1,7 @@
+-- set while a playlist has an image uploaded with SET_PLAYLIST_IMAGE, otherwise its cover is made
+-- from the artwork of its songs
+ALTER TABLE playlist ADD COLUMN custom_image BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- there's no telling an uploaded image from a random one for playlists made before this, they
+-- keep whatever image they have until REMOVE_PLAYLIST_IMAGE
+UPDATE playlist SET custom_image = TRUE;
@@ -0,0 +1,3 @@
+ALTER TABLE songs DROP COLUMN original_title;
+ALTER TABLE songs DROP COLUMN original_creator;
+ALTER TABLE songs DROP COLUMN original_upload_date;
@@ -0,0 +1,47 @@
+use serde::Serialize;
+
+// max amount of entries returned by a single FEED request
+pub(crate) const FEED_PAGE_SIZE: i64 = 50;
+
+/*
+ * Things a user did that show up in the feed of the people following them, only activity that
+ * is already public is logged (songs added to private playlists are not)
+ */
+pub(crate) enum Activity {
+    CreatedPlaylist { playlist: String },
+    AddedSong { playlist: String, song_hash: u64 },
+    QueuedSong { url: String },
+    Followed { display_name: String },
+}
+
+impl Activity {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::CreatedPlaylist { .. } => "CreatedPlaylist",
+            Self::AddedSong { .. } => "AddedSong",
+            Self::QueuedSong { .. } => "QueuedSong",
+            Self::Followed { .. } => "Followed",
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct FeedItem {
+    pub id: String,
+    pub user: Option<String>, // display name
+    pub kind: String,
+    pub playlist: Option<String>,
+    pub song: Option<String>,
+    pub detail: Option<String>,
+    pub timestamp: u64,
+}
+
+/*
+ * A page of the feed, newest first. To get the next page send the cursor back with FEED, when
+ * there is nothing left the cursor is null
+ */
+#[derive(Serialize)]
+pub(crate) struct Feed {
+    pub items: Vec<FeedItem>,
+    pub cursor: Option<String>,
+}
@@ -0,0 +1,329 @@
+use crate::{clear_presence, delete_artwork, retag_song, AppState, Bucket, SongEdit, WsClient};
+use log::{info, warn};
+use num_traits::ToPrimitive;
+use serde::Serialize;
+use serde_json::json;
+
+/*
+ * Commands that can only be used by clients that authenticated with the ADMIN_KEY on an admin
+ * account, every use of them is written to the admin_log table along with the response
+ */
+pub(crate) static ADMIN_COMMANDS: [&str; 14] = [
+    "ADMIN_CLEAR_QUEUE",
+    "ADMIN_MOVE_QUEUE",
+    "ADMIN_REMOVE_QUEUE",
+    "ADMIN_DELETE_SONG",
+    "ADMIN_LIST_CLIENTS",
+    "ADMIN_KICK",
+    "ADMIN_BAN",
+    "ADMIN_UNBAN",
+    "ADMIN_PROMOTE",
+    "ADMIN_DEMOTE",
+    "ADMIN_LOG",
+    "ADMIN_SET_INVITES",
+    "EDIT_SONG",
+    "SONG_HISTORY",
+];
+
+// max amount of entries returned by a single ADMIN_LOG request
+pub(crate) const ADMIN_LOG_PAGE_SIZE: i64 = 50;
+
+#[derive(Serialize)]
+struct ConnectedClient {
+    user: Option<String>, // display name
+    username_hash: String,
+    auth: bool,
+    admin: bool,
+}
+
+pub(crate) async fn handle_admin(
+    command: &str,
+    args: &[&str],
+    ws_client: &WsClient,
+    state: &AppState,
+) -> Option<String> {
+    if !ws_client.admin {
+        warn!("non admin client attempted {command}");
+        return Some(String::from("Unauthorized"));
+    }
+
+    let response = match command {
+        "ADMIN_CLEAR_QUEUE" => {
+            state.song_manager.write().await.clear_queue();
+            String::from("OK")
+        }
+        // move a song in the download queue from one position to another, positions start at 0
+        "ADMIN_MOVE_QUEUE" => match args {
+            [from, to] => match (from.parse::<usize>(), to.parse::<usize>()) {
+                (Ok(from), Ok(to)) => {
+                    match state.song_manager.write().await.move_in_queue(from, to) {
+                        Ok(()) => String::from("OK"),
+                        Err(_) => String::from("InvalidPosition"),
+                    }
+                }
+                _ => String::from("InvalidPosition"),
+            },
+            _ => String::from("InvalidMessage"),
+        },
+        "ADMIN_REMOVE_QUEUE" => match args {
+            [position] => match position.parse::<usize>() {
+                Ok(v) => match state.song_manager.write().await.remove_from_queue(v) {
+                    Some(_) => String::from("OK"),
+                    None => String::from("InvalidPosition"),
+                },
+                Err(_) => String::from("InvalidPosition"),
+            },
+            _ => String::from("InvalidMessage"),
+        },
+        // remove a song from the library, every playlist, and the file store
+        "ADMIN_DELETE_SONG" => match args {
+            [song] => match song.parse::<u64>() {
+                Ok(v) => match state.storage.delete_song(v).await {
+                    Ok(()) => {
+                        let _ = state.files.delete(Bucket::Songs, &v.to_string()).await;
+                        delete_artwork(state.files.as_ref(), v).await;
+                        String::from("OK")
+                    }
+                    Err(_) => String::from("InvalidHash"),
+                },
+                Err(_) => String::from("ExpectedHash"),
+            },
+            _ => String::from("InvalidMessage"),
+        },
+        "ADMIN_LIST_CLIENTS" => {
+            let connected = state
+                .clients
+                .lock()
+                .await
+                .values()
+                .cloned()
+                .collect::<Vec<WsClient>>();
+            let mut list = Vec::with_capacity(connected.len());
+            for client in connected {
+                let user = match client.auth {
+                    true => match state.storage.get_user_data(client.username_hash).await {
+                        Ok(Some(v)) => v.display_name,
+                        _ => None,
+                    },
+                    false => None,
+                };
+                list.push(ConnectedClient {
+                    user,
+                    username_hash: client.username_hash.to_string(),
+                    auth: client.auth,
+                    admin: client.admin,
+                });
+            }
+            json!(list).to_string()
+        }
+        // close every connection of a user by their display name
+        "ADMIN_KICK" => match args {
+            [name] => match state.storage.userhash_from_username(name).await {
+                Ok(v) => kick(state, v.to_u64().unwrap_or_default())
+                    .await
+                    .to_string(),
+                Err(_) => String::from("CouldNotBeFound"),
+            },
+            _ => String::from("InvalidMessage"),
+        },
+        // ban a user by their display name for an amount of seconds, or forever if not given,
+        // banned users are kicked and cannot authenticate
+        "ADMIN_BAN" => match args {
+            [name] | [name, _] => {
+                let seconds = match args.get(1) {
+                    Some(v) => v.parse::<u64>().map(Some),
+                    None => Ok(None),
+                };
+                match (state.storage.userhash_from_username(name).await, seconds) {
+                    (Ok(v), Ok(seconds)) => {
+                        let userhash = v.to_u64().unwrap_or_default();
+                        if userhash == ws_client.username_hash {
+                            String::from("CannotBanSelf")
+                        } else {
+                            match state
+                                .storage
+                                .ban_user(userhash, ws_client.username_hash, seconds)
+                                .await
+                            {
+                                Ok(()) => {
+                                    kick(state, userhash).await;
+                                    String::from("OK")
+                                }
+                                Err(_) => String::from("FailedToBan"),
+                            }
+                        }
+                    }
+                    (Err(_), _) => String::from("CouldNotBeFound"),
+                    (_, Err(_)) => String::from("InvalidDuration"),
+                }
+            }
+            _ => String::from("InvalidMessage"),
+        },
+        "ADMIN_UNBAN" => match args {
+            [name] => match state.storage.userhash_from_username(name).await {
+                Ok(v) => match state
+                    .storage
+                    .unban_user(v.to_u64().unwrap_or_default())
+                    .await
+                {
+                    Ok(()) => String::from("OK"),
+                    Err(_) => String::from("FailedToUnban"),
+                },
+                Err(_) => String::from("CouldNotBeFound"),
+            },
+            _ => String::from("InvalidMessage"),
+        },
+        // promoted users still have to authenticate with the ADMIN_KEY to use admin commands,
+        // demoted users lose admin on all of their connections right away
+        "ADMIN_PROMOTE" | "ADMIN_DEMOTE" => match args {
+            [name] => match state.storage.userhash_from_username(name).await {
+                Ok(v) => {
+                    let userhash = v.to_u64().unwrap_or_default();
+                    let admin = command == "ADMIN_PROMOTE";
+                    if !admin && userhash == ws_client.username_hash {
+                        String::from("CannotDemoteSelf")
+                    } else {
+                        match state.storage.set_admin(userhash, admin).await {
+                            Ok(()) => {
+                                if !admin {
+                                    state
+                                        .clients
+                                        .lock()
+                                        .await
+                                        .values_mut()
+                                        .filter(|x| x.username_hash == userhash)
+                                        .for_each(|x| x.admin = false);
+                                }
+                                String::from("OK")
+                            }
+                            Err(_) => String::from("FailedToUpdate"),
+                        }
+                    }
+                }
+                Err(_) => String::from("CouldNotBeFound"),
+            },
+            _ => String::from("InvalidMessage"),
+        },
+        // set how many invite uses a user has left to hand out
+        "ADMIN_SET_INVITES" => match args {
+            [name, count] => match (
+                state.storage.userhash_from_username(name).await,
+                count.parse::<i32>(),
+            ) {
+                (Ok(v), Ok(count)) if count >= 0 => match state
+                    .storage
+                    .set_invites_left(v.to_u64().unwrap_or_default(), count)
+                    .await
+                {
+                    Ok(()) => String::from("OK"),
+                    Err(_) => String::from("FailedToUpdate"),
+                },
+                (Err(_), _) => String::from("CouldNotBeFound"),
+                _ => String::from("InvalidCount"),
+            },
+            _ => String::from("InvalidMessage"),
+        },
+        // the most recent admin actions, send the cursor from the previous page to get the next
+        "ADMIN_LOG" => {
+            let cursor = match args {
+                [] | [""] => Ok(None),
+                [v] => v.parse::<i64>().map(Some),
+                _ => return Some(String::from("InvalidCursor")),
+            };
+            match cursor {
+                Ok(v) => match state.storage.admin_log(v).await {
+                    Ok(v) => json!(v).to_string(),
+                    Err(_) => String::from("FailedToFetchLog"),
+                },
+                Err(_) => String::from("InvalidCursor"),
+            }
+        }
+        // correct the metadata of a song for everyone, see metadata.rs
+        "EDIT_SONG" => match args {
+            [song, edit @ ..] if !edit.is_empty() => match (
+                song.parse::<u64>(),
+                serde_json::from_str::<SongEdit>(&edit.join(" ")),
+            ) {
+                (Ok(v), Ok(edit)) => match state
+                    .storage
+                    .edit_song(v, ws_client.username_hash, &edit)
+                    .await
+                {
+                    Ok(_) => {
+                        retag_song(state.storage.as_ref(), state.files.as_ref(), v).await;
+                        String::from("OK")
+                    }
+                    Err(e) => e.to_string(),
+                },
+                (Err(_), _) => String::from("InvalidHash"),
+                (_, Err(_)) => String::from("InvalidEdit"),
+            },
+            _ => String::from("InvalidMessage"),
+        },
+        // the edits made to a song, newest first, paged the same way as ADMIN_LOG
+        "SONG_HISTORY" => match args {
+            [song] | [song, _] => {
+                let cursor = match args.get(1) {
+                    Some(v) => v.parse::<i64>().map(Some),
+                    None => Ok(None),
+                };
+                match (song.parse::<u64>(), cursor) {
+                    (Ok(v), Ok(cursor)) => match state.storage.song_history(v, cursor).await {
+                        Ok(v) => json!(v).to_string(),
+                        Err(_) => String::from("FailedToFetchHistory"),
+                    },
+                    (Err(_), _) => String::from("InvalidHash"),
+                    (_, Err(_)) => String::from("InvalidCursor"),
+                }
+            }
+            _ => String::from("InvalidMessage"),
+        },
+        _ => return None,
+    };
+
+    // reading the log or the history of a song is not worth logging
+    if !matches!(command, "ADMIN_LOG" | "SONG_HISTORY") {
+        info!(
+            "admin {} ran {command}: {response}",
+            ws_client.username_hash
+        );
+        let _ = state
+            .storage
+            .log_admin_action(ws_client.username_hash, command, &args.join(" "), &response)
+            .await;
+    }
+
+    Some(response)
+}
+
+#[derive(Serialize)]
+pub(crate) struct AdminLogEntry {
+    pub id: String,
+    pub admin: Option<String>, // display name
+    pub command: String,
+    pub args: String,
+    pub response: String,
+    pub timestamp: u64,
+}
+
+// a page of the admin log, newest first, the cursor is null on the last page
+#[derive(Serialize)]
+pub(crate) struct AdminLog {
+    pub items: Vec<AdminLogEntry>,
+    pub cursor: Option<String>,
+}
+
+// remove every connection of a user, returns how many were closed
+pub(crate) async fn kick(state: &AppState, userhash: u64) -> usize {
+    let kicked = {
+        let mut locked = state.clients.lock().await;
+        let before = locked.len();
+        // dropping the sender ends the stream that forwards to the websocket, closing it
+        locked.retain(|_, v| v.username_hash != userhash || !v.auth);
+        before - locked.len()
+    };
+    if kicked > 0 {
+        clear_presence(state, userhash).await;
+    }
+    kicked
+}
@@ -0,0 +1,175 @@
+use crate::{config, Bucket, FileStore, Storage};
+use anyhow::anyhow;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageOutputFormat};
+use log::{error, info};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::io::Cursor;
+use std::time::Duration;
+
+/*
+ * Song artwork kept next to the playlist covers instead of sending clients to the thumbnail url
+ * yt-dlp gives, which is a third party that would see what everyone listens to
+ *
+ * The thumbnail is fetched once when a song is downloaded, cropped to a square from the middle
+ * and stored as a jpeg in every size of ARTWORK_SIZES, named art-<id>-<size>.jpg. SYNC_LIB then
+ * has the urls of those under /<instance_key>-cdn and the largest is embedded in the song as its
+ * cover (see tags.rs). If the thumbnail can't be fetched the song has no artwork
+ *
+ * Songs downloaded before this are fetched once in the background when the server starts
+ */
+pub(crate) const ARTWORK_SIZES: [u32; 3] = [96, 256, 512];
+
+// the size embedded in the song file and sent as the thumbnail
+pub(crate) const COVER_SIZE: u32 = 512;
+
+const THUMBNAIL_TIMEOUT: Duration = Duration::from_secs(10);
+
+// thumbnails bigger than this are skipped, youtube's largest are a few hundred KB
+const MAX_THUMBNAIL_BYTES: usize = 5 * 1024 * 1024;
+
+pub(crate) fn artwork_name(song_hash: u64, size: u32) -> String {
+    format!("art-{song_hash}-{size}.jpg")
+}
+
+// the urls of the artwork of a song in SYNC_LIB, relative to the server
+#[derive(Serialize)]
+pub(crate) struct Artwork {
+    pub thumbnail: Option<String>,
+    pub thumbnails: Option<BTreeMap<u32, String>>,
+}
+
+impl Artwork {
+    // songs only have artwork if they still have a thumbnail, see cache_artwork
+    pub fn of(song_hash: u64, thumbnail: &Option<String>) -> Self {
+        if thumbnail.is_none() {
+            return Self {
+                thumbnail: None,
+                thumbnails: None,
+            };
+        }
+        let url = |size| {
+            format!(
+                "/{}-cdn/{}",
+                config().instance_key,
+                artwork_name(song_hash, size)
+            )
+        };
+        Self {
+            thumbnail: Some(url(COVER_SIZE)),
+            thumbnails: Some(ARTWORK_SIZES.iter().map(|x| (*x, url(*x))).collect()),
+        }
+    }
+}
+
+// the middle of the image as a square, youtube thumbnails are 16:9 with the art in the middle
+fn square(image: &DynamicImage) -> DynamicImage {
+    let (width, height) = image.dimensions();
+    let side = width.min(height);
+    image.crop_imm((width - side) / 2, (height - side) / 2, side, side)
+}
+
+// every size of the artwork as (size, jpeg)
+pub(crate) fn resize_artwork(data: &[u8]) -> anyhow::Result<Vec<(u32, Vec<u8>)>> {
+    let image = square(&image::load_from_memory(data)?);
+    let mut sizes = Vec::with_capacity(ARTWORK_SIZES.len());
+    for size in ARTWORK_SIZES {
+        let resized = DynamicImage::ImageRgb8(
+            image
+                .resize_exact(size, size, FilterType::Lanczos3)
+                .to_rgb8(),
+        );
+        let mut jpeg = Vec::new();
+        resized.write_to(&mut Cursor::new(&mut jpeg), ImageOutputFormat::Jpeg(90))?;
+        sizes.push((size, jpeg));
+    }
+    Ok(sizes)
+}
+
+async fn fetch_thumbnail(url: &str) -> anyhow::Result<Vec<u8>> {
+    let data = reqwest::Client::new()
+        .get(url)
+        .timeout(THUMBNAIL_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    if data.len() > MAX_THUMBNAIL_BYTES {
+        return Err(anyhow!("thumbnail is {} bytes", data.len()));
+    }
+    Ok(data.to_vec())
+}
+
+// fetch the thumbnail of a song and store every size of it
+pub(crate) async fn cache_artwork(
+    files: &dyn FileStore,
+    song_hash: u64,
+    url: &str,
+) -> anyhow::Result<()> {
+    let data = fetch_thumbnail(url).await?;
+    let sizes = tokio::task::spawn_blocking(move || resize_artwork(&data)).await??;
+    for (size, jpeg) in sizes {
+        files
+            .put(Bucket::Images, &artwork_name(song_hash, size), jpeg)
+            .await?;
+    }
+    Ok(())
+}
+
+// the embedded cover of a song, None if it has no artwork
+pub(crate) async fn cover(files: &dyn FileStore, song_hash: u64) -> Option<Vec<u8>> {
+    match files
+        .get(Bucket::Images, &artwork_name(song_hash, COVER_SIZE))
+        .await
+    {
+        Ok(v) => v,
+        Err(e) => {
+            error!("failed to read the artwork of {song_hash}: {e}");
+            None
+        }
+    }
+}
+
+pub(crate) async fn delete_artwork(files: &dyn FileStore, song_hash: u64) {
+    for size in ARTWORK_SIZES {
+        if let Err(e) = files
+            .delete(Bucket::Images, &artwork_name(song_hash, size))
+            .await
+        {
+            error!("failed to delete the artwork of {song_hash}: {e}");
+        }
+    }
+}
+
+// artwork for the songs that were downloaded before it was cached, one at a time
+pub(crate) async fn cache_missing_artwork(storage: &dyn Storage, files: &dyn FileStore) {
+    let songs = match storage.song_thumbnails().await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("failed to find songs without artwork: {e}");
+            return;
+        }
+    };
+    let cached: HashSet<String> = match files.list(Bucket::Images, "art-").await {
+        Ok(v) => v.into_iter().map(|x| x.0).collect(),
+        Err(e) => {
+            error!("failed to list the cached artwork: {e}");
+            return;
+        }
+    };
+    let mut fetched = 0;
+    for (song_hash, url) in songs {
+        if cached.contains(&artwork_name(song_hash, COVER_SIZE)) {
+            continue;
+        }
+        match cache_artwork(files, song_hash, &url).await {
+            Ok(_) => fetched += 1,
+            Err(e) => error!("failed to cache the artwork of {song_hash}: {e}"),
+        }
+    }
+    if fetched > 0 {
+        info!("cached the artwork of {fetched} songs");
+    }
+}
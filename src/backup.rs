@@ -0,0 +1,438 @@
+use crate::{config, file_store, Bucket, Database, MIGRATOR};
+use anyhow::anyhow;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::migrate::Migrate;
+use sqlx::{Pool, Postgres};
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/*
+ * seanify backup <path> and seanify restore <path>
+ *
+ * A backup is a .tar.gz with every table dumped as json (taken in one read only transaction so
+ * the tables agree with each other), the songs in CACHE_DIR and the images in CDN_DIR. The
+ * manifest has the migration the database was at and a sha256 of every other file, restore checks
+ * all of them before touching anything
+ *
+ * restore only works on an empty instance, it migrates the database up to the version in the
+ * manifest, loads the tables and then applies whatever migrations are newer than the backup
+ *
+ * If BACKUP_DIR is set the server also takes a backup every BACKUP_INTERVAL_HOURS and keeps the
+ * newest BACKUP_RETENTION of them
+ *
+ * Backups are only for postgres, a sqlite database is a single file that can be copied while the
+ * server is stopped. Songs and images kept in S3 are left out of the archive as the bucket has its
+ * own versioning and replication, restore still uploads any that are in it
+ */
+const BACKUP_FORMAT: u32 = 1;
+const BACKUP_ROOT: &str = "seanify-backup";
+
+#[derive(Serialize, Deserialize)]
+struct BackupFile {
+    size: u64,
+    sha256: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    format: u32,
+    created_at: u64,
+    migration: i64,
+    tables: BTreeMap<String, i64>, // rows in each table
+    files: BTreeMap<String, BackupFile>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn checksum(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+fn check_postgres(command: &str) -> anyhow::Result<()> {
+    match config().database_url.split(':').next() {
+        Some("sqlite") | Some("memory") => Err(anyhow!(
+            "{command} only works with postgres, stop the server and copy the sqlite database instead"
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn quote(table: &str) -> String {
+    format!("\"{}\"", table.replace('"', "\"\""))
+}
+
+pub(crate) async fn backup_command<S: AsRef<str>>(args: &[S]) -> anyhow::Result<()> {
+    let path = match args.first() {
+        Some(v) => PathBuf::from(v.as_ref()),
+        None => return Err(anyhow!("usage: seanify backup <path>")),
+    };
+    check_postgres("backup")?;
+    let db = Database::without_migrations().await;
+    let manifest = backup(&db.database, &path).await?;
+    println!(
+        "backed up {} tables and {} files to {}",
+        manifest.tables.len(),
+        manifest.files.len() - manifest.tables.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+pub(crate) async fn restore_command<S: AsRef<str>>(args: &[S]) -> anyhow::Result<()> {
+    let path = match args.first() {
+        Some(v) => PathBuf::from(v.as_ref()),
+        None => return Err(anyhow!("usage: seanify restore <path>")),
+    };
+    check_postgres("restore")?;
+    let manifest = restore(&path).await?;
+    println!(
+        "restored {} rows from a backup taken at {}",
+        manifest.tables.values().sum::<i64>(),
+        manifest.created_at
+    );
+    Ok(())
+}
+
+// every table in one snapshot, as (name, rows, json array of the rows)
+async fn dump_tables(pool: &Pool<Postgres>) -> anyhow::Result<(i64, Vec<(String, i64, String)>)> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+        .execute(&mut tx)
+        .await?;
+    let migration = sqlx::query_scalar::<_, Option<i64>>(
+        r#"
+        SELECT MAX(version)
+        FROM _sqlx_migrations
+        WHERE success
+        "#,
+    )
+    .fetch_one(&mut tx)
+    .await?
+    .unwrap_or(0);
+    let names = sqlx::query_scalar::<_, String>(
+        r#"
+        SELECT table_name::text
+        FROM information_schema.tables
+        WHERE table_schema = 'public'
+        AND table_type = 'BASE TABLE'
+        AND table_name <> '_sqlx_migrations'
+        ORDER BY table_name
+        "#,
+    )
+    .fetch_all(&mut tx)
+    .await?;
+
+    let mut tables = Vec::with_capacity(names.len());
+    for name in names {
+        // numeric columns come out as json numbers with every digit, so nothing is lost
+        let (json, rows) = sqlx::query_as::<_, (Option<String>, i64)>(&format!(
+            "SELECT json_agg(t)::text, COUNT(*) FROM {} t",
+            quote(&name)
+        ))
+        .fetch_one(&mut tx)
+        .await?;
+        tables.push((name, rows, json.unwrap_or_else(|| String::from("[]"))));
+    }
+    tx.commit().await?;
+    Ok((migration, tables))
+}
+
+// songs and images are both stored flat so there is no need to recurse
+fn list_files(dir: &str) -> Vec<PathBuf> {
+    let mut files = match std::fs::read_dir(dir) {
+        Ok(v) => v
+            .filter_map(|x| x.ok())
+            .filter(|x| x.file_type().map(|x| x.is_file()).unwrap_or(false))
+            .map(|x| x.path())
+            .collect::<Vec<PathBuf>>(),
+        Err(_) => Vec::new(),
+    };
+    files.sort();
+    files
+}
+
+fn append(
+    tar: &mut tar::Builder<GzEncoder<std::fs::File>>,
+    manifest: &mut Manifest,
+    name: String,
+    data: &[u8],
+) -> anyhow::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(manifest.created_at);
+    header.set_cksum();
+    tar.append_data(&mut header, format!("{BACKUP_ROOT}/{name}"), data)?;
+    manifest.files.insert(
+        name,
+        BackupFile {
+            size: data.len() as u64,
+            sha256: checksum(data),
+        },
+    );
+    Ok(())
+}
+
+/*
+ * Write a backup of the instance to path, the archive is written next to it first and renamed
+ * once it's complete so a failed backup never leaves a broken archive behind
+ */
+async fn backup(pool: &Pool<Postgres>, path: &Path) -> anyhow::Result<Manifest> {
+    let (migration, tables) = dump_tables(pool).await?;
+    let files = file_store();
+    let dirs = [(Bucket::Songs, "cache"), (Bucket::Images, "cdn")]
+        .into_iter()
+        .filter_map(|(bucket, prefix)| Some((files.local_dir(bucket)?.to_string(), prefix)))
+        .collect::<Vec<(String, &str)>>();
+    let path = path.to_path_buf();
+
+    tokio::task::spawn_blocking(move || -> anyhow::Result<Manifest> {
+        let mut manifest = Manifest {
+            format: BACKUP_FORMAT,
+            created_at: now(),
+            migration,
+            tables: BTreeMap::new(),
+            files: BTreeMap::new(),
+        };
+        let partial = path.with_extension("partial");
+        let file = std::fs::File::create(&partial)?;
+        let mut tar = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+        for (name, rows, json) in tables {
+            append(
+                &mut tar,
+                &mut manifest,
+                format!("tables/{name}.json"),
+                json.as_bytes(),
+            )?;
+            manifest.tables.insert(name, rows);
+        }
+        for (dir, prefix) in dirs {
+            for file in list_files(&dir) {
+                let name = match file.file_name().and_then(|x| x.to_str()) {
+                    Some(v) => format!("{prefix}/{v}"),
+                    None => continue,
+                };
+                // files can be removed by the cache limit while the backup runs
+                let data = match std::fs::read(&file) {
+                    Ok(v) => v,
+                    Err(_) => continue,
+                };
+                append(&mut tar, &mut manifest, name, &data)?;
+            }
+        }
+        let json = serde_json::to_vec_pretty(&manifest)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(json.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(manifest.created_at);
+        header.set_cksum();
+        tar.append_data(
+            &mut header,
+            format!("{BACKUP_ROOT}/manifest.json"),
+            json.as_slice(),
+        )?;
+        tar.into_inner()?.finish()?;
+        std::fs::rename(&partial, &path)?;
+        Ok(manifest)
+    })
+    .await?
+}
+
+// unpack the archive and check it against the manifest before anything is restored
+async fn unpack(path: &Path, dir: &Path) -> anyhow::Result<(PathBuf, Manifest)> {
+    let (path, dir) = (path.to_path_buf(), dir.to_path_buf());
+    tokio::task::spawn_blocking(move || -> anyhow::Result<(PathBuf, Manifest)> {
+        let _ = std::fs::remove_dir_all(&dir);
+        let file = std::fs::File::open(&path)?;
+        tar::Archive::new(GzDecoder::new(file)).unpack(&dir)?;
+        let root = dir.join(BACKUP_ROOT);
+        let manifest: Manifest =
+            serde_json::from_slice(&std::fs::read(root.join("manifest.json"))?)?;
+        if manifest.format != BACKUP_FORMAT {
+            return Err(anyhow!("unsupported backup format {}", manifest.format));
+        }
+        for (name, file) in manifest.files.iter() {
+            let data = std::fs::read(root.join(name))
+                .map_err(|e| anyhow!("{name} is missing from the backup: {e}"))?;
+            if data.len() as u64 != file.size || checksum(&data) != file.sha256 {
+                return Err(anyhow!("{name} does not match its checksum"));
+            }
+        }
+        for table in manifest.tables.keys() {
+            if !manifest.files.contains_key(&format!("tables/{table}.json")) {
+                return Err(anyhow!("{table} is missing from the backup"));
+            }
+        }
+        Ok((root, manifest))
+    })
+    .await?
+}
+
+async fn restore(path: &Path) -> anyhow::Result<Manifest> {
+    let dir = env::temp_dir().join(format!("seanify-restore-{}", std::process::id()));
+    let result = match unpack(path, &dir).await {
+        Ok((root, manifest)) => restore_from(&root, &manifest).await.map(|_| manifest),
+        Err(e) => Err(e),
+    };
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+    result
+}
+
+async fn restore_from(root: &Path, manifest: &Manifest) -> anyhow::Result<()> {
+    if manifest.migration != 0 && !MIGRATOR.iter().any(|x| x.version == manifest.migration) {
+        return Err(anyhow!(
+            "the backup is at migration {:04} which this build doesn't know about",
+            manifest.migration
+        ));
+    }
+
+    let db = Database::without_migrations().await;
+    let mut conn = db.database.acquire().await?;
+    conn.ensure_migrations_table().await?;
+    let applied = conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|x| x.version)
+        .collect::<Vec<i64>>();
+    if let Some(v) = applied.iter().find(|x| **x > manifest.migration) {
+        return Err(anyhow!(
+            "the database is already at migration {v:04}, restore needs an empty database"
+        ));
+    }
+
+    // bring the schema to where it was when the backup was taken
+    conn.lock().await?;
+    let migrated = async {
+        for migration in MIGRATOR.iter().filter(|x| {
+            !x.migration_type.is_down_migration()
+                && x.version <= manifest.migration
+                && !applied.contains(&x.version)
+        }) {
+            conn.apply(migration).await?;
+        }
+        Ok::<(), sqlx::migrate::MigrateError>(())
+    }
+    .await;
+    // unlocked even if a migration failed, the connection would go back to the pool still holding
+    // the lock and the next migrator would wait on it forever
+    let unlocked = conn.unlock().await;
+    migrated?;
+    unlocked?;
+    drop(conn);
+
+    let mut tx = db.database.begin().await?;
+    for table in manifest.tables.keys() {
+        let not_empty = sqlx::query_scalar::<_, bool>(&format!(
+            "SELECT EXISTS (SELECT 1 FROM {})",
+            quote(table)
+        ))
+        .fetch_one(&mut tx)
+        .await?;
+        if not_empty {
+            return Err(anyhow!(
+                "{table} is not empty, restore needs an empty database"
+            ));
+        }
+        let json = tokio::fs::read_to_string(root.join(format!("tables/{table}.json"))).await?;
+        sqlx::query(&format!(
+            "INSERT INTO {0} SELECT * FROM json_populate_recordset(NULL::{0}, $1::json)",
+            quote(table)
+        ))
+        .bind(json)
+        .execute(&mut tx)
+        .await?;
+    }
+
+    // serial columns have to carry on from the restored rows
+    let serials = sqlx::query_as::<_, (String, String)>(
+        r#"
+        SELECT table_name::text, column_name::text
+        FROM information_schema.columns
+        WHERE table_schema = 'public'
+        AND column_default LIKE 'nextval(%'
+        "#,
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    for (table, column) in serials {
+        sqlx::query(&format!(
+            "SELECT setval(pg_get_serial_sequence($1, $2), COALESCE(MAX({}), 0) + 1, false) FROM {}",
+            quote(&column),
+            quote(&table)
+        ))
+        .bind(quote(&table))
+        .bind(column)
+        .execute(&mut tx)
+        .await?;
+    }
+    tx.commit().await?;
+
+    MIGRATOR.run(&db.database).await?;
+
+    let files = file_store();
+    for (prefix, bucket) in [("cache/", Bucket::Songs), ("cdn/", Bucket::Images)] {
+        for name in manifest.files.keys() {
+            if let Some(file) = name.strip_prefix(prefix) {
+                let data = tokio::fs::read(root.join(name)).await?;
+                files.put(bucket, file, data).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// drop the oldest backups so only the newest retention are left
+async fn prune_backups(dir: &Path, retention: usize) -> anyhow::Result<()> {
+    let mut backups = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with("seanify-") && name.ends_with(".tar.gz") {
+            backups.push(entry.path());
+        }
+    }
+    // names have the timestamp in them so they sort oldest first
+    backups.sort();
+    let extra = backups.len().saturating_sub(retention);
+    for path in backups.into_iter().take(extra) {
+        tokio::fs::remove_file(&path).await?;
+        info!("removed old backup {}", path.display());
+    }
+    Ok(())
+}
+
+pub(crate) async fn scheduled_backups(pool: Pool<Postgres>, dir: String) {
+    let (interval, retention) = (config().backup_interval_hours, config().backup_retention);
+    let dir = PathBuf::from(dir);
+    loop {
+        tokio::time::sleep(Duration::from_secs(interval as u64 * 60 * 60)).await;
+        if let Err(e) = tokio::fs::create_dir_all(&dir).await {
+            error!("failed to create {}: {e}", dir.display());
+            continue;
+        }
+        let path = dir.join(format!("seanify-{}.tar.gz", now()));
+        match backup(&pool, &path).await {
+            Ok(_) => info!("created backup {}", path.display()),
+            Err(e) => {
+                error!("scheduled backup failed: {e}");
+                continue;
+            }
+        }
+        if let Err(e) = prune_backups(&dir, retention as usize).await {
+            error!("failed to remove old backups: {e}");
+        }
+    }
+}
@@ -0,0 +1,406 @@
+use crate::enrich::EnrichRules;
+use anyhow::anyhow;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::net::IpAddr;
+use std::path::Path;
+use tokio::sync::OnceCell;
+
+/*
+ * Everything that can be configured, loaded once at the start of run
+ *
+ * Each setting can come from (later ones win)
+ *   - the defaults below
+ *   - a TOML file, seanify.toml or whatever --config/SEANIFY_CONFIG points to
+ *   - env variables (and the .env file), the setting name in caps, ex: CACHE_DIR
+ *   - flags passed to seanify, the setting name with dashes, ex: --cache-dir assets
+ *
+ * The whole config is checked before anything else starts so a bad value is reported right away
+ * instead of when it's first used
+ */
+const DEFAULT_CONFIG_FILE: &str = "seanify.toml";
+
+const DEFAULT_MAX_CONNECTIONS: u32 = 3;
+const DEFAULT_MAX_TIMEOUT: u32 = 2;
+const DEFAULT_PORT: u16 = 8080;
+
+// time in seconds between downloading from queue
+const DEFAULT_QUEUE_COOLDOWN: u32 = 10;
+const DEFAULT_QUEUE_LIMIT: u32 = 50;
+
+const DEFAULT_RATE_BLACKLIST_CYCLE_MS: u32 = 10000;
+const DEFAULT_RATE_BAN_IN_SECONDS: u32 = 60;
+
+// max request allowed per a second per an address
+const DEFAULT_RATE_MAX_COUNT: u32 = 50;
+
+const DEFAULT_S3_REGION: &str = "us-east-1";
+
+// the longest a presigned url can be valid for with signature version 4, 7 days
+const MAX_S3_PRESIGN_SECONDS: u32 = 604800;
+
+const DEFAULT_BACKUP_INTERVAL_HOURS: u32 = 24;
+const DEFAULT_BACKUP_RETENTION: u32 = 7;
+
+// time in seconds between enrichment passes over new songs
+const DEFAULT_ENRICH_INTERVAL: u32 = 60;
+
+macro_rules! settings {
+    ($($field:ident: $ty:ty),* $(,)?) => {
+        // one layer of settings, anything that wasn't given is None
+        #[derive(Default, Deserialize)]
+        #[serde(default, deny_unknown_fields)]
+        struct Settings {
+            $($field: Option<$ty>,)*
+        }
+
+        const SETTINGS: &[&str] = &[$(stringify!($field),)*];
+
+        impl Settings {
+            fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> anyhow::Result<Self> {
+                Ok(Self {
+                    $($field: match lookup(stringify!($field)) {
+                        Some(v) => Some(v.parse::<$ty>().map_err(|e| {
+                            anyhow!("{} is invalid: {e}", stringify!($field))
+                        })?),
+                        None => None,
+                    },)*
+                })
+            }
+
+            // put other on top of this layer
+            fn merge(&mut self, other: Settings) {
+                $(if other.$field.is_some() {
+                    self.$field = other.$field;
+                })*
+            }
+        }
+    };
+}
+
+settings! {
+    database_url: String,
+    max_connections: u32,
+    max_timeout: u32,
+    instance_key: String,
+    admin_key: String,
+    cache_dir: String,
+    cdn_dir: String,
+    bind: IpAddr,
+    port: u16,
+    tls_cert: String,
+    tls_key: String,
+    http_redirect_port: u16,
+    queue_cooldown: u32,
+    queue_limit: u32,
+    max_cache_size_mb: u64,
+    ytdl_call_limit: u64,
+    bandwidth_limit_mb: u64,
+    max_file_size_mb: u64,
+    rate_blacklist_cycle_ms: u32,
+    rate_ban_in_seconds: u32,
+    rate_max_count: u32,
+    s3_endpoint: String,
+    s3_bucket: String,
+    s3_region: String,
+    s3_access_key: String,
+    s3_secret_key: String,
+    s3_presign_seconds: u32,
+    backup_dir: String,
+    backup_interval_hours: u32,
+    backup_retention: u32,
+    enrich_rules: String,
+    enrich_noise: String,
+    enrich_interval: u32,
+}
+
+pub struct Config {
+    pub database_url: String,
+    pub max_connections: u32,
+    pub max_timeout: u32,
+    pub instance_key: String,
+    pub admin_key: String, // empty disables everything admin
+    pub cache_dir: String,
+    pub cdn_dir: String,
+    pub bind: IpAddr,
+    pub port: u16,
+    pub tls_cert: Option<String>, // pem files, see tls.rs
+    pub tls_key: Option<String>,
+    pub http_redirect_port: Option<u16>,
+    pub queue_cooldown: u32,
+    pub queue_limit: u32,
+    pub max_cache_size_mb: Option<u64>,
+    pub ytdl_call_limit: Option<u64>,
+    pub bandwidth_limit_mb: Option<u64>,
+    pub max_file_size_mb: Option<u64>,
+    pub rate_blacklist_cycle_ms: u32,
+    pub rate_ban_in_seconds: u32,
+    pub rate_max_count: u32,
+    pub s3_endpoint: Option<String>, // songs and images go to S3 instead of the dirs, see s3.rs
+    pub s3_bucket: Option<String>,
+    pub s3_region: String,
+    pub s3_access_key: Option<String>,
+    pub s3_secret_key: Option<String>,
+    pub s3_presign_seconds: Option<u32>, // redirect to presigned urls instead of proxying
+    pub backup_dir: Option<String>,
+    pub backup_interval_hours: u32,
+    pub backup_retention: u32,
+    pub enrich_rules: EnrichRules, // see enrich.rs
+    pub enrich_interval: u32,
+}
+
+lazy_static! {
+    static ref CONFIG: OnceCell<Config> = OnceCell::new();
+}
+
+// the loaded config, only valid after load_config (or set_config) has been called
+pub(crate) fn config() -> &'static Config {
+    CONFIG.get().expect("config is loaded at the start of run")
+}
+
+/*
+ * Split the args given to seanify into settings and everything else (the subcommand and its
+ * args), flags can be given as --name value or --name=value
+ */
+fn parse_flags<S: AsRef<str>>(
+    args: &[S],
+) -> anyhow::Result<(HashMap<String, String>, Vec<String>)> {
+    let mut flags = HashMap::new();
+    let mut rest = Vec::new();
+    let mut args = args.iter().map(|x| x.as_ref());
+    while let Some(arg) = args.next() {
+        let flag = match arg.strip_prefix("--") {
+            Some(v) => v,
+            None => {
+                rest.push(arg.to_string());
+                continue;
+            }
+        };
+        let (name, value) = match flag.split_once('=') {
+            Some((k, v)) => (k, v.to_string()),
+            None => match args.next() {
+                Some(v) => (flag, v.to_string()),
+                None => return Err(anyhow!("--{flag} needs a value")),
+            },
+        };
+        let name = name.replace('-', "_");
+        if name != "config" && !SETTINGS.contains(&name.as_str()) {
+            return Err(anyhow!("unknown flag --{}", name.replace('_', "-")));
+        }
+        flags.insert(name, value);
+    }
+    Ok((flags, rest))
+}
+
+fn from_file(path: &str, required: bool) -> anyhow::Result<Settings> {
+    match std::fs::read_to_string(path) {
+        Ok(v) => toml::from_str(&v).map_err(|e| anyhow!("failed to parse {path}: {e}")),
+        Err(_) if !required => Ok(Settings::default()),
+        Err(e) => Err(anyhow!("failed to read {path}: {e}")),
+    }
+}
+
+// check a directory exists or can be made
+fn check_dir(name: &str, dir: &str, problems: &mut Vec<String>) {
+    if let Err(e) = std::fs::create_dir_all(dir) {
+        problems.push(format!("{name} {dir} can't be used: {e}"));
+    } else if !Path::new(dir).is_dir() {
+        problems.push(format!("{name} {dir} is not a directory"));
+    }
+}
+
+impl Config {
+    /*
+     * A config from flags alone with the defaults for everything else, the file and env are not
+     * looked at. Used by the integration tests so the .env of whoever runs them doesn't leak in
+     */
+    pub fn from_flags<S: AsRef<str>>(args: &[S]) -> anyhow::Result<Self> {
+        let (flags, rest) = parse_flags(args)?;
+        if let Some(arg) = rest.first() {
+            return Err(anyhow!("unexpected argument {arg}"));
+        }
+        Self::validate(Settings::from_lookup(|x| flags.get(x).cloned())?)
+    }
+
+    fn validate(s: Settings) -> anyhow::Result<Self> {
+        let mut problems = Vec::new();
+        let mut required = |name: &str, v: Option<String>| match v {
+            Some(v) if !v.is_empty() => v,
+            _ => {
+                problems.push(format!("{name} has to be set"));
+                String::new()
+            }
+        };
+        let database_url = required("database_url", s.database_url);
+        let instance_key = required("instance_key", s.instance_key);
+        // the local directories are only used when the files aren't kept in s3
+        let (cache_dir, cdn_dir) = match s.s3_bucket {
+            None => (
+                required("cache_dir", s.cache_dir),
+                required("cdn_dir", s.cdn_dir),
+            ),
+            Some(_) => (
+                s.cache_dir.unwrap_or_default(),
+                s.cdn_dir.unwrap_or_default(),
+            ),
+        };
+
+        for dir in [("cache_dir", &cache_dir), ("cdn_dir", &cdn_dir)] {
+            if !dir.1.is_empty() {
+                check_dir(dir.0, dir.1, &mut problems);
+            }
+        }
+        if let Some(dir) = &s.backup_dir {
+            check_dir("backup_dir", dir, &mut problems);
+        }
+        let positive = [
+            ("max_connections", s.max_connections),
+            ("queue_limit", s.queue_limit),
+            ("rate_blacklist_cycle_ms", s.rate_blacklist_cycle_ms),
+            ("rate_max_count", s.rate_max_count),
+            ("backup_interval_hours", s.backup_interval_hours),
+            ("backup_retention", s.backup_retention),
+            ("enrich_interval", s.enrich_interval),
+        ];
+        for (name, v) in positive {
+            if v == Some(0) {
+                problems.push(format!("{name} has to be more than 0"));
+            }
+        }
+        if s.port == Some(0) {
+            problems.push(String::from("port has to be more than 0"));
+        }
+        match (&s.tls_cert, &s.tls_key) {
+            (Some(cert), Some(key)) => {
+                for file in [("tls_cert", cert), ("tls_key", key)] {
+                    if let Err(e) = std::fs::File::open(file.1) {
+                        problems.push(format!("{} {} can't be read: {e}", file.0, file.1));
+                    }
+                }
+            }
+            (None, None) => {
+                if s.http_redirect_port.is_some() {
+                    problems.push(String::from(
+                        "http_redirect_port needs tls_cert and tls_key",
+                    ));
+                }
+            }
+            _ => problems.push(String::from("tls_cert and tls_key have to be set together")),
+        }
+        if s.http_redirect_port.is_some() && s.http_redirect_port == s.port {
+            problems.push(String::from("http_redirect_port can't be the same as port"));
+        }
+        let s3 = [
+            s.s3_endpoint.is_some(),
+            s.s3_bucket.is_some(),
+            s.s3_access_key.is_some(),
+            s.s3_secret_key.is_some(),
+        ];
+        if s3.contains(&true) && s3.contains(&false) {
+            problems.push(String::from(
+                "s3_endpoint, s3_bucket, s3_access_key and s3_secret_key have to be set together",
+            ));
+        }
+        if let Some(v) = &s.s3_endpoint {
+            if !v.starts_with("http://") && !v.starts_with("https://") {
+                problems.push(format!(
+                    "s3_endpoint {v} has to start with http:// or https://"
+                ));
+            }
+        }
+        if let Some(v) = s.s3_presign_seconds {
+            if v == 0 || v > MAX_S3_PRESIGN_SECONDS {
+                problems.push(format!(
+                    "s3_presign_seconds has to be between 1 and {MAX_S3_PRESIGN_SECONDS}"
+                ));
+            }
+            if s.s3_endpoint.is_none() {
+                problems.push(String::from("s3_presign_seconds needs s3_endpoint"));
+            }
+        }
+        let enrich_rules = EnrichRules::parse(s.enrich_rules.as_deref(), s.enrich_noise.as_deref())
+            .unwrap_or_else(|e| {
+                problems.push(e.to_string());
+                EnrichRules::default()
+            });
+
+        if !problems.is_empty() {
+            return Err(anyhow!("invalid config:\n  {}", problems.join("\n  ")));
+        }
+
+        Ok(Self {
+            database_url,
+            max_connections: s.max_connections.unwrap_or(DEFAULT_MAX_CONNECTIONS),
+            max_timeout: s.max_timeout.unwrap_or(DEFAULT_MAX_TIMEOUT),
+            instance_key,
+            admin_key: s.admin_key.unwrap_or_default(),
+            cache_dir,
+            cdn_dir,
+            bind: s.bind.unwrap_or_else(|| IpAddr::from([127, 0, 0, 1])),
+            port: s.port.unwrap_or(DEFAULT_PORT),
+            tls_cert: s.tls_cert,
+            tls_key: s.tls_key,
+            http_redirect_port: s.http_redirect_port,
+            queue_cooldown: s.queue_cooldown.unwrap_or(DEFAULT_QUEUE_COOLDOWN),
+            queue_limit: s.queue_limit.unwrap_or(DEFAULT_QUEUE_LIMIT),
+            max_cache_size_mb: s.max_cache_size_mb,
+            ytdl_call_limit: s.ytdl_call_limit,
+            bandwidth_limit_mb: s.bandwidth_limit_mb,
+            max_file_size_mb: s.max_file_size_mb,
+            rate_blacklist_cycle_ms: s
+                .rate_blacklist_cycle_ms
+                .unwrap_or(DEFAULT_RATE_BLACKLIST_CYCLE_MS),
+            rate_ban_in_seconds: s.rate_ban_in_seconds.unwrap_or(DEFAULT_RATE_BAN_IN_SECONDS),
+            rate_max_count: s.rate_max_count.unwrap_or(DEFAULT_RATE_MAX_COUNT),
+            s3_endpoint: s.s3_endpoint,
+            s3_bucket: s.s3_bucket,
+            s3_region: s
+                .s3_region
+                .unwrap_or_else(|| String::from(DEFAULT_S3_REGION)),
+            s3_access_key: s.s3_access_key,
+            s3_secret_key: s.s3_secret_key,
+            s3_presign_seconds: s.s3_presign_seconds,
+            backup_dir: s.backup_dir,
+            backup_interval_hours: s
+                .backup_interval_hours
+                .unwrap_or(DEFAULT_BACKUP_INTERVAL_HOURS),
+            backup_retention: s.backup_retention.unwrap_or(DEFAULT_BACKUP_RETENTION),
+            enrich_rules,
+            enrich_interval: s.enrich_interval.unwrap_or(DEFAULT_ENRICH_INTERVAL),
+        })
+    }
+}
+
+/*
+ * Build the config from the file, env and flags and store it for config(), returns the args
+ * that weren't flags
+ */
+pub(crate) fn load_config<S: AsRef<str>>(args: &[S]) -> anyhow::Result<Vec<String>> {
+    let (mut flags, rest) = parse_flags(args)?;
+    let _ = dotenv::dotenv();
+
+    let mut settings = match flags
+        .remove("config")
+        .or_else(|| env::var("SEANIFY_CONFIG").ok())
+    {
+        Some(v) => from_file(&v, true)?,
+        None => from_file(DEFAULT_CONFIG_FILE, false)?,
+    };
+    settings.merge(Settings::from_lookup(|x| {
+        env::var(x.to_uppercase()).ok().filter(|x| !x.is_empty())
+    })?);
+    settings.merge(Settings::from_lookup(|x| flags.get(x).cloned())?);
+
+    set_config(Config::validate(settings)?)?;
+    Ok(rest)
+}
+
+// store the config for config(), it can only be set once
+pub fn set_config(config: Config) -> anyhow::Result<()> {
+    if CONFIG.set(config).is_err() {
+        return Err(anyhow!("config was already loaded"));
+    }
+    Ok(())
+}
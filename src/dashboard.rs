@@ -0,0 +1,134 @@
+use crate::{config, with_state, AppState, Bucket, QueueStatus};
+use log::error;
+use serde::Serialize;
+use std::collections::HashSet;
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+/*
+ * JSON status of the instance for operators, served at /admin/status
+ *
+ * Requests have to send the ADMIN_KEY as a bearer token (Authorization: Bearer ADMIN_KEY), if no
+ * ADMIN_KEY is set the dashboard can't be used at all
+ */
+#[derive(Serialize)]
+pub(crate) struct InstanceStatus {
+    pub clients: ClientStatus,
+    pub song_manager: QueueStatus,
+    pub cache: CacheStatus,
+    pub database: Option<PoolStatus>, // null for the in memory storage
+    pub rate_limited: Vec<RateLimitedUser>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ClientStatus {
+    pub connected: usize,
+    pub authenticated: usize,
+    pub unique_users: usize,
+}
+
+#[derive(Serialize)]
+pub(crate) struct CacheStatus {
+    pub files: u64,
+    pub bytes: u64,
+    pub limit_mb: Option<u64>, // MAX_CACHE_SIZE_MB
+}
+
+#[derive(Serialize)]
+pub(crate) struct PoolStatus {
+    pub connections: u32,
+    pub idle: usize,
+    pub closed: bool,
+}
+
+#[derive(Serialize)]
+pub(crate) struct RateLimitedUser {
+    pub username_hash: String,
+    pub seconds_left: usize,
+}
+
+pub(crate) async fn instance_status(state: &AppState) -> InstanceStatus {
+    let clients = {
+        let locked = state.clients.lock().await;
+        let authenticated = locked.values().filter(|x| x.auth);
+        ClientStatus {
+            connected: locked.len(),
+            authenticated: authenticated.clone().count(),
+            unique_users: authenticated
+                .map(|x| x.username_hash)
+                .collect::<HashSet<u64>>()
+                .len(),
+        }
+    };
+
+    let song_manager = state.song_manager.read().await.status();
+
+    let rate_limited = state
+        .blocked_list
+        .read()
+        .unwrap()
+        .list
+        .iter()
+        .map(|(k, v)| RateLimitedUser {
+            username_hash: k.to_string(),
+            seconds_left: *v,
+        })
+        .collect();
+
+    InstanceStatus {
+        clients,
+        song_manager,
+        cache: cache_status(state).await,
+        database: state.storage.pool_status(),
+        rate_limited,
+    }
+}
+
+// the songs in the file store, wherever that is
+async fn cache_status(state: &AppState) -> CacheStatus {
+    let mut status = CacheStatus {
+        files: 0,
+        bytes: 0,
+        limit_mb: config().max_cache_size_mb,
+    };
+    match state.files.list(Bucket::Songs, "").await {
+        Ok(v) => {
+            status.files = v.len() as u64;
+            status.bytes = v.iter().map(|(_, size)| size).sum();
+        }
+        Err(e) => error!("failed to list the cached songs: {e}"),
+    }
+    status
+}
+
+// bearer token check against the ADMIN_KEY, an empty key never matches
+pub(crate) fn is_admin_token(header: Option<String>) -> bool {
+    match header {
+        Some(v) => {
+            !config().admin_key.is_empty()
+                && v.strip_prefix("Bearer ") == Some(config().admin_key.as_str())
+        }
+        None => false,
+    }
+}
+
+pub(crate) fn dashboard(
+    state: AppState,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("admin" / "status")
+        .and(warp::get())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(with_state(state))
+        .and_then(|auth: Option<String>, state: AppState| async move {
+            if !is_admin_token(auth) {
+                return Ok::<_, Rejection>(warp::reply::with_status(
+                    warp::reply::json(&"Unauthorized"),
+                    StatusCode::UNAUTHORIZED,
+                ));
+            }
+            Ok(warp::reply::with_status(
+                warp::reply::json(&instance_status(&state).await),
+                StatusCode::OK,
+            ))
+        })
+}
@@ -1,27 +1,34 @@
-use crate::pictures::{default_playlist_image, save_playlist_image};
-use crate::user::Playlist;
+use crate::user::{FollowList, FollowStatus, Playlist, Profile, FOLLOW_PAGE_SIZE};
 use crate::{UserData, UserDataBigD};
 use anyhow::anyhow;
 use log::{error, info, LevelFilter};
 use num_traits::ToPrimitive;
 use seahash::hash;
 use serde::Serialize;
+use sqlx::migrate::Migrator;
 use sqlx::postgres::{PgConnectOptions, PgPoolOptions, Postgres};
 use sqlx::ConnectOptions;
-use sqlx::Pool;
-use std::env::{self, var};
+use sqlx::{Pool, Transaction};
 use std::thread::sleep;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tokio::fs::remove_file;
 
-use crate::env_fetch;
+use crate::activity::*;
+use crate::admin::{AdminLog, AdminLogEntry, ADMIN_LOG_PAGE_SIZE};
+use crate::artwork::Artwork;
+use crate::config;
+use crate::enrich::SongFields;
+use crate::export::{ExportedPlay, ExportedPlaylist, ExportedTrack};
+use crate::invites::*;
+use crate::metadata::*;
+use crate::metrics::TimedQuery;
 use crate::songs::Song;
-
-const DEFAULT_MAX_CONNECTIONS: u32 = 3;
-const DEFAULT_MAX_TIMEOUT: u32 = 2;
+use crate::stats::*;
 
 pub(crate) type BigD = sqlx::types::BigDecimal;
 
+// everything under migrations/ is embedded in the binary and applied in order of the version
+pub(crate) static MIGRATOR: Migrator = sqlx::migrate!();
+
 // Only used for authentication and signup
 struct UserAuth {
     pub username: Option<BigD>,
@@ -42,6 +49,18 @@ struct UserHash {
     pub username: BigD,
 }
 
+// The user being followed and if they need to accept follow requests
+struct FollowTarget {
+    pub username: BigD,
+    pub public_profile: Option<bool>,
+}
+
+// A single follower or followed user when listing them
+pub(crate) struct FollowResult {
+    pub display_name: Option<String>,
+    pub created_at: BigD,
+}
+
 // Used to fetch the user displayname from the hash
 struct DisplayName {
     pub display_name: Option<String>,
@@ -109,6 +128,7 @@ pub(crate) struct SongTitleResult {
     pub album_artist: Option<String>,
     pub artist: Option<String>,
     pub creator: Option<String>,
+    pub genre: Option<String>,
     pub upload_date: Option<String>,
     pub downloaded: bool,
 }
@@ -118,11 +138,13 @@ pub(crate) struct SongTitleResultOut {
     pub id: String,
     pub title: String,
     pub uploader: Option<String>,
-    pub thumbnail: Option<String>,
+    #[serde(flatten)]
+    pub artwork: Artwork,
     pub album: Option<String>,
     pub album_artist: Option<String>,
     pub artist: Option<String>,
     pub creator: Option<String>,
+    pub genre: Option<String>,
     pub upload_date: Option<String>,
     pub downloaded: bool,
 }
@@ -133,20 +155,233 @@ impl From<SongTitleResult> for SongTitleResultOut {
             id: s.id.to_u64().unwrap_or_default().to_string(),
             title: s.title,
             uploader: s.uploader,
-            thumbnail: s.thumbnail,
+            artwork: Artwork::of(s.id.to_u64().unwrap_or_default(), &s.thumbnail),
             album: s.album,
             album_artist: s.album_artist,
             artist: s.artist,
             creator: s.creator,
+            genre: s.genre,
             upload_date: s.upload_date,
             downloaded: s.downloaded
         } 
     }
 }
 
-struct SongDetails {
+pub(crate) struct SongDetails {
+    pub id: BigD,
+    pub title: String,
+}
+
+// an EDIT_SONG with the display name of the admin that made it
+struct SongEditResult {
+    id: i64,
+    display_name: Option<String>,
+    old_values: String,
+    new_values: String,
+    created_at: BigD,
+}
+
+impl From<SongEditResult> for SongEditEntry {
+    fn from(e: SongEditResult) -> Self {
+        Self {
+            id: e.id.to_string(),
+            editor: e.display_name,
+            old: metadata_from_json(&e.old_values),
+            new: metadata_from_json(&e.new_values),
+            timestamp: e.created_at.to_u64().unwrap_or_default(),
+        }
+    }
+}
+
+struct SongOverrideResult {
+    song_hash: BigD,
+    custom_name: String,
+}
+
+// every column of a song that Song has
+struct SongResult {
+    id: BigD,
+    title: String,
+    upload_date: Option<String>,
+    uploader: Option<String>,
+    url: Option<String>,
+    genre: Option<String>,
+    thumbnail: Option<String>,
+    album: Option<String>,
+    album_artist: Option<String>,
+    artist: Option<String>,
+    creator: Option<String>,
+    filesize: Option<i64>,
+    duration: Option<i64>,
+}
+
+impl From<SongResult> for Song {
+    fn from(x: SongResult) -> Self {
+        Self {
+            id: x.id.to_u64(),
+            title: Some(x.title),
+            upload_date: x.upload_date,
+            uploader: x.uploader,
+            url: x.url,
+            genre: x.genre,
+            thumbnail: x.thumbnail,
+            album: x.album,
+            album_artist: x.album_artist,
+            artist: x.artist,
+            creator: x.creator,
+            filesize: x.filesize,
+            duration: x.duration,
+        }
+    }
+}
+
+// a song the enrichment pass hasn't looked at yet
+struct UnenrichedSong {
     id: BigD,
     title: String,
+    artist: Option<String>,
+    album: Option<String>,
+    album_artist: Option<String>,
+    genre: Option<String>,
+    uploader: Option<String>,
+    creator: Option<String>,
+    upload_date: Option<String>,
+}
+
+impl From<UnenrichedSong> for (u64, SongFields) {
+    fn from(x: UnenrichedSong) -> Self {
+        let fields = SongFields {
+            title: x.title,
+            artist: x.artist,
+            album: x.album,
+            album_artist: x.album_artist,
+            genre: x.genre,
+            uploader: x.uploader,
+            creator: x.creator,
+            upload_date: x.upload_date,
+        };
+        (x.id.to_u64().unwrap_or_default(), fields)
+    }
+}
+
+// a single entry of the play history
+struct PlayedSong {
+    song_hash: BigD,
+}
+
+struct TopSongResult {
+    song_hash: BigD,
+    title: String,
+    artist: Option<String>,
+    plays: i64,
+}
+
+impl From<TopSongResult> for TopSong {
+    fn from(s: TopSongResult) -> Self {
+        Self {
+            id: s.song_hash.to_u64().unwrap_or_default().to_string(),
+            title: s.title,
+            artist: s.artist,
+            plays: s.plays,
+        }
+    }
+}
+
+struct FeedResult {
+    id: i64,
+    display_name: Option<String>,
+    kind: String,
+    playlist: Option<String>,
+    song_hash: Option<BigD>,
+    detail: Option<String>,
+    created_at: BigD,
+}
+
+impl From<FeedResult> for FeedItem {
+    fn from(f: FeedResult) -> Self {
+        Self {
+            id: f.id.to_string(),
+            user: f.display_name,
+            kind: f.kind,
+            playlist: f.playlist,
+            song: f
+                .song_hash
+                .map(|x| x.to_u64().unwrap_or_default().to_string()),
+            detail: f.detail,
+            timestamp: f.created_at.to_u64().unwrap_or_default(),
+        }
+    }
+}
+
+struct AdminLogResult {
+    id: i64,
+    display_name: Option<String>,
+    command: String,
+    args: String,
+    response: String,
+    created_at: BigD,
+}
+
+impl From<AdminLogResult> for AdminLogEntry {
+    fn from(a: AdminLogResult) -> Self {
+        Self {
+            id: a.id.to_string(),
+            admin: a.display_name,
+            command: a.command,
+            args: a.args,
+            response: a.response,
+            timestamp: a.created_at.to_u64().unwrap_or_default(),
+        }
+    }
+}
+
+struct ExportPlaylistResult {
+    name: String,
+    description: Option<String>,
+    public_playlist: bool,
+    creation_timestamp: BigD,
+    last_update: BigD,
+}
+
+struct ExportTrackResult {
+    playlist_name: String,
+    song_hash: BigD,
+    song_name: String,
+    custom_name: Option<String>,
+    date_added: BigD,
+}
+
+struct ExportPlayResult {
+    song_hash: BigD,
+    title: Option<String>,
+    played_at: BigD,
+}
+
+struct InviteResult {
+    code: String,
+    uses: i32,
+    max_uses: i32,
+    expires_at: Option<BigD>,
+    created_at: BigD,
+}
+
+struct InviteUseResult {
+    code: String,
+    display_name: Option<String>,
+}
+
+struct InvitedBy {
+    created_by: BigD,
+}
+
+struct InvitesLeft {
+    invites_left: i32,
+}
+
+struct ListenTimeResult {
+    plays: i64,
+    unique_songs: i64,
+    seconds: BigD,
 }
 
 macro_rules! to_big_d {
@@ -158,31 +393,28 @@ macro_rules! to_big_d {
     };
 }
 
-#[macro_export]
-macro_rules! env_num_or_default {
-    ($val:expr, $default:expr) => {
-        match std::env::var($val)
-            .unwrap_or(String::from(""))
-            .parse::<u32>()
-        {
-            Ok(v) => v,
-            Err(e) => {
-                error!(
-                    "{} is invalid due to: {e}, using default of {}",
-                    $val, $default
-                );
-                $default
-            }
-        }
-    };
-}
-
 impl Database {
+    // connect and bring the schema up to date
     pub async fn new() -> anyhow::Result<Self> {
-        let uri = var("DATABASE_URL").unwrap();
-        Ok(Self {
-            database: Self::try_connect(&uri).await,
-        })
+        let db = Self::without_migrations().await;
+        MIGRATOR.run(&db.database).await?;
+        Ok(db)
+    }
+
+    // connect without touching the schema, used by the migrate subcommand
+    pub async fn without_migrations() -> Self {
+        Self {
+            database: Self::try_connect(&config().database_url).await,
+        }
+    }
+
+    // connect to some other database than the one in the config and bring it up to date, the
+    // tests use this to get a database of their own
+    #[cfg(any(test, feature = "test-util"))]
+    pub async fn with_url(uri: &str) -> anyhow::Result<Self> {
+        let database = Self::connect(uri).await?;
+        MIGRATOR.run(&database).await?;
+        Ok(Self { database })
     }
 
     pub async fn try_connect(uri: &str) -> Pool<Postgres> {
@@ -201,12 +433,9 @@ impl Database {
         let mut connect_opts = PgConnectOptions::new();
         connect_opts.log_statements(LevelFilter::Debug);
 
-        let timeout = env_num_or_default!("MAX_TIMEOUT", DEFAULT_MAX_TIMEOUT);
-        let max_connections = env_num_or_default!("MAX_CONNECTIONS", DEFAULT_MAX_CONNECTIONS);
-
         let my_pool = PgPoolOptions::new()
-            .max_connections(max_connections)
-            .connect_timeout(Duration::from_secs(timeout.into()))
+            .max_connections(config().max_connections)
+            .connect_timeout(Duration::from_secs(config().max_timeout.into()))
             .connect(uri)
             .await?;
 
@@ -231,6 +460,7 @@ WHERE
             name
         )
         .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
         .await?;
 
         Ok(playlist)
@@ -252,6 +482,7 @@ WHERE
             time!()
         )
         .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
         .await?;
 
         Ok(())
@@ -273,6 +504,7 @@ WHERE
             "
         )
         .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
         .await?;
 
         Ok(())
@@ -291,16 +523,19 @@ SELECT
     album_artist, 
     artist, 
     creator, 
+    genre,
     upload_date, 
     downloaded 
 FROM 
     songs
 WHERE
     downloaded_timestamp >= $1
+    OR edited_timestamp >= $1
             ",
             BigD::from(timestamp)
         )
         .fetch_all(&mut self.database.acquire().await?)
+        .timed()
         .await?;
         let mut songs: Vec<SongTitleResultOut> = Vec::with_capacity(data.len());
         for song in data {
@@ -331,9 +566,13 @@ WHERE
         creator, 
         filesize, 
         downloaded_timestamp, 
-        downloaded
+        downloaded,
+        duration,
+        original_title,
+        original_creator,
+        original_upload_date
     )
- VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14);
+ VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $2, $11, $3);
              ",
             to_big_d!(song.id),
             song.title,
@@ -348,9 +587,11 @@ WHERE
             song.creator,
             song.filesize,
             BigD::from(0),
-            false
+            false,
+            song.duration
         )
         .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
         .await?;
 
         Ok(())
@@ -360,21 +601,18 @@ WHERE
         let hash = sqlx::query_as!(
             UserHash,
             "
-SELECT 
-    username 
-FROM 
-    auth 
-WHERE 
-    (
-        SELECT 
-            (userdata).display_name 
-        FROM 
-            auth
-    ) = $1;
+SELECT
+    username
+FROM
+    auth
+WHERE
+    (userdata).display_name = $1
+LIMIT 1;
             ",
             display_name.to_owned(),
         )
         .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
         .await?;
         match hash {
             Some(v) => Ok(v.username),
@@ -384,103 +622,418 @@ WHERE
 
     pub async fn unfollow_user(&self, userhash: u64, name_to_unfollow: &str) -> anyhow::Result<()> {
         let hash = self.userhash_from_username(name_to_unfollow).await?;
+        let mut tx = self.database.begin().await?;
         sqlx::query!(
             "
-UPDATE 
-    auth 
-SET
-    userdata.following = array_remove(
-        (
-            SELECT 
-                (userdata).following 
-            FROM 
-                auth
-        ), 
-        $1
-    )
-WHERE 
-    username = $2; 
+DELETE FROM
+    follows
+WHERE
+    follower = $1
+    AND followee = $2;
             ",
-            hash,
-            BigD::from(userhash)
+            BigD::from(userhash),
+            hash
         )
-        .fetch_optional(&mut self.database.acquire().await?)
+        .execute(&mut tx)
+        .timed()
         .await?;
 
+        // unfollowing a private profile also cancels a pending request
         sqlx::query!(
             "
-UPDATE 
-    auth 
-SET
-    userdata.followers = array_remove(
-        (
-            SELECT 
-                (userdata).followers 
-            FROM 
-                auth
-        ), 
-        $1)
-WHERE 
-    username = $2; 
+DELETE FROM
+    follow_requests
+WHERE
+    requester = $1
+    AND target = $2;
             ",
             BigD::from(userhash),
             hash
         )
-        .fetch_optional(&mut self.database.acquire().await?)
+        .execute(&mut tx)
+        .timed()
         .await?;
+        tx.commit().await?;
         Ok(())
     }
 
-    pub async fn follow_user(&self, userhash: u64, name_to_follow: &str) -> anyhow::Result<()> {
-        let hash = self.userhash_from_username(name_to_follow).await?;
+    /*
+     * Following is a single row in follows, the user being followed is locked while inserting so
+     * they cannot be removed half way through
+     *
+     * If the profile is private a follow request is created instead which has to be accepted, and
+     * following someone you already follow does nothing
+     */
+    pub async fn follow_user(
+        &self,
+        userhash: u64,
+        name_to_follow: &str,
+    ) -> anyhow::Result<FollowStatus> {
+        let mut tx = self.database.begin().await?;
+        let target = match sqlx::query_as!(
+            FollowTarget,
+            "
+SELECT
+    username,
+    (userdata).public_profile
+FROM
+    auth
+WHERE
+    (userdata).display_name = $1
+LIMIT 1
+FOR SHARE;
+            ",
+            name_to_follow
+        )
+        .fetch_optional(&mut tx)
+        .timed()
+        .await?
+        {
+            Some(v) => v,
+            None => return Err(anyhow!("no user of that name")),
+        };
+        let hash = target.username;
+        if hash == BigD::from(userhash) {
+            return Err(anyhow!("CannotFollowSelf"));
+        }
+        if self.is_blocked(&BigD::from(userhash), &hash).await? {
+            return Err(anyhow!("Blocked"));
+        }
+
+        if target.public_profile != Some(true) {
+            let requested = sqlx::query!(
+                "
+INSERT INTO
+    follow_requests(
+        requester,
+        target,
+        created_at
+    )
+SELECT
+    $1::NUMERIC, $2::NUMERIC, $3::NUMERIC
+WHERE NOT EXISTS(
+    SELECT
+        1
+    FROM
+        follows
+    WHERE
+        follower = $1
+        AND followee = $2
+)
+ON CONFLICT DO NOTHING;
+                ",
+                BigD::from(userhash),
+                hash,
+                time!()
+            )
+            .execute(&mut tx)
+            .timed()
+            .await?
+            .rows_affected();
+            tx.commit().await?;
+
+            if requested == 0
+                && self
+                    .following_of(userhash)
+                    .await?
+                    .contains(&hash.to_u64().unwrap_or_default())
+            {
+                return Ok(FollowStatus::Following);
+            }
+            return Ok(FollowStatus::Requested {
+                target: hash.to_u64().unwrap_or_default(),
+                new: requested > 0,
+            });
+        }
+
+        self.insert_follow(&mut tx, userhash, &hash).await?;
+        tx.commit().await?;
+
+        let _ = self
+            .log_activity(
+                userhash,
+                Activity::Followed {
+                    display_name: name_to_follow.to_string(),
+                },
+            )
+            .await;
+        Ok(FollowStatus::Following)
+    }
+
+    async fn insert_follow(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        follower: u64,
+        followee: &BigD,
+    ) -> anyhow::Result<()> {
         sqlx::query!(
             "
-UPDATE 
-    auth 
-SET
-    userdata.following = array_append(
-        (
-            SELECT 
-                (userdata).following 
-            FROM 
-                auth
-        ), 
-        $1)
-WHERE 
-    username = $2; 
+INSERT INTO
+    follows(
+        follower,
+        followee,
+        created_at
+    )
+VALUES($1, $2, $3)
+ON CONFLICT DO NOTHING;
             ",
-            hash,
+            BigD::from(follower),
+            followee,
+            time!()
+        )
+        .execute(&mut *tx)
+        .timed()
+        .await?;
+        Ok(())
+    }
+
+    /*
+     * Accept a pending follow request from the user with the given display name, the username hash
+     * of the requester is returned so they can be told
+     */
+    pub async fn accept_follow(&self, userhash: u64, requester_name: &str) -> anyhow::Result<u64> {
+        let requester = self.userhash_from_username(requester_name).await?;
+        let mut tx = self.database.begin().await?;
+        let removed = sqlx::query!(
+            "
+DELETE FROM
+    follow_requests
+WHERE
+    requester = $1
+    AND target = $2;
+            ",
+            requester,
             BigD::from(userhash)
         )
-        .fetch_optional(&mut self.database.acquire().await?)
+        .execute(&mut tx)
+        .timed()
+        .await?
+        .rows_affected();
+        if removed == 0 {
+            return Err(anyhow!("NoFollowRequest"));
+        }
+        let requester = requester.to_u64().unwrap_or_default();
+        self.insert_follow(&mut tx, requester, &BigD::from(userhash))
+            .await?;
+        tx.commit().await?;
+
+        if let Some(display_name) = self
+            .get_user_data(userhash)
+            .await?
+            .and_then(|x| x.display_name)
+        {
+            let _ = self
+                .log_activity(requester, Activity::Followed { display_name })
+                .await;
+        }
+        Ok(requester)
+    }
+
+    pub async fn reject_follow(&self, userhash: u64, requester_name: &str) -> anyhow::Result<()> {
+        let requester = self.userhash_from_username(requester_name).await?;
+        let removed = sqlx::query!(
+            "
+DELETE FROM
+    follow_requests
+WHERE
+    requester = $1
+    AND target = $2;
+            ",
+            requester,
+            BigD::from(userhash)
+        )
+        .execute(&mut self.database.acquire().await?)
+        .timed()
+        .await?
+        .rows_affected();
+        match removed {
+            0 => Err(anyhow!("NoFollowRequest")),
+            _ => Ok(()),
+        }
+    }
+
+    // a page of the pending follow requests of the user, newest first, the cursor is the offset
+    pub async fn list_follow_requests(
+        &self,
+        userhash: u64,
+        cursor: i64,
+    ) -> anyhow::Result<FollowList> {
+        let entries = sqlx::query_as!(
+            FollowResult,
+            "
+SELECT
+    (a.userdata).display_name,
+    r.created_at
+FROM
+    follow_requests r
+    INNER JOIN auth a ON a.username = r.requester
+WHERE
+    r.target = $1
+ORDER BY
+    r.created_at DESC,
+    r.requester
+LIMIT $2
+OFFSET $3;
+            ",
+            BigD::from(userhash),
+            FOLLOW_PAGE_SIZE,
+            cursor
+        )
+        .fetch_all(&mut self.database.acquire().await?)
+        .timed()
         .await?;
 
-        sqlx::query!(
+        Ok(FollowList::from_page(entries, cursor))
+    }
+
+    // username hashes of everyone following the user
+    pub async fn followers_of(&self, userhash: u64) -> anyhow::Result<Vec<u64>> {
+        let followers = sqlx::query_as!(
+            UserHash,
+            r#"
+SELECT
+    follower AS username
+FROM
+    follows
+WHERE
+    followee = $1;
+            "#,
+            BigD::from(userhash)
+        )
+        .fetch_all(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+
+        Ok(followers
+            .iter()
+            .map(|x| x.username.to_u64().unwrap_or_default())
+            .collect())
+    }
+
+    // username hashes of everyone the user follows
+    pub async fn following_of(&self, userhash: u64) -> anyhow::Result<Vec<u64>> {
+        let following = sqlx::query_as!(
+            UserHash,
+            r#"
+SELECT
+    followee AS username
+FROM
+    follows
+WHERE
+    follower = $1;
+            "#,
+            BigD::from(userhash)
+        )
+        .fetch_all(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+
+        Ok(following
+            .iter()
+            .map(|x| x.username.to_u64().unwrap_or_default())
+            .collect())
+    }
+
+    // a page of the people following the user, newest first, the cursor is the offset
+    pub async fn list_followers(&self, userhash: u64, cursor: i64) -> anyhow::Result<FollowList> {
+        let entries = sqlx::query_as!(
+            FollowResult,
             "
-UPDATE 
-    auth 
-SET
-    userdata.followers = array_append(
-        (
-            SELECT 
-                (userdata).followers 
-            FROM 
-                auth
-        ), 
-        $1)
-WHERE 
-    username = $2; 
+SELECT
+    (a.userdata).display_name,
+    f.created_at
+FROM
+    follows f
+    INNER JOIN auth a ON a.username = f.follower
+WHERE
+    f.followee = $1
+ORDER BY
+    f.created_at DESC,
+    f.follower
+LIMIT $2
+OFFSET $3;
             ",
-            self.userhash_from_username(name_to_follow).await?,
-            hash
+            BigD::from(userhash),
+            FOLLOW_PAGE_SIZE,
+            cursor
         )
-        .fetch_optional(&mut self.database.acquire().await?)
+        .fetch_all(&mut self.database.acquire().await?)
+        .timed()
         .await?;
-        Ok(())
+
+        Ok(FollowList::from_page(entries, cursor))
+    }
+
+    // a page of the people the user follows, newest first, the cursor is the offset
+    pub async fn list_following(&self, userhash: u64, cursor: i64) -> anyhow::Result<FollowList> {
+        let entries = sqlx::query_as!(
+            FollowResult,
+            "
+SELECT
+    (a.userdata).display_name,
+    f.created_at
+FROM
+    follows f
+    INNER JOIN auth a ON a.username = f.followee
+WHERE
+    f.follower = $1
+ORDER BY
+    f.created_at DESC,
+    f.followee
+LIMIT $2
+OFFSET $3;
+            ",
+            BigD::from(userhash),
+            FOLLOW_PAGE_SIZE,
+            cursor
+        )
+        .fetch_all(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+
+        Ok(FollowList::from_page(entries, cursor))
     }
 
-    pub async fn new_user(&self, username: &str, password: &str) -> anyhow::Result<()> {
+    /*
+     * Create a new account, invite is the code used to sign up and is redeemed in the same
+     * transaction so a failed signup doesn't use it up. Accounts created without an invite (with
+     * the ADMIN_KEY) are admins
+     */
+    pub async fn new_user(
+        &self,
+        username: &str,
+        password: &str,
+        invite: Option<&str>,
+    ) -> anyhow::Result<()> {
         let user = UserAuth::new(username, password);
+        let mut tx = self.database.begin().await?;
+        // without an invite this is the first admin, once there is one the rest are made with
+        // ADMIN_PROMOTE. The lock stops two signups from both seeing that there isn't one yet
+        if invite.is_none() {
+            sqlx::query!("LOCK TABLE auth IN SHARE ROW EXCLUSIVE MODE;")
+                .execute(&mut tx)
+                .timed()
+                .await?;
+            let admin = sqlx::query_as!(
+                Exists,
+                "
+SELECT EXISTS(
+    SELECT 
+        1 
+    FROM 
+        auth 
+    WHERE 
+        admin
+);
+                "
+            )
+            .fetch_one(&mut tx)
+            .timed()
+            .await?;
+            if admin.exists.unwrap_or(true) {
+                return Err(anyhow!("AdminExists"));
+            }
+        }
         sqlx::query!(
             "
 INSERT INTO 
@@ -490,15 +1043,21 @@ INSERT INTO
         admin, 
         last_login
     )
-VALUES($1, $2, false, $3);
+VALUES($1, $2, $3, $4);
             ",
             user.username,
             user.password,
+            invite.is_none(),
             user.last_login
         )
-        .fetch_optional(&mut self.database.acquire().await?)
+        .execute(&mut tx)
+        .timed()
         .await?;
 
+        if let Some(code) = invite {
+            self.redeem_invite(&mut tx, code, user.username_u64).await?;
+        }
+
         let data = UserData::default();
         sqlx::query!(
             "
@@ -536,8 +1095,10 @@ WHERE
                 .collect::<Vec<BigD>>(),
             user.username
         )
-        .fetch_optional(&mut self.database.acquire().await?)
+        .execute(&mut tx)
+        .timed()
         .await?;
+        tx.commit().await?;
 
         Ok(())
     }
@@ -556,20 +1117,18 @@ WHERE
             name
         )
         .fetch_all(&mut self.database.acquire().await?)
+        .timed()
         .await?;
         names.retain(|x| x.display_name.is_some());
         Ok(!names.is_empty())
     }
 
     pub async fn set_userdata(&self, username: u64, new_data: UserData) -> anyhow::Result<()> {
-        match &new_data.display_name {
-            Some(v) => {
-                if self.is_name_taken(v).await? {
-                    return Err(anyhow!("DisplayNameAlreadyTaken"));
-                }
+        if let Some(v) = &new_data.display_name {
+            if self.is_name_taken(v).await? {
+                return Err(anyhow!("DisplayNameAlreadyTaken"));
             }
-            None => {}
-        };
+        }
 
         sqlx::query!(
             "
@@ -580,20 +1139,19 @@ SET
     userdata.display_name = $2, 
     userdata.share_status = $3, 
     userdata.now_playing = $4, 
-    userdata.public_status = $5, 
-    userdata.recent_plays = $6
+    userdata.public_status = $5
 WHERE 
-    username = $7;
+    username = $6;
             ",
             new_data.public_profile,
             new_data.display_name,
             new_data.share_status,
             new_data.now_playing,
             new_data.public_status,
-            &new_data.recent_plays.unwrap_or_default()[..], // FUCK POSTGRES
             BigD::from(username)
         )
         .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
         .await?;
 
         Ok(())
@@ -605,6 +1163,7 @@ WHERE
         song_author: &str,
         song_release: &str,
     ) -> anyhow::Result<BigD> {
+        // what yt-dlp gave the song first, then what it is now so details from SYNC_LIB work too
         let result = sqlx::query_as!(
             SongLookupResult,
             "
@@ -613,8 +1172,17 @@ SELECT
 FROM 
     songs
 WHERE 
-    title = $1 AND creator = $2 
-    AND upload_date = $3;
+    (
+        original_title = $1 AND original_creator = $2 
+        AND original_upload_date = $3
+    ) OR (
+        title = $1 AND creator = $2 
+        AND upload_date = $3
+    )
+ORDER BY
+    original_title = $1 AND original_creator = $2 
+    AND original_upload_date = $3 DESC
+LIMIT 1;
             ",
             song_name,
             song_author, // refactor? this is misleading as it's the yt uploader NOT the
@@ -622,6 +1190,7 @@ WHERE
             song_release
         )
         .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
         .await?;
 
         if let Some(v) = result {
@@ -650,10 +1219,10 @@ WHERE
             BigD::from(username),
             playlist_name, // check if valid playlist
             self.find_song_from_details(song_name, song_author, song_release)
-                .await
-                .unwrap()
+                .await?
         )
         .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
         .await?;
 
         self.update_playlist_timestamp(username, playlist_name)
@@ -662,7 +1231,7 @@ WHERE
         Ok(())
     }
 
-    async fn find_song_from_hash(&self, song_hash: u64) -> anyhow::Result<SongDetails> {
+    pub async fn find_song_from_hash(&self, song_hash: u64) -> anyhow::Result<SongDetails> {
         let result = sqlx::query_as!(
             SongDetails,
             "
@@ -676,6 +1245,7 @@ WHERE
             BigD::from(song_hash)
         )
         .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
         .await?;
 
         match result {
@@ -684,23 +1254,77 @@ WHERE
         }
     }
 
-    pub async fn update_playlist(
-        &self,
-        username: u64,
-        playlist_name: &str,
-        data: Playlist,
-    ) -> anyhow::Result<()> {
-        let mut data = data;
-
-        data.name = truncate!(Some(data.name), 30).unwrap();
-
-        data.description = truncate!(data.description, 200);
-
-        sqlx::query!(
+    // everything stored about a song, used to tag its file
+    pub async fn song_from_hash(&self, song_hash: u64) -> anyhow::Result<Option<Song>> {
+        let song = sqlx::query_as!(
+            SongResult,
             "
-UPDATE 
-    playlist 
-SET
+SELECT
+    id,
+    title,
+    upload_date,
+    uploader,
+    url,
+    genre,
+    thumbnail,
+    album,
+    album_artist,
+    artist,
+    creator,
+    filesize,
+    duration
+FROM
+    songs
+WHERE
+    id = $1
+LIMIT 1;
+            ",
+            BigD::from(song_hash)
+        )
+        .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+        Ok(song.map(|x| x.into()))
+    }
+
+    pub async fn song_thumbnails(&self) -> anyhow::Result<Vec<(u64, String)>> {
+        let songs = sqlx::query!(
+            "
+SELECT DISTINCT ON (id)
+    id,
+    thumbnail
+FROM
+    songs
+WHERE
+    thumbnail IS NOT NULL;
+            "
+        )
+        .fetch_all(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+        Ok(songs
+            .into_iter()
+            .filter_map(|x| Some((x.id.to_u64()?, x.thumbnail?)))
+            .collect())
+    }
+
+    pub async fn update_playlist(
+        &self,
+        username: u64,
+        playlist_name: &str,
+        data: Playlist,
+    ) -> anyhow::Result<()> {
+        let mut data = data;
+
+        data.name = truncate!(Some(data.name), 30).unwrap();
+
+        data.description = truncate!(data.description, 200);
+
+        sqlx::query!(
+            "
+UPDATE 
+    playlist 
+SET
     name = $1,
     description = $2,
     public_playlist = $3,
@@ -717,6 +1341,7 @@ WHERE
             playlist_name
         )
         .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
         .await?;
 
         self.update_playlist_timestamp(username, &data.name).await?;
@@ -750,11 +1375,15 @@ VALUES($1, $2, $3, $4, $5);
             time!()
         )
         .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
         .await?;
 
         self.update_playlist_timestamp(username, playlist_name)
             .await?;
 
+        self.log_added_song(username, playlist_name, song_hash)
+            .await;
+
         Ok(())
     }
 
@@ -778,6 +1407,7 @@ WHERE
             BigD::from(song_hash),
         )
         .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
         .await?;
 
         self.update_playlist_timestamp(username, playlist_name)
@@ -786,6 +1416,90 @@ WHERE
         Ok(())
     }
 
+    pub async fn set_playlist_custom_image(
+        &self,
+        username: u64,
+        playlist_name: &str,
+        custom_image: bool,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "
+UPDATE
+    playlist
+SET
+    custom_image = $3
+WHERE
+    username = $1
+    AND name = $2;
+            ",
+            BigD::from(username),
+            playlist_name,
+            custom_image
+        )
+        .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+        Ok(())
+    }
+
+    pub async fn playlist_cover_songs(
+        &self,
+        username: u64,
+        playlist_name: &str,
+    ) -> anyhow::Result<Option<Vec<(u64, Option<String>)>>> {
+        let playlist = sqlx::query!(
+            "
+SELECT
+    custom_image
+FROM
+    playlist
+WHERE
+    username = $1
+    AND name = $2
+LIMIT 1;
+            ",
+            BigD::from(username),
+            playlist_name
+        )
+        .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+        if playlist.map(|x| x.custom_image) != Some(false) {
+            return Ok(None);
+        }
+
+        // date_added is in seconds, songs added in the same second keep the order they were written
+        // in (as rowid does for sqlite)
+        let songs = sqlx::query!(
+            "
+SELECT
+    playlistdata.song_hash,
+    songs.album
+FROM
+    playlistdata
+    JOIN songs ON songs.id = playlistdata.song_hash
+WHERE
+    playlistdata.username = $1
+    AND playlistdata.playlist_name = $2
+    AND songs.thumbnail IS NOT NULL
+ORDER BY
+    playlistdata.date_added,
+    playlistdata.ctid;
+            ",
+            BigD::from(username),
+            playlist_name
+        )
+        .fetch_all(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+        Ok(Some(
+            songs
+                .into_iter()
+                .filter_map(|x| Some((x.song_hash.to_u64()?, x.album)))
+                .collect(),
+        ))
+    }
+
     pub async fn append_song(
         &self,
         username: u64,
@@ -794,6 +1508,9 @@ WHERE
         song_author: &str,
         song_release: &str,
     ) -> anyhow::Result<()> {
+        let song_hash = self
+            .find_song_from_details(song_name, song_author, song_release)
+            .await?;
         sqlx::query!(
             "
 INSERT INTO 
@@ -808,23 +1525,44 @@ VALUES($1, $2, $3, $4, $5);
             ",
             BigD::from(username),
             playlist_name, // check if valid playlist
-            BigD::from(
-                self.find_song_from_details(song_name, song_author, song_release)
-                    .await
-                    .unwrap()
-            ),
+            song_hash,
             song_name,
             time!()
         )
         .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
         .await?;
 
         self.update_playlist_timestamp(username, playlist_name)
             .await?;
 
+        self.log_added_song(
+            username,
+            playlist_name,
+            song_hash.to_u64().unwrap_or_default(),
+        )
+        .await;
+
         Ok(())
     }
 
+    // songs added to public playlists show up in the feed
+    async fn log_added_song(&self, username: u64, playlist_name: &str, song_hash: u64) {
+        if let Ok(Some(playlist)) = self.request_playlist(username, playlist_name).await {
+            if playlist.public_playlist {
+                let _ = self
+                    .log_activity(
+                        username,
+                        Activity::AddedSong {
+                            playlist: playlist_name.to_string(),
+                            song_hash,
+                        },
+                    )
+                    .await;
+            }
+        }
+    }
+
     pub async fn update_playlist_timestamp(
         &self,
         username: u64,
@@ -845,6 +1583,7 @@ WHERE
             play_list_name
         )
         .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
         .await?;
 
         Ok(())
@@ -869,6 +1608,7 @@ SELECT EXISTS(
             name
         )
         .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
         .await?;
         Ok(match result {
             Some(v) => v.exists.unwrap_or_default(),
@@ -881,10 +1621,10 @@ SELECT EXISTS(
         username: u64,
         name: &str,
         public_playlist: &str,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<bool> {
         if let Ok(true) = self.does_playlist_exists(username, name).await {
             info!("playlist already exists");
-            return Ok(());
+            return Ok(false);
         }
 
         let public_playlist = match public_playlist.to_lowercase().as_str() {
@@ -914,36 +1654,21 @@ VALUES($1, $2, $3, $4, $5);
             timestamp
         )
         .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
         .await?;
 
-        default_playlist_image(username, name).await?;
-
-        Ok(())
-    }
-
-    pub async fn set_playlist_image(
-        &self,
-        username: u64,
-        name: &str,
-        image: &str,
-    ) -> anyhow::Result<()> {
-        save_playlist_image(username, name, image.to_string()).await?;
-
-        Ok(())
-    }
-
-    pub async fn remove_playlist_image(&self, username: u64, name: &str) -> anyhow::Result<()> {
-        remove_file(&format!(
-            "{}/{}-{}.png",
-            env_fetch!("CDN_DIR"),
-            username,
-            hash(name.as_bytes())
-        ))
-        .await?;
-
-        default_playlist_image(username, name).await?;
+        if public_playlist {
+            let _ = self
+                .log_activity(
+                    username,
+                    Activity::CreatedPlaylist {
+                        playlist: name.to_string(),
+                    },
+                )
+                .await;
+        }
 
-        Ok(())
+        Ok(true)
     }
 
     pub async fn set_playlist_description(
@@ -967,6 +1692,7 @@ WHERE
             description
         )
         .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
         .await?;
         Ok(())
     }
@@ -992,6 +1718,7 @@ WHERE
             new_name
         )
         .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
         .await?;
         Ok(())
     }
@@ -1009,6 +1736,7 @@ WHERE
             playlist_name // check if valid playlist
         )
         .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
         .await?;
 
         Ok(())
@@ -1031,6 +1759,7 @@ SELECT EXISTS(
             Some(BigD::from(hash(username.as_bytes())))
         )
         .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
         .await?;
 
         Ok(match output {
@@ -1057,6 +1786,7 @@ SELECT EXISTS(
             BigD::from(username)
         )
         .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
         .await?;
 
         Ok(match result {
@@ -1079,8 +1809,17 @@ WHERE
             BigD::from(userhash)
         )
         .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
         .await?;
-        Ok(data.map(|v| v.into()))
+        let mut data: Option<UserData> = data.map(|v| v.into());
+        // recent plays and follows are derived from their own tables rather than stored on the
+        // user
+        if let Some(v) = data.as_mut() {
+            v.recent_plays = Some(self.recent_plays(userhash).await?);
+            v.followers = Some(self.followers_of(userhash).await?);
+            v.following = Some(self.following_of(userhash).await?);
+        }
+        Ok(data)
     }
 
     pub async fn update_login_timestamp(&self, userhash: u64) -> anyhow::Result<()> {
@@ -1097,6 +1836,7 @@ WHERE
             time!()
         )
         .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
         .await?;
         Ok(())
     }
@@ -1125,6 +1865,7 @@ SELECT EXISTS(
             user.password
         )
         .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
         .await?;
 
         Ok(match output {
@@ -1135,4 +1876,2140 @@ SELECT EXISTS(
             None => (false, None),
         })
     }
+
+    // record that a user listened to a song, repeated reports of the same song inside of
+    // PLAY_DEDUP_SECONDS are ignored
+    pub async fn record_play(&self, userhash: u64, song_hash: u64) -> anyhow::Result<()> {
+        let song = self.find_song_from_hash(song_hash).await?;
+        sqlx::query!(
+            "
+INSERT INTO
+    plays(
+        username,
+        song_hash,
+        played_at
+    )
+SELECT
+    $1::NUMERIC, $2::NUMERIC, $3::NUMERIC
+WHERE NOT EXISTS(
+    SELECT
+        1
+    FROM
+        plays
+    WHERE
+        username = $1
+        AND song_hash = $2
+        AND played_at > $4
+);
+            ",
+            BigD::from(userhash),
+            song.id,
+            time!(),
+            time!() - BigD::from(PLAY_DEDUP_SECONDS)
+        )
+        .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+
+        Ok(())
+    }
+
+    // the ids of the last RECENT_PLAYS_LIMIT songs played, newest first
+    pub async fn recent_plays(&self, userhash: u64) -> anyhow::Result<Vec<String>> {
+        let plays = sqlx::query_as!(
+            PlayedSong,
+            "
+SELECT
+    song_hash
+FROM
+    plays
+WHERE
+    username = $1
+ORDER BY
+    played_at DESC
+LIMIT $2;
+            ",
+            BigD::from(userhash),
+            RECENT_PLAYS_LIMIT
+        )
+        .fetch_all(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+
+        Ok(plays
+            .iter()
+            .map(|x| x.song_hash.to_u64().unwrap_or_default().to_string())
+            .collect())
+    }
+
+    // most played songs between two timestamps (seconds since unix epoch)
+    pub async fn top_songs(
+        &self,
+        userhash: u64,
+        from: u64,
+        to: u64,
+        limit: i64,
+    ) -> anyhow::Result<Vec<TopSong>> {
+        let songs = sqlx::query_as!(
+            TopSongResult,
+            r#"
+SELECT
+    p.song_hash,
+    s.title,
+    COALESCE(s.artist, s.creator, s.uploader) AS artist,
+    COUNT(*) AS "plays!"
+FROM
+    plays p
+    INNER JOIN songs s ON s.id = p.song_hash
+WHERE
+    p.username = $1
+    AND p.played_at >= $2
+    AND p.played_at < $3
+GROUP BY
+    p.song_hash, s.title, s.artist, s.creator, s.uploader
+ORDER BY
+    COUNT(*) DESC
+LIMIT $4;
+            "#,
+            BigD::from(userhash),
+            BigD::from(from),
+            BigD::from(to),
+            limit.clamp(1, MAX_TOP_LIMIT)
+        )
+        .fetch_all(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+
+        Ok(songs.into_iter().map(|x| x.into()).collect())
+    }
+
+    // most played artists between two timestamps, songs without any artist info are skipped
+    pub async fn top_artists(
+        &self,
+        userhash: u64,
+        from: u64,
+        to: u64,
+        limit: i64,
+    ) -> anyhow::Result<Vec<TopArtist>> {
+        let artists = sqlx::query_as!(
+            TopArtist,
+            r#"
+SELECT
+    COALESCE(s.artist, s.creator, s.uploader) AS "artist!",
+    COUNT(*) AS "plays!"
+FROM
+    plays p
+    INNER JOIN songs s ON s.id = p.song_hash
+WHERE
+    p.username = $1
+    AND p.played_at >= $2
+    AND p.played_at < $3
+    AND COALESCE(s.artist, s.creator, s.uploader) IS NOT NULL
+GROUP BY
+    1
+ORDER BY
+    COUNT(*) DESC
+LIMIT $4;
+            "#,
+            BigD::from(userhash),
+            BigD::from(from),
+            BigD::from(to),
+            limit.clamp(1, MAX_TOP_LIMIT)
+        )
+        .fetch_all(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+
+        Ok(artists)
+    }
+
+    // total plays and time listened between two timestamps, the listening time is based off the
+    // duration yt-dlp reported for each song played
+    pub async fn listen_time(
+        &self,
+        userhash: u64,
+        from: u64,
+        to: u64,
+    ) -> anyhow::Result<ListenTime> {
+        let result = sqlx::query_as!(
+            ListenTimeResult,
+            r#"
+SELECT
+    COUNT(*) AS "plays!",
+    COUNT(DISTINCT p.song_hash) AS "unique_songs!",
+    COALESCE(SUM(s.duration), 0) AS "seconds!"
+FROM
+    plays p
+    LEFT JOIN songs s ON s.id = p.song_hash
+WHERE
+    p.username = $1
+    AND p.played_at >= $2
+    AND p.played_at < $3;
+            "#,
+            BigD::from(userhash),
+            BigD::from(from),
+            BigD::from(to)
+        )
+        .fetch_one(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+
+        Ok(ListenTime {
+            plays: result.plays,
+            unique_songs: result.unique_songs,
+            seconds: result.seconds.to_u64().unwrap_or_default(),
+        })
+    }
+
+    pub async fn year_in_review(&self, userhash: u64, year: i64) -> anyhow::Result<YearInReview> {
+        if !(1970..=9999).contains(&year) {
+            return Err(anyhow!("InvalidYear"));
+        }
+        let from = year_start(year) as u64;
+        let to = year_start(year + 1) as u64;
+        let total = self.listen_time(userhash, from, to).await?;
+
+        Ok(YearInReview {
+            year,
+            plays: total.plays,
+            unique_songs: total.unique_songs,
+            seconds: total.seconds,
+            top_songs: self.top_songs(userhash, from, to, 5).await?,
+            top_artists: self.top_artists(userhash, from, to, 5).await?,
+        })
+    }
+
+    // add an entry to the activity log, this shows up in the feed of followers
+    pub async fn log_activity(&self, userhash: u64, activity: Activity) -> anyhow::Result<()> {
+        let kind = activity.kind();
+        let (playlist, song_hash, detail) = match activity {
+            Activity::CreatedPlaylist { playlist } => (Some(playlist), None, None),
+            Activity::AddedSong {
+                playlist,
+                song_hash,
+            } => (Some(playlist), Some(BigD::from(song_hash)), None),
+            Activity::QueuedSong { url } => (None, None, Some(url)),
+            Activity::Followed { display_name } => (None, None, Some(display_name)),
+        };
+        sqlx::query!(
+            "
+INSERT INTO
+    activity(
+        username,
+        kind,
+        playlist,
+        song_hash,
+        detail,
+        created_at
+    )
+VALUES($1, $2, $3, $4, $5, $6);
+            ",
+            BigD::from(userhash),
+            kind,
+            playlist,
+            song_hash,
+            detail,
+            time!()
+        )
+        .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+
+        Ok(())
+    }
+
+    /*
+     * Activity of everyone the user follows that has a public profile, newest first
+     *
+     * The cursor is the id of the last item of the previous page, without one the feed starts from
+     * the newest activity
+     */
+    pub async fn feed(&self, userhash: u64, cursor: Option<i64>) -> anyhow::Result<Feed> {
+        let items = sqlx::query_as!(
+            FeedResult,
+            "
+SELECT
+    a.id,
+    (u.userdata).display_name,
+    a.kind,
+    a.playlist,
+    a.song_hash,
+    a.detail,
+    a.created_at
+FROM
+    activity a
+    INNER JOIN auth u ON u.username = a.username
+    INNER JOIN follows f ON f.followee = a.username
+WHERE
+    f.follower = $1
+    AND (u.userdata).public_profile = true
+    AND NOT EXISTS(
+        SELECT 1 FROM mutes m WHERE m.muter = $1 AND m.muted = a.username
+    )
+    AND NOT EXISTS(
+        SELECT
+            1
+        FROM
+            blocks b
+        WHERE
+            (b.blocker = a.username AND b.blocked = $1)
+            OR (b.blocker = $1 AND b.blocked = a.username)
+    )
+    AND a.id < $2
+ORDER BY
+    a.id DESC
+LIMIT $3;
+            ",
+            BigD::from(userhash),
+            cursor.unwrap_or(i64::MAX),
+            FEED_PAGE_SIZE
+        )
+        .fetch_all(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+
+        let cursor = match items.len() as i64 {
+            FEED_PAGE_SIZE => items.last().map(|x| x.id.to_string()),
+            _ => None,
+        };
+        Ok(Feed {
+            items: items.into_iter().map(|x| x.into()).collect(),
+            cursor,
+        })
+    }
+
+    // true if either user has blocked the other
+    async fn is_blocked(&self, a: &BigD, b: &BigD) -> anyhow::Result<bool> {
+        let result = sqlx::query_as!(
+            Exists,
+            "
+SELECT EXISTS(
+    SELECT
+        1
+    FROM
+        blocks
+    WHERE
+        (blocker = $1 AND blocked = $2)
+        OR (blocker = $2 AND blocked = $1)
+    LIMIT 1
+);
+            ",
+            a,
+            b
+        )
+        .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+
+        Ok(match result {
+            Some(v) => v.exists.unwrap_or_default(),
+            None => false,
+        })
+    }
+
+    /*
+     * Blocking removes any follow between the two users, stops either of them from following the
+     * other, hides the profile of the blocker from the blocked user and stops activity and presence
+     * from being sent between them
+     */
+    pub async fn block_user(&self, userhash: u64, name_to_block: &str) -> anyhow::Result<()> {
+        let hash = self.userhash_from_username(name_to_block).await?;
+        let userhash = BigD::from(userhash);
+        if hash == userhash {
+            return Err(anyhow!("CannotBlockSelf"));
+        }
+
+        let mut tx = self.database.begin().await?;
+        sqlx::query!(
+            "
+INSERT INTO
+    blocks(
+        blocker,
+        blocked,
+        created_at
+    )
+VALUES($1, $2, $3)
+ON CONFLICT DO NOTHING;
+            ",
+            userhash,
+            hash,
+            time!()
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+DELETE FROM
+    follows
+WHERE
+    (follower = $1 AND followee = $2)
+    OR (follower = $2 AND followee = $1);
+            ",
+            userhash,
+            hash
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+DELETE FROM
+    follow_requests
+WHERE
+    (requester = $1 AND target = $2)
+    OR (requester = $2 AND target = $1);
+            ",
+            userhash,
+            hash
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn unblock_user(&self, userhash: u64, name_to_unblock: &str) -> anyhow::Result<()> {
+        let hash = self.userhash_from_username(name_to_unblock).await?;
+        sqlx::query!(
+            "
+DELETE FROM
+    blocks
+WHERE
+    blocker = $1
+    AND blocked = $2;
+            ",
+            BigD::from(userhash),
+            hash
+        )
+        .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+        Ok(())
+    }
+
+    // muting keeps the follow but hides the activity and presence of the muted user
+    pub async fn mute_user(&self, userhash: u64, name_to_mute: &str) -> anyhow::Result<()> {
+        let hash = self.userhash_from_username(name_to_mute).await?;
+        sqlx::query!(
+            "
+INSERT INTO
+    mutes(
+        muter,
+        muted,
+        created_at
+    )
+VALUES($1, $2, $3)
+ON CONFLICT DO NOTHING;
+            ",
+            BigD::from(userhash),
+            hash,
+            time!()
+        )
+        .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+        Ok(())
+    }
+
+    pub async fn unmute_user(&self, userhash: u64, name_to_unmute: &str) -> anyhow::Result<()> {
+        let hash = self.userhash_from_username(name_to_unmute).await?;
+        sqlx::query!(
+            "
+DELETE FROM
+    mutes
+WHERE
+    muter = $1
+    AND muted = $2;
+            ",
+            BigD::from(userhash),
+            hash
+        )
+        .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+        Ok(())
+    }
+
+    // display names of everyone the user has blocked
+    pub async fn list_blocked(&self, userhash: u64) -> anyhow::Result<Vec<String>> {
+        let names = sqlx::query_as!(
+            DisplayName,
+            "
+SELECT
+    (a.userdata).display_name
+FROM
+    blocks b
+    INNER JOIN auth a ON a.username = b.blocked
+WHERE
+    b.blocker = $1
+ORDER BY
+    b.created_at DESC;
+            ",
+            BigD::from(userhash)
+        )
+        .fetch_all(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+
+        Ok(names.into_iter().filter_map(|x| x.display_name).collect())
+    }
+
+    // display names of everyone the user has muted
+    pub async fn list_muted(&self, userhash: u64) -> anyhow::Result<Vec<String>> {
+        let names = sqlx::query_as!(
+            DisplayName,
+            "
+SELECT
+    (a.userdata).display_name
+FROM
+    mutes m
+    INNER JOIN auth a ON a.username = m.muted
+WHERE
+    m.muter = $1
+ORDER BY
+    m.created_at DESC;
+            ",
+            BigD::from(userhash)
+        )
+        .fetch_all(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+
+        Ok(names.into_iter().filter_map(|x| x.display_name).collect())
+    }
+
+    // followers that should be sent live events of the user, leaving out anyone that muted them
+    pub async fn audience_of(&self, userhash: u64) -> anyhow::Result<Vec<u64>> {
+        let audience = sqlx::query_as!(
+            UserHash,
+            r#"
+SELECT
+    f.follower AS username
+FROM
+    follows f
+WHERE
+    f.followee = $1
+    AND NOT EXISTS(
+        SELECT 1 FROM mutes m WHERE m.muter = f.follower AND m.muted = $1
+    )
+    AND NOT EXISTS(
+        SELECT
+            1
+        FROM
+            blocks b
+        WHERE
+            (b.blocker = f.follower AND b.blocked = $1)
+            OR (b.blocker = $1 AND b.blocked = f.follower)
+    );
+            "#,
+            BigD::from(userhash)
+        )
+        .fetch_all(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+
+        Ok(audience
+            .iter()
+            .map(|x| x.username.to_u64().unwrap_or_default())
+            .collect())
+    }
+
+    // followed users whose live events the user wants to see, leaving out anyone they muted
+    pub async fn visible_following(&self, userhash: u64) -> anyhow::Result<Vec<u64>> {
+        let following = sqlx::query_as!(
+            UserHash,
+            r#"
+SELECT
+    f.followee AS username
+FROM
+    follows f
+WHERE
+    f.follower = $1
+    AND NOT EXISTS(
+        SELECT 1 FROM mutes m WHERE m.muter = $1 AND m.muted = f.followee
+    )
+    AND NOT EXISTS(
+        SELECT
+            1
+        FROM
+            blocks b
+        WHERE
+            (b.blocker = f.followee AND b.blocked = $1)
+            OR (b.blocker = $1 AND b.blocked = f.followee)
+    );
+            "#,
+            BigD::from(userhash)
+        )
+        .fetch_all(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+
+        Ok(following
+            .iter()
+            .map(|x| x.username.to_u64().unwrap_or_default())
+            .collect())
+    }
+
+    /*
+     * The public profile of a user along with their public playlists, None if the profile is
+     * private or if the viewer is blocked by (or has blocked) the user
+     *
+     * The username hash of the profile is returned alongside it
+     */
+    pub async fn request_profile(
+        &self,
+        viewer: u64,
+        display_name: &str,
+    ) -> anyhow::Result<Option<(u64, Profile)>> {
+        let hash = self.userhash_from_username(display_name).await?;
+        if self.is_blocked(&BigD::from(viewer), &hash).await? {
+            return Ok(None);
+        }
+        let userhash = hash.to_u64().unwrap_or_default();
+        let userdata = match self.get_user_data(userhash).await? {
+            Some(v) if v.public_profile == Some(true) => v,
+            _ => return Ok(None),
+        };
+
+        let playlists = sqlx::query_as!(
+            Playlist,
+            "
+SELECT
+    name,
+    description,
+    public_playlist
+FROM
+    playlist
+WHERE
+    username = $1
+    AND public_playlist = true
+ORDER BY
+    name;
+            ",
+            hash
+        )
+        .fetch_all(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+
+        Ok(Some((
+            userhash,
+            Profile {
+                userdata,
+                playlists,
+            },
+        )))
+    }
+
+    // remove a song from the library and from every playlist it is in
+    pub async fn delete_song(&self, song_hash: u64) -> anyhow::Result<()> {
+        let mut tx = self.database.begin().await?;
+        let removed = sqlx::query!(
+            "
+DELETE FROM
+    songs
+WHERE
+    id = $1;
+            ",
+            BigD::from(song_hash)
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?
+        .rows_affected();
+        if removed == 0 {
+            return Err(anyhow!("InvalidHash"));
+        }
+
+        sqlx::query!(
+            "
+DELETE FROM
+    playlistdata
+WHERE
+    song_hash = $1;
+            ",
+            BigD::from(song_hash)
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+DELETE FROM
+    song_edits
+WHERE
+    song_hash = $1;
+            ",
+            BigD::from(song_hash)
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    // the fields EDIT_SONG can change, None if there is no song with that id
+    pub async fn song_metadata(&self, song_hash: u64) -> anyhow::Result<Option<SongMetadata>> {
+        let metadata = sqlx::query_as!(
+            SongMetadata,
+            "
+SELECT
+    title,
+    artist,
+    album,
+    album_artist,
+    genre
+FROM
+    songs
+WHERE
+    id = $1
+LIMIT 1;
+            ",
+            BigD::from(song_hash)
+        )
+        .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+        Ok(metadata)
+    }
+
+    // edit a song for everyone, the values it had before are kept in song_edits
+    pub async fn edit_song(
+        &self,
+        song_hash: u64,
+        editor: u64,
+        edit: &SongEdit,
+    ) -> anyhow::Result<SongMetadata> {
+        let mut tx = self.database.begin().await?;
+        let old = sqlx::query_as!(
+            SongMetadata,
+            "
+SELECT
+    title,
+    artist,
+    album,
+    album_artist,
+    genre
+FROM
+    songs
+WHERE
+    id = $1
+LIMIT 1
+FOR UPDATE;
+            ",
+            BigD::from(song_hash)
+        )
+        .fetch_optional(&mut tx)
+        .timed()
+        .await?
+        .ok_or_else(|| anyhow!("InvalidHash"))?;
+
+        let new = edit.apply(&old)?;
+        if new == old {
+            return Ok(new);
+        }
+        let now = time!();
+        sqlx::query!(
+            "
+UPDATE
+    songs
+SET
+    title = $2,
+    artist = $3,
+    album = $4,
+    album_artist = $5,
+    genre = $6,
+    edited_timestamp = $7
+WHERE
+    id = $1;
+            ",
+            BigD::from(song_hash),
+            new.title,
+            new.artist,
+            new.album,
+            new.album_artist,
+            new.genre,
+            now
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+INSERT INTO
+    song_edits(
+        song_hash,
+        edited_by,
+        old_values,
+        new_values,
+        created_at
+    )
+VALUES($1, $2, $3, $4, $5);
+            ",
+            BigD::from(song_hash),
+            BigD::from(editor),
+            serde_json::to_string(&old)?,
+            serde_json::to_string(&new)?,
+            now
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+        tx.commit().await?;
+        Ok(new)
+    }
+
+    // a page of the edits of a song, newest first, the cursor is the id of the last edit of the
+    // last page
+    pub async fn song_history(
+        &self,
+        song_hash: u64,
+        cursor: Option<i64>,
+    ) -> anyhow::Result<SongHistory> {
+        let items = sqlx::query_as!(
+            SongEditResult,
+            "
+SELECT
+    e.id,
+    (a.userdata).display_name,
+    e.old_values,
+    e.new_values,
+    e.created_at
+FROM
+    song_edits e
+    LEFT JOIN auth a ON a.username = e.edited_by
+WHERE
+    e.song_hash = $1
+    AND e.id < $2
+ORDER BY
+    e.id DESC
+LIMIT $3;
+            ",
+            BigD::from(song_hash),
+            cursor.unwrap_or(i64::MAX),
+            SONG_HISTORY_PAGE_SIZE
+        )
+        .fetch_all(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+
+        let cursor = match items.len() as i64 {
+            SONG_HISTORY_PAGE_SIZE => items.last().map(|x| x.id.to_string()),
+            _ => None,
+        };
+        Ok(SongHistory {
+            items: items.into_iter().map(|x| x.into()).collect(),
+            cursor,
+        })
+    }
+
+    // the name is set on every entry of the song in the playlists of the user, None removes it
+    pub async fn rename_song(
+        &self,
+        userhash: u64,
+        song_hash: u64,
+        name: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let updated = sqlx::query!(
+            "
+UPDATE
+    playlistdata
+SET
+    custom_name = $3
+WHERE
+    username = $1
+    AND song_hash = $2;
+            ",
+            BigD::from(userhash),
+            BigD::from(song_hash),
+            name
+        )
+        .execute(&mut self.database.acquire().await?)
+        .timed()
+        .await?
+        .rows_affected();
+        if updated == 0 {
+            return Err(anyhow!("NotInPlaylist"));
+        }
+        Ok(())
+    }
+
+    pub async fn song_overrides(&self, userhash: u64) -> anyhow::Result<Vec<SongOverride>> {
+        let overrides = sqlx::query_as!(
+            SongOverrideResult,
+            r#"
+SELECT
+    song_hash,
+    MAX(custom_name) AS "custom_name!"
+FROM
+    playlistdata
+WHERE
+    username = $1
+    AND custom_name IS NOT NULL
+GROUP BY
+    song_hash
+ORDER BY
+    song_hash;
+            "#,
+            BigD::from(userhash)
+        )
+        .fetch_all(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+        Ok(overrides
+            .into_iter()
+            .map(|x| SongOverride {
+                id: x.song_hash.to_u64().unwrap_or_default().to_string(),
+                name: x.custom_name,
+            })
+            .collect())
+    }
+
+    // songs the enrichment pass hasn't looked at, songs an admin already edited are skipped
+    pub async fn unenriched_songs(&self, limit: i64) -> anyhow::Result<Vec<(u64, SongFields)>> {
+        let songs = sqlx::query_as!(
+            UnenrichedSong,
+            "
+SELECT
+    DISTINCT ON (id) id,
+    title,
+    artist,
+    album,
+    album_artist,
+    genre,
+    uploader,
+    creator,
+    upload_date
+FROM
+    songs
+WHERE
+    NOT enriched
+    AND edited_timestamp IS NULL
+ORDER BY
+    id
+LIMIT $1;
+            ",
+            limit
+        )
+        .fetch_all(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+        Ok(songs.into_iter().map(|x| x.into()).collect())
+    }
+
+    /*
+     * Mark a song as looked at by the enrichment pass, if the rules changed anything it's saved
+     * like an EDIT_SONG by nobody so it shows up in SONG_HISTORY and SYNC_LIB sends it again
+     */
+    pub async fn save_enrichment(
+        &self,
+        song_hash: u64,
+        old: &SongFields,
+        new: &SongFields,
+    ) -> anyhow::Result<()> {
+        let mut tx = self.database.begin().await?;
+        if new == old {
+            sqlx::query!(
+                "
+UPDATE
+    songs
+SET
+    enriched = TRUE
+WHERE
+    id = $1;
+                ",
+                BigD::from(song_hash)
+            )
+            .execute(&mut tx)
+            .timed()
+            .await?;
+            tx.commit().await?;
+            return Ok(());
+        }
+
+        let now = time!();
+        sqlx::query!(
+            "
+UPDATE
+    songs
+SET
+    title = $2,
+    artist = $3,
+    album = $4,
+    upload_date = $5,
+    edited_timestamp = $6,
+    enriched = TRUE
+WHERE
+    id = $1;
+            ",
+            BigD::from(song_hash),
+            new.title,
+            new.artist,
+            new.album,
+            new.upload_date,
+            now
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+INSERT INTO
+    song_edits(
+        song_hash,
+        edited_by,
+        old_values,
+        new_values,
+        created_at
+    )
+VALUES($1, 0, $2, $3, $4);
+            ",
+            BigD::from(song_hash),
+            serde_json::to_string(&old.metadata())?,
+            serde_json::to_string(&new.metadata())?,
+            now
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    // ban a user for an amount of seconds, or until they are unbanned if none is given
+    pub async fn ban_user(
+        &self,
+        userhash: u64,
+        banned_by: u64,
+        seconds: Option<u64>,
+    ) -> anyhow::Result<()> {
+        let now = time!();
+        let expires_at = seconds.map(|x| now.clone() + BigD::from(x));
+        sqlx::query!(
+            "
+INSERT INTO
+    bans(
+        username,
+        banned_by,
+        expires_at,
+        created_at
+    )
+VALUES($1, $2, $3, $4)
+ON CONFLICT (username) DO UPDATE SET
+    banned_by = EXCLUDED.banned_by,
+    expires_at = EXCLUDED.expires_at,
+    created_at = EXCLUDED.created_at;
+            ",
+            BigD::from(userhash),
+            BigD::from(banned_by),
+            expires_at,
+            now
+        )
+        .execute(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+        Ok(())
+    }
+
+    pub async fn unban_user(&self, userhash: u64) -> anyhow::Result<()> {
+        sqlx::query!(
+            "
+DELETE FROM
+    bans
+WHERE
+    username = $1;
+            ",
+            BigD::from(userhash)
+        )
+        .execute(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+        Ok(())
+    }
+
+    pub async fn is_banned(&self, userhash: u64) -> anyhow::Result<bool> {
+        let banned = sqlx::query_as!(
+            Exists,
+            "
+SELECT EXISTS(
+    SELECT
+        1
+    FROM
+        bans
+    WHERE
+        username = $1
+        AND (expires_at IS NULL OR expires_at > $2)
+);
+            ",
+            BigD::from(userhash),
+            time!()
+        )
+        .fetch_one(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+        Ok(banned.exists.unwrap_or(false))
+    }
+
+    pub async fn set_admin(&self, userhash: u64, admin: bool) -> anyhow::Result<()> {
+        sqlx::query!(
+            "
+UPDATE
+    auth
+SET
+    admin = $2
+WHERE
+    username = $1;
+            ",
+            BigD::from(userhash),
+            admin
+        )
+        .execute(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+        Ok(())
+    }
+
+    pub async fn log_admin_action(
+        &self,
+        admin: u64,
+        command: &str,
+        args: &str,
+        response: &str,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "
+INSERT INTO
+    admin_log(
+        admin,
+        command,
+        args,
+        response,
+        created_at
+    )
+VALUES($1, $2, $3, $4, $5);
+            ",
+            BigD::from(admin),
+            command,
+            args,
+            response,
+            time!()
+        )
+        .execute(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+        Ok(())
+    }
+
+    // a page of the admin log, newest first, the cursor is the id of the last entry of the last page
+    pub async fn admin_log(&self, cursor: Option<i64>) -> anyhow::Result<AdminLog> {
+        let items = sqlx::query_as!(
+            AdminLogResult,
+            "
+SELECT
+    l.id,
+    (a.userdata).display_name,
+    l.command,
+    l.args,
+    l.response,
+    l.created_at
+FROM
+    admin_log l
+    LEFT JOIN auth a ON a.username = l.admin
+WHERE
+    l.id < $1
+ORDER BY
+    l.id DESC
+LIMIT $2;
+            ",
+            cursor.unwrap_or(i64::MAX),
+            ADMIN_LOG_PAGE_SIZE
+        )
+        .fetch_all(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+
+        let cursor = match items.len() as i64 {
+            ADMIN_LOG_PAGE_SIZE => items.last().map(|x| x.id.to_string()),
+            _ => None,
+        };
+        Ok(AdminLog {
+            items: items.into_iter().map(|x| x.into()).collect(),
+            cursor,
+        })
+    }
+
+    async fn redeem_invite(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        code: &str,
+        invited: u64,
+    ) -> anyhow::Result<()> {
+        let now = time!();
+        let invite = sqlx::query_as!(
+            InvitedBy,
+            "
+UPDATE
+    invites
+SET
+    uses = uses + 1
+WHERE
+    code = $1
+    AND uses < max_uses
+    AND (expires_at IS NULL OR expires_at > $2)
+RETURNING
+    created_by;
+            ",
+            code,
+            now
+        )
+        .fetch_optional(&mut *tx)
+        .timed()
+        .await?;
+        let invited_by = match invite {
+            Some(v) => v.created_by,
+            None => return Err(anyhow!("InvalidInvite")),
+        };
+
+        sqlx::query!(
+            "
+INSERT INTO
+    invite_uses(
+        code,
+        invited,
+        invited_by,
+        created_at
+    )
+VALUES($1, $2, $3, $4);
+            ",
+            code,
+            BigD::from(invited),
+            invited_by,
+            now
+        )
+        .execute(&mut *tx)
+        .timed()
+        .await?;
+        Ok(())
+    }
+
+    /*
+     * Create an invite code, users that aren't admins need enough invites left to cover every use
+     * of it
+     */
+    pub async fn create_invite(
+        &self,
+        userhash: u64,
+        max_uses: i32,
+        expires_in: Option<u64>,
+    ) -> anyhow::Result<String> {
+        let admin = self.is_admin(userhash).await?;
+        let mut tx = self.database.begin().await?;
+        if !admin {
+            let updated = sqlx::query!(
+                "
+UPDATE
+    auth
+SET
+    invites_left = invites_left - $2
+WHERE
+    username = $1
+    AND invites_left >= $2;
+                ",
+                BigD::from(userhash),
+                max_uses
+            )
+            .execute(&mut tx)
+            .timed()
+            .await?
+            .rows_affected();
+            if updated == 0 {
+                return Err(anyhow!("NoInvitesLeft"));
+            }
+        }
+
+        let now = time!();
+        let code = generate_invite_code();
+        sqlx::query!(
+            "
+INSERT INTO
+    invites(
+        code,
+        created_by,
+        max_uses,
+        expires_at,
+        created_at
+    )
+VALUES($1, $2, $3, $4, $5);
+            ",
+            code,
+            BigD::from(userhash),
+            max_uses,
+            expires_in.map(|x| now.clone() + BigD::from(x)),
+            now
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+        tx.commit().await?;
+        Ok(code)
+    }
+
+    // the invites created by a user and who used them, newest first
+    pub async fn list_invites(&self, userhash: u64) -> anyhow::Result<InviteList> {
+        let invites_left = sqlx::query_as!(
+            InvitesLeft,
+            "
+SELECT
+    invites_left
+FROM
+    auth
+WHERE
+    username = $1;
+            ",
+            BigD::from(userhash)
+        )
+        .fetch_optional(&mut self.database.acquire().await?)
+        .timed()
+        .await?
+        .map(|x| x.invites_left)
+        .unwrap_or_default();
+
+        let invites = sqlx::query_as!(
+            InviteResult,
+            "
+SELECT
+    code,
+    uses,
+    max_uses,
+    expires_at,
+    created_at
+FROM
+    invites
+WHERE
+    created_by = $1
+ORDER BY
+    created_at DESC;
+            ",
+            BigD::from(userhash)
+        )
+        .fetch_all(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+
+        let used = sqlx::query_as!(
+            InviteUseResult,
+            "
+SELECT
+    u.code,
+    (a.userdata).display_name
+FROM
+    invite_uses u
+    INNER JOIN auth a ON a.username = u.invited
+WHERE
+    u.invited_by = $1
+ORDER BY
+    u.created_at;
+            ",
+            BigD::from(userhash)
+        )
+        .fetch_all(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+
+        Ok(InviteList {
+            invites_left,
+            invites: invites
+                .into_iter()
+                .map(|x| Invite {
+                    used_by: used
+                        .iter()
+                        .filter(|u| u.code == x.code)
+                        .filter_map(|u| u.display_name.clone())
+                        .collect(),
+                    code: x.code,
+                    uses: x.uses,
+                    max_uses: x.max_uses,
+                    expires_at: x.expires_at.and_then(|e| e.to_u64()),
+                    created_at: x.created_at.to_u64().unwrap_or_default(),
+                })
+                .collect(),
+        })
+    }
+
+    // delete an unused or partly used invite, the uses left over go back to the allowance
+    pub async fn revoke_invite(&self, userhash: u64, code: &str) -> anyhow::Result<()> {
+        let mut tx = self.database.begin().await?;
+        let revoked = sqlx::query_as!(
+            InviteResult,
+            "
+DELETE FROM
+    invites
+WHERE
+    code = $1
+    AND created_by = $2
+RETURNING
+    code,
+    uses,
+    max_uses,
+    expires_at,
+    created_at;
+            ",
+            code,
+            BigD::from(userhash)
+        )
+        .fetch_optional(&mut tx)
+        .timed()
+        .await?;
+        let revoked = match revoked {
+            Some(v) => v,
+            None => return Err(anyhow!("InvalidInvite")),
+        };
+
+        sqlx::query!(
+            "
+UPDATE
+    auth
+SET
+    invites_left = invites_left + $2
+WHERE
+    username = $1
+    AND admin = false;
+            ",
+            BigD::from(userhash),
+            revoked.max_uses - revoked.uses
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn set_invites_left(&self, userhash: u64, invites_left: i32) -> anyhow::Result<()> {
+        sqlx::query!(
+            "
+UPDATE
+    auth
+SET
+    invites_left = $2
+WHERE
+    username = $1;
+            ",
+            BigD::from(userhash),
+            invites_left
+        )
+        .execute(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+        Ok(())
+    }
+
+    pub async fn change_password(
+        &self,
+        userhash: u64,
+        old_password: &str,
+        new_password: &str,
+    ) -> anyhow::Result<()> {
+        let updated = sqlx::query!(
+            "
+UPDATE
+    auth
+SET
+    password = $3
+WHERE
+    username = $1
+    AND password = $2;
+            ",
+            BigD::from(userhash),
+            BigD::from(hash(old_password.as_bytes())),
+            BigD::from(hash(new_password.as_bytes()))
+        )
+        .execute(&mut self.database.acquire().await?)
+        .timed()
+        .await?
+        .rows_affected();
+        match updated {
+            0 => Err(anyhow!("InvalidPassword")),
+            _ => Ok(()),
+        }
+    }
+
+    /*
+     * The username is only stored as a hash which every other table uses to refer to the user, so
+     * renaming means moving every row over to the hash of the new name. Returns the new hash
+     */
+    pub async fn change_username(
+        &self,
+        userhash: u64,
+        password: &str,
+        new_username: &str,
+    ) -> anyhow::Result<u64> {
+        let new_hash = hash(new_username.as_bytes());
+        if self.check_if_username_exists_in_auth(new_username).await? {
+            return Err(anyhow!("UsernameTaken"));
+        }
+        let (old, new) = (BigD::from(userhash), BigD::from(new_hash));
+
+        let mut tx = self.database.begin().await?;
+        let updated = sqlx::query!(
+            "
+UPDATE
+    auth
+SET
+    username = $2
+WHERE
+    username = $1
+    AND password = $3;
+            ",
+            old,
+            new,
+            BigD::from(hash(password.as_bytes()))
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?
+        .rows_affected();
+        if updated == 0 {
+            return Err(anyhow!("InvalidPassword"));
+        }
+
+        sqlx::query!(
+            "
+UPDATE
+    playlist
+SET
+    username = $2
+WHERE
+    username = $1;
+            ",
+            old,
+            new
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+UPDATE
+    playlistdata
+SET
+    username = $2
+WHERE
+    username = $1;
+            ",
+            old,
+            new
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+UPDATE
+    plays
+SET
+    username = $2
+WHERE
+    username = $1;
+            ",
+            old,
+            new
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+UPDATE
+    activity
+SET
+    username = $2
+WHERE
+    username = $1;
+            ",
+            old,
+            new
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+UPDATE
+    follows
+SET
+    follower = $2
+WHERE
+    follower = $1;
+            ",
+            old,
+            new
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+UPDATE
+    follows
+SET
+    followee = $2
+WHERE
+    followee = $1;
+            ",
+            old,
+            new
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+UPDATE
+    follow_requests
+SET
+    requester = $2
+WHERE
+    requester = $1;
+            ",
+            old,
+            new
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+UPDATE
+    follow_requests
+SET
+    target = $2
+WHERE
+    target = $1;
+            ",
+            old,
+            new
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+UPDATE
+    blocks
+SET
+    blocker = $2
+WHERE
+    blocker = $1;
+            ",
+            old,
+            new
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+UPDATE
+    blocks
+SET
+    blocked = $2
+WHERE
+    blocked = $1;
+            ",
+            old,
+            new
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+UPDATE
+    mutes
+SET
+    muter = $2
+WHERE
+    muter = $1;
+            ",
+            old,
+            new
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+UPDATE
+    mutes
+SET
+    muted = $2
+WHERE
+    muted = $1;
+            ",
+            old,
+            new
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+UPDATE
+    bans
+SET
+    username = $2
+WHERE
+    username = $1;
+            ",
+            old,
+            new
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+UPDATE
+    bans
+SET
+    banned_by = $2
+WHERE
+    banned_by = $1;
+            ",
+            old,
+            new
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+UPDATE
+    admin_log
+SET
+    admin = $2
+WHERE
+    admin = $1;
+            ",
+            old,
+            new
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+UPDATE
+    invites
+SET
+    created_by = $2
+WHERE
+    created_by = $1;
+            ",
+            old,
+            new
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+UPDATE
+    invite_uses
+SET
+    invited = $2
+WHERE
+    invited = $1;
+            ",
+            old,
+            new
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+UPDATE
+    invite_uses
+SET
+    invited_by = $2
+WHERE
+    invited_by = $1;
+            ",
+            old,
+            new
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+UPDATE
+    song_edits
+SET
+    edited_by = $2
+WHERE
+    edited_by = $1;
+            ",
+            old,
+            new
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+        tx.commit().await?;
+        Ok(new_hash)
+    }
+
+    /*
+     * Delete an account along with everything that belongs to it. The admin log and the record of
+     * who the user invited are kept, invites they created that weren't used up stop working. Songs
+     * they edited keep the edits with no editor, like the ones the server makes, so whoever signs
+     * up with the same username later isn't shown as the editor
+     */
+    pub async fn delete_account(&self, userhash: u64, password: &str) -> anyhow::Result<()> {
+        let user = BigD::from(userhash);
+        let mut tx = self.database.begin().await?;
+        let deleted = sqlx::query!(
+            "
+DELETE FROM
+    auth
+WHERE
+    username = $1
+    AND password = $2;
+            ",
+            user,
+            BigD::from(hash(password.as_bytes()))
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?
+        .rows_affected();
+        if deleted == 0 {
+            return Err(anyhow!("InvalidPassword"));
+        }
+
+        sqlx::query!(
+            "
+DELETE FROM
+    playlist
+WHERE
+    username = $1;
+            ",
+            user
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+DELETE FROM
+    playlistdata
+WHERE
+    username = $1;
+            ",
+            user
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+DELETE FROM
+    plays
+WHERE
+    username = $1;
+            ",
+            user
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+DELETE FROM
+    activity
+WHERE
+    username = $1;
+            ",
+            user
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+DELETE FROM
+    follows
+WHERE
+    follower = $1
+    OR followee = $1;
+            ",
+            user
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+DELETE FROM
+    follow_requests
+WHERE
+    requester = $1
+    OR target = $1;
+            ",
+            user
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+DELETE FROM
+    blocks
+WHERE
+    blocker = $1
+    OR blocked = $1;
+            ",
+            user
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+DELETE FROM
+    mutes
+WHERE
+    muter = $1
+    OR muted = $1;
+            ",
+            user
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+DELETE FROM
+    bans
+WHERE
+    username = $1;
+            ",
+            user
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+DELETE FROM
+    invites
+WHERE
+    created_by = $1;
+            ",
+            user
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+DELETE FROM
+    invite_uses
+WHERE
+    invited = $1;
+            ",
+            user
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "
+UPDATE
+    song_edits
+SET
+    edited_by = 0
+WHERE
+    edited_by = $1;
+            ",
+            user
+        )
+        .execute(&mut tx)
+        .timed()
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    // every playlist of a user with its tracks, private ones included, for EXPORT_MY_DATA
+    pub async fn export_playlists(&self, userhash: u64) -> anyhow::Result<Vec<ExportedPlaylist>> {
+        let playlists = sqlx::query_as!(
+            ExportPlaylistResult,
+            "
+SELECT
+    name,
+    description,
+    public_playlist,
+    creation_timestamp,
+    last_update
+FROM
+    playlist
+WHERE
+    username = $1
+ORDER BY
+    creation_timestamp;
+            ",
+            BigD::from(userhash)
+        )
+        .fetch_all(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+
+        let tracks = sqlx::query_as!(
+            ExportTrackResult,
+            "
+SELECT
+    playlist_name,
+    song_hash,
+    song_name,
+    custom_name,
+    date_added
+FROM
+    playlistdata
+WHERE
+    username = $1
+ORDER BY
+    date_added;
+            ",
+            BigD::from(userhash)
+        )
+        .fetch_all(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+
+        Ok(playlists
+            .into_iter()
+            .map(|p| ExportedPlaylist {
+                tracks: tracks
+                    .iter()
+                    .filter(|t| t.playlist_name == p.name)
+                    .map(|t| ExportedTrack {
+                        song: t.song_hash.to_u64().unwrap_or_default().to_string(),
+                        title: t.song_name.clone(),
+                        custom_name: t.custom_name.clone(),
+                        date_added: t.date_added.to_u64().unwrap_or_default(),
+                    })
+                    .collect(),
+                name: p.name,
+                description: p.description,
+                public_playlist: p.public_playlist,
+                created_at: p.creation_timestamp.to_u64().unwrap_or_default(),
+                last_update: p.last_update.to_u64().unwrap_or_default(),
+                image: None,
+            })
+            .collect())
+    }
+
+    // the whole play history of a user, oldest first
+    pub async fn export_plays(&self, userhash: u64) -> anyhow::Result<Vec<ExportedPlay>> {
+        let plays = sqlx::query_as!(
+            ExportPlayResult,
+            "
+SELECT
+    p.song_hash,
+    (SELECT s.title FROM songs s WHERE s.id = p.song_hash LIMIT 1) AS title,
+    p.played_at
+FROM
+    plays p
+WHERE
+    p.username = $1
+ORDER BY
+    p.played_at;
+            ",
+            BigD::from(userhash)
+        )
+        .fetch_all(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+
+        Ok(plays
+            .into_iter()
+            .map(|x| ExportedPlay {
+                song: x.song_hash.to_u64().unwrap_or_default().to_string(),
+                title: x.title,
+                played_at: x.played_at.to_u64().unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    // everything the user has done that was logged for the feed, oldest first
+    pub async fn export_activity(&self, userhash: u64) -> anyhow::Result<Vec<FeedItem>> {
+        let items = sqlx::query_as!(
+            FeedResult,
+            "
+SELECT
+    a.id,
+    (u.userdata).display_name,
+    a.kind,
+    a.playlist,
+    a.song_hash,
+    a.detail,
+    a.created_at
+FROM
+    activity a
+    INNER JOIN auth u ON u.username = a.username
+WHERE
+    a.username = $1
+ORDER BY
+    a.id;
+            ",
+            BigD::from(userhash)
+        )
+        .fetch_all(&mut self.database.acquire().await?)
+        .timed()
+        .await?;
+
+        Ok(items.into_iter().map(|x| x.into()).collect())
+    }
 }
@@ -0,0 +1,98 @@
+use crate::{tag_new_song, Bucket, FileStore, Song, SongError};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use log::error;
+use std::sync::Arc;
+use tokio::process::Command;
+
+/*
+ * Where songs come from, the SongManager asks the downloader what is at a queued url and then for
+ * the audio to be saved to the file store (see files.rs)
+ *
+ * YtDlp is what the server uses, MemoryDownloader (see memory.rs) only knows about the songs it was
+ * given so the tests never go out to the network
+ */
+#[async_trait]
+pub(crate) trait Downloader: Send + Sync {
+    async fn details(&self, url: &str) -> Result<Song, SongError>;
+
+    // the file is named after the song id, it doesn't have to be stored when this returns
+    async fn save(&self, song: &Song, files: Arc<dyn FileStore>) -> anyhow::Result<()>;
+}
+
+pub(crate) struct YtDlp;
+
+#[async_trait]
+impl Downloader for YtDlp {
+    async fn details(&self, url: &str) -> Result<Song, SongError> {
+        let url = url.to_string();
+        // youtube_dl waits on yt-dlp so it can't run on the async threads
+        match tokio::task::spawn_blocking(move || Song::new(&url)).await {
+            Ok(v) => v,
+            Err(_) => Err(SongError::UnableToDownload),
+        }
+    }
+
+    /*
+     * aria2c downloads straight into the cache directory when the files are local, otherwise into
+     * a temporary directory and the song is uploaded once it's done. Either way the file is tagged
+     * once aria2c is done, see tags.rs
+     */
+    async fn save(&self, song: &Song, files: Arc<dyn FileStore>) -> anyhow::Result<()> {
+        let (id, url) = match (song.id, &song.url) {
+            (Some(id), Some(url)) => (id.to_string(), url),
+            _ => return Err(anyhow!("song is missing an id or url")),
+        };
+        let song = song.clone();
+        if let Some(dir) = files.local_dir(Bucket::Songs) {
+            let mut aria2c = Command::new("aria2c")
+                .args(["-d", dir, "-o", &id, url])
+                .spawn()?;
+            tokio::spawn(async move {
+                let result = match aria2c.wait().await {
+                    Ok(v) if v.success() => match files.get(Bucket::Songs, &id).await {
+                        Ok(Some(data)) => {
+                            let data = tag_new_song(data, &song, files.as_ref()).await;
+                            files.put(Bucket::Songs, &id, data).await
+                        }
+                        Ok(None) => Err(anyhow!("aria2c left no file")),
+                        Err(e) => Err(e),
+                    },
+                    Ok(v) => Err(anyhow!("aria2c exited with {v}")),
+                    Err(e) => Err(e.into()),
+                };
+                if let Err(e) = result {
+                    error!("failed to store song {id}: {e}");
+                }
+            });
+            return Ok(());
+        }
+
+        let dir = std::env::temp_dir().join("seanify-downloads");
+        tokio::fs::create_dir_all(&dir).await?;
+        let mut aria2c = Command::new("aria2c")
+            .arg("-d")
+            .arg(&dir)
+            .args(["-o", &id, url])
+            .spawn()?;
+        tokio::spawn(async move {
+            let path = dir.join(&id);
+            let result = match aria2c.wait().await {
+                Ok(v) if v.success() => match tokio::fs::read(&path).await {
+                    Ok(data) => {
+                        let data = tag_new_song(data, &song, files.as_ref()).await;
+                        files.put(Bucket::Songs, &id, data).await
+                    }
+                    Err(e) => Err(e.into()),
+                },
+                Ok(v) => Err(anyhow!("aria2c exited with {v}")),
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = result {
+                error!("failed to store song {id}: {e}");
+            }
+            let _ = tokio::fs::remove_file(&path).await;
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,356 @@
+use crate::{retag_song, FileStore, SongMetadata, Storage};
+use anyhow::anyhow;
+use log::{error, info};
+
+/*
+ * A pass over newly inserted songs that tidies up what yt-dlp gives, run every enrich_interval
+ * seconds. Songs an admin already edited are left alone and every song is only looked at once
+ *
+ * The rules, applied in this order:
+ *   album_from_title  "Song (From "Movie")" fills an empty album with Movie
+ *   noise             "(Lyrics)", "[HD]", a trailing "Official Audio" and the like are removed
+ *                     from the title, any bracket with only noise phrases in it goes
+ *   split_artist      "Artist - Title" fills an empty artist and leaves Title, if the song
+ *                     already has that artist the prefix is only removed
+ *   channel_artist    an empty artist is filled from the creator, or the uploader when it's an
+ *                     official channel ("Artist - Topic", "ArtistVEVO")
+ *   dates             upload_date YYYYMMDD becomes YYYY-MM-DD
+ *
+ * enrich_rules picks which of them run (comma separated, "none" turns the pass off) and
+ * enrich_noise replaces the list of noise phrases. Changes are written to song_edits like an
+ * EDIT_SONG with no editor and the file is tagged again
+ */
+pub(crate) const ENRICH_BATCH_SIZE: i64 = 50;
+
+pub const RULES: [&str; 5] = [
+    "album_from_title",
+    "noise",
+    "split_artist",
+    "channel_artist",
+    "dates",
+];
+
+pub const DEFAULT_NOISE: [&str; 22] = [
+    "official music video",
+    "official lyric video",
+    "official video",
+    "official audio",
+    "official visualizer",
+    "official",
+    "music video",
+    "lyric video",
+    "lyrics video",
+    "lyrics",
+    "lyric",
+    "letra",
+    "audio",
+    "video",
+    "visualizer",
+    "hd",
+    "hq",
+    "4k",
+    "1080p",
+    "720p",
+    "tiktok song",
+    "tiktok",
+];
+
+// characters that can be left between noise phrases or at the end of a title after removing one
+const SEPARATORS: &str = "-–—|/:,&+~";
+
+// the fields of a song the rules look at or change
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SongFields {
+    pub title: String,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub genre: Option<String>,
+    pub uploader: Option<String>,
+    pub creator: Option<String>,
+    pub upload_date: Option<String>,
+}
+
+impl SongFields {
+    pub(crate) fn metadata(&self) -> SongMetadata {
+        SongMetadata {
+            title: self.title.clone(),
+            artist: self.artist.clone(),
+            album: self.album.clone(),
+            album_artist: self.album_artist.clone(),
+            genre: self.genre.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnrichRules {
+    pub album_from_title: bool,
+    pub noise: bool,
+    pub split_artist: bool,
+    pub channel_artist: bool,
+    pub dates: bool,
+    pub noise_phrases: Vec<String>, // lowercase, longest first
+}
+
+impl Default for EnrichRules {
+    fn default() -> Self {
+        Self::parse(None, None).expect("the default rules are valid")
+    }
+}
+
+// whitespace collapsed and any separators left at either end removed
+fn tidy(s: &str) -> String {
+    s.split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .trim_matches(|c: char| c.is_whitespace() || SEPARATORS.contains(c))
+        .to_string()
+}
+
+fn not_empty(s: &Option<String>) -> bool {
+    s.as_deref().is_some_and(|x| !x.trim().is_empty())
+}
+
+impl EnrichRules {
+    /*
+     * rules is a comma separated list of RULES, every rule runs if it's None. noise is a comma
+     * separated list of phrases that replaces DEFAULT_NOISE
+     */
+    pub fn parse(rules: Option<&str>, noise: Option<&str>) -> anyhow::Result<Self> {
+        let rules = match rules.map(str::trim) {
+            Some("none") => Vec::new(),
+            Some(v) => v
+                .split(',')
+                .map(str::trim)
+                .filter(|x| !x.is_empty())
+                .collect(),
+            None => RULES.to_vec(),
+        };
+        if let Some(v) = rules.iter().find(|x| !RULES.contains(x)) {
+            return Err(anyhow!(
+                "enrich_rules has an unknown rule {v}, the rules are {}",
+                RULES.join(", ")
+            ));
+        }
+        let mut noise_phrases: Vec<String> = match noise {
+            Some(v) => v
+                .split(',')
+                .map(|x| x.trim().to_lowercase())
+                .filter(|x| !x.is_empty())
+                .collect(),
+            None => DEFAULT_NOISE.iter().map(|x| x.to_string()).collect(),
+        };
+        noise_phrases.sort_by_key(|x| std::cmp::Reverse(x.len()));
+        Ok(Self {
+            album_from_title: rules.contains(&"album_from_title"),
+            noise: rules.contains(&"noise"),
+            split_artist: rules.contains(&"split_artist"),
+            channel_artist: rules.contains(&"channel_artist"),
+            dates: rules.contains(&"dates"),
+            noise_phrases,
+        })
+    }
+
+    // true if no rule runs, the pass isn't started at all then
+    pub fn is_empty(&self) -> bool {
+        ![
+            self.album_from_title,
+            self.noise,
+            self.split_artist,
+            self.channel_artist,
+            self.dates,
+        ]
+        .contains(&true)
+    }
+
+    // the song with every rule applied, a title is never left empty
+    pub fn enrich(&self, original: &SongFields) -> SongFields {
+        let mut song = original.clone();
+        if self.album_from_title && !not_empty(&song.album) {
+            if let Some((title, album)) = album_from_title(&song.title) {
+                song.title = title;
+                song.album = Some(album);
+            }
+        }
+        if self.noise {
+            song.title = self.strip_noise(&song.title);
+        }
+        if self.split_artist {
+            if let Some((artist, title)) = split_artist(&song.title) {
+                let known = [&song.artist, &song.creator].iter().any(|x| {
+                    x.as_deref()
+                        .is_some_and(|x| x.eq_ignore_ascii_case(&artist))
+                });
+                if !not_empty(&song.artist) {
+                    song.artist = Some(artist);
+                    song.title = title;
+                } else if known {
+                    song.title = title;
+                }
+            }
+        }
+        if self.channel_artist && !not_empty(&song.artist) {
+            song.artist = match not_empty(&song.creator) {
+                true => song.creator.clone(),
+                false => song.uploader.as_deref().and_then(channel_artist),
+            };
+        }
+        if self.dates {
+            if let Some(v) = song.upload_date.as_deref().and_then(normalize_date) {
+                song.upload_date = Some(v);
+            }
+        }
+        if song.title.trim().is_empty() {
+            song.title = original.title.clone();
+        }
+        song
+    }
+
+    // a bracket or the end of a title is noise if nothing but noise phrases and separators is left
+    fn is_noise(&self, text: &str) -> bool {
+        let mut rest = text.to_lowercase();
+        for phrase in &self.noise_phrases {
+            rest = rest.replace(phrase.as_str(), " ");
+        }
+        !text.trim().is_empty()
+            && rest
+                .chars()
+                .all(|c| c.is_whitespace() || SEPARATORS.contains(c))
+    }
+
+    fn strip_noise(&self, title: &str) -> String {
+        let mut out = String::with_capacity(title.len());
+        let mut rest = title;
+        while let Some(start) = rest.find(['(', '[', '{']) {
+            let close = match &rest[start..start + 1] {
+                "(" => ')',
+                "[" => ']',
+                _ => '}',
+            };
+            let end = match rest[start..].find(close) {
+                Some(v) => start + v,
+                None => break,
+            };
+            out.push_str(&rest[..start]);
+            if !self.is_noise(&rest[start + 1..end]) {
+                out.push_str(&rest[start..=end]);
+            }
+            rest = &rest[end + 1..];
+        }
+        out.push_str(rest);
+
+        // then phrases at the very end, "Song - Official Audio"
+        let mut title = tidy(&out);
+        while let Some(phrase) = self.noise_phrases.iter().find(|x| {
+            let cut = title.len().checked_sub(x.len());
+            cut.is_some_and(|cut| {
+                cut > 0
+                    && title.is_char_boundary(cut)
+                    && title[cut..].eq_ignore_ascii_case(x)
+                    && title[..cut].ends_with(|c: char| c.is_whitespace() || SEPARATORS.contains(c))
+            })
+        }) {
+            let stripped = tidy(&title[..title.len() - phrase.len()]);
+            if stripped.is_empty() {
+                break;
+            }
+            title = stripped;
+        }
+        title
+    }
+}
+
+// "Artist - Title" as (Artist, Title)
+fn split_artist(title: &str) -> Option<(String, String)> {
+    for separator in [" - ", " – ", " — "] {
+        if let Some((artist, title)) = title.split_once(separator) {
+            let (artist, title) = (tidy(artist), tidy(title));
+            if !artist.is_empty() && !title.is_empty() {
+                return Some((artist, title));
+            }
+        }
+    }
+    None
+}
+
+// the artist of an official channel, "Artist - Topic" and "ArtistVEVO"
+fn channel_artist(uploader: &str) -> Option<String> {
+    let artist = uploader
+        .strip_suffix(" - Topic")
+        .or_else(|| uploader.strip_suffix("VEVO"))?;
+    match artist.trim().is_empty() {
+        true => None,
+        false => Some(artist.trim().to_string()),
+    }
+}
+
+// "Song (From "Movie")" as (Song, Movie)
+fn album_from_title(title: &str) -> Option<(String, String)> {
+    for (open, close) in [('(', ')'), ('[', ']')] {
+        let mut offset = 0;
+        while let Some(start) = title[offset..].find(open).map(|x| x + offset) {
+            let end = start + title[start..].find(close)?;
+            let inner = title[start + 1..end].trim();
+            if inner.len() > 5 && inner[..5].eq_ignore_ascii_case("from ") {
+                let album = inner[5..].trim_matches(|c: char| {
+                    c.is_whitespace() || matches!(c, '"' | '\'' | '“' | '”' | '‘' | '’')
+                });
+                if !album.is_empty() {
+                    let title = tidy(&format!("{}{}", &title[..start], &title[end + 1..]));
+                    if !title.is_empty() {
+                        return Some((title, album.to_string()));
+                    }
+                }
+            }
+            offset = end + 1;
+        }
+    }
+    None
+}
+
+// YYYYMMDD as YYYY-MM-DD, None if it isn't a date in that format
+fn normalize_date(date: &str) -> Option<String> {
+    if date.len() != 8 || !date.bytes().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+    let (year, month, day) = (&date[..4], &date[4..6], &date[6..]);
+    match (month.parse::<u32>().ok()?, day.parse::<u32>().ok()?) {
+        (1..=12, 1..=31) => Some(format!("{year}-{month}-{day}")),
+        _ => None,
+    }
+}
+
+/*
+ * Enrich a batch of songs that haven't been looked at yet, returns how many were changed. Songs
+ * that don't change are still marked so they aren't looked at again
+ */
+pub(crate) async fn enrich_songs(
+    storage: &dyn Storage,
+    files: &dyn FileStore,
+    rules: &EnrichRules,
+) -> usize {
+    let songs = match storage.unenriched_songs(ENRICH_BATCH_SIZE).await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("failed to find songs to enrich: {e}");
+            return 0;
+        }
+    };
+    let mut changed = 0;
+    for (id, song) in songs {
+        let enriched = rules.enrich(&song);
+        match storage.save_enrichment(id, &song, &enriched).await {
+            Ok(_) if enriched != song => {
+                changed += 1;
+                retag_song(storage, files, id).await;
+            }
+            Ok(_) => {}
+            Err(e) => error!("failed to enrich song {id}: {e}"),
+        }
+    }
+    if changed > 0 {
+        info!("enriched the metadata of {changed} songs");
+    }
+    changed
+}
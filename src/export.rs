@@ -0,0 +1,313 @@
+use crate::{
+    user_images, with_state, AppState, Bucket, FeedItem, FollowEntry, FollowList, InviteList,
+    UserData, FOLLOW_PAGE_SIZE,
+};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use hmac::{Hmac, Mac};
+use log::{error, info};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use seahash::hash;
+use serde::Serialize;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs::{create_dir_all, read, remove_file};
+use tokio::sync::Mutex;
+use warp::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use warp::{Filter, Rejection, Reply};
+
+/*
+ * EXPORT_MY_DATA builds a .tar.gz of everything stored about the user (data.json and their
+ * images) and responds with a link to download it from
+ *
+ * Links are signed with a key that is made with the AppState and can only be used once, after
+ * EXPORT_LINK_SECONDS or a restart they stop working and the archive is deleted
+ */
+const EXPORT_LINK_SECONDS: u64 = 60 * 60;
+
+type HmacSha256 = Hmac<Sha256>;
+
+struct PendingExport {
+    userhash: u64,
+    path: PathBuf,
+}
+
+pub(crate) struct Exports {
+    key: [u8; 32],
+
+    // exports waiting to be downloaded, keyed by the nonce in the link
+    pending: Mutex<HashMap<String, PendingExport>>,
+}
+
+impl Exports {
+    pub fn new() -> Self {
+        Self {
+            key: thread_rng().gen(),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct ExportedTrack {
+    pub song: String,
+    pub title: String,
+    pub custom_name: Option<String>,
+    pub date_added: u64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ExportedPlaylist {
+    pub name: String,
+    pub description: Option<String>,
+    pub public_playlist: bool,
+    pub created_at: u64,
+    pub last_update: u64,
+    pub image: Option<String>, // path inside the archive
+    pub tracks: Vec<ExportedTrack>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ExportedPlay {
+    pub song: String,
+    pub title: Option<String>,
+    pub played_at: u64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct QueuedSong {
+    pub url: String,
+    pub timestamp: u64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct PersonalData {
+    pub exported_at: u64,
+    pub userdata: Option<UserData>,
+    pub playlists: Vec<ExportedPlaylist>,
+    pub followers: Vec<FollowEntry>,
+    pub following: Vec<FollowEntry>,
+    pub follow_requests: Vec<FollowEntry>,
+    pub blocked: Vec<String>,
+    pub muted: Vec<String>,
+    pub plays: Vec<ExportedPlay>,
+    pub queued_songs: Vec<QueuedSong>,
+    pub activity: Vec<FeedItem>,
+    pub invites: InviteList,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// keep asking for the next page of a follow list until there are none left
+async fn all_pages<F, Fut>(page: F) -> anyhow::Result<Vec<FollowEntry>>
+where
+    F: Fn(i64) -> Fut,
+    Fut: Future<Output = anyhow::Result<FollowList>>,
+{
+    let mut entries = Vec::new();
+    let mut cursor = 0;
+    loop {
+        let list = page(cursor).await?;
+        entries.extend(list.items);
+        match list.cursor {
+            Some(_) => cursor += FOLLOW_PAGE_SIZE,
+            None => return Ok(entries),
+        }
+    }
+}
+
+async fn personal_data(state: &AppState, userhash: u64) -> anyhow::Result<PersonalData> {
+    let db = &state.storage;
+    let activity = db.export_activity(userhash).await?;
+    let queued_songs = activity
+        .iter()
+        .filter(|x| x.kind == "QueuedSong")
+        .map(|x| QueuedSong {
+            url: x.detail.clone().unwrap_or_default(),
+            timestamp: x.timestamp,
+        })
+        .collect();
+
+    Ok(PersonalData {
+        exported_at: now(),
+        userdata: db.get_user_data(userhash).await?,
+        playlists: db.export_playlists(userhash).await?,
+        followers: all_pages(|x| db.list_followers(userhash, x)).await?,
+        following: all_pages(|x| db.list_following(userhash, x)).await?,
+        follow_requests: all_pages(|x| db.list_follow_requests(userhash, x)).await?,
+        blocked: db.list_blocked(userhash).await?,
+        muted: db.list_muted(userhash).await?,
+        plays: db.export_plays(userhash).await?,
+        queued_songs,
+        activity,
+        invites: db.list_invites(userhash).await?,
+    })
+}
+
+fn sign(key: &[u8], nonce: &str, expires: u64) -> anyhow::Result<String> {
+    let mut mac = HmacSha256::new_from_slice(key)?;
+    mac.update(format!("{nonce}.{expires}").as_bytes());
+    Ok(base64::encode_config(
+        mac.finalize().into_bytes(),
+        base64::URL_SAFE_NO_PAD,
+    ))
+}
+
+fn verify(key: &[u8], token: &str) -> Option<String> {
+    let mut parts = token.splitn(3, '.');
+    let (nonce, expires, signature) = (parts.next()?, parts.next()?, parts.next()?);
+    let expires = expires.parse::<u64>().ok()?;
+    if expires < now() {
+        return None;
+    }
+    let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD).ok()?;
+    let mut mac = HmacSha256::new_from_slice(key).ok()?;
+    mac.update(format!("{nonce}.{expires}").as_bytes());
+    mac.verify_slice(&signature).ok()?;
+    Some(nonce.to_string())
+}
+
+/*
+ * Gather everything about the user into an archive and return the path of the download link,
+ * relative to the root url. Asking for a new export throws away the one that is still pending
+ */
+pub(crate) async fn export_user_data(state: &AppState, userhash: u64) -> anyhow::Result<String> {
+    let mut data = personal_data(state, userhash).await?;
+
+    // the pfp is "{userhash}.png" and playlist images are "{userhash}-{hash of name}.png"
+    let mut images = HashMap::new();
+    for (fname, rest) in user_images(state.files.as_ref(), userhash).await {
+        if rest == ".png" {
+            images.insert(String::from("images/pfp.png"), fname);
+            continue;
+        }
+        let playlist_hash = rest.trim_start_matches('-').trim_end_matches(".png");
+        let name = format!("images/playlists/{playlist_hash}.png");
+        if let Some(v) = data
+            .playlists
+            .iter_mut()
+            .find(|x| hash(x.name.as_bytes()).to_string() == playlist_hash)
+        {
+            v.image = Some(name.clone());
+        }
+        // custom images used to be saved with a second .png on the end, they win over the default
+        // one. Now they replace it
+        if rest.ends_with(".png.png") || !images.contains_key(&name) {
+            images.insert(name, fname);
+        }
+    }
+
+    // the images might not be on this machine so they are read before building the archive
+    let mut image_data = Vec::with_capacity(images.len());
+    for (name, fname) in images {
+        if let Some(v) = state.files.get(Bucket::Images, &fname).await? {
+            image_data.push((name, v));
+        }
+    }
+
+    let dir = std::env::temp_dir().join("seanify-exports");
+    create_dir_all(&dir).await?;
+    let nonce: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect();
+    let path = dir.join(format!("{nonce}.tar.gz"));
+
+    let json = serde_json::to_vec_pretty(&data)?;
+    let archive = path.clone();
+    tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+        let file = std::fs::File::create(&archive)?;
+        let mut tar = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+        let mut append = |name: &str, data: &[u8]| {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(now());
+            header.set_cksum();
+            tar.append_data(&mut header, format!("seanify-export/{name}"), data)
+        };
+        append("data.json", &json)?;
+        for (name, data) in image_data {
+            append(&name, &data)?;
+        }
+        tar.into_inner()?.finish()?;
+        Ok(())
+    })
+    .await??;
+
+    let expires = now() + EXPORT_LINK_SECONDS;
+    let token = format!(
+        "{nonce}.{expires}.{}",
+        sign(&state.exports.key, &nonce, expires)?
+    );
+    {
+        let mut pending = state.exports.pending.lock().await;
+        let previous = pending
+            .iter()
+            .filter(|(_, v)| v.userhash == userhash)
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<String>>();
+        for k in previous {
+            if let Some(v) = pending.remove(&k) {
+                let _ = remove_file(v.path).await;
+            }
+        }
+        pending.insert(nonce.clone(), PendingExport { userhash, path });
+    }
+
+    // links that are never used still have their archive cleaned up
+    let exports = Arc::clone(&state.exports);
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(EXPORT_LINK_SECONDS)).await;
+        if let Some(v) = exports.pending.lock().await.remove(&nonce) {
+            let _ = remove_file(v.path).await;
+        }
+    });
+
+    info!("created data export for {userhash}");
+    Ok(format!("/export/{token}"))
+}
+
+// downloading removes the export, so a link only ever works once
+pub(crate) fn export_route(
+    state: AppState,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("export" / String)
+        .and(warp::get())
+        .and(with_state(state))
+        .and_then(|token: String, state: AppState| async move {
+            let nonce = match verify(&state.exports.key, &token) {
+                Some(v) => v,
+                None => return Err(warp::reject::not_found()),
+            };
+            let pending = match state.exports.pending.lock().await.remove(&nonce) {
+                Some(v) => v,
+                None => return Err(warp::reject::not_found()),
+            };
+            let data = read(&pending.path).await;
+            let _ = remove_file(&pending.path).await;
+            let data = match data {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("failed to read export: {e}");
+                    return Err(warp::reject::not_found());
+                }
+            };
+            Ok(warp::reply::with_header(
+                warp::reply::with_header(data, CONTENT_TYPE, "application/gzip"),
+                CONTENT_DISPOSITION,
+                "attachment; filename=\"seanify-export.tar.gz\"",
+            ))
+        })
+}
@@ -0,0 +1,186 @@
+use crate::{config, with_state, AppState, S3Files};
+use async_trait::async_trait;
+use log::error;
+use std::io::ErrorKind;
+use std::sync::Arc;
+use uuid::Uuid;
+use warp::filters::BoxedFilter;
+use warp::http::StatusCode;
+use warp::hyper::Body;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+/*
+ * Where songs and images are kept, Storage (storage.rs) is the database and FileStore is
+ * everything that is served as a file. Songs are named after their id and images after the user
+ * they belong to (see pictures.rs)
+ *
+ * LocalFiles keeps them in CACHE_DIR and CDN_DIR and they are served straight from disk.
+ * S3Files (see s3.rs) keeps them in a bucket of any S3 compatible service when the s3_* settings
+ * are set, the routes then redirect to a presigned url or proxy the object through the server
+ */
+#[derive(Clone, Copy)]
+pub(crate) enum Bucket {
+    Songs,
+    Images,
+}
+
+#[async_trait]
+pub(crate) trait FileStore: Send + Sync {
+    async fn put(&self, bucket: Bucket, name: &str, data: Vec<u8>) -> anyhow::Result<()>;
+
+    // None if there is no file with that name
+    async fn get(&self, bucket: Bucket, name: &str) -> anyhow::Result<Option<Vec<u8>>>;
+
+    // removing a file that doesn't exist is not an error
+    async fn delete(&self, bucket: Bucket, name: &str) -> anyhow::Result<()>;
+
+    // name and size in bytes of every file whose name starts with prefix
+    async fn list(&self, bucket: Bucket, prefix: &str) -> anyhow::Result<Vec<(String, u64)>>;
+
+    async fn rename(&self, bucket: Bucket, from: &str, to: &str) -> anyhow::Result<()> {
+        if let Some(data) = self.get(bucket, from).await? {
+            self.put(bucket, to, data).await?;
+            self.delete(bucket, from).await?;
+        }
+        Ok(())
+    }
+
+    // the directory the files are in if they are on this machine, they are then served with
+    // warp::fs::dir instead of serve
+    fn local_dir(&self, _bucket: Bucket) -> Option<&str> {
+        None
+    }
+
+    // the reply to a request for a file, range is the Range header of the request
+    async fn serve(
+        &self,
+        bucket: Bucket,
+        name: &str,
+        _range: Option<String>,
+    ) -> anyhow::Result<Response> {
+        Ok(match self.get(bucket, name).await? {
+            Some(v) => Response::new(Body::from(v)),
+            None => StatusCode::NOT_FOUND.into_response(),
+        })
+    }
+}
+
+// S3Files if the s3 settings are set, the local directories otherwise
+pub(crate) fn file_store() -> Arc<dyn FileStore> {
+    match S3Files::from_config() {
+        Some(v) => Arc::new(v),
+        None => Arc::new(LocalFiles {
+            songs: config().cache_dir.clone(),
+            images: config().cdn_dir.clone(),
+        }),
+    }
+}
+
+pub(crate) struct LocalFiles {
+    songs: String,
+    images: String,
+}
+
+impl LocalFiles {
+    fn dir(&self, bucket: Bucket) -> &str {
+        match bucket {
+            Bucket::Songs => &self.songs,
+            Bucket::Images => &self.images,
+        }
+    }
+
+    fn path(&self, bucket: Bucket, name: &str) -> String {
+        format!("{}/{name}", self.dir(bucket))
+    }
+}
+
+#[async_trait]
+impl FileStore for LocalFiles {
+    // written next to the file and renamed over it so a song that is tagged again is never
+    // served half written
+    async fn put(&self, bucket: Bucket, name: &str, data: Vec<u8>) -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(self.dir(bucket)).await?;
+        let partial = self.path(bucket, &format!(".{name}.{}", Uuid::new_v4()));
+        tokio::fs::write(&partial, data).await?;
+        if let Err(e) = tokio::fs::rename(&partial, self.path(bucket, name)).await {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    async fn get(&self, bucket: Bucket, name: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        match tokio::fs::read(self.path(bucket, name)).await {
+            Ok(v) => Ok(Some(v)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete(&self, bucket: Bucket, name: &str) -> anyhow::Result<()> {
+        match tokio::fs::remove_file(self.path(bucket, name)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    // songs and images are both stored flat so there is no need to recurse
+    async fn list(&self, bucket: Bucket, prefix: &str) -> anyhow::Result<Vec<(String, u64)>> {
+        let mut files = Vec::new();
+        let mut entries = match tokio::fs::read_dir(self.dir(bucket)).await {
+            Ok(v) => v,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(files),
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !name.starts_with(prefix) || name.starts_with('.') {
+                continue;
+            }
+            if let Ok(meta) = entry.metadata().await {
+                if meta.is_file() {
+                    files.push((name, meta.len()));
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    async fn rename(&self, bucket: Bucket, from: &str, to: &str) -> anyhow::Result<()> {
+        tokio::fs::rename(self.path(bucket, from), self.path(bucket, to)).await?;
+        Ok(())
+    }
+
+    fn local_dir(&self, bucket: Bucket) -> Option<&str> {
+        Some(self.dir(bucket))
+    }
+}
+
+/*
+ * The files of a bucket under whatever path this is mounted at, local files keep using
+ * warp::fs::dir so range requests and caching headers work as they always have
+ */
+pub(crate) fn serve_files(bucket: Bucket, state: AppState) -> BoxedFilter<(Response,)> {
+    if let Some(dir) = state.files.local_dir(bucket) {
+        return warp::fs::dir(dir.to_string())
+            .map(|x: warp::fs::File| x.into_response())
+            .boxed();
+    }
+    warp::get()
+        .and(warp::path::tail())
+        .and(warp::header::optional::<String>("range"))
+        .and(with_state(state))
+        .and_then(
+            move |name: warp::path::Tail, range: Option<String>, state: AppState| async move {
+                match state.files.serve(bucket, name.as_str(), range).await {
+                    Ok(v) => Ok(v),
+                    Err(e) => {
+                        error!("failed to serve {}: {e}", name.as_str());
+                        Err::<Response, Rejection>(warp::reject::not_found())
+                    }
+                }
+            },
+        )
+        .boxed()
+}
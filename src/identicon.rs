@@ -0,0 +1,128 @@
+use image::{Rgb, RgbImage};
+
+/*
+ * The default pfp and playlist image, a 5x5 grid mirrored down the middle like the ones github
+ * gives new accounts. Everything comes from the hash it's made from, so the same user or playlist
+ * always gets the same picture
+ *
+ * The hash is mixed first so hashes that are close together still look nothing alike. The cells
+ * take 15 bits (the middle column and the two on the left, the right is a mirror), the color a
+ * hue out of 360 and a saturation and lightness out of 20 each. The color is darkened until it
+ * has at least MIN_CONTRAST against the background, which is the 3:1 WCAG asks for in graphics
+ */
+pub const GRID: usize = 5;
+
+// the identicon is rendered at least this big so every cell is at least a pixel
+pub const MIN_SIZE: u32 = 12;
+
+pub const MIN_CONTRAST: f64 = 3.0;
+
+pub const BACKGROUND: [u8; 3] = [240, 240, 240];
+
+// a grid with only a couple of cells or nearly all of them doesn't look like anything
+const MIN_CELLS: u32 = 4;
+const MAX_CELLS: u32 = 11;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Identicon {
+    pub cells: [[bool; GRID]; GRID], // rows of columns
+    pub foreground: [u8; 3],
+    pub background: [u8; 3],
+}
+
+// splitmix64
+fn mix(x: u64) -> u64 {
+    let x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+// h in degrees, s and l between 0 and 1
+fn hsl(h: f64, s: f64, l: f64) -> [u8; 3] {
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 / 60 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = l - c / 2.0;
+    [r, g, b].map(|v| ((v + m) * 255.0).round() as u8)
+}
+
+// relative luminance as WCAG defines it
+fn luminance(color: [u8; 3]) -> f64 {
+    let [r, g, b] = color.map(|v| {
+        let v = v as f64 / 255.0;
+        match v <= 0.03928 {
+            true => v / 12.92,
+            false => ((v + 0.055) / 1.055).powf(2.4),
+        }
+    });
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+// between 1 (the same) and 21 (black on white)
+pub fn contrast_ratio(a: [u8; 3], b: [u8; 3]) -> f64 {
+    let (a, b) = (luminance(a), luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+impl Identicon {
+    pub fn new(hash: u64) -> Self {
+        let mut pattern = mix(hash);
+        while !(MIN_CELLS..=MAX_CELLS).contains(&(pattern & 0x7fff).count_ones()) {
+            pattern = mix(pattern);
+        }
+        let mut cells = [[false; GRID]; GRID];
+        for (row, line) in cells.iter_mut().enumerate() {
+            for col in 0..=GRID / 2 {
+                let on = (pattern >> (col * GRID + row)) & 1 == 1;
+                line[col] = on;
+                line[GRID - 1 - col] = on;
+            }
+        }
+
+        let color = mix(!hash);
+        let hue = (color % 360) as f64;
+        let saturation = 0.5 + ((color >> 16) % 20) as f64 / 100.0;
+        let mut lightness = 0.4 + ((color >> 32) % 20) as f64 / 100.0;
+        let mut foreground = hsl(hue, saturation, lightness);
+        while contrast_ratio(foreground, BACKGROUND) < MIN_CONTRAST {
+            lightness -= 0.02;
+            foreground = hsl(hue, saturation, lightness);
+        }
+
+        Self {
+            cells,
+            foreground,
+            background: BACKGROUND,
+        }
+    }
+
+    /*
+     * A size x size image with a margin of a twelfth of it on every side, the cells split what's
+     * left as evenly as they can. Pixels are mapped from the left half so the image is exactly
+     * symmetric whatever the size
+     */
+    pub fn render(&self, size: u32) -> RgbImage {
+        let size = size.max(MIN_SIZE);
+        let margin = size / 12;
+        let inner = size - margin * 2;
+        let cell = |at: u32| match (margin..margin + inner).contains(&at) {
+            true => Some(((at - margin) * GRID as u32 / inner) as usize),
+            false => None,
+        };
+        RgbImage::from_fn(size, size, |x, y| {
+            let x = x.min(size - 1 - x);
+            match (cell(x), cell(y)) {
+                (Some(col), Some(row)) if self.cells[row][col] => Rgb(self.foreground),
+                _ => Rgb(self.background),
+            }
+        })
+    }
+}
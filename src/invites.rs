@@ -0,0 +1,39 @@
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use serde::Serialize;
+
+/*
+ * Accounts can only be created with an invite code (SIGN username password code), the
+ * INSTANCE_KEY is only used for serving files now
+ *
+ * Admins can create as many invites as they want, everyone else has an allowance of uses that is
+ * set by admins with ADMIN_SET_INVITES. An invite with 5 uses takes 5 from the allowance, and
+ * revoking an invite gives back the uses that weren't used
+ */
+pub(crate) const INVITE_CODE_LENGTH: usize = 12;
+pub(crate) const DEFAULT_INVITE_USES: i32 = 1;
+pub(crate) const MAX_INVITE_USES: i32 = 100;
+
+#[derive(Serialize)]
+pub(crate) struct Invite {
+    pub code: String,
+    pub uses: i32,
+    pub max_uses: i32,
+    pub expires_at: Option<u64>,
+    pub created_at: u64,
+    pub used_by: Vec<String>, // display names
+}
+
+#[derive(Serialize)]
+pub(crate) struct InviteList {
+    pub invites_left: i32,
+    pub invites: Vec<Invite>,
+}
+
+pub(crate) fn generate_invite_code() -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(INVITE_CODE_LENGTH)
+        .map(char::from)
+        .collect()
+}
@@ -1,24 +1,75 @@
+mod activity;
+mod admin;
+mod artwork;
+mod backup;
+mod config;
+mod dashboard;
 mod db;
+mod downloader;
+mod enrich;
+mod export;
+mod files;
+mod identicon;
+mod invites;
+#[cfg(any(test, feature = "test-util"))]
+mod memory;
+mod metadata;
+mod metrics;
+mod migrate;
 mod pictures;
+mod presence;
+mod s3;
 mod songs;
+mod sqlite;
+mod stats;
+mod storage;
+mod tags;
+mod tls;
 mod user;
+use activity::*;
+use admin::*;
+use artwork::*;
+use backup::*;
+use config::*;
+use dashboard::*;
 use db::*;
+use downloader::*;
+use enrich::*;
+use export::*;
+use files::*;
+use invites::*;
+#[cfg(any(test, feature = "test-util"))]
+use memory::*;
+use metadata::*;
+use metrics::*;
+use migrate::*;
 use pictures::*;
+use presence::*;
+use s3::*;
 use seahash::hash;
 use songs::*;
+use sqlite::*;
+use stats::*;
+use storage::*;
+use tags::*;
+use tls::*;
 use user::*;
 
+pub use config::{set_config, Config};
+pub use enrich::{EnrichRules, SongFields};
+pub use identicon::{contrast_ratio, Identicon, BACKGROUND, MIN_CONTRAST, MIN_SIZE};
+#[cfg(any(test, feature = "test-util"))]
+pub use memory::MemoryDownloader;
+
 use crate::user::Playlist;
-use async_once::AsyncOnce;
 use futures_util::{FutureExt, StreamExt};
-use lazy_static::lazy_static;
 use log::{debug, error, info, warn};
-use num_traits::cast::ToPrimitive;
 use serde_json::json;
 use std::collections::{BTreeMap, VecDeque};
 use std::convert::Infallible;
-use std::env;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Instant;
 use std::{collections::HashMap, time::Duration};
 use tokio::sync::{mpsc, Mutex, RwLock};
 use tokio_stream::wrappers::UnboundedReceiverStream;
@@ -29,58 +80,110 @@ use warp::{
 };
 
 /*
- * If the variable we want to check is not set, then we'll abort
- * This is for required variables
+ * Everything shared between connections, it is handed to the warp filters with with_state instead
+ * of living in globals so the storage, file store and downloader can be swapped out (the
+ * integration tests run against MemoryStorage and MemoryDownloader)
  */
-#[macro_export]
-macro_rules! env_fetch {
-    ($val:expr) => {
-        match env::var($val) {
-            Ok(v) => v,
-            Err(_) => {
-                error!("Please set {} in env", $val);
-                std::process::exit(1);
-            }
-        }
-    };
-}
+#[derive(Clone)]
+pub struct AppState {
+    pub(crate) storage: Arc<dyn Storage>,
+    pub(crate) files: Arc<dyn FileStore>,
+    pub(crate) song_manager: Arc<RwLock<SongManager>>,
+    pub(crate) clients: Clients,
 
-lazy_static! {
-    // TODO better error handling here
-    static ref DB: AsyncOnce<Database> = AsyncOnce::new(async { Database::new().await.unwrap() });
+    // list of username hashes that are blocked
+    pub(crate) blocked_list: Arc<std::sync::RwLock<BlockedList>>,
 
-    // TODO swap out env vars for params
-    static ref SONG_MANAGER: RwLock<SongManager> = RwLock::new(SongManager::new(None, None, None));
-    static ref INSTANCE_KEY: String = env_fetch!("INSTANCE_KEY");
-    static ref ADMIN_KEY: String = env::var("ADMIN_KEY").unwrap_or_default();
-    pub static ref CACHE_DIR: String = env_fetch!("CACHE_DIR");
+    // the ratelimiter with the blocked list inside it, this is done seperately to allow only
+    // loading the blocked list and not the current list as well
+    pub(crate) rate_limit: Arc<std::sync::Mutex<RateLimiter>>,
 
-    // create list of username hashes that are blocked
-    static ref BLOCKED_LIST: Arc<std::sync::RwLock<BlockedList>> =
-        Arc::new(std::sync::RwLock::new(BlockedList::default()));
+    // what each user is currently listening to, keyed by username hash
+    pub(crate) presence: Arc<RwLock<HashMap<u64, Presence>>>,
 
-    // create the ratelimiter with the blocked list inside it, this is done seperately to allow
-    // only loading the blocked list and not the current list as well
-    static ref RATE_LIMIT: Arc<std::sync::Mutex<RateLimiter<'static>>> =
-        Arc::new(std::sync::Mutex::new(RateLimiter::new(&BLOCKED_LIST)));
-
-    // max request allowed per a second per an address
-    static ref MAX_RATELIMIT: usize = env_num_or_default!("RATE_MAX_COUNT", 50) as usize;
+    // the key export links are signed with and the exports waiting to be downloaded
+    pub(crate) exports: Arc<Exports>,
 }
 
-const DEFAULT_PORT: u16 = 8080;
+impl AppState {
+    pub(crate) fn new(
+        storage: Arc<dyn Storage>,
+        files: Arc<dyn FileStore>,
+        downloader: Arc<dyn Downloader>,
+    ) -> Self {
+        let blocked_list = Arc::new(std::sync::RwLock::new(BlockedList::default()));
+        Self {
+            song_manager: Arc::new(RwLock::new(SongManager::new(
+                storage.clone(),
+                files.clone(),
+                downloader,
+                config().ytdl_call_limit,
+                config().bandwidth_limit_mb,
+                config().max_file_size_mb,
+            ))),
+            storage,
+            files,
+            clients: Arc::new(Mutex::new(HashMap::new())),
+            rate_limit: Arc::new(std::sync::Mutex::new(RateLimiter::new(
+                blocked_list.clone(),
+            ))),
+            blocked_list,
+            presence: Arc::new(RwLock::new(HashMap::new())),
+            exports: Arc::new(Exports::new()),
+        }
+    }
 
-// time in seconds between downloading from queue
-const DEFAULT_QUEUE_COOLDOWN: u32 = 10;
+    // nothing is kept in storage once the state is dropped, songs can only be queued if the
+    // downloader knows about them. Files still go to the file store in the config
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn in_memory(downloader: MemoryDownloader) -> Self {
+        Self::new(
+            Arc::new(MemoryStorage::default()),
+            file_store(),
+            Arc::new(downloader),
+        )
+    }
+
+    // the same with sqlite storage, sqlite::memory: gives a fresh database that is never written
+    // to disk
+    #[cfg(any(test, feature = "test-util"))]
+    pub async fn sqlite(url: &str, downloader: MemoryDownloader) -> anyhow::Result<Self> {
+        let storage = SqliteStorage::new(url).await?;
+        Ok(Self::new(Arc::new(storage), file_store(), Arc::new(downloader)))
+    }
+
+    // and with postgres, the database in the url is dropped and created again so whatever the
+    // last run left behind is gone
+    #[cfg(any(test, feature = "test-util"))]
+    pub async fn postgres(url: &str, downloader: MemoryDownloader) -> anyhow::Result<Self> {
+        use sqlx::migrate::MigrateDatabase;
+        sqlx::Postgres::drop_database(url).await?;
+        sqlx::Postgres::create_database(url).await?;
+        let storage = Database::with_url(url).await?;
+        Ok(Self::new(Arc::new(storage), file_store(), Arc::new(downloader)))
+    }
+
+    // attempt to download the first song in the queue, run does this every queue_cooldown seconds
+    pub async fn cycle_queue(&self) {
+        let _ = self.song_manager.write().await.cycle_queue().await;
+    }
+
+    // enrich a batch of new songs with the rules in the config, run does this every
+    // enrich_interval seconds. Returns how many songs were changed
+    pub async fn enrich_songs(&self) -> usize {
+        enrich_songs(
+            self.storage.as_ref(),
+            self.files.as_ref(),
+            &config().enrich_rules,
+        )
+        .await
+    }
+}
 
 // a list of all the client's ips that connected are stored, this is the max length for that before
 // the list does not get appended to
 const MAX_CLIENT_RATE_CACHE: usize = 200;
 
-const RATE_BAN_IN_SECONDS: usize = 60;
-
-const RATE_BLACKLIST_CYCLE_MS: u32 = 10000;
-
 /*
  * These are predefined commands that are valid to send client to client
  *
@@ -99,38 +202,33 @@ static CLIENT_COMMANDS: [&str; 6] = ["PLAY", "PAUSE", "SKIP", "VOL_UP", "VOL_DOW
  * immediately after logging their ip(TODO implement rate limiting) to prevent answering
  * unnecessary request
  *
- * The username_hash is used to communicate with other instances of itself
+ * The username_hash is used to communicate with other instances of itself, the stream_token is a
+ * random value of its own so handing it out (see STREAM_TOKEN) says nothing about the connection
  */
+#[derive(Clone)]
 pub(crate) struct WsClient {
     pub sender: Option<mpsc::UnboundedSender<std::result::Result<Message, warp::Error>>>,
     pub auth: bool,
     pub admin: bool,
     pub username_hash: u64,
+    pub stream_token: String,
 }
 
-
 // We store the websocket clients in this hashmap, the string being a uuid
 pub(crate) type Clients = Arc<Mutex<HashMap<String, WsClient>>>;
 type Result<T> = std::result::Result<T, Rejection>;
 
-async fn ws_handler(ws: warp::ws::Ws, clients: Clients) -> Result<impl Reply> {
-    Ok(ws.on_upgrade(move |socket| client_connection(socket, clients)))
-}
-
-#[macro_export]
-macro_rules! acquire_db {
-    ($val:expr) => {
-        &$val.get().await
-    };
+async fn ws_handler(ws: warp::ws::Ws, state: AppState) -> Result<impl Reply> {
+    Ok(ws.on_upgrade(move |socket| client_connection(socket, state)))
 }
 
 // handle any messages that are sent by the client here
-async fn client_msg(client_id: &str, msg: &Message, clients: &Clients) {
+async fn client_msg(client_id: &str, msg: &Message, state: &AppState) {
     let msg = match msg.to_str() {
         Ok(v) => v,
         Err(_) => return,
     };
-    let mut locked = clients.lock().await;
+    let mut locked = state.clients.lock().await;
     let client = match locked.get_mut(client_id) {
         Some(v) => v,
         None => return,
@@ -141,12 +239,14 @@ async fn client_msg(client_id: &str, msg: &Message, clients: &Clients) {
         let args = msg.split(' ').collect::<Vec<&str>>();
         match args[0] {
             "AUTH" => {
-                if args.len() != 3 && !(args.len() == 4 && args[3] == ADMIN_KEY.to_string()) {
+                if args.len() != 3 && !(args.len() == 4 && args[3] == config().admin_key) {
                     // TODO CUSTOM ERROR
                     warn!("invalid args");
+                    METRICS.auth_failures.inc();
                     return;
                 }
-                match acquire_db!(DB)
+                match state
+                    .storage
                     .check_if_user_exists_in_auth(args[1], args[2])
                     .await
                 {
@@ -160,13 +260,20 @@ async fn client_msg(client_id: &str, msg: &Message, clients: &Clients) {
                                     return;
                                 }
                             };
+                            if let Ok(true) = state.storage.is_banned(client.username_hash).await {
+                                locked.remove(client_id);
+                                METRICS.auth_failures.inc();
+                                info!("banned user tried to authenticate, removed client");
+                                return;
+                            }
                             client.auth = true;
-                            let _ = acquire_db!(DB)
+                            let _ = state
+                                .storage
                                 .update_login_timestamp(client.username_hash)
                                 .await;
-                            if args.len() == 4 && args[3] == ADMIN_KEY.to_string() {
+                            if args.len() == 4 && args[3] == config().admin_key {
                                 if let Ok(admin) =
-                                    acquire_db!(DB).is_admin(client.username_hash).await
+                                    state.storage.is_admin(client.username_hash).await
                                 {
                                     // there are definitely better ways to do this
                                     if admin {
@@ -178,6 +285,7 @@ async fn client_msg(client_id: &str, msg: &Message, clients: &Clients) {
                             info!("authenticated user")
                         } else {
                             locked.remove(client_id);
+                            METRICS.auth_failures.inc();
                             info!("auth failed, removed client");
                         }
                     }
@@ -194,33 +302,37 @@ async fn client_msg(client_id: &str, msg: &Message, clients: &Clients) {
                     warn!("invalid args");
                     return;
                 }
-                if args[3] != INSTANCE_KEY.as_str() {
-                    // TODO CUSTOM ERROR
-                    warn!("invalid instance key");
-                    return;
-                }
-                if let Ok(true) = DB
-                    .get()
-                    .await
+                // signing up with the ADMIN_KEY instead of an invite creates an admin account, this
+                // is how the first account on an instance is made and only works until there is one
+                let invite = match !config().admin_key.is_empty() && args[3] == config().admin_key {
+                    true => None,
+                    false => Some(args[3]),
+                };
+                if let Ok(true) = state
+                    .storage
                     .check_if_username_exists_in_auth(args[1])
                     .await
                 {
                     warn!("username already exist");
                     return;
                 }
-                match acquire_db!(DB).new_user(args[1], args[2]).await {
+                match state.storage.new_user(args[1], args[2], invite).await {
                     Ok(_) => {
                         info!("inserted user");
-                        let _ = default_pfp(hash(args[1].as_bytes())).await;
+                        let _ = default_pfp(state.files.as_ref(), hash(args[1].as_bytes())).await;
                     }
-                    Err(_) => warn!("failed to insert user"),
+                    Err(e) => warn!("failed to insert user: {e}"),
                 };
             }
             _ => return,
         };
         return;
     }
-    handle_response(msg, client, clients).await;
+    // copy the client out so the lock isn't held while handling the request, otherwise anything
+    // that sends to other clients would deadlock
+    let client = client.clone();
+    drop(locked);
+    handle_response(msg, &client, state).await;
 }
 
 /*
@@ -234,7 +346,7 @@ async fn client_msg(client_id: &str, msg: &Message, clients: &Clients) {
  * then we add to the hashmap of clients and wait for messages, when the client closes we can
  * remove them from the hashmap
  */
-async fn client_connection(ws: WebSocket, clients: Clients) {
+async fn client_connection(ws: WebSocket, state: AppState) {
     info!("establishing new client connection...");
     let (tx, mut rx) = ws.split();
     let (client_sender, client_rcv) = mpsc::unbounded_channel();
@@ -254,8 +366,10 @@ async fn client_connection(ws: WebSocket, clients: Clients) {
         auth: false,
         admin: false,
         username_hash: 0,
+        stream_token: Uuid::new_v4().to_simple().to_string(),
     };
-    clients.lock().await.insert(mapped_uuid, new_client);
+    state.clients.lock().await.insert(mapped_uuid, new_client);
+    METRICS.connections.inc();
     info!("s");
     while let Some(result) = rx.next().await {
         let msg = match result {
@@ -265,10 +379,22 @@ async fn client_connection(ws: WebSocket, clients: Clients) {
                 break;
             }
         };
-        client_msg(&uuid, &msg, &clients).await;
+        client_msg(&uuid, &msg, &state).await;
     }
-    clients.lock().await.remove(&uuid);
+    let removed = state.clients.lock().await.remove(&uuid);
     info!("{} disconnected", uuid);
+    // once the last connection of a user is gone they are no longer listening to anything
+    if let Some(client) = removed.filter(|x| x.auth) {
+        let still_connected = state
+            .clients
+            .lock()
+            .await
+            .values()
+            .any(|x| x.auth && x.username_hash == client.username_hash);
+        if !still_connected {
+            clear_presence(&state, client.username_hash).await;
+        }
+    }
 }
 
 /*
@@ -287,6 +413,40 @@ macro_rules! send_to_clients {
     };
 }
 
+// send a text message to every authenticated connection of the given users
+pub(crate) async fn send_to_users(clients: &Clients, users: &[u64], msg: &str) {
+    for (_, value) in clients.lock().await.iter() {
+        if !value.auth || !users.contains(&value.username_hash) {
+            continue;
+        }
+        if let Some(sender) = &value.sender {
+            let _ = sender.send(Ok(Message::text(msg)));
+        }
+    }
+}
+
+/*
+ * Send an event about another user to every connection of a user, the message is the event name
+ * followed by json with the display name of the other user
+ *
+ * Example: FOLLOW_REQUEST {"user":"sean","timestamp":1653354713}
+ */
+async fn notify_user(state: &AppState, userhash: u64, event: &str, from: u64) {
+    let display_name = match state.storage.get_user_data(from).await {
+        Ok(Some(v)) => v.display_name,
+        _ => None,
+    };
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let msg = format!(
+        "{event} {}",
+        json!({ "user": display_name, "timestamp": timestamp })
+    );
+    send_to_users(&state.clients, &[userhash], &msg).await;
+}
+
 /*
 macro_rules! disconnect {
     ($val:expr, $uuid:expr) => {
@@ -313,8 +473,9 @@ macro_rules! disconnect {
  * then we can just echo the message, this *should* be secure since you can only send to usernames
  * that you are logged in under, but I could be very wrong
  */
-async fn handle_response<'a>(msg: &str, ws_client: &WsClient, clients: &Clients) {
-    if BLOCKED_LIST
+async fn handle_response(msg: &str, ws_client: &WsClient, state: &AppState) {
+    if state
+        .blocked_list
         .read()
         .unwrap()
         .list
@@ -322,13 +483,19 @@ async fn handle_response<'a>(msg: &str, ws_client: &WsClient, clients: &Clients)
     {
         return;
     }
-    RATE_LIMIT.lock().unwrap().add(ws_client.username_hash);
+    state
+        .rate_limit
+        .lock()
+        .unwrap()
+        .add(ws_client.username_hash);
     if let Some(v) = msg.find(' ') {
         let command = &msg[..v];
         let message = &msg[v..].trim_start();
         debug!("{command}%{message}");
+        METRICS.commands.inc(command);
+        let start = Instant::now();
         if CLIENT_COMMANDS.contains(&command) {
-            send_to_clients!(clients, ws_client, msg);
+            send_to_clients!(state.clients, ws_client, msg);
             return;
         }
         let args: Vec<&str> = message.split(' ').collect();
@@ -338,30 +505,94 @@ async fn handle_response<'a>(msg: &str, ws_client: &WsClient, clients: &Clients)
             // plaintext is considered the url
             "QUEUE" => {
                 // Send new song to download queue
-                let mut locked = SONG_MANAGER.write().await;
+                let mut locked = state.song_manager.write().await;
                 Some(String::from(match locked.request(message.to_string()) {
-                    Ok(_) => "AddedSong",
+                    Ok(_) => {
+                        let _ = state
+                            .storage
+                            .log_activity(
+                                ws_client.username_hash,
+                                Activity::QueuedSong {
+                                    url: message.to_string(),
+                                },
+                            )
+                            .await;
+                        "AddedSong"
+                    }
                     Err(_) => "InvalidRequest",
                 }))
             }
-            // Follow a new user, updates two rows to show that you followed on their profile and
-            // to add to your followers list
+            // Follow a new user by their display name
+            //
+            // Private profiles have to accept the follow first, they are sent a FOLLOW_REQUEST
+            // with your display name and you get REQUESTED back
             "FOLLOW" => match args.len() {
                 1 => {
-                    match acquire_db!(DB)
+                    match state
+                        .storage
                         .follow_user(ws_client.username_hash, args[0])
                         .await
                     {
-                        Ok(_) => Some(String::from("OK")),
+                        Ok(FollowStatus::Following) => Some(String::from("OK")),
+                        Ok(FollowStatus::Requested { target, new }) => {
+                            if new {
+                                notify_user(
+                                    state,
+                                    target,
+                                    "FOLLOW_REQUEST",
+                                    ws_client.username_hash,
+                                )
+                                .await;
+                            }
+                            Some(String::from("REQUESTED"))
+                        }
                         Err(_) => None,
                     }
                 }
                 _ => None,
             },
-            // Does the inverse of follow, again updating two rows
+            // accept or reject a pending follow request by the display name of the requester,
+            // they are sent FOLLOW_ACCEPTED with your display name if accepted
+            "ACCEPT_FOLLOW" => match args.len() {
+                1 => {
+                    match state
+                        .storage
+                        .accept_follow(ws_client.username_hash, args[0])
+                        .await
+                    {
+                        Ok(requester) => {
+                            notify_user(
+                                state,
+                                requester,
+                                "FOLLOW_ACCEPTED",
+                                ws_client.username_hash,
+                            )
+                            .await;
+                            Some(String::from("OK"))
+                        }
+                        Err(_) => Some(String::from("NoFollowRequest")),
+                    }
+                }
+                _ => None,
+            },
+            "REJECT_FOLLOW" => match args.len() {
+                1 => {
+                    match state
+                        .storage
+                        .reject_follow(ws_client.username_hash, args[0])
+                        .await
+                    {
+                        Ok(()) => Some(String::from("OK")),
+                        Err(_) => Some(String::from("NoFollowRequest")),
+                    }
+                }
+                _ => None,
+            },
+            // Does the inverse of follow
             "UNFOLLOW" => match args.len() {
                 1 => {
-                    match acquire_db!(DB)
+                    match state
+                        .storage
                         .unfollow_user(ws_client.username_hash, args[0])
                         .await
                     {
@@ -371,17 +602,150 @@ async fn handle_response<'a>(msg: &str, ws_client: &WsClient, clients: &Clients)
                 }
                 _ => None,
             },
+            // Block a user by their display name, this removes follows in both directions and
+            // hides your profile, activity and presence from them
+            "BLOCK" | "UNBLOCK" | "MUTE" | "UNMUTE" => match args.len() {
+                1 => {
+                    let db = &state.storage;
+                    let result = match command {
+                        "BLOCK" => db.block_user(ws_client.username_hash, args[0]).await,
+                        "UNBLOCK" => db.unblock_user(ws_client.username_hash, args[0]).await,
+                        // muting only hides the activity and presence of a user from you
+                        "MUTE" => db.mute_user(ws_client.username_hash, args[0]).await,
+                        _ => db.unmute_user(ws_client.username_hash, args[0]).await,
+                    };
+                    match result {
+                        Ok(_) => Some(String::from("OK")),
+                        Err(_) => Some(String::from("CouldNotBeFound")),
+                    }
+                }
+                _ => None,
+            },
+            "LIST_BLOCKED" | "LIST_MUTED" => {
+                let db = &state.storage;
+                let result = match command {
+                    "LIST_BLOCKED" => db.list_blocked(ws_client.username_hash).await,
+                    _ => db.list_muted(ws_client.username_hash).await,
+                };
+                match result {
+                    Ok(v) => Some(json!(v).to_string()),
+                    Err(_) => None,
+                }
+            }
+            "CHANGE_PASSWORD" => match args.len() {
+                2 => match state
+                    .storage
+                    .change_password(ws_client.username_hash, args[0], args[1])
+                    .await
+                {
+                    Ok(()) => Some(String::from("OK")),
+                    Err(e) => Some(e.to_string()),
+                },
+                _ => None,
+            },
+            // every connection of the user is moved over to the new username hash, so clients don't
+            // have to log in again
+            "CHANGE_USERNAME" => match args.len() {
+                2 => match state
+                    .storage
+                    .change_username(ws_client.username_hash, args[0], args[1])
+                    .await
+                {
+                    Ok(new_hash) => {
+                        move_user_images(state.files.as_ref(), ws_client.username_hash, new_hash)
+                            .await;
+                        state
+                            .clients
+                            .lock()
+                            .await
+                            .values_mut()
+                            .filter(|x| x.auth && x.username_hash == ws_client.username_hash)
+                            .for_each(|x| x.username_hash = new_hash);
+                        let mut presence = state.presence.write().await;
+                        if let Some(v) = presence.remove(&ws_client.username_hash) {
+                            presence.insert(new_hash, v);
+                        }
+                        Some(String::from("OK"))
+                    }
+                    Err(e) => Some(e.to_string()),
+                },
+                _ => None,
+            },
+            // deletes everything belonging to the account and closes all of its connections
+            "DELETE_ACCOUNT" => match args.len() {
+                1 => match state
+                    .storage
+                    .delete_account(ws_client.username_hash, args[0])
+                    .await
+                {
+                    Ok(()) => {
+                        remove_user_images(state.files.as_ref(), ws_client.username_hash).await;
+                        kick(state, ws_client.username_hash).await;
+                        return;
+                    }
+                    Err(e) => Some(e.to_string()),
+                },
+                _ => None,
+            },
+            // responds with a one time link to download everything stored about the user
+            "EXPORT_MY_DATA" => match export_user_data(state, ws_client.username_hash).await {
+                Ok(v) => Some(v),
+                Err(e) => {
+                    error!("failed to export user data: {e}");
+                    Some(String::from("FailedToExport"))
+                }
+            },
+            // create an invite code, uses defaults to 1 and expires_in is in seconds, invites that
+            // are not given an expiry never expire
+            "CREATE_INVITE" => {
+                let max_uses = match args.first() {
+                    Some(&"") | None => Ok(DEFAULT_INVITE_USES),
+                    Some(v) => v.parse::<i32>(),
+                };
+                let expires_in = match args.get(1) {
+                    Some(v) => v.parse::<u64>().map(Some),
+                    None => Ok(None),
+                };
+                match (max_uses, expires_in) {
+                    (Ok(uses), Ok(expires_in)) if (1..=MAX_INVITE_USES).contains(&uses) => {
+                        match state
+                            .storage
+                            .create_invite(ws_client.username_hash, uses, expires_in)
+                            .await
+                        {
+                            Ok(v) => Some(v),
+                            Err(e) => Some(e.to_string()),
+                        }
+                    }
+                    _ => Some(String::from("InvalidMessage")),
+                }
+            }
+            "LIST_INVITES" => match state.storage.list_invites(ws_client.username_hash).await {
+                Ok(v) => Some(json!(v).to_string()),
+                Err(_) => None,
+            },
+            "REVOKE_INVITE" => match args.len() {
+                1 => match state
+                    .storage
+                    .revoke_invite(ws_client.username_hash, args[0])
+                    .await
+                {
+                    Ok(()) => Some(String::from("OK")),
+                    Err(e) => Some(e.to_string()),
+                },
+                _ => None,
+            },
             // return plain text formatted song url download queue
             "QUEUE_LIST" => {
-                let locked = SONG_MANAGER.read().await;
+                let locked = state.song_manager.read().await;
                 Some(locked.list_queue())
             }
-            // return all new songs in json format that were inputed after the given timestamp, to
-            // completely resync you would send 0 as the timestamp since all songs that are
-            // actually downloaded are sent
+            // return all new songs in json format that were inputed or edited after the given
+            // timestamp, to completely resync you would send 0 as the timestamp since all songs
+            // that are actually downloaded are sent
             "SYNC_LIB" => match args.len() {
                 1 => match args[0].parse::<u64>() {
-                    Ok(v) => match acquire_db!(DB).sync_library(v).await {
+                    Ok(v) => match state.storage.sync_library(v).await {
                         Ok(v) => Some(v),
                         Err(e) => Some(e.to_string()),
                     },
@@ -389,11 +753,44 @@ async fn handle_response<'a>(msg: &str, ws_client: &WsClient, clients: &Clients)
                 },
                 _ => None,
             },
+            // rename a song for yourself in every playlist it is in, spaces are sent as % like in
+            // playlist descriptions. Without a name the song goes back to its title
+            "RENAME_SONG" => match args.len() {
+                1 | 2 => match args[0].parse::<u64>() {
+                    Ok(v) => {
+                        let name = args
+                            .get(1)
+                            .map(|x| x.replace('%', " ").trim().to_string())
+                            .filter(|x| !x.is_empty());
+                        match name {
+                            Some(n) if n.chars().count() > MAX_METADATA_LENGTH => {
+                                Some(String::from("InvalidName"))
+                            }
+                            _ => match state
+                                .storage
+                                .rename_song(ws_client.username_hash, v, name.as_deref())
+                                .await
+                            {
+                                Ok(()) => Some(String::from("OK")),
+                                Err(e) => Some(e.to_string()),
+                            },
+                        }
+                    }
+                    Err(_) => Some(String::from("InvalidHash")),
+                },
+                _ => None,
+            },
+            // every song renamed with RENAME_SONG as [{"id": "...", "name": "..."}]
+            "SONG_OVERRIDES" => match state.storage.song_overrides(ws_client.username_hash).await {
+                Ok(v) => Some(json!(v).to_string()),
+                Err(_) => None,
+            },
             // return the hash/id of a song from the song_name, uploader on yt, and the release
             // date as a string
             "FIND_SONG" => match args.len() {
                 3 => {
-                    match acquire_db!(DB)
+                    match state
+                        .storage
                         .find_song_from_details(args[0], args[1], args[2])
                         .await
                     {
@@ -407,11 +804,25 @@ async fn handle_response<'a>(msg: &str, ws_client: &WsClient, clients: &Clients)
             // release date as a string
             "REMOVE_SONG" => match args.len() {
                 4 => {
-                    match acquire_db!(DB)
-                        .remove_song(ws_client.username_hash, args[0], args[1], args[2], args[3])
+                    let name = args[0].replace('%', " ");
+                    let cover =
+                        cover_songs(state.storage.as_ref(), ws_client.username_hash, &name).await;
+                    match state
+                        .storage
+                        .remove_song(ws_client.username_hash, &name, args[1], args[2], args[3])
                         .await
                     {
-                        Ok(_) => Some(String::from("OK")),
+                        Ok(_) => {
+                            update_playlist_cover(
+                                state.storage.as_ref(),
+                                state.files.as_ref(),
+                                ws_client.username_hash,
+                                &name,
+                                cover,
+                            )
+                            .await;
+                            Some(String::from("OK"))
+                        }
                         Err(_) => Some(String::from("CouldNotBeFound")),
                     }
                 }
@@ -422,17 +833,25 @@ async fn handle_response<'a>(msg: &str, ws_client: &WsClient, clients: &Clients)
             // Internally this just hashes them to find the id
             "ADD_SONG" => match args.len() {
                 4 => {
-                    match acquire_db!(DB)
-                        .append_song(
-                            ws_client.username_hash,
-                            &args[0].replace('%', " "),
-                            args[1],
-                            args[2],
-                            args[3],
-                        )
+                    let name = args[0].replace('%', " ");
+                    let cover =
+                        cover_songs(state.storage.as_ref(), ws_client.username_hash, &name).await;
+                    match state
+                        .storage
+                        .append_song(ws_client.username_hash, &name, args[1], args[2], args[3])
                         .await
                     {
-                        Ok(_) => Some(String::from("OK")),
+                        Ok(_) => {
+                            update_playlist_cover(
+                                state.storage.as_ref(),
+                                state.files.as_ref(),
+                                ws_client.username_hash,
+                                &name,
+                                cover,
+                            )
+                            .await;
+                            Some(String::from("OK"))
+                        }
                         Err(_) => Some(String::from("CouldNotFindSong")),
                     }
                 }
@@ -443,11 +862,26 @@ async fn handle_response<'a>(msg: &str, ws_client: &WsClient, clients: &Clients)
             "ADD_SONG_HASH" => match args.len() {
                 3 => match args[1].parse::<u64>() {
                     Ok(v) => {
-                        match acquire_db!(DB)
-                            .append_song_from_hash(ws_client.username_hash, args[0], v)
+                        let name = args[0].replace('%', " ");
+                        let cover =
+                            cover_songs(state.storage.as_ref(), ws_client.username_hash, &name)
+                                .await;
+                        match state
+                            .storage
+                            .append_song_from_hash(ws_client.username_hash, &name, v)
                             .await
                         {
-                            Ok(()) => Some(String::from("OK")),
+                            Ok(()) => {
+                                update_playlist_cover(
+                                    state.storage.as_ref(),
+                                    state.files.as_ref(),
+                                    ws_client.username_hash,
+                                    &name,
+                                    cover,
+                                )
+                                .await;
+                                Some(String::from("OK"))
+                            }
                             Err(_) => Some(String::from("InvalidHash")),
                         }
                     }
@@ -460,11 +894,26 @@ async fn handle_response<'a>(msg: &str, ws_client: &WsClient, clients: &Clients)
             "REMOVE_SONG_HASH" => match args.len() {
                 3 => match args[1].parse::<u64>() {
                     Ok(v) => {
-                        match acquire_db!(DB)
-                            .append_song_from_hash(ws_client.username_hash, args[0], v)
+                        let name = args[0].replace('%', " ");
+                        let cover =
+                            cover_songs(state.storage.as_ref(), ws_client.username_hash, &name)
+                                .await;
+                        match state
+                            .storage
+                            .remove_song_from_hash(ws_client.username_hash, &name, v)
                             .await
                         {
-                            Ok(()) => Some(String::from("OK")),
+                            Ok(()) => {
+                                update_playlist_cover(
+                                    state.storage.as_ref(),
+                                    state.files.as_ref(),
+                                    ws_client.username_hash,
+                                    &name,
+                                    cover,
+                                )
+                                .await;
+                                Some(String::from("OK"))
+                            }
                             Err(_) => Some(String::from("InvalidHash")),
                         }
                     }
@@ -477,7 +926,8 @@ async fn handle_response<'a>(msg: &str, ws_client: &WsClient, clients: &Clients)
             // ^ not caps sensitive
             "CREATE_PLAYLIST" => match args.len() {
                 2 => {
-                    match acquire_db!(DB)
+                    match state
+                        .storage
                         .create_playlist(
                             ws_client.username_hash,
                             &args[0].replace('%', " "),
@@ -485,7 +935,17 @@ async fn handle_response<'a>(msg: &str, ws_client: &WsClient, clients: &Clients)
                         )
                         .await
                     {
-                        Ok(()) => Some(String::from("OK")),
+                        Ok(created) => {
+                            if created {
+                                let _ = default_playlist_image(
+                                    state.files.as_ref(),
+                                    ws_client.username_hash,
+                                    &args[0].replace('%', " "),
+                                )
+                                .await;
+                            }
+                            Some(String::from("OK"))
+                        }
                         Err(_) => Some(String::from("InvalidHash")),
                     }
                 }
@@ -500,7 +960,8 @@ async fn handle_response<'a>(msg: &str, ws_client: &WsClient, clients: &Clients)
                         Err(_) => return,
                     };
 
-                    match acquire_db!(DB)
+                    match state
+                        .storage
                         .update_playlist(
                             ws_client.username_hash,
                             &args[0].replace('%', " "),
@@ -518,7 +979,8 @@ async fn handle_response<'a>(msg: &str, ws_client: &WsClient, clients: &Clients)
             "REMOVE_PLAYLIST" => match args.len() {
                 1 => {
                     // apply delim change here too, create macro or function for it
-                    match acquire_db!(DB)
+                    match state
+                        .storage
                         .delete_playlist(ws_client.username_hash, &args[0].replace('%', " "))
                         .await
                     {
@@ -534,38 +996,52 @@ async fn handle_response<'a>(msg: &str, ws_client: &WsClient, clients: &Clients)
             // the resolution is checked before saving
             "SET_PLAYLIST_IMAGE" => match args.len() {
                 2 => {
-                    match acquire_db!(DB)
-                        .set_playlist_image(ws_client.username_hash, args[0], args[1])
-                        .await
+                    let name = args[0].replace('%', " ");
+                    match save_playlist_image(
+                        state.files.as_ref(),
+                        ws_client.username_hash,
+                        &name,
+                        args[1].to_string(),
+                    )
+                    .await
                     {
-                        Ok(()) => Some(String::from("OK")),
+                        Ok(()) => {
+                            let _ = state
+                                .storage
+                                .set_playlist_custom_image(ws_client.username_hash, &name, true)
+                                .await;
+                            Some(String::from("OK"))
+                        }
                         Err(_) => Some(String::from("InvalidBase64")),
                     }
                 }
                 _ => None,
             },
-            // remove the custom playlist image, internally generates a new random art that is used
-            // for the playlist
+            // remove the custom playlist image, internally makes a new cover from the artwork of
+            // the songs in the playlist, or random art if it's empty
             //
             // this is intended to always return an image for either profile picture of playlist
             // art
             "REMOVE_PLAYLIST_IMAGE" => match args.len() {
-                1 => {
-                    match acquire_db!(DB)
-                        .remove_playlist_image(ws_client.username_hash, args[0])
-                        .await
-                    {
-                        Ok(()) => Some(String::from("OK")),
-                        Err(_) => None,
-                    }
-                }
+                1 => match remove_playlist_image(
+                    state.storage.as_ref(),
+                    state.files.as_ref(),
+                    ws_client.username_hash,
+                    &args[0].replace('%', " "),
+                )
+                .await
+                {
+                    Ok(()) => Some(String::from("OK")),
+                    Err(_) => None,
+                },
                 _ => None,
             },
             // Set the playlist description, to get around the fact that we cannot have spaces in
             // the argument splitting we have a % as a placeholder for space
             "SET_PLAYLIST_DESCRIPTION" => match args.len() {
                 2 => {
-                    match acquire_db!(DB)
+                    match state
+                        .storage
                         .set_playlist_description(
                             ws_client.username_hash,
                             args[0],
@@ -581,7 +1057,8 @@ async fn handle_response<'a>(msg: &str, ws_client: &WsClient, clients: &Clients)
             },
             "RENAME_PLAYLIST" => match args.len() {
                 2 => {
-                    match acquire_db!(DB)
+                    match state
+                        .storage
                         .rename_playlist(ws_client.username_hash, args[0], args[1])
                         .await
                     {
@@ -591,12 +1068,18 @@ async fn handle_response<'a>(msg: &str, ws_client: &WsClient, clients: &Clients)
                 }
                 _ => None,
             },
-            "RESET_PFP" => match default_pfp(ws_client.username_hash).await {
+            "RESET_PFP" => match default_pfp(state.files.as_ref(), ws_client.username_hash).await {
                 Ok(_) => Some(String::from("OK")),
                 Err(_) => None,
             },
             "SET_PFP" => match args.len() {
-                1 => match save_pfp(ws_client.username_hash, args[0].to_string()).await {
+                1 => match save_pfp(
+                    state.files.as_ref(),
+                    ws_client.username_hash,
+                    args[0].to_string(),
+                )
+                .await
+                {
                     Ok(_) => Some(String::from("OK")),
                     Err(_) => None,
                 },
@@ -604,7 +1087,7 @@ async fn handle_response<'a>(msg: &str, ws_client: &WsClient, clients: &Clients)
             },
             "REQUEST_USERDATA" => {
                 // TODO fix
-                match acquire_db!(DB).get_user_data(ws_client.username_hash).await {
+                match state.storage.get_user_data(ws_client.username_hash).await {
                     Ok(v) => {
                         let data = json!(&v).to_string();
                         Some(data)
@@ -612,35 +1095,194 @@ async fn handle_response<'a>(msg: &str, ws_client: &WsClient, clients: &Clients)
                     Err(_) => None,
                 }
             }
-            "REQUEST_PROFILE" => {
-                // this is awful but I'll fix it later
-                match acquire_db!(DB).userhash_from_username(args[0]).await {
-                    Ok(v) => match acquire_db!(DB).get_user_data(v.to_u64().unwrap()).await {
-                        Ok(v) => match v {
-                            Some(v) => match v.public_profile {
-                                Some(true) => Some(serde_json::to_string(&v).unwrap()),
-                                _ => None,
-                            },
-                            None => None,
-                        },
-                        Err(_) => None,
-                    },
-                    Err(_) => None,
+            // public profile and playlists of another user by their display name
+            "REQUEST_PROFILE" => match state
+                .storage
+                .request_profile(ws_client.username_hash, args[0])
+                .await
+            {
+                Ok(Some((userhash, profile))) => {
+                    let mut profile = profile.seen_by(userhash, ws_client.username_hash);
+                    // show what they are listening to right now if they share it
+                    if let Some(song) = shared_song(state, userhash).await {
+                        profile.userdata.now_playing = Some(song);
+                    }
+                    Some(json!(profile).to_string())
                 }
-            }
+                _ => None,
+            },
             "REQUEST_PLAYLIST" => {
-                match acquire_db!(DB).request_playlist(ws_client.username_hash, &args[0].replace('%', "")).await {
+                match state
+                    .storage
+                    .request_playlist(ws_client.username_hash, &args[0].replace('%', ""))
+                    .await
+                {
                     Ok(v) => Some(json!(v).to_string()),
-                    Err(_) => None
+                    Err(_) => None,
+                }
+            }
+            // update what is currently being listened to and the position in the song in
+            // seconds, followers are sent the update if share_status is enabled
+            //
+            // a new song (or going back in the current one) is also added to the play history,
+            // this is also done when a song is streamed with a stream token
+            "NOW_PLAYING" => match args.len() {
+                1 | 2 => {
+                    let position = args.get(1).map_or(Ok(0), |x| x.parse::<u64>());
+                    match (args[0].parse::<u64>(), position) {
+                        (Ok(song), Ok(position)) => {
+                            let db = &state.storage;
+                            match db.find_song_from_hash(song).await {
+                                Ok(_) => {
+                                    if let Ok(true) = update_presence(
+                                        state,
+                                        ws_client.username_hash,
+                                        song,
+                                        position,
+                                    )
+                                    .await
+                                    {
+                                        let _ = db.record_play(ws_client.username_hash, song).await;
+                                    }
+                                    Some(String::from("OK"))
+                                }
+                                Err(_) => Some(String::from("InvalidHash")),
+                            }
+                        }
+                        _ => Some(String::from("ExpectedHash")),
+                    }
+                }
+                _ => None,
+            },
+            // paginated activity of the users you follow, send the cursor from the previous page
+            // to get the next one
+            "FEED" => {
+                let cursor = match args[0] {
+                    "" => Ok(None),
+                    v => v.parse::<i64>().map(Some),
+                };
+                match cursor {
+                    Ok(v) => match state.storage.feed(ws_client.username_hash, v).await {
+                        Ok(v) => Some(json!(v).to_string()),
+                        Err(_) => Some(String::from("FailedToFetchFeed")),
+                    },
+                    Err(_) => Some(String::from("InvalidCursor")),
                 }
             }
+            // what the users you follow are listening to right now
+            "LIST_PRESENCE" => match state
+                .storage
+                .visible_following(ws_client.username_hash)
+                .await
+            {
+                Ok(v) => Some(json!(list_presence(state, &v).await).to_string()),
+                Err(_) => None,
+            },
+            // paginated list of who follows you or who you follow, send the cursor from the
+            // previous page to get the next one
+            "LIST_FOLLOWERS" | "LIST_FOLLOWING" | "LIST_FOLLOW_REQUESTS" => {
+                let cursor = match args[0] {
+                    "" => Ok(0),
+                    v => v.parse::<i64>(),
+                };
+                match cursor {
+                    Ok(cursor) if cursor >= 0 => {
+                        let db = &state.storage;
+                        let result = match command {
+                            "LIST_FOLLOWERS" => {
+                                db.list_followers(ws_client.username_hash, cursor).await
+                            }
+                            "LIST_FOLLOWING" => {
+                                db.list_following(ws_client.username_hash, cursor).await
+                            }
+                            _ => {
+                                db.list_follow_requests(ws_client.username_hash, cursor)
+                                    .await
+                            }
+                        };
+                        match result {
+                            Ok(v) => Some(json!(v).to_string()),
+                            Err(_) => Some(String::from("FailedToFetchFollows")),
+                        }
+                    }
+                    _ => Some(String::from("InvalidCursor")),
+                }
+            }
+            // the stream token is added to song requests as ?token= so that streams are counted
+            // towards the play history of the user, it is only valid while this connection is
+            // open
+            "STREAM_TOKEN" => Some(ws_client.stream_token.clone()),
+            // most played songs or artists between two timestamps, optionally with a limit on how
+            // many are returned
+            "TOP_SONGS" | "TOP_ARTISTS" => match args.len() {
+                2 | 3 => {
+                    let range = (args[0].parse::<u64>(), args[1].parse::<u64>());
+                    let limit = match args.get(2) {
+                        Some(v) => v.parse::<i64>().ok(),
+                        None => Some(DEFAULT_TOP_LIMIT),
+                    };
+                    match (range, limit) {
+                        ((Ok(from), Ok(to)), Some(limit)) => {
+                            let db = &state.storage;
+                            let result = match command {
+                                "TOP_SONGS" => db
+                                    .top_songs(ws_client.username_hash, from, to, limit)
+                                    .await
+                                    .map(|v| json!(v).to_string()),
+                                _ => db
+                                    .top_artists(ws_client.username_hash, from, to, limit)
+                                    .await
+                                    .map(|v| json!(v).to_string()),
+                            };
+                            match result {
+                                Ok(v) => Some(v),
+                                Err(_) => Some(String::from("FailedToFetchStats")),
+                            }
+                        }
+                        _ => Some(String::from("InvalidMessage")),
+                    }
+                }
+                _ => None,
+            },
+            // total time listened between two timestamps
+            "LISTEN_TIME" => match args.len() {
+                2 => match (args[0].parse::<u64>(), args[1].parse::<u64>()) {
+                    (Ok(from), Ok(to)) => {
+                        match state
+                            .storage
+                            .listen_time(ws_client.username_hash, from, to)
+                            .await
+                        {
+                            Ok(v) => Some(json!(v).to_string()),
+                            Err(_) => Some(String::from("FailedToFetchStats")),
+                        }
+                    }
+                    _ => Some(String::from("InvalidMessage")),
+                },
+                _ => None,
+            },
+            "YEAR_IN_REVIEW" => match args.len() {
+                1 => match args[0].parse::<i64>() {
+                    Ok(v) => match state
+                        .storage
+                        .year_in_review(ws_client.username_hash, v)
+                        .await
+                    {
+                        Ok(v) => Some(json!(v).to_string()),
+                        Err(_) => Some(String::from("InvalidYear")),
+                    },
+                    Err(_) => Some(String::from("InvalidYear")),
+                },
+                _ => None,
+            },
             "UPDATE_USERDATA" => match args.len() {
                 3.. => {
                     let data: UserData = match serde_json::from_str(message) {
                         Ok(v) => v,
                         Err(_) => return,
                     };
-                    match acquire_db!(DB)
+                    match state
+                        .storage
                         .set_userdata(ws_client.username_hash, data)
                         .await
                     {
@@ -650,8 +1292,10 @@ async fn handle_response<'a>(msg: &str, ws_client: &WsClient, clients: &Clients)
                 }
                 _ => None,
             },
+            c if ADMIN_COMMANDS.contains(&c) => handle_admin(c, &args, ws_client, state).await,
             _ => None,
         };
+        METRICS.command_duration.observe(command, start.elapsed());
         if let Some(data_out) = response {
             if let Some(v) = &ws_client.sender {
                 let _ = v.send(Ok(Message::text(data_out)));
@@ -660,18 +1304,67 @@ async fn handle_response<'a>(msg: &str, ws_client: &WsClient, clients: &Clients)
     }
 }
 
-fn with_clients(clients: Clients) -> impl Filter<Extract = (Clients,), Error = Infallible> + Clone {
-    warp::any().map(move || clients.clone())
+// size of the music served before compression, range requests only count the range sent
+fn count_streamed_bytes(response: warp::reply::Response) -> warp::reply::Response {
+    if let Some(v) = response
+        .headers()
+        .get(warp::http::header::CONTENT_LENGTH)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.parse::<u64>().ok())
+    {
+        METRICS.streamed_bytes.add(v);
+    }
+    response
+}
+
+fn with_state(state: AppState) -> impl Filter<Extract = (AppState,), Error = Infallible> + Clone {
+    warp::any().map(move || state.clone())
 }
 
 /*
- * Start logger, check env arguments, create a new client map
+ * Songs requested with ?token= set to the stream token of an authenticated connection are added to
+ * the play history of that user, requests without a token are served as normal
+ */
+fn record_stream(state: AppState) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::path::peek()
+        .and(
+            warp::query::<HashMap<String, String>>()
+                .or(warp::any().map(HashMap::new))
+                .unify(),
+        )
+        .and(with_state(state))
+        .and_then(
+            |path: warp::path::Peek, query: HashMap<String, String>, state: AppState| async move {
+                let song = path.as_str().parse::<u64>();
+                if let (Ok(song), Some(token)) = (song, query.get("token")) {
+                    let username_hash = state
+                        .clients
+                        .lock()
+                        .await
+                        .values()
+                        .find(|x| x.auth && &x.stream_token == token)
+                        .map(|x| x.username_hash);
+                    if let Some(v) = username_hash {
+                        tokio::spawn(async move {
+                            let _ = state.storage.record_play(v, song).await;
+                        });
+                    }
+                }
+                Ok::<_, Rejection>(())
+            },
+        )
+        .untuple_one()
+}
+
+/*
+ * Every route the server answers, run serves these and the integration tests drive them with
+ * warp::test
  *
  * The route "seanify" (example: 127.0.0.1:3030/seanify) is the route that is connected to access
  * the main service
  *
- * The route with the value of INSTANCE_KEY is used to store music download, it points to the cache
- * directory
+ * The route with the value of INSTANCE_KEY is used to store music download, it serves the songs
+ * of the file store (the cache directory unless the files are in S3, see files.rs)
  *
  * If I want to download a song, I'll send the server a SEARCH request and it will return a hash of
  * the song (this can also be computed client side), visiting the websocket at this route will
@@ -680,45 +1373,140 @@ fn with_clients(clients: Clients) -> impl Filter<Extract = (Clients,), Error = I
  * For example, if a song has a hash of 12 and my instance key is songs then it can be downloaded
  * from 127.0.0.1:3030/songs/12
  */
+pub fn routes(state: AppState) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let ws_route = warp::path("seanify")
+        .and(warp::ws())
+        .and(with_state(state.clone()))
+        .and_then(ws_handler);
+
+    let routes = ws_route.with(warp::cors().allow_any_origin());
+
+    let music = warp::path(config().instance_key.clone())
+        .and(record_stream(state.clone()))
+        .and(serve_files(Bucket::Songs, state.clone()))
+        .map(count_streamed_bytes)
+        .with(warp::compression::gzip());
+
+    let cdn = warp::path(format!("{}-cdn", config().instance_key))
+        .and(serve_files(Bucket::Images, state.clone()))
+        .with(warp::compression::gzip());
+
+    // the same songs as an attachment with a filename, see tags.rs
+    let download = warp::path(format!("{}-download", config().instance_key))
+        .and(download_route(state.clone()))
+        .map(count_streamed_bytes);
+
+    // unfortunate conversions has to be done here, might be worth fixing in the future
+    // instance health for operators, see dashboard.rs and metrics.rs
+    let admin = dashboard(state.clone()).or(metrics_route(state.clone()));
 
-//TODO add arg handling
-pub async fn run<S: AsRef<str>>(_args: &[S]) -> anyhow::Result<()> {
+    routes
+        .or(music)
+        .or(cdn)
+        .or(download)
+        .or(admin)
+        .or(export_route(state))
+}
+
+/*
+ * Start logger, load the config, start the background loops and serve the routes above
+ *
+ * The storage is picked from the database_url
+ *   - sqlite: a single sqlite file (ex: sqlite://data/seanify.db), enough for a small instance
+ *   - memory: keeps everything in memory, which is only meant for trying the server out and only
+ *     there when built with the test-util feature
+ *   - anything else is a postgres url
+ */
+pub async fn run<S: AsRef<str>>(args: &[S]) -> anyhow::Result<()> {
     pretty_env_logger::init();
-    check_env_args().unwrap();
+    // flags are taken out here, what's left is the subcommand and its args
+    let args = load_config(args)?;
+    log_config();
+
+    // subcommands that run and exit instead of starting the server
+    match args.first().map(|x| x.as_ref()) {
+        Some("migrate") => return migrate(&args[1..]).await,
+        Some("backup") => return backup_command(&args[1..]).await,
+        Some("restore") => return restore_command(&args[1..]).await,
+        _ => {}
+    }
+
+    let url = &config().database_url;
+    let storage: Arc<dyn Storage> = match url.split(':').next() {
+        #[cfg(feature = "test-util")]
+        Some("memory") => {
+            warn!("Using in memory storage, nothing is kept once the server stops");
+            Arc::new(MemoryStorage::default())
+        }
+        #[cfg(not(feature = "test-util"))]
+        Some("memory") => {
+            return Err(anyhow::anyhow!(
+                "memory:// storage is only built with the test-util feature"
+            ))
+        }
+        Some("sqlite") => {
+            if config().backup_dir.is_some() {
+                warn!("backup_dir only works with postgres, no backups will be taken");
+            }
+            Arc::new(SqliteStorage::new(url).await?)
+        }
+        _ => {
+            let db = Database::new().await?;
+            // optional scheduled backups, see backup.rs
+            if let Some(dir) = &config().backup_dir {
+                tokio::spawn(scheduled_backups(db.database.clone(), dir.clone()));
+            }
+            Arc::new(db)
+        }
+    };
+    let state = AppState::new(storage, file_store(), Arc::new(YtDlp));
 
     // go through queue every x amount of seconds to attempt to download the first song
-    // 5 second default
+    let queue = state.clone();
     tokio::spawn(async move {
         loop {
-            tokio::time::sleep(Duration::from_secs(
-                env_num_or_default!("QUEUE_COOLDOWN", DEFAULT_QUEUE_COOLDOWN).into(),
-            ))
-            .await;
+            tokio::time::sleep(Duration::from_secs(config().queue_cooldown.into())).await;
 
-            tokio::spawn(async move {
-                let mut locked = SONG_MANAGER.write().await;
-                let _ = locked.cycle_queue().await;
-            });
+            let queue = queue.clone();
+            tokio::spawn(async move { queue.cycle_queue().await });
         }
     });
 
+    // artwork for songs downloaded before it was stored locally, see artwork.rs
+    let artwork = state.clone();
+    tokio::spawn(async move {
+        cache_missing_artwork(artwork.storage.as_ref(), artwork.files.as_ref()).await;
+    });
+
+    // clean up the metadata of new songs, see enrich.rs
+    if !config().enrich_rules.is_empty() {
+        let enrich = state.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_secs(config().enrich_interval.into())).await;
+                enrich.enrich_songs().await;
+            }
+        });
+    }
+
+    let rate_limit = state.rate_limit.clone();
     tokio::spawn(async move {
         // ip blacklist cycle
         loop {
-            tokio::time::sleep(Duration::from_millis(env_num_or_default!(
-                "RATE_BLACKLIST_CYCLE_MS",
-                RATE_BLACKLIST_CYCLE_MS
-            ) as u64))
+            tokio::time::sleep(Duration::from_millis(
+                config().rate_blacklist_cycle_ms.into(),
+            ))
             .await;
-            let mut locked = RATE_LIMIT.lock().unwrap();
+            let mut locked = rate_limit.lock().unwrap();
             locked.cycle();
         }
     });
 
+    let blocked_list = state.blocked_list.clone();
     tokio::spawn(async move {
         loop {
             tokio::time::sleep(Duration::from_secs(1)).await;
-            let mut locked = BLOCKED_LIST.write().unwrap();
+            let mut locked = blocked_list.write().unwrap();
             for (_, v) in locked.list.iter_mut() {
                 *v -= 1;
             }
@@ -726,31 +1514,17 @@ pub async fn run<S: AsRef<str>>(_args: &[S]) -> anyhow::Result<()> {
         }
     });
 
-    let clients: Clients = Arc::new(Mutex::new(HashMap::new()));
-
-    let ws_route = warp::path("seanify")
-        .and(warp::ws())
-        .and(with_clients(clients.clone()))
-        .and_then(ws_handler);
-
-    let routes = ws_route.with(warp::cors().allow_any_origin());
-
-    let music = warp::path(INSTANCE_KEY.to_string())
-        .and(warp::fs::dir(CACHE_DIR.to_string()))
-        .with(warp::compression::gzip());
-
-    // TODO require check for cdndir
-    let cdn = warp::path(format!("{}-cdn", *INSTANCE_KEY))
-        .and(warp::fs::dir(env_fetch!("CDN_DIR")))
-        .with(warp::compression::gzip());
-
-    // unfortunate conversions has to be done here, might be worth fixing in the future
-    warp::serve(routes.or(music).or(cdn))
-        .run((
-            [127, 0, 0, 1],
-            env_num_or_default!("PORT", DEFAULT_PORT as u32) as u16,
-        ))
-        .await;
+    let routes = routes(state);
+    let addr = SocketAddr::new(config().bind, config().port);
+    match config().tls_cert.is_some() {
+        true => {
+            if let Some(port) = config().http_redirect_port {
+                tokio::spawn(redirect_to_https(SocketAddr::new(config().bind, port)));
+            }
+            serve_tls(routes, addr).await?;
+        }
+        false => warp::serve(routes).run(addr).await,
+    }
 
     Ok(())
 }
@@ -768,19 +1542,19 @@ struct BlockedList {
  * list
  *
  * Since the blockedlist needs to be read everytime there is a new connection we only store a
- * reference to it in this struct so we can avoid locking both the ratelimiter and blockedlist at
- * the same time
+ * shared reference to it in this struct so we can avoid locking both the ratelimiter and
+ * blockedlist at the same time
  *
  * Since the blocked list is going to be read a lot more often we keep it in an RwLock instead of a
  * mutex
  */
-struct RateLimiter<'a> {
+struct RateLimiter {
     username_list: VecDeque<u64>,
-    blocked_list: &'a Arc<std::sync::RwLock<BlockedList>>,
+    blocked_list: Arc<std::sync::RwLock<BlockedList>>,
 }
 
-impl<'a> RateLimiter<'a> {
-    pub fn new(blocked_list: &'a Arc<std::sync::RwLock<BlockedList>>) -> Self {
+impl RateLimiter {
+    pub fn new(blocked_list: Arc<std::sync::RwLock<BlockedList>>) -> Self {
         Self {
             username_list: VecDeque::with_capacity(1),
             blocked_list,
@@ -806,47 +1580,38 @@ impl<'a> RateLimiter<'a> {
         }
         for username in usernames.iter() {
             if let Some(v) = usernames.get(username.0) {
-                if v > &*MAX_RATELIMIT {
+                if *v > config().rate_max_count as usize {
                     let mut locked = self.blocked_list.write().unwrap();
                     // "**" lmao wtf
-                    locked.list.insert(**username.0, RATE_BAN_IN_SECONDS);
+                    if locked
+                        .list
+                        .insert(**username.0, config().rate_ban_in_seconds as usize)
+                        .is_none()
+                    {
+                        METRICS.rate_limit_bans.inc();
+                    }
                 }
             }
         }
     }
 }
 
-macro_rules! check_or_warn_env {
-    ($val:expr) => {
-        match env::var($val) {
-            Ok(v) => info!("{}: {v}", $val),
-            Err(_) => warn!("It is recommended to set {} in env", $val),
-        }
-    };
-}
-
 /*
- * Check the env variables for certain arguments, these are non essential and only emit a warning
- * at startup if not present
+ * Print the settings worth knowing about at startup, the config has already been checked so these
+ * are only reminders
  */
-fn check_env_args() -> anyhow::Result<()> {
-    dotenv::dotenv()?;
-
-    let uri = env_fetch!("DATABASE_URL");
-
-    info!("Database: {uri}");
-
-    let vars = [
-        "MAX_CONNECTIONS",
-        "MAX_TIMEOUT",
-        "MAX_CACHE_SIZE_MB",
-        "QUEUE_COOLDOWN",
-        "PORT",
-        "ADMIN_KEY",
-        "RATE_BAN_IN_SECONDS",
-        "RATE_MAX_COUNT",
-    ];
-
-    vars.iter().for_each(|x| check_or_warn_env!(x));
-    Ok(())
+fn log_config() {
+    let config = config();
+    info!("Database: {}", config.database_url);
+    info!("Listening on {}:{}", config.bind, config.port);
+    match (&config.s3_endpoint, &config.s3_bucket) {
+        (Some(endpoint), Some(bucket)) => info!("Files: {endpoint}/{bucket}"),
+        _ => info!("Cache: {} Cdn: {}", config.cache_dir, config.cdn_dir),
+    }
+    if config.admin_key.is_empty() {
+        warn!("It is recommended to set admin_key, admin accounts and the dashboard are disabled");
+    }
+    if config.max_cache_size_mb.is_none() {
+        warn!("It is recommended to set max_cache_size_mb");
+    }
 }
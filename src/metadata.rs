@@ -0,0 +1,118 @@
+use crate::Song;
+use anyhow::anyhow;
+use serde::{Deserialize, Deserializer, Serialize};
+
+/*
+ * Corrections to the metadata yt-dlp gives songs, titles like "Artist - Song (Lyrics)" with no
+ * artist or album are common
+ *
+ * Admins fix a song for everyone with EDIT_SONG <id> <json>, only the fields in the json are
+ * changed and null clears one (the title can't be cleared). The songs row is updated and the old
+ * and new values go to song_edits, which SONG_HISTORY <id> pages through. Edited songs are sent
+ * again by SYNC_LIB and keep their metadata if they are downloaded again
+ *
+ * Users can rename a song for themselves with RENAME_SONG <id> <name>, the name is kept in the
+ * custom_name of every entry of the song in their playlists so the song has to be in one of them.
+ * SONG_OVERRIDES lists the renamed songs of the user
+ */
+pub(crate) const SONG_HISTORY_PAGE_SIZE: i64 = 50;
+pub(crate) const MAX_METADATA_LENGTH: usize = 200;
+
+// the fields of a song that can be edited
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SongMetadata {
+    pub title: String,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub genre: Option<String>,
+}
+
+impl SongMetadata {
+    pub fn apply_to(self, song: &mut Song) {
+        song.title = Some(self.title);
+        song.artist = self.artist;
+        song.album = self.album;
+        song.album_artist = self.album_artist;
+        song.genre = self.genre;
+    }
+}
+
+// a field that is in the json, even if it's null
+fn present<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Option<String>>, D::Error> {
+    Option::deserialize(d).map(Some)
+}
+
+// the json of EDIT_SONG, None leaves the field as it is and Some(None) clears it
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct SongEdit {
+    #[serde(default, deserialize_with = "present")]
+    pub title: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub artist: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub album: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub album_artist: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub genre: Option<Option<String>>,
+}
+
+// trimmed, an empty value is the same as no value
+fn clean(value: &Option<String>) -> anyhow::Result<Option<String>> {
+    match value.as_deref().map(str::trim) {
+        Some(v) if v.chars().count() > MAX_METADATA_LENGTH => Err(anyhow!("InvalidEdit")),
+        Some(v) if !v.is_empty() => Ok(Some(v.to_string())),
+        _ => Ok(None),
+    }
+}
+
+impl SongEdit {
+    // the metadata of the song after the edit
+    pub fn apply(&self, song: &SongMetadata) -> anyhow::Result<SongMetadata> {
+        let mut edited = song.clone();
+        if let Some(v) = &self.title {
+            edited.title = clean(v)?.ok_or_else(|| anyhow!("InvalidEdit"))?;
+        }
+        for (field, value) in [
+            (&mut edited.artist, &self.artist),
+            (&mut edited.album, &self.album),
+            (&mut edited.album_artist, &self.album_artist),
+            (&mut edited.genre, &self.genre),
+        ] {
+            if let Some(v) = value {
+                *field = clean(v)?;
+            }
+        }
+        Ok(edited)
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct SongEditEntry {
+    pub id: String,
+    pub editor: Option<String>, // display name
+    pub old: SongMetadata,
+    pub new: SongMetadata,
+    pub timestamp: u64,
+}
+
+// a page of the edits of a song, newest first, the cursor is null on the last page
+#[derive(Serialize)]
+pub(crate) struct SongHistory {
+    pub items: Vec<SongEditEntry>,
+    pub cursor: Option<String>,
+}
+
+// the name a user gave a song with RENAME_SONG
+#[derive(Serialize)]
+pub(crate) struct SongOverride {
+    pub id: String,
+    pub name: String,
+}
+
+// the json stored in song_edits, rows that can't be read back come out empty
+pub(crate) fn metadata_from_json(json: &str) -> SongMetadata {
+    serde_json::from_str(json).unwrap_or_default()
+}
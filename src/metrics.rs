@@ -0,0 +1,304 @@
+use crate::{is_admin_token, with_state, AppState};
+use lazy_static::lazy_static;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+/*
+ * Metrics in the prometheus text format, served at /metrics with the same bearer token as the
+ * dashboard
+ *
+ * There are only a handful of counters and histograms so they are kept by hand here instead of
+ * pulling in the prometheus crate
+ */
+
+// the default buckets of the prometheus client libraries, in seconds
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+// commands are sent by clients so anything can show up, past this many labels the rest are
+// counted as "other" to keep the amount of series bounded
+const MAX_LABELS: usize = 128;
+
+#[derive(Default)]
+pub(crate) struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, v: u64) {
+        self.0.fetch_add(v, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct HistogramData {
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl HistogramData {
+    fn observe(&mut self, v: f64) {
+        for (i, bucket) in BUCKETS.iter().enumerate() {
+            if v <= *bucket {
+                self.buckets[i] += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += v;
+    }
+}
+
+// a set of counters or histograms split by a single label
+pub(crate) struct Labeled<T> {
+    label: &'static str,
+    values: Mutex<BTreeMap<String, T>>,
+}
+
+impl<T: Default> Labeled<T> {
+    fn new(label: &'static str) -> Self {
+        Self {
+            label,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn with(&self, value: &str, f: impl FnOnce(&mut T)) {
+        let mut locked = self.values.lock().unwrap();
+        let key = match locked.contains_key(value) || locked.len() < MAX_LABELS {
+            true => value,
+            false => "other",
+        };
+        f(locked.entry(key.to_string()).or_default());
+    }
+}
+
+impl Labeled<u64> {
+    pub fn inc(&self, value: &str) {
+        self.with(value, |x| *x += 1);
+    }
+}
+
+impl Labeled<HistogramData> {
+    pub fn observe(&self, value: &str, duration: Duration) {
+        self.with(value, |x| x.observe(duration.as_secs_f64()));
+    }
+}
+
+pub(crate) struct Metrics {
+    pub connections: Counter,
+    pub commands: Labeled<u64>,
+    pub command_duration: Labeled<HistogramData>,
+    pub auth_failures: Counter,
+    pub rate_limit_bans: Counter,
+    pub downloads: Labeled<u64>,
+    pub streamed_bytes: Counter,
+    db_query_duration: Mutex<HistogramData>,
+}
+
+lazy_static! {
+    pub(crate) static ref METRICS: Metrics = Metrics {
+        connections: Counter::default(),
+        commands: Labeled::new("command"),
+        command_duration: Labeled::new("command"),
+        auth_failures: Counter::default(),
+        rate_limit_bans: Counter::default(),
+        downloads: Labeled::new("result"),
+        streamed_bytes: Counter::default(),
+        db_query_duration: Mutex::new(HistogramData::default()),
+    };
+}
+
+impl Metrics {
+    pub fn observe_db_query(&self, duration: Duration) {
+        self.db_query_duration
+            .lock()
+            .unwrap()
+            .observe(duration.as_secs_f64());
+    }
+
+    fn render(&self, connected: usize) -> String {
+        let mut out = String::new();
+        write_counter(
+            &mut out,
+            "seanify_websocket_connections_total",
+            "Websocket connections opened",
+            self.connections.get(),
+        );
+        let _ = writeln!(
+            out,
+            "# HELP seanify_websocket_connections Websocket connections currently open"
+        );
+        let _ = writeln!(out, "# TYPE seanify_websocket_connections gauge");
+        let _ = writeln!(out, "seanify_websocket_connections {connected}");
+        write_labeled_counter(
+            &mut out,
+            "seanify_commands_total",
+            "Websocket commands handled",
+            &self.commands,
+        );
+        write_labeled_histogram(
+            &mut out,
+            "seanify_command_duration_seconds",
+            "Time taken to handle a websocket command",
+            &self.command_duration,
+        );
+        write_counter(
+            &mut out,
+            "seanify_auth_failures_total",
+            "Failed AUTH attempts",
+            self.auth_failures.get(),
+        );
+        write_counter(
+            &mut out,
+            "seanify_rate_limit_bans_total",
+            "Users added to the rate limit blocked list",
+            self.rate_limit_bans.get(),
+        );
+        write_labeled_counter(
+            &mut out,
+            "seanify_downloads_total",
+            "Songs downloaded from the queue",
+            &self.downloads,
+        );
+        write_counter(
+            &mut out,
+            "seanify_streamed_bytes_total",
+            "Bytes of music served",
+            self.streamed_bytes.get(),
+        );
+        let _ = writeln!(
+            out,
+            "# HELP seanify_db_query_duration_seconds Time taken by database queries"
+        );
+        let _ = writeln!(out, "# TYPE seanify_db_query_duration_seconds histogram");
+        write_histogram(
+            &mut out,
+            "seanify_db_query_duration_seconds",
+            "",
+            &self.db_query_duration.lock().unwrap(),
+        );
+        out
+    }
+}
+
+fn write_counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} counter");
+    let _ = writeln!(out, "{name} {value}");
+}
+
+fn write_labeled_counter(out: &mut String, name: &str, help: &str, counter: &Labeled<u64>) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} counter");
+    for (k, v) in counter.values.lock().unwrap().iter() {
+        let _ = writeln!(out, "{name}{{{}=\"{}\"}} {v}", counter.label, escape(k));
+    }
+}
+
+fn write_labeled_histogram(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    histogram: &Labeled<HistogramData>,
+) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} histogram");
+    for (k, v) in histogram.values.lock().unwrap().iter() {
+        let labels = format!("{}=\"{}\",", histogram.label, escape(k));
+        write_histogram(out, name, &labels, v);
+    }
+}
+
+// labels is either empty or a list of label pairs ending with a comma
+fn write_histogram(out: &mut String, name: &str, labels: &str, data: &HistogramData) {
+    for (bucket, count) in BUCKETS.iter().zip(data.buckets.iter()) {
+        let _ = writeln!(out, "{name}_bucket{{{labels}le=\"{bucket}\"}} {count}");
+    }
+    let _ = writeln!(out, "{name}_bucket{{{labels}le=\"+Inf\"}} {}", data.count);
+    let labels = labels.trim_end_matches(',');
+    let labels = match labels.is_empty() {
+        true => String::new(),
+        false => format!("{{{labels}}}"),
+    };
+    let _ = writeln!(out, "{name}_sum{labels} {}", data.sum);
+    let _ = writeln!(out, "{name}_count{labels} {}", data.count);
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/*
+ * Wraps a database query to record how long it took, the query is boxed so it can be polled
+ * without needing to be Unpin (which costs nothing noticeable next to a round trip to postgres)
+ */
+pub(crate) struct Timed<F> {
+    inner: Pin<Box<F>>,
+    start: Instant,
+}
+
+pub(crate) trait TimedQuery: Future + Sized {
+    fn timed(self) -> Timed<Self> {
+        Timed {
+            inner: Box::pin(self),
+            start: Instant::now(),
+        }
+    }
+}
+
+impl<F: Future> TimedQuery for F {}
+
+impl<F: Future> Future for Timed<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.inner.as_mut().poll(cx) {
+            Poll::Ready(v) => {
+                METRICS.observe_db_query(self.start.elapsed());
+                Poll::Ready(v)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+pub(crate) fn metrics_route(
+    state: AppState,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("metrics")
+        .and(warp::get())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(with_state(state))
+        .and_then(|auth: Option<String>, state: AppState| async move {
+            if !is_admin_token(auth) {
+                return Ok::<_, Rejection>(warp::reply::with_status(
+                    String::from("Unauthorized"),
+                    StatusCode::UNAUTHORIZED,
+                ));
+            }
+            let connected = state.clients.lock().await.len();
+            Ok(warp::reply::with_status(
+                METRICS.render(connected),
+                StatusCode::OK,
+            ))
+        })
+}
@@ -0,0 +1,85 @@
+use crate::{config, Database, SqliteStorage, MIGRATOR, SQLITE_MIGRATOR};
+use anyhow::anyhow;
+use sqlx::migrate::Migrate;
+
+/*
+ * seanify migrate status|up|down [version]
+ *
+ * The server applies every pending migration on startup so this is mostly useful for checking
+ * what state a database is in, or for rolling back. down reverts every migration newer than the
+ * given version, or only the latest one if no version is given
+ *
+ * Postgres and sqlite have their own pool and migrator types, so the body is a macro used for both
+ */
+macro_rules! migrate_with {
+    ($pool:expr, $migrator:expr, $args:expr) => {{
+        let (pool, migrator, args) = ($pool, &$migrator, $args);
+        let mut conn = pool.acquire().await?;
+        conn.ensure_migrations_table().await?;
+        let mut applied = conn
+            .list_applied_migrations()
+            .await?
+            .into_iter()
+            .map(|x| x.version)
+            .collect::<Vec<i64>>();
+        applied.sort_unstable();
+
+        match args.first().map(|x| x.as_ref()) {
+            Some("status") | None => {
+                if let Some(v) = conn.dirty_version().await? {
+                    println!("migration {v} failed part way through and has to be fixed by hand");
+                }
+                for migration in migrator
+                    .iter()
+                    .filter(|x| !x.migration_type.is_down_migration())
+                {
+                    let state = match applied.contains(&migration.version) {
+                        true => "applied",
+                        false => "pending",
+                    };
+                    println!(
+                        "{:04} {:<8} {}",
+                        migration.version, state, migration.description
+                    );
+                }
+            }
+            Some("up") => {
+                drop(conn);
+                migrator.run(pool).await?;
+                println!("database is up to date");
+            }
+            Some("down") => {
+                let target = match args.get(1) {
+                    Some(v) => v.as_ref().parse::<i64>()?,
+                    // the version before the latest, or nothing at all if there is only one
+                    None => match applied.len() {
+                        0 => return Err(anyhow!("no migrations have been applied")),
+                        n => applied.get(n.wrapping_sub(2)).copied().unwrap_or(0),
+                    },
+                };
+                drop(conn);
+                migrator.undo(pool, target).await?;
+                println!("reverted to {target:04}");
+            }
+            Some(v) => {
+                return Err(anyhow!(
+                    "unknown migrate command {v}, expected status, up or down"
+                ))
+            }
+        }
+        Ok(())
+    }};
+}
+
+pub(crate) async fn migrate<S: AsRef<str>>(args: &[S]) -> anyhow::Result<()> {
+    match config().database_url.starts_with("sqlite:") {
+        true => {
+            let storage = SqliteStorage::without_migrations(&config().database_url).await?;
+            migrate_with!(&storage.database, SQLITE_MIGRATOR, args)
+        }
+        false => {
+            let db = Database::without_migrations().await;
+            migrate_with!(&db.database, MIGRATOR, args)
+        }
+    }
+}
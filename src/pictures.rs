@@ -1,17 +1,35 @@
-use crate::env_fetch;
+use crate::{artwork_name, Bucket, FileStore, Identicon, Storage, COVER_SIZE};
 use anyhow::anyhow;
+use image::imageops::FilterType;
 use image::DynamicImage::ImageRgba8;
-use image::{GenericImageView, ImageBuffer};
+use image::{DynamicImage, GenericImageView, ImageOutputFormat, RgbImage};
 use log::error;
-use rand::{thread_rng, Rng};
 use seahash::hash;
-use std::env;
-use tokio::fs::create_dir;
+use std::collections::HashSet;
 
 // images are squares, they must be rescaled on the client to this size before sending
 const IMAGE_SIZE: usize = 400;
 
-pub(crate) async fn save_base64(fname: &str, data: String) -> anyhow::Result<()> {
+// the artwork of this many songs makes up the cover of a playlist, as a 2x2 grid
+const COVER_TILES: usize = 4;
+
+// the size of the artwork a tile of a playlist cover is made from, see artwork.rs
+const TILE_ARTWORK_SIZE: u32 = 256;
+
+// images are encoded in memory and handed to the file store, which might not be on this machine
+async fn save_png(files: &dyn FileStore, fname: &str, image: &DynamicImage) -> anyhow::Result<()> {
+    let mut png = Vec::new();
+    image.write_to(&mut std::io::Cursor::new(&mut png), ImageOutputFormat::Png)?;
+    files
+        .put(Bucket::Images, &format!("{fname}.png"), png)
+        .await
+}
+
+pub(crate) async fn save_base64(
+    files: &dyn FileStore,
+    fname: &str,
+    data: String,
+) -> anyhow::Result<()> {
     // remove the header if we need to
     let data = data.replace("data:image/png;base64,", "");
 
@@ -27,89 +45,215 @@ pub(crate) async fn save_base64(fname: &str, data: String) -> anyhow::Result<()>
         return Err(anyhow!("InvalidDimensions"));
     }
 
-    match image.save(&format!("{}/{fname}.png", env_fetch!("CDN_DIR"))) {
+    match save_png(files, fname, &image).await {
         Ok(_) => Ok(()),
         Err(_) => Err(anyhow!("FailedToSave")),
     }
 }
 
-pub(crate) async fn save_pfp(userhash: u64, data: String) -> anyhow::Result<()> {
-    save_base64(&userhash.to_string(), data).await
+pub(crate) async fn save_pfp(
+    files: &dyn FileStore,
+    userhash: u64,
+    data: String,
+) -> anyhow::Result<()> {
+    save_base64(files, &userhash.to_string(), data).await
 }
 
 pub(crate) async fn save_playlist_image(
+    files: &dyn FileStore,
     userhash: u64,
     playlistname: &str,
     data: String,
 ) -> anyhow::Result<()> {
-    let _ = create_dir(env_fetch!("CDN_DIR")).await;
     // hash the name so that we don't have to deal with weird names causing an epic RCE
     save_base64(
-        &format!("{userhash}-{}.png", hash(playlistname.as_bytes())),
+        files,
+        &format!("{userhash}-{}", hash(playlistname.as_bytes())),
         data,
     )
     .await
 }
 
 pub(crate) async fn default_playlist_image(
+    files: &dyn FileStore,
     username: u64,
     playlistname: &str,
 ) -> anyhow::Result<()> {
-    let playlist_hash = hash(playlistname.as_bytes());
-    default_image(playlist_hash, &format!("{username}-{playlist_hash}")).await
+    // seeded with the user as well so playlists with the same name don't look the same
+    let fname = format!("{username}-{}", hash(playlistname.as_bytes()));
+    default_image(files, hash(fname.as_bytes()), &fname).await
 }
 
-pub(crate) async fn default_pfp(username: u64) -> anyhow::Result<()> {
-    default_image(username, &username.to_string()).await
+// the custom image is removed and the cover made from its songs is put in its place
+pub(crate) async fn remove_playlist_image(
+    storage: &dyn Storage,
+    files: &dyn FileStore,
+    username: u64,
+    name: &str,
+) -> anyhow::Result<()> {
+    files
+        .delete(
+            Bucket::Images,
+            &format!("{}-{}.png", username, hash(name.as_bytes())),
+        )
+        .await?;
+    storage
+        .set_playlist_custom_image(username, name, false)
+        .await?;
+
+    let songs = cover_songs(storage, username, name).await;
+    playlist_cover(files, username, name, songs.unwrap_or_default()).await
 }
 
-async fn default_image(hash: u64, fname: &str) -> anyhow::Result<()> {
-    let mut rng = thread_rng();
-    let grid = rng.gen_range(3..=6);
+/*
+ * Playlists without a custom image have a cover made from the artwork of their songs, a 2x2 grid
+ * of the first four songs from different albums. With fewer than four the first one fills the
+ * whole cover and an empty playlist gets the same image as a new one
+ *
+ * The cover is made again whenever adding or removing a song changes which songs it's made of,
+ * get the songs with cover_songs before the change and pass them to update_playlist_cover after
+ */
+pub(crate) async fn cover_songs(
+    storage: &dyn Storage,
+    username: u64,
+    playlistname: &str,
+) -> Option<Vec<u64>> {
+    let songs = match storage.playlist_cover_songs(username, playlistname).await {
+        Ok(v) => v?,
+        Err(e) => {
+            error!("failed to find the cover songs of {playlistname}: {e}");
+            return None;
+        }
+    };
+    // songs without an album are all different
+    let mut albums = HashSet::new();
+    Some(
+        songs
+            .into_iter()
+            .filter(|(id, album)| match album.as_deref().map(str::trim) {
+                Some(v) if !v.is_empty() => albums.insert((Some(v.to_lowercase()), 0)),
+                _ => albums.insert((None, *id)),
+            })
+            .map(|(id, _)| id)
+            .take(COVER_TILES)
+            .collect(),
+    )
+}
 
-    // randomly generate a grid
-    let mut indexes = Vec::with_capacity(grid * grid);
-    let mut at_least_one = false;
+pub(crate) async fn update_playlist_cover(
+    storage: &dyn Storage,
+    files: &dyn FileStore,
+    username: u64,
+    playlistname: &str,
+    before: Option<Vec<u64>>,
+) {
+    let songs = match cover_songs(storage, username, playlistname).await {
+        Some(v) if Some(&v) != before.as_ref() => v,
+        _ => return,
+    };
+    if let Err(e) = playlist_cover(files, username, playlistname, songs).await {
+        error!("failed to make the cover of {playlistname}: {e}");
+    }
+}
 
-    // this is really awful
-    // make sure at least one is true
-    while !at_least_one {
-        for _ in 0..grid * grid {
-            let square = rng.gen_bool(0.6);
-            if square {
-                at_least_one = true;
-            }
-            indexes.push(square);
+async fn playlist_cover(
+    files: &dyn FileStore,
+    username: u64,
+    playlistname: &str,
+    songs: Vec<u64>,
+) -> anyhow::Result<()> {
+    let size = match songs.len() {
+        COVER_TILES => TILE_ARTWORK_SIZE,
+        _ => COVER_SIZE,
+    };
+    let mut artwork = Vec::with_capacity(songs.len());
+    for id in songs {
+        if let Some(v) = files.get(Bucket::Images, &artwork_name(id, size)).await? {
+            artwork.push(v);
         }
     }
+    if artwork.is_empty() {
+        return default_playlist_image(files, username, playlistname).await;
+    }
 
-    let mut image_data: Vec<u8> = Vec::with_capacity(640000);
-    let (r, g, b, o) = (hash % 120 * 2, hash % 220, hash % 60 * 3, hash % 6 * 40);
-
-    //let split = 160000 / grid;
-    for i in 0..160000 {
-        let x = (i % IMAGE_SIZE) / (IMAGE_SIZE / grid);
-        let y = (i / IMAGE_SIZE) / (IMAGE_SIZE / grid);
-        if indexes[y.clamp(0, grid - 1) * grid + x.clamp(0, grid - 1)] {
-            image_data.push(r.clamp(0, 255) as u8);
-            image_data.push(g.clamp(0, 255) as u8);
-            image_data.push(b.clamp(0, 255) as u8);
-            image_data.push(o.clamp(160, 255) as u8);
-            continue;
-        }
-        for _ in 0..4 {
-            image_data.push((hash % 255) as u8);
-        }
+    let cover = tokio::task::spawn_blocking(move || cover_mosaic(&artwork)).await??;
+    let playlist_hash = hash(playlistname.as_bytes());
+    save_png(files, &format!("{username}-{playlist_hash}"), &cover).await
+}
+
+// the artwork as a 2x2 grid if there are four of them, otherwise only the first
+fn cover_mosaic(artwork: &[Vec<u8>]) -> anyhow::Result<DynamicImage> {
+    let size = IMAGE_SIZE as u32;
+    if artwork.len() < COVER_TILES {
+        let image = image::load_from_memory(&artwork[0])?;
+        return Ok(image.resize_exact(size, size, FilterType::Lanczos3));
+    }
+
+    let tile = size / 2;
+    let mut cover = RgbImage::new(size, size);
+    for (i, data) in artwork.iter().enumerate() {
+        let image = image::load_from_memory(data)?
+            .resize_exact(tile, tile, FilterType::Lanczos3)
+            .to_rgb8();
+        let (x, y) = (i as u32 % 2 * tile, i as u32 / 2 * tile);
+        image::imageops::replace(&mut cover, &image, x.into(), y.into());
+    }
+    Ok(DynamicImage::ImageRgb8(cover))
+}
+
+pub(crate) async fn default_pfp(files: &dyn FileStore, username: u64) -> anyhow::Result<()> {
+    default_image(files, username, &username.to_string()).await
+}
+
+// the identicon of the hash, see identicon.rs
+async fn default_image(files: &dyn FileStore, hash: u64, fname: &str) -> anyhow::Result<()> {
+    let image = DynamicImage::ImageRgb8(Identicon::new(hash).render(IMAGE_SIZE as u32));
+    save_png(files, fname, &image).await
+}
+
+// the pfp of a user is "{userhash}.png" and their playlist images all start with "{userhash}-"
+fn is_user_image(fname: &str, userhash: u64) -> Option<&str> {
+    let rest = fname.strip_prefix(&userhash.to_string())?;
+    match rest.starts_with('-') || rest == ".png" {
+        true => Some(rest),
+        false => None,
     }
+}
 
-    let pfp = image::DynamicImage::ImageRgba8(
-        match ImageBuffer::from_raw(IMAGE_SIZE as u32, IMAGE_SIZE as u32, image_data) {
-            Some(v) => v,
-            None => return Ok(()),
-        },
-    );
+// every image belonging to a user along with what comes after the username hash in the file name
+pub(crate) async fn user_images(files: &dyn FileStore, userhash: u64) -> Vec<(String, String)> {
+    let names = match files.list(Bucket::Images, &userhash.to_string()).await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("failed to list the images of {userhash}: {e}");
+            return Vec::new();
+        }
+    };
+    names
+        .into_iter()
+        .filter_map(|(name, _)| {
+            let rest = is_user_image(&name, userhash)?.to_string();
+            Some((name, rest))
+        })
+        .collect()
+}
 
-    pfp.save(&format!("{}/{fname}.png", env_fetch!("CDN_DIR")))?;
+// move the pfp and playlist images of a user that changed their username over to the new hash
+pub(crate) async fn move_user_images(files: &dyn FileStore, old: u64, new: u64) {
+    for (name, rest) in user_images(files, old).await {
+        if let Err(e) = files
+            .rename(Bucket::Images, &name, &format!("{new}{rest}"))
+            .await
+        {
+            error!("failed to move {name}: {e}");
+        }
+    }
+}
 
-    Ok(())
+pub(crate) async fn remove_user_images(files: &dyn FileStore, userhash: u64) {
+    for (name, _) in user_images(files, userhash).await {
+        if let Err(e) = files.delete(Bucket::Images, &name).await {
+            error!("failed to remove {name}: {e}");
+        }
+    }
 }
@@ -0,0 +1,121 @@
+use crate::{send_to_users, AppState};
+use serde::Serialize;
+use serde_json::json;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/*
+ * What a user is currently listening to, this is only kept in memory and is removed once all of
+ * the connections of a user are closed
+ *
+ * Followers are sent a "PRESENCE {json}" message whenever this changes if the user has
+ * share_status enabled, when the presence is cleared song is null
+ */
+#[derive(Clone, Serialize)]
+pub(crate) struct Presence {
+    pub user: String, // display name
+    pub song: Option<String>,
+    pub position: u64, // in seconds
+    pub timestamp: u64,
+    #[serde(skip)]
+    pub shared: bool,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/*
+ * Update the presence of a user and notify their followers
+ *
+ * Returns true if this is a new play (a different song or the position went backwards) rather than
+ * a progress update on the current one, so the caller knows when to add to the play history
+ */
+pub(crate) async fn update_presence(
+    state: &AppState,
+    userhash: u64,
+    song: u64,
+    position: u64,
+) -> anyhow::Result<bool> {
+    let data = state
+        .storage
+        .get_user_data(userhash)
+        .await?
+        .unwrap_or_default();
+    let presence = Presence {
+        user: data.display_name.unwrap_or_default(),
+        song: Some(song.to_string()),
+        position,
+        timestamp: now(),
+        shared: data.share_status == Some(true),
+    };
+
+    let new_play = match state
+        .presence
+        .write()
+        .await
+        .insert(userhash, presence.clone())
+    {
+        Some(v) => v.song != presence.song || position < v.position,
+        None => true,
+    };
+
+    if presence.shared {
+        send_to_users(
+            &state.clients,
+            &state.storage.audience_of(userhash).await?,
+            &format!("PRESENCE {}", json!(presence)),
+        )
+        .await;
+    }
+
+    Ok(new_play)
+}
+
+// remove the presence of a user, followers that could see it are told it is gone
+pub(crate) async fn clear_presence(state: &AppState, userhash: u64) {
+    let presence = match state.presence.write().await.remove(&userhash) {
+        Some(v) => v,
+        None => return,
+    };
+    if !presence.shared {
+        return;
+    }
+    let followers = match state.storage.audience_of(userhash).await {
+        Ok(v) => v,
+        Err(_) => return,
+    };
+    let cleared = Presence {
+        song: None,
+        position: 0,
+        timestamp: now(),
+        ..presence
+    };
+    send_to_users(
+        &state.clients,
+        &followers,
+        &format!("PRESENCE {}", json!(cleared)),
+    )
+    .await;
+}
+
+// the presence of everyone in the list that is currently sharing it
+pub(crate) async fn list_presence(state: &AppState, users: &[u64]) -> Vec<Presence> {
+    let locked = state.presence.read().await;
+    users
+        .iter()
+        .filter_map(|x| locked.get(x))
+        .filter(|x| x.shared)
+        .cloned()
+        .collect()
+}
+
+// song that is currently shared as playing by a user
+pub(crate) async fn shared_song(state: &AppState, userhash: u64) -> Option<String> {
+    match state.presence.read().await.get(&userhash) {
+        Some(v) if v.shared => v.song.clone(),
+        _ => None,
+    }
+}
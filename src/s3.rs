@@ -0,0 +1,451 @@
+use crate::{config, Bucket, FileStore};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use reqwest::{Client, Method, Url};
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+use warp::http::{header, StatusCode};
+use warp::hyper::Body;
+use warp::reply::{Reply, Response};
+
+/*
+ * Songs and images in a bucket of an S3 compatible service (AWS, MinIO, R2, ...), set with
+ * s3_endpoint, s3_bucket, s3_access_key and s3_secret_key. Songs are kept under songs/ and images
+ * under images/
+ *
+ * Requests are signed with signature version 4 and use path style urls (endpoint/bucket/key),
+ * which every S3 compatible service understands. When s3_presign_seconds is set files are served
+ * by redirecting to a presigned url that is valid for that long so the bytes never go through the
+ * server, otherwise they are proxied
+ */
+type HmacSha256 = Hmac<Sha256>;
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+
+// the reply headers passed on when a file is proxied
+const PROXIED_HEADERS: [header::HeaderName; 6] = [
+    header::CONTENT_TYPE,
+    header::CONTENT_LENGTH,
+    header::CONTENT_RANGE,
+    header::ACCEPT_RANGES,
+    header::ETAG,
+    header::LAST_MODIFIED,
+];
+
+pub(crate) struct S3Files {
+    client: Client,
+    endpoint: Url,
+    host: String,
+    bucket: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    presign_seconds: Option<u32>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/*
+ * The date format used when signing, ex: 20221018T093000Z
+ *
+ * The date is the civil from days algorithm from http://howardhinnant.github.io/date_algorithms.html
+ * which is the other direction of year_start in stats.rs
+ */
+fn amz_date(secs: u64) -> String {
+    let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = match mp < 10 {
+        true => mp + 3,
+        false => mp - 9,
+    };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}{month:02}{day:02}T{:02}{:02}{:02}Z",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+// percent encode everything except the unreserved characters, and / when it separates a path
+fn uri_encode(s: &str, path: bool) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            b'/' if path => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{b:02X}")),
+        }
+    }
+    encoded
+}
+
+// query parameters sorted and encoded the way they are signed, the same string is sent
+fn canonical_query(params: &[(&str, &str)]) -> String {
+    let mut params = params
+        .iter()
+        .map(|(k, v)| (uri_encode(k, false), uri_encode(v, false)))
+        .collect::<Vec<(String, String)>>();
+    params.sort();
+    params
+        .iter()
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<String>>()
+        .join("&")
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("hmac takes keys of any size");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+// the text inside of every <tag> in a list response, entities are decoded
+fn xml_values(xml: &str, tag: &str) -> Vec<String> {
+    let (open, close) = (format!("<{tag}>"), format!("</{tag}>"));
+    xml.split(open.as_str())
+        .skip(1)
+        .filter_map(|x| {
+            let value = x.split(close.as_str()).next()?;
+            Some(
+                value
+                    .replace("&lt;", "<")
+                    .replace("&gt;", ">")
+                    .replace("&quot;", "\"")
+                    .replace("&apos;", "'")
+                    .replace("&amp;", "&"),
+            )
+        })
+        .collect()
+}
+
+impl S3Files {
+    // None unless the s3 settings are set, config makes sure they are set together
+    pub fn from_config() -> Option<Self> {
+        let c = config();
+        let endpoint = Url::parse(c.s3_endpoint.as_ref()?).ok()?;
+        let host = match endpoint.port() {
+            Some(port) => format!("{}:{port}", endpoint.host_str()?),
+            None => endpoint.host_str()?.to_string(),
+        };
+        Some(Self {
+            client: Client::new(),
+            endpoint,
+            host,
+            bucket: c.s3_bucket.clone()?,
+            region: c.s3_region.clone(),
+            access_key: c.s3_access_key.clone()?,
+            secret_key: c.s3_secret_key.clone()?,
+            presign_seconds: c.s3_presign_seconds,
+        })
+    }
+
+    fn key(bucket: Bucket, name: &str) -> String {
+        match bucket {
+            Bucket::Songs => format!("songs/{name}"),
+            Bucket::Images => format!("images/{name}"),
+        }
+    }
+
+    // the encoded path of a key, or of the bucket itself
+    fn path(&self, key: Option<&str>) -> String {
+        let base = self.endpoint.path().trim_end_matches('/');
+        match key {
+            Some(v) => uri_encode(&format!("{base}/{}/{v}", self.bucket), true),
+            None => uri_encode(&format!("{base}/{}", self.bucket), true),
+        }
+    }
+
+    fn url(&self, path: &str, query: &str) -> String {
+        let origin = format!("{}://{}", self.endpoint.scheme(), self.host);
+        match query.is_empty() {
+            true => format!("{origin}{path}"),
+            false => format!("{origin}{path}?{query}"),
+        }
+    }
+
+    fn scope(&self, date: &str) -> String {
+        format!("{}/{}/s3/aws4_request", &date[..8], self.region)
+    }
+
+    /*
+     * The signature of a request, headers are (lowercase name, value) and every one of them is
+     * signed. See https://docs.aws.amazon.com/AmazonS3/latest/API/sig-v4-header-based-auth.html
+     */
+    fn signature(
+        &self,
+        method: &str,
+        path: &str,
+        query: &str,
+        headers: &[(String, String)],
+        payload_hash: &str,
+        date: &str,
+    ) -> String {
+        let mut headers = headers.to_vec();
+        headers.sort();
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(k, v)| format!("{k}:{}\n", v.trim()))
+            .collect();
+        let signed_headers = headers
+            .iter()
+            .map(|(k, _)| k.as_str())
+            .collect::<Vec<&str>>()
+            .join(";");
+        let request = format!(
+            "{method}\n{path}\n{query}\n{canonical_headers}\n{signed_headers}\n{payload_hash}"
+        );
+        let string_to_sign = format!(
+            "{ALGORITHM}\n{date}\n{}\n{:x}",
+            self.scope(date),
+            Sha256::digest(request.as_bytes())
+        );
+
+        let key = hmac(format!("AWS4{}", self.secret_key).as_bytes(), &date[..8]);
+        let key = hmac(&key, &self.region);
+        let key = hmac(&key, "s3");
+        let key = hmac(&key, "aws4_request");
+        hmac(&key, &string_to_sign)
+            .iter()
+            .map(|x| format!("{x:02x}"))
+            .collect()
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        key: Option<&str>,
+        query: &[(&str, &str)],
+        headers: &[(&str, String)],
+        body: Vec<u8>,
+    ) -> anyhow::Result<reqwest::Response> {
+        let (path, query) = (self.path(key), canonical_query(query));
+        let date = amz_date(now());
+        let payload_hash = format!("{:x}", Sha256::digest(&body));
+
+        let mut signed = vec![
+            (String::from("host"), self.host.clone()),
+            (String::from("x-amz-content-sha256"), payload_hash.clone()),
+            (String::from("x-amz-date"), date.clone()),
+        ];
+        signed.extend(headers.iter().map(|(k, v)| (k.to_lowercase(), v.clone())));
+        let signature = self.signature(
+            method.as_str(),
+            &path,
+            &query,
+            &signed,
+            &payload_hash,
+            &date,
+        );
+        let signed_headers = {
+            let mut names = signed.iter().map(|x| x.0.as_str()).collect::<Vec<&str>>();
+            names.sort_unstable();
+            names.join(";")
+        };
+
+        let mut request = self
+            .client
+            .request(method, self.url(&path, &query))
+            .header(
+                header::AUTHORIZATION,
+                format!(
+                    "{ALGORITHM} Credential={}/{}, SignedHeaders={signed_headers}, Signature={signature}",
+                    self.access_key,
+                    self.scope(&date)
+                ),
+            )
+            .body(body);
+        // reqwest sets the host itself
+        for (k, v) in signed.into_iter().filter(|x| x.0 != "host") {
+            request = request.header(k, v);
+        }
+        Ok(request.send().await?)
+    }
+
+    // a GET url anyone can use until it expires, see sig-v4-query-string-auth in the AWS docs
+    fn presigned_url(&self, key: &str, seconds: u32) -> String {
+        let date = amz_date(now());
+        let credential = format!("{}/{}", self.access_key, self.scope(&date));
+        let expires = seconds.to_string();
+        let query = canonical_query(&[
+            ("X-Amz-Algorithm", ALGORITHM),
+            ("X-Amz-Credential", &credential),
+            ("X-Amz-Date", &date),
+            ("X-Amz-Expires", &expires),
+            ("X-Amz-SignedHeaders", "host"),
+        ]);
+        let path = self.path(Some(key));
+        let signature = self.signature(
+            "GET",
+            &path,
+            &query,
+            &[(String::from("host"), self.host.clone())],
+            "UNSIGNED-PAYLOAD",
+            &date,
+        );
+        self.url(&path, &format!("{query}&X-Amz-Signature={signature}"))
+    }
+}
+
+// anything but a success is turned into an error with the body S3 sent back
+async fn check(response: reqwest::Response) -> anyhow::Result<reqwest::Response> {
+    match response.status().is_success() {
+        true => Ok(response),
+        false => Err(anyhow!(
+            "s3 replied with {}: {}",
+            response.status(),
+            response.text().await.unwrap_or_default()
+        )),
+    }
+}
+
+#[async_trait]
+impl FileStore for S3Files {
+    async fn put(&self, bucket: Bucket, name: &str, data: Vec<u8>) -> anyhow::Result<()> {
+        let content_type = match bucket {
+            Bucket::Songs => "application/octet-stream",
+            Bucket::Images if name.ends_with(".jpg") => "image/jpeg",
+            Bucket::Images => "image/png",
+        };
+        let key = Self::key(bucket, name);
+        let headers = [("content-type", content_type.to_string())];
+        check(
+            self.request(Method::PUT, Some(&key), &[], &headers, data)
+                .await?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn get(&self, bucket: Bucket, name: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let key = Self::key(bucket, name);
+        let response = self
+            .request(Method::GET, Some(&key), &[], &[], Vec::new())
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Ok(Some(check(response).await?.bytes().await?.to_vec()))
+    }
+
+    async fn delete(&self, bucket: Bucket, name: &str) -> anyhow::Result<()> {
+        let key = Self::key(bucket, name);
+        let response = self
+            .request(Method::DELETE, Some(&key), &[], &[], Vec::new())
+            .await?;
+        if response.status() != reqwest::StatusCode::NOT_FOUND {
+            check(response).await?;
+        }
+        Ok(())
+    }
+
+    // ListObjectsV2 returns up to 1000 keys at a time, the continuation token gets the next page
+    async fn list(&self, bucket: Bucket, prefix: &str) -> anyhow::Result<Vec<(String, u64)>> {
+        let prefix = Self::key(bucket, prefix);
+        let strip = Self::key(bucket, "");
+        let mut files = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", prefix.as_str())];
+            if let Some(v) = &token {
+                query.push(("continuation-token", v.as_str()));
+            }
+            let response = self
+                .request(Method::GET, None, &query, &[], Vec::new())
+                .await?;
+            let xml = check(response).await?.text().await?;
+
+            for contents in xml_values(&xml, "Contents") {
+                let key = xml_values(&contents, "Key").into_iter().next();
+                let size = xml_values(&contents, "Size")
+                    .first()
+                    .and_then(|x| x.parse::<u64>().ok());
+                if let (Some(key), Some(size)) = (key, size) {
+                    if let Some(name) = key.strip_prefix(&strip) {
+                        files.push((name.to_string(), size));
+                    }
+                }
+            }
+            token = match xml_values(&xml, "IsTruncated").first().map(String::as_str) {
+                Some("true") => xml_values(&xml, "NextContinuationToken").into_iter().next(),
+                _ => None,
+            };
+            if token.is_none() {
+                return Ok(files);
+            }
+        }
+    }
+
+    // copied on the S3 side so the file never has to be downloaded
+    async fn rename(&self, bucket: Bucket, from: &str, to: &str) -> anyhow::Result<()> {
+        let source = format!("/{}/{}", self.bucket, Self::key(bucket, from));
+        let headers = [("x-amz-copy-source", uri_encode(&source, true))];
+        let key = Self::key(bucket, to);
+        check(
+            self.request(Method::PUT, Some(&key), &[], &headers, Vec::new())
+                .await?,
+        )
+        .await?;
+        self.delete(bucket, from).await
+    }
+
+    async fn serve(
+        &self,
+        bucket: Bucket,
+        name: &str,
+        range: Option<String>,
+    ) -> anyhow::Result<Response> {
+        let key = Self::key(bucket, name);
+        if let Some(seconds) = self.presign_seconds {
+            let mut response = StatusCode::TEMPORARY_REDIRECT.into_response();
+            response
+                .headers_mut()
+                .insert(header::LOCATION, self.presigned_url(&key, seconds).parse()?);
+            return Ok(response);
+        }
+
+        let headers = match range {
+            Some(v) => vec![("range", v)],
+            None => Vec::new(),
+        };
+        let upstream = self
+            .request(Method::GET, Some(&key), &[], &headers, Vec::new())
+            .await?;
+        let status = upstream.status();
+        if status == reqwest::StatusCode::NOT_FOUND {
+            return Ok(StatusCode::NOT_FOUND.into_response());
+        }
+        if !status.is_success() {
+            return Err(check(upstream)
+                .await
+                .err()
+                .unwrap_or_else(|| anyhow!("{status}")));
+        }
+
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::from_u16(status.as_u16())?;
+        for name in PROXIED_HEADERS {
+            if let Some(v) = upstream.headers().get(name.as_str()) {
+                response
+                    .headers_mut()
+                    .insert(name, header::HeaderValue::from_bytes(v.as_bytes())?);
+            }
+        }
+        *response.body_mut() = Body::wrap_stream(upstream.bytes_stream());
+        Ok(response)
+    }
+}
@@ -1,11 +1,14 @@
-use crate::{acquire_db, env_num_or_default, CACHE_DIR, DB};
+use crate::{cache_artwork, config, Bucket, Downloader, FileStore, Storage, METRICS};
 use core::fmt;
 use log::error;
 use seahash::hash;
-use std::{collections::VecDeque, fs::read_dir, path::PathBuf};
-use tokio::{fs::create_dir_all, process::Command};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use youtube_dl::{YoutubeDl, YoutubeDlOutput};
 
+#[derive(Clone)]
 pub(crate) struct Song {
     pub id: Option<u64>,
     pub title: Option<String>,
@@ -19,6 +22,7 @@ pub(crate) struct Song {
     pub artist: Option<String>,
     pub creator: Option<String>,
     pub filesize: Option<i64>,
+    pub duration: Option<i64>, // in seconds
 }
 
 pub enum SongError {
@@ -55,17 +59,9 @@ impl Song {
 
         match output {
             YoutubeDlOutput::SingleVideo(v) => {
-                let hash_id = format!(
-                    "{} {} {}",
-                    v.title,
-                    v.uploader.clone().unwrap_or_default(),
-                    v.upload_date.clone().unwrap_or_default()
-                );
-
                 Ok(Self {
                     // TODO PROPER ERR HANDLING HERE
-                    // TITLE UPLOADER DATE
-                    id: Some(hash(hash_id.as_bytes())),
+                    id: Some(Self::id_for(&v.title, &v.uploader, &v.upload_date)),
                     title: Some(v.title),
                     upload_date: v.upload_date,
                     uploader: v.uploader,
@@ -77,18 +73,57 @@ impl Song {
                     artist: v.artist,
                     creator: v.creator,
                     filesize: v.filesize,
+                    duration: v.duration.and_then(|d| d.as_f64()).map(|d| d as i64),
                 })
             }
             _ => Err(SongError::NotSingleVideo),
         }
     }
+
+    // songs are identified by the hash of the title, uploader and upload date
+    pub fn id_for(title: &str, uploader: &Option<String>, upload_date: &Option<String>) -> u64 {
+        let hash_id = format!(
+            "{} {} {}",
+            title,
+            uploader.clone().unwrap_or_default(),
+            upload_date.clone().unwrap_or_default()
+        );
+        hash(hash_id.as_bytes())
+    }
 }
 
+// how many of the most recent failed downloads are kept around for the dashboard
+const MAX_DOWNLOAD_FAILURES: usize = 20;
+
 pub(crate) struct SongManager {
+    storage: Arc<dyn Storage>,
+    files: Arc<dyn FileStore>,
+    downloader: Arc<dyn Downloader>,
     download_queue: VecDeque<String>,
     hourly_ytdl_call_max: (u64, Option<u64>),
     hourly_bandwidth_limit_mb: (u64, Option<u64>),
     max_file_size_mb: Option<u64>,
+    failures: VecDeque<DownloadFailure>,
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct DownloadFailure {
+    pub url: String,
+    pub reason: String,
+    pub timestamp: u64,
+}
+
+// snapshot of the queue and the limit counters, the limits are null when not set
+#[derive(Serialize)]
+pub(crate) struct QueueStatus {
+    pub queue: Vec<String>,
+    pub queue_limit: u64,
+    pub ytdl_calls: u64,
+    pub ytdl_call_limit: Option<u64>,
+    pub bandwidth_mb: u64,
+    pub bandwidth_limit_mb: Option<u64>,
+    pub max_file_size_mb: Option<u64>,
+    pub recent_failures: Vec<DownloadFailure>,
 }
 
 pub enum SongManagerError {
@@ -97,6 +132,7 @@ pub enum SongManagerError {
     MaxFileSizeLimit,
     QueueLimit,
     InvalidSong,
+    InvalidPosition,
 }
 
 impl fmt::Display for SongManagerError {
@@ -107,28 +143,36 @@ impl fmt::Display for SongManagerError {
             Self::MaxFileSizeLimit => write!(f, "Max file size limit reached"),
             Self::QueueLimit => write!(f, "Queue limit reached"),
             Self::InvalidSong => write!(f, "Provided with invalid song"),
+            Self::InvalidPosition => write!(f, "No song at that position in the queue"),
         }
     }
 }
 
 impl SongManager {
     pub fn new(
+        storage: Arc<dyn Storage>,
+        files: Arc<dyn FileStore>,
+        downloader: Arc<dyn Downloader>,
         hourly_ytdl_call_max: Option<u64>,
         hourly_bandwidth_limit_mb: Option<u64>,
         max_file_size_mb: Option<u64>,
     ) -> Self {
         Self {
+            storage,
+            files,
+            downloader,
             download_queue: VecDeque::with_capacity(1),
             hourly_ytdl_call_max: (0, hourly_ytdl_call_max),
             hourly_bandwidth_limit_mb: (0, hourly_bandwidth_limit_mb),
             max_file_size_mb,
+            failures: VecDeque::with_capacity(MAX_DOWNLOAD_FAILURES),
         }
     }
 
     // TODO
     // sound cloud
     pub fn request(&mut self, url: String) -> anyhow::Result<(), SongManagerError> {
-        if self.download_queue.len() < env_num_or_default!("QUEUE_LIMIT", 50) as usize {
+        if self.download_queue.len() < config().queue_limit as usize {
             self.download_queue.push_back(url);
             return Ok(());
         }
@@ -136,10 +180,30 @@ impl SongManager {
     }
 
     // ADMIN ONLY
-    pub fn _clear_queue(&mut self) {
+    pub fn clear_queue(&mut self) {
         self.download_queue.clear();
     }
 
+    // ADMIN ONLY
+    pub fn move_in_queue(
+        &mut self,
+        from: usize,
+        to: usize,
+    ) -> anyhow::Result<(), SongManagerError> {
+        if from >= self.download_queue.len() || to >= self.download_queue.len() {
+            return Err(SongManagerError::InvalidPosition);
+        }
+        if let Some(url) = self.download_queue.remove(from) {
+            self.download_queue.insert(to, url);
+        }
+        Ok(())
+    }
+
+    // ADMIN ONLY
+    pub fn remove_from_queue(&mut self, position: usize) -> Option<String> {
+        self.download_queue.remove(position)
+    }
+
     pub fn list_queue(&self) -> String {
         let mut queue = String::new();
         self.download_queue
@@ -148,6 +212,33 @@ impl SongManager {
         queue
     }
 
+    pub fn status(&self) -> QueueStatus {
+        QueueStatus {
+            queue: self.download_queue.iter().cloned().collect(),
+            queue_limit: config().queue_limit as u64,
+            ytdl_calls: self.hourly_ytdl_call_max.0,
+            ytdl_call_limit: self.hourly_ytdl_call_max.1,
+            bandwidth_mb: self.hourly_bandwidth_limit_mb.0,
+            bandwidth_limit_mb: self.hourly_bandwidth_limit_mb.1,
+            max_file_size_mb: self.max_file_size_mb,
+            recent_failures: self.failures.iter().cloned().collect(),
+        }
+    }
+
+    fn record_failure(&mut self, url: String, err: &SongManagerError) {
+        if self.failures.len() >= MAX_DOWNLOAD_FAILURES {
+            self.failures.pop_front();
+        }
+        self.failures.push_back(DownloadFailure {
+            url,
+            reason: err.to_string(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        });
+    }
+
     pub async fn cycle_queue(&mut self) -> anyhow::Result<(), SongManagerError> {
         // TODO
         // check size of cache dir and return error or not from it
@@ -164,50 +255,72 @@ impl SongManager {
         }
 
         if let Some(url) = self.download_queue.pop_front() {
-            let song = match Song::new(&url) {
-                Ok(v) => v,
-                Err(_) => return Err(SongManagerError::InvalidSong),
-            };
-            if song.title.is_none() || song.title.clone().unwrap_or_default().is_empty() {
-                return Err(SongManagerError::InvalidSong);
+            if let Err(e) = self.download(&url).await {
+                error!("failed to download {url}: {e}");
+                METRICS.downloads.inc("failure");
+                self.record_failure(url, &e);
+                return Err(e);
             }
-            self.hourly_ytdl_call_max.0 += 1;
-            if let Some(config_max) = self.max_file_size_mb {
-                if let Some(video_size) = song.filesize {
-                    if config_max * 1024 < video_size as u64 {
-                        return Err(SongManagerError::MaxFileSizeLimit);
-                    }
+            METRICS.downloads.inc("success");
+        }
+        Ok(())
+    }
+
+    async fn download(&mut self, url: &str) -> anyhow::Result<(), SongManagerError> {
+        let mut song = match self.downloader.details(url).await {
+            Ok(v) => v,
+            Err(_) => return Err(SongManagerError::InvalidSong),
+        };
+        if song.title.is_none() || song.title.clone().unwrap_or_default().is_empty() {
+            return Err(SongManagerError::InvalidSong);
+        }
+        self.hourly_ytdl_call_max.0 += 1;
+        if let Some(config_max) = self.max_file_size_mb {
+            if let Some(video_size) = song.filesize {
+                if config_max * 1024 < video_size as u64 {
+                    return Err(SongManagerError::MaxFileSizeLimit);
                 }
             }
+        }
 
-            // create cache directory if it doesn't exist
-            if PathBuf::from(CACHE_DIR.to_string()).exists() {
-                let _ = create_dir_all(CACHE_DIR.to_string()).await;
+        // every song that has finished downloading since the last one is marked as downloaded
+        let songs = match self.files.list(Bucket::Songs, "").await {
+            Ok(v) => v,
+            Err(e) => {
+                error!("failed to list the stored songs: {e}");
+                Vec::new()
             }
-
-            for song in read_dir(CACHE_DIR.to_owned()).unwrap().flatten() {
-                let name = match song.file_name().to_str().unwrap().parse::<u64>() {
-                    Ok(v) => v,
-                    Err(_) => continue,
-                };
-                let _ = acquire_db!(DB).update_downloaded(name).await;
+        };
+        for (name, _) in songs {
+            if let Ok(v) = name.parse::<u64>() {
+                let _ = self.storage.update_downloaded(v).await;
             }
+        }
 
-            // MAKE THIS PROPER
-            let _ = acquire_db!(DB).remove_duplicate_songs().await;
+        // MAKE THIS PROPER
+        let _ = self.storage.remove_duplicate_songs().await;
 
-            let _ = Command::new("aria2c")
-                .args([
-                    "-d",
-                    &CACHE_DIR,
-                    "-o",
-                    &song.id.unwrap().to_string(), // FIX this is hashed wrong
-                    &song.url.clone().unwrap(),
-                ])
-                .spawn();
+        // a song that is downloaded again keeps the metadata it was corrected to, the file is
+        // tagged with it as well
+        if let Some(id) = song.id {
+            if let Ok(Some(v)) = self.storage.song_metadata(id).await {
+                v.apply_to(&mut song);
+            }
+        }
+
+        // the artwork is stored before the song so it can be its cover, a song is only left with
+        // a thumbnail if it has artwork
+        if let (Some(id), Some(url)) = (song.id, song.thumbnail.clone()) {
+            if let Err(e) = cache_artwork(self.files.as_ref(), id, &url).await {
+                error!("failed to cache the artwork of {url}: {e}");
+                song.thumbnail = None;
+            }
+        }
 
-            DB.get().await.insert_song(song).await.unwrap();
+        if let Err(e) = self.downloader.save(&song, self.files.clone()).await {
+            error!("failed to start download of {url}: {e}");
         }
+        self.storage.insert_song(song).await.unwrap();
         Ok(())
     }
 }
@@ -0,0 +1,2346 @@
+use crate::activity::{Activity, Feed, FeedItem, FEED_PAGE_SIZE};
+use crate::admin::{AdminLog, AdminLogEntry, ADMIN_LOG_PAGE_SIZE};
+use crate::enrich::SongFields;
+use crate::export::{ExportedPlay, ExportedPlaylist, ExportedTrack};
+use crate::invites::{generate_invite_code, Invite, InviteList};
+use crate::metadata::{
+    metadata_from_json, SongEdit, SongEditEntry, SongHistory, SongMetadata, SongOverride,
+    SONG_HISTORY_PAGE_SIZE,
+};
+use crate::metrics::TimedQuery;
+use crate::stats::{
+    year_start, ListenTime, TopArtist, TopSong, YearInReview, MAX_TOP_LIMIT, PLAY_DEDUP_SECONDS,
+    RECENT_PLAYS_LIMIT,
+};
+use crate::user::{FollowList, FollowStatus, Playlist, Profile, UserData, FOLLOW_PAGE_SIZE};
+use crate::{
+    config, Artwork, BigD, FollowResult, PoolStatus, Song, SongDetails, SongTitleResultOut, Storage,
+};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use seahash::hash;
+use sqlx::migrate::Migrator;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::{Pool, Sqlite, Transaction};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/*
+ * Storage in a single sqlite file, used when database_url starts with sqlite: (for example
+ * sqlite://data/seanify.db) so a small instance doesn't need a postgres server next to it
+ *
+ * The schema is migrations/sqlite, it has the same versions and tables as the postgres one so
+ * every method here is the postgres query from db.rs written for sqlite. Sqlite only has signed
+ * 64 bit integers so username and song hashes are stored as an i64 with the same bits, see int and
+ * uint
+ */
+pub(crate) static SQLITE_MIGRATOR: Migrator = sqlx::migrate!("migrations/sqlite");
+
+pub(crate) struct SqliteStorage {
+    pub database: Pool<Sqlite>,
+}
+
+// (id, display name, kind, playlist, song hash, detail, created at) from the activity table
+type FeedRow = (
+    i64,
+    Option<String>,
+    String,
+    Option<String>,
+    Option<i64>,
+    Option<String>,
+    i64,
+);
+
+// (title, artist, album, album artist, genre) from the songs table
+type MetadataRow = (
+    String,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+);
+
+fn metadata(row: MetadataRow) -> SongMetadata {
+    SongMetadata {
+        title: row.0,
+        artist: row.1,
+        album: row.2,
+        album_artist: row.3,
+        genre: row.4,
+    }
+}
+
+// every column of the songs table that Song has, in the order of Song
+type SongRow = (
+    i64,
+    String,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<i64>,
+    Option<i64>,
+);
+
+// (id, title, artist, album, album artist, genre, uploader, creator, upload date) from the songs
+// table
+type UnenrichedRow = (
+    i64,
+    String,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+);
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+fn int(v: u64) -> i64 {
+    v as i64
+}
+
+fn uint(v: i64) -> u64 {
+    v as u64
+}
+
+fn truncate(s: &str, len: usize) -> String {
+    s.chars().take(len).collect()
+}
+
+fn feed_item(row: FeedRow) -> FeedItem {
+    FeedItem {
+        id: row.0.to_string(),
+        user: row.1,
+        kind: row.2,
+        playlist: row.3,
+        song: row.4.map(|x| uint(x).to_string()),
+        detail: row.5,
+        timestamp: uint(row.6),
+    }
+}
+
+fn follow_page(rows: Vec<(Option<String>, i64)>, cursor: i64) -> FollowList {
+    let entries = rows
+        .into_iter()
+        .map(|(display_name, created_at)| FollowResult {
+            display_name,
+            created_at: BigD::from(created_at),
+        })
+        .collect();
+    FollowList::from_page(entries, cursor)
+}
+
+impl SqliteStorage {
+    // open the database (creating it if it doesn't exist) and bring the schema up to date
+    pub async fn new(url: &str) -> anyhow::Result<Self> {
+        let storage = Self::without_migrations(url).await?;
+        SQLITE_MIGRATOR.run(&storage.database).await?;
+        Ok(storage)
+    }
+
+    // open without touching the schema, used by the migrate subcommand
+    pub async fn without_migrations(url: &str) -> anyhow::Result<Self> {
+        let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+        // an in memory database only lives as long as its connection, so there is exactly one
+        // and it is never closed
+        let pool = match url.contains(":memory:") || url.contains("mode=memory") {
+            true => SqlitePoolOptions::new()
+                .max_connections(1)
+                .min_connections(1)
+                .idle_timeout(None)
+                .max_lifetime(None),
+            false => SqlitePoolOptions::new().max_connections(config().max_connections),
+        };
+        let database = pool
+            .connect_timeout(Duration::from_secs(config().max_timeout.into()))
+            .connect_with(options)
+            .await?;
+        Ok(Self { database })
+    }
+
+    // the stored hash of the user with a display name
+    async fn userhash(&self, display_name: &str) -> anyhow::Result<i64> {
+        let hash = sqlx::query_scalar::<_, i64>(
+            "SELECT username FROM auth WHERE display_name = ?1 LIMIT 1",
+        )
+        .bind(display_name)
+        .fetch_optional(&self.database)
+        .timed()
+        .await?;
+        match hash {
+            Some(v) => Ok(v),
+            None => Err(anyhow!("no user of that name")),
+        }
+    }
+
+    async fn find_song(
+        &self,
+        song_name: &str,
+        song_author: &str,
+        song_release: &str,
+    ) -> anyhow::Result<i64> {
+        // what yt-dlp gave the song first, then what it is now so details from SYNC_LIB work too
+        let id = sqlx::query_scalar::<_, i64>(
+            r#"
+            SELECT id FROM songs
+            WHERE (original_title = ?1 AND original_creator = ?2 AND original_upload_date = ?3)
+                OR (title = ?1 AND creator = ?2 AND upload_date = ?3)
+            ORDER BY original_title = ?1 AND original_creator = ?2 AND original_upload_date = ?3 DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(song_name)
+        .bind(song_author)
+        .bind(song_release)
+        .fetch_optional(&self.database)
+        .timed()
+        .await?;
+        match id {
+            Some(v) => Ok(v),
+            None => Err(anyhow!("no song exists")),
+        }
+    }
+
+    async fn is_blocked(&self, a: i64, b: i64) -> anyhow::Result<bool> {
+        Ok(sqlx::query_scalar::<_, bool>(
+            r#"
+            SELECT EXISTS(
+                SELECT 1 FROM blocks
+                WHERE (blocker = ?1 AND blocked = ?2) OR (blocker = ?2 AND blocked = ?1)
+            )
+            "#,
+        )
+        .bind(a)
+        .bind(b)
+        .fetch_one(&self.database)
+        .timed()
+        .await?)
+    }
+
+    async fn is_name_taken(&self, name: &str) -> anyhow::Result<bool> {
+        Ok(sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM auth WHERE display_name = ?1)",
+        )
+        .bind(name)
+        .fetch_one(&self.database)
+        .timed()
+        .await?)
+    }
+
+    // username hashes of one side of the follows table, column is followee or follower
+    async fn follows_of(&self, userhash: u64, column: &str) -> anyhow::Result<Vec<u64>> {
+        let other = match column {
+            "followee" => "follower",
+            _ => "followee",
+        };
+        let users = sqlx::query_scalar::<_, i64>(&format!(
+            "SELECT {other} FROM follows WHERE {column} = ?1"
+        ))
+        .bind(int(userhash))
+        .fetch_all(&self.database)
+        .timed()
+        .await?;
+        Ok(users.into_iter().map(uint).collect())
+    }
+
+    async fn recent_plays(&self, userhash: u64) -> anyhow::Result<Vec<String>> {
+        let plays = sqlx::query_scalar::<_, i64>(
+            "SELECT song_hash FROM plays WHERE username = ?1 ORDER BY played_at DESC LIMIT ?2",
+        )
+        .bind(int(userhash))
+        .bind(RECENT_PLAYS_LIMIT)
+        .fetch_all(&self.database)
+        .timed()
+        .await?;
+        Ok(plays.into_iter().map(|x| uint(x).to_string()).collect())
+    }
+
+    async fn update_playlist_timestamp(&self, username: u64, name: &str) -> anyhow::Result<()> {
+        sqlx::query("UPDATE playlist SET last_update = ?1 WHERE username = ?2 AND name = ?3")
+            .bind(now())
+            .bind(int(username))
+            .bind(name)
+            .execute(&self.database)
+            .timed()
+            .await?;
+        Ok(())
+    }
+
+    async fn insert_track(
+        &self,
+        username: u64,
+        playlist_name: &str,
+        song_hash: u64,
+        song_name: &str,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO playlistdata(username, playlist_name, song_hash, song_name, date_added)
+            VALUES(?1, ?2, ?3, ?4, ?5)
+            "#,
+        )
+        .bind(int(username))
+        .bind(playlist_name)
+        .bind(int(song_hash))
+        .bind(song_name)
+        .bind(now())
+        .execute(&self.database)
+        .timed()
+        .await?;
+        self.update_playlist_timestamp(username, playlist_name)
+            .await?;
+
+        // songs added to public playlists show up in the feed
+        if let Ok(Some(playlist)) = self.request_playlist(username, playlist_name).await {
+            if playlist.public_playlist {
+                let _ = self
+                    .log_activity(
+                        username,
+                        Activity::AddedSong {
+                            playlist: playlist_name.to_string(),
+                            song_hash,
+                        },
+                    )
+                    .await;
+            }
+        }
+        Ok(())
+    }
+
+    async fn remove_track(
+        &self,
+        username: u64,
+        playlist_name: &str,
+        song_hash: u64,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            "DELETE FROM playlistdata WHERE username = ?1 AND playlist_name = ?2 AND song_hash = ?3",
+        )
+        .bind(int(username))
+        .bind(playlist_name)
+        .bind(int(song_hash))
+        .execute(&self.database)
+        .timed()
+        .await?;
+        self.update_playlist_timestamp(username, playlist_name)
+            .await
+    }
+
+    // a page of display names and timestamps, the query takes the user, page size and offset
+    async fn follow_list(
+        &self,
+        query: &str,
+        userhash: u64,
+        cursor: i64,
+    ) -> anyhow::Result<FollowList> {
+        let rows = sqlx::query_as::<_, (Option<String>, i64)>(query)
+            .bind(int(userhash))
+            .bind(FOLLOW_PAGE_SIZE)
+            .bind(cursor)
+            .fetch_all(&self.database)
+            .timed()
+            .await?;
+        Ok(follow_page(rows, cursor))
+    }
+
+    // display names of the users a relation table points to, newest first
+    async fn names(&self, query: &str, userhash: u64) -> anyhow::Result<Vec<String>> {
+        let names = sqlx::query_scalar::<_, Option<String>>(query)
+            .bind(int(userhash))
+            .fetch_all(&self.database)
+            .timed()
+            .await?;
+        Ok(names.into_iter().flatten().collect())
+    }
+
+    async fn redeem_invite(
+        tx: &mut Transaction<'_, Sqlite>,
+        code: &str,
+        invited: u64,
+    ) -> anyhow::Result<()> {
+        let now = now();
+        let invited_by = sqlx::query_scalar::<_, i64>(
+            r#"
+            SELECT created_by FROM invites
+            WHERE code = ?1 AND uses < max_uses AND (expires_at IS NULL OR expires_at > ?2)
+            "#,
+        )
+        .bind(code)
+        .bind(now)
+        .fetch_optional(&mut *tx)
+        .timed()
+        .await?;
+        let invited_by = match invited_by {
+            Some(v) => v,
+            None => return Err(anyhow!("InvalidInvite")),
+        };
+
+        sqlx::query("UPDATE invites SET uses = uses + 1 WHERE code = ?1")
+            .bind(code)
+            .execute(&mut *tx)
+            .timed()
+            .await?;
+        sqlx::query(
+            "INSERT INTO invite_uses(code, invited, invited_by, created_at) VALUES(?1, ?2, ?3, ?4)",
+        )
+        .bind(code)
+        .bind(int(invited))
+        .bind(invited_by)
+        .bind(now)
+        .execute(&mut *tx)
+        .timed()
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn update_downloaded(&self, hash: u64) -> anyhow::Result<()> {
+        sqlx::query("UPDATE songs SET downloaded_timestamp = ?2, downloaded = ?3 WHERE id = ?1")
+            .bind(int(hash))
+            .bind(now())
+            .bind(true)
+            .execute(&self.database)
+            .timed()
+            .await?;
+        Ok(())
+    }
+
+    // remove old copies of a song if they are redownloaded, (based off id only)
+    async fn remove_duplicate_songs(&self) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM songs
+            WHERE EXISTS(
+                SELECT 1 FROM songs b
+                WHERE b.id = songs.id AND songs.downloaded_timestamp < b.downloaded_timestamp
+            )
+            "#,
+        )
+        .execute(&self.database)
+        .timed()
+        .await?;
+        Ok(())
+    }
+
+    async fn sync_library(&self, timestamp: u64) -> anyhow::Result<String> {
+        let rows = sqlx::query_as::<
+            _,
+            (
+                i64,
+                String,
+                Option<String>,
+                Option<String>,
+                Option<String>,
+                Option<String>,
+                Option<String>,
+                Option<String>,
+                Option<String>,
+                Option<String>,
+                bool,
+            ),
+        >(
+            r#"
+            SELECT
+                id, title, uploader, thumbnail, album, album_artist, artist, creator, genre,
+                upload_date, downloaded
+            FROM songs
+            WHERE downloaded_timestamp >= ?1 OR edited_timestamp >= ?1
+            "#,
+        )
+        .bind(int(timestamp))
+        .fetch_all(&self.database)
+        .timed()
+        .await?;
+        let songs: Vec<SongTitleResultOut> = rows
+            .into_iter()
+            .map(|x| SongTitleResultOut {
+                id: uint(x.0).to_string(),
+                title: x.1,
+                uploader: x.2,
+                artwork: Artwork::of(uint(x.0), &x.3),
+                album: x.4,
+                album_artist: x.5,
+                artist: x.6,
+                creator: x.7,
+                genre: x.8,
+                upload_date: x.9,
+                downloaded: x.10,
+            })
+            .collect();
+
+        match serde_json::to_string(&songs) {
+            Ok(v) => Ok(v),
+            Err(_) => Err(anyhow!("FailedToSync")),
+        }
+    }
+
+    async fn insert_song(&self, song: Song) -> anyhow::Result<()> {
+        let id = match song.id {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+        sqlx::query(
+            r#"
+            INSERT INTO songs(
+                id, title, upload_date, uploader, url, genre, thumbnail, album, album_artist,
+                artist, creator, filesize, downloaded_timestamp, downloaded, duration,
+                original_title, original_creator, original_upload_date
+            )
+            VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?2, ?11, ?3)
+            "#,
+        )
+        .bind(int(id))
+        .bind(song.title)
+        .bind(song.upload_date)
+        .bind(song.uploader)
+        .bind(song.url)
+        .bind(song.genre)
+        .bind(song.thumbnail)
+        .bind(song.album)
+        .bind(song.album_artist)
+        .bind(song.artist)
+        .bind(song.creator)
+        .bind(song.filesize)
+        .bind(0_i64)
+        .bind(false)
+        .bind(song.duration)
+        .execute(&self.database)
+        .timed()
+        .await?;
+        Ok(())
+    }
+
+    async fn find_song_from_details(
+        &self,
+        song_name: &str,
+        song_author: &str,
+        song_release: &str,
+    ) -> anyhow::Result<BigD> {
+        let id = self.find_song(song_name, song_author, song_release).await?;
+        Ok(BigD::from(uint(id)))
+    }
+
+    async fn find_song_from_hash(&self, song_hash: u64) -> anyhow::Result<SongDetails> {
+        let song = sqlx::query_as::<_, (i64, String)>("SELECT id, title FROM songs WHERE id = ?1")
+            .bind(int(song_hash))
+            .fetch_optional(&self.database)
+            .timed()
+            .await?;
+        match song {
+            Some((id, title)) => Ok(SongDetails {
+                id: BigD::from(uint(id)),
+                title,
+            }),
+            None => Err(anyhow!("Invalid song")),
+        }
+    }
+
+    // everything stored about a song, used to tag its file
+    async fn song_from_hash(&self, song_hash: u64) -> anyhow::Result<Option<Song>> {
+        let row = sqlx::query_as::<_, SongRow>(
+            r#"
+            SELECT id, title, upload_date, uploader, url, genre, thumbnail, album, album_artist,
+                artist, creator, filesize, duration
+            FROM songs
+            WHERE id = ?1
+            LIMIT 1
+            "#,
+        )
+        .bind(int(song_hash))
+        .fetch_optional(&self.database)
+        .timed()
+        .await?;
+        Ok(row.map(|x| Song {
+            id: Some(uint(x.0)),
+            title: Some(x.1),
+            upload_date: x.2,
+            uploader: x.3,
+            url: x.4,
+            genre: x.5,
+            thumbnail: x.6,
+            album: x.7,
+            album_artist: x.8,
+            artist: x.9,
+            creator: x.10,
+            filesize: x.11,
+            duration: x.12,
+        }))
+    }
+
+    async fn song_thumbnails(&self) -> anyhow::Result<Vec<(u64, String)>> {
+        let rows = sqlx::query_as::<_, (i64, String)>(
+            r#"
+            SELECT id, MAX(thumbnail)
+            FROM songs
+            WHERE thumbnail IS NOT NULL
+            GROUP BY id
+            "#,
+        )
+        .fetch_all(&self.database)
+        .timed()
+        .await?;
+        Ok(rows.into_iter().map(|x| (uint(x.0), x.1)).collect())
+    }
+
+    // remove a song from the library and from every playlist it is in
+    async fn delete_song(&self, song_hash: u64) -> anyhow::Result<()> {
+        let mut tx = self.database.begin().await?;
+        let removed = sqlx::query("DELETE FROM songs WHERE id = ?1")
+            .bind(int(song_hash))
+            .execute(&mut tx)
+            .timed()
+            .await?
+            .rows_affected();
+        if removed == 0 {
+            return Err(anyhow!("InvalidHash"));
+        }
+        sqlx::query("DELETE FROM playlistdata WHERE song_hash = ?1")
+            .bind(int(song_hash))
+            .execute(&mut tx)
+            .timed()
+            .await?;
+        sqlx::query("DELETE FROM song_edits WHERE song_hash = ?1")
+            .bind(int(song_hash))
+            .execute(&mut tx)
+            .timed()
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn song_metadata(&self, song_hash: u64) -> anyhow::Result<Option<SongMetadata>> {
+        let row = sqlx::query_as::<_, MetadataRow>(
+            "SELECT title, artist, album, album_artist, genre FROM songs WHERE id = ?1 LIMIT 1",
+        )
+        .bind(int(song_hash))
+        .fetch_optional(&self.database)
+        .timed()
+        .await?;
+        Ok(row.map(metadata))
+    }
+
+    // edit a song for everyone, the values it had before are kept in song_edits
+    async fn edit_song(
+        &self,
+        song_hash: u64,
+        editor: u64,
+        edit: &SongEdit,
+    ) -> anyhow::Result<SongMetadata> {
+        let mut tx = self.database.begin().await?;
+        let old = sqlx::query_as::<_, MetadataRow>(
+            "SELECT title, artist, album, album_artist, genre FROM songs WHERE id = ?1 LIMIT 1",
+        )
+        .bind(int(song_hash))
+        .fetch_optional(&mut tx)
+        .timed()
+        .await?
+        .map(metadata)
+        .ok_or_else(|| anyhow!("InvalidHash"))?;
+
+        let new = edit.apply(&old)?;
+        if new == old {
+            return Ok(new);
+        }
+        let now = now();
+        sqlx::query(
+            r#"
+            UPDATE songs
+            SET title = ?2, artist = ?3, album = ?4, album_artist = ?5, genre = ?6,
+                edited_timestamp = ?7
+            WHERE id = ?1
+            "#,
+        )
+        .bind(int(song_hash))
+        .bind(&new.title)
+        .bind(&new.artist)
+        .bind(&new.album)
+        .bind(&new.album_artist)
+        .bind(&new.genre)
+        .bind(now)
+        .execute(&mut tx)
+        .timed()
+        .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO song_edits(song_hash, edited_by, old_values, new_values, created_at)
+            VALUES(?1, ?2, ?3, ?4, ?5)
+            "#,
+        )
+        .bind(int(song_hash))
+        .bind(int(editor))
+        .bind(serde_json::to_string(&old)?)
+        .bind(serde_json::to_string(&new)?)
+        .bind(now)
+        .execute(&mut tx)
+        .timed()
+        .await?;
+        tx.commit().await?;
+        Ok(new)
+    }
+
+    async fn song_history(
+        &self,
+        song_hash: u64,
+        cursor: Option<i64>,
+    ) -> anyhow::Result<SongHistory> {
+        let items = sqlx::query_as::<_, (i64, Option<String>, String, String, i64)>(
+            r#"
+            SELECT e.id, a.display_name, e.old_values, e.new_values, e.created_at
+            FROM song_edits e LEFT JOIN auth a ON a.username = e.edited_by
+            WHERE e.song_hash = ?1 AND e.id < ?2
+            ORDER BY e.id DESC
+            LIMIT ?3
+            "#,
+        )
+        .bind(int(song_hash))
+        .bind(cursor.unwrap_or(i64::MAX))
+        .bind(SONG_HISTORY_PAGE_SIZE)
+        .fetch_all(&self.database)
+        .timed()
+        .await?;
+
+        let cursor = match items.len() as i64 {
+            SONG_HISTORY_PAGE_SIZE => items.last().map(|x| x.0.to_string()),
+            _ => None,
+        };
+        Ok(SongHistory {
+            items: items
+                .into_iter()
+                .map(|x| SongEditEntry {
+                    id: x.0.to_string(),
+                    editor: x.1,
+                    old: metadata_from_json(&x.2),
+                    new: metadata_from_json(&x.3),
+                    timestamp: uint(x.4),
+                })
+                .collect(),
+            cursor,
+        })
+    }
+
+    // the name is set on every entry of the song in the playlists of the user, None removes it
+    async fn rename_song(
+        &self,
+        userhash: u64,
+        song_hash: u64,
+        name: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let updated = sqlx::query(
+            "UPDATE playlistdata SET custom_name = ?3 WHERE username = ?1 AND song_hash = ?2",
+        )
+        .bind(int(userhash))
+        .bind(int(song_hash))
+        .bind(name)
+        .execute(&self.database)
+        .timed()
+        .await?
+        .rows_affected();
+        if updated == 0 {
+            return Err(anyhow!("NotInPlaylist"));
+        }
+        Ok(())
+    }
+
+    // ordered by the hash as an unsigned number like the NUMERIC column in postgres
+    async fn song_overrides(&self, userhash: u64) -> anyhow::Result<Vec<SongOverride>> {
+        let rows = sqlx::query_as::<_, (i64, String)>(
+            r#"
+            SELECT song_hash, MAX(custom_name)
+            FROM playlistdata
+            WHERE username = ?1 AND custom_name IS NOT NULL
+            GROUP BY song_hash
+            "#,
+        )
+        .bind(int(userhash))
+        .fetch_all(&self.database)
+        .timed()
+        .await?;
+        let mut overrides = rows
+            .into_iter()
+            .map(|(id, name)| (uint(id), name))
+            .collect::<Vec<(u64, String)>>();
+        overrides.sort_by_key(|x| x.0);
+        Ok(overrides
+            .into_iter()
+            .map(|(id, name)| SongOverride {
+                id: id.to_string(),
+                name,
+            })
+            .collect())
+    }
+
+    // songs the enrichment pass hasn't looked at, songs an admin already edited are skipped
+    async fn unenriched_songs(&self, limit: i64) -> anyhow::Result<Vec<(u64, SongFields)>> {
+        let rows = sqlx::query_as::<_, UnenrichedRow>(
+            r#"
+            SELECT id, title, artist, album, album_artist, genre, uploader, creator, upload_date
+            FROM songs
+            WHERE NOT enriched AND edited_timestamp IS NULL
+            GROUP BY id
+            LIMIT ?1
+            "#,
+        )
+        .bind(limit)
+        .fetch_all(&self.database)
+        .timed()
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|x| {
+                let fields = SongFields {
+                    title: x.1,
+                    artist: x.2,
+                    album: x.3,
+                    album_artist: x.4,
+                    genre: x.5,
+                    uploader: x.6,
+                    creator: x.7,
+                    upload_date: x.8,
+                };
+                (uint(x.0), fields)
+            })
+            .collect())
+    }
+
+    // changes are saved like an EDIT_SONG by nobody, see db.rs
+    async fn save_enrichment(
+        &self,
+        song_hash: u64,
+        old: &SongFields,
+        new: &SongFields,
+    ) -> anyhow::Result<()> {
+        if new == old {
+            sqlx::query("UPDATE songs SET enriched = TRUE WHERE id = ?1")
+                .bind(int(song_hash))
+                .execute(&self.database)
+                .timed()
+                .await?;
+            return Ok(());
+        }
+        let mut tx = self.database.begin().await?;
+        let now = now();
+        sqlx::query(
+            r#"
+            UPDATE songs
+            SET title = ?2, artist = ?3, album = ?4, upload_date = ?5, edited_timestamp = ?6,
+                enriched = TRUE
+            WHERE id = ?1
+            "#,
+        )
+        .bind(int(song_hash))
+        .bind(&new.title)
+        .bind(&new.artist)
+        .bind(&new.album)
+        .bind(&new.upload_date)
+        .bind(now)
+        .execute(&mut tx)
+        .timed()
+        .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO song_edits(song_hash, edited_by, old_values, new_values, created_at)
+            VALUES(?1, 0, ?2, ?3, ?4)
+            "#,
+        )
+        .bind(int(song_hash))
+        .bind(serde_json::to_string(&old.metadata())?)
+        .bind(serde_json::to_string(&new.metadata())?)
+        .bind(now)
+        .execute(&mut tx)
+        .timed()
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /*
+     * Create a new account, the invite is redeemed in the same transaction so a failed signup
+     * doesn't use it up. Accounts created without an invite (with the ADMIN_KEY) are admins
+     */
+    async fn new_user(
+        &self,
+        username: &str,
+        password: &str,
+        invite: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let username = hash(username.as_bytes());
+        let mut tx = self.database.begin().await?;
+        // without an invite this is the first admin, once there is one the rest are made with
+        // ADMIN_PROMOTE
+        if invite.is_none()
+            && sqlx::query_scalar::<_, bool>("SELECT EXISTS(SELECT 1 FROM auth WHERE admin)")
+                .fetch_one(&mut tx)
+                .timed()
+                .await?
+        {
+            return Err(anyhow!("AdminExists"));
+        }
+        sqlx::query(
+            "INSERT INTO auth(username, password, admin, last_login) VALUES(?1, ?2, ?3, ?4)",
+        )
+        .bind(int(username))
+        .bind(int(hash(password.as_bytes())))
+        .bind(invite.is_none())
+        .bind(now())
+        .execute(&mut tx)
+        .timed()
+        .await?;
+
+        if let Some(code) = invite {
+            Self::redeem_invite(&mut tx, code, username).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn check_if_username_exists_in_auth(&self, username: &str) -> anyhow::Result<bool> {
+        Ok(
+            sqlx::query_scalar::<_, bool>("SELECT EXISTS(SELECT 1 FROM auth WHERE username = ?1)")
+                .bind(int(hash(username.as_bytes())))
+                .fetch_one(&self.database)
+                .timed()
+                .await?,
+        )
+    }
+
+    async fn check_if_user_exists_in_auth(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> anyhow::Result<(bool, Option<u64>)> {
+        let username = hash(username.as_bytes());
+        let exists = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM auth WHERE username = ?1 AND password = ?2)",
+        )
+        .bind(int(username))
+        .bind(int(hash(password.as_bytes())))
+        .fetch_one(&self.database)
+        .timed()
+        .await?;
+        Ok((exists, Some(username)))
+    }
+
+    async fn update_login_timestamp(&self, userhash: u64) -> anyhow::Result<()> {
+        sqlx::query("UPDATE auth SET last_login = ?2 WHERE username = ?1")
+            .bind(int(userhash))
+            .bind(now())
+            .execute(&self.database)
+            .timed()
+            .await?;
+        Ok(())
+    }
+
+    async fn userhash_from_username(&self, display_name: &str) -> anyhow::Result<BigD> {
+        Ok(BigD::from(uint(self.userhash(display_name).await?)))
+    }
+
+    async fn get_user_data(&self, userhash: u64) -> anyhow::Result<Option<UserData>> {
+        let data = sqlx::query_as::<
+            _,
+            (
+                Option<bool>,
+                Option<String>,
+                Option<bool>,
+                Option<String>,
+                Option<String>,
+            ),
+        >(
+            r#"
+            SELECT public_profile, display_name, share_status, now_playing, public_status
+            FROM auth
+            WHERE username = ?1
+            "#,
+        )
+        .bind(int(userhash))
+        .fetch_optional(&self.database)
+        .timed()
+        .await?;
+        let data = match data {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        // recent plays and follows are derived from their own tables rather than stored on the
+        // user
+        Ok(Some(UserData {
+            public_profile: data.0,
+            display_name: data.1,
+            share_status: data.2,
+            now_playing: data.3,
+            public_status: data.4,
+            recent_plays: Some(self.recent_plays(userhash).await?),
+            followers: Some(self.follows_of(userhash, "followee").await?),
+            following: Some(self.follows_of(userhash, "follower").await?),
+        }))
+    }
+
+    async fn set_userdata(&self, username: u64, new_data: UserData) -> anyhow::Result<()> {
+        if let Some(v) = &new_data.display_name {
+            if self.is_name_taken(v).await? {
+                return Err(anyhow!("DisplayNameAlreadyTaken"));
+            }
+        }
+        sqlx::query(
+            r#"
+            UPDATE auth
+            SET public_profile = ?1, display_name = ?2, share_status = ?3, now_playing = ?4,
+                public_status = ?5
+            WHERE username = ?6
+            "#,
+        )
+        .bind(new_data.public_profile)
+        .bind(new_data.display_name)
+        .bind(new_data.share_status)
+        .bind(new_data.now_playing)
+        .bind(new_data.public_status)
+        .bind(int(username))
+        .execute(&self.database)
+        .timed()
+        .await?;
+        Ok(())
+    }
+
+    async fn request_profile(
+        &self,
+        viewer: u64,
+        display_name: &str,
+    ) -> anyhow::Result<Option<(u64, Profile)>> {
+        let userhash = uint(self.userhash(display_name).await?);
+        if self.is_blocked(int(viewer), int(userhash)).await? {
+            return Ok(None);
+        }
+        let userdata = match self.get_user_data(userhash).await? {
+            Some(v) if v.public_profile == Some(true) => v,
+            _ => return Ok(None),
+        };
+
+        let playlists = sqlx::query_as::<_, (String, Option<String>, bool)>(
+            r#"
+            SELECT name, description, public_playlist
+            FROM playlist
+            WHERE username = ?1 AND public_playlist = TRUE
+            ORDER BY name
+            "#,
+        )
+        .bind(int(userhash))
+        .fetch_all(&self.database)
+        .timed()
+        .await?
+        .into_iter()
+        .map(|(name, description, public_playlist)| Playlist {
+            name,
+            description,
+            public_playlist,
+        })
+        .collect();
+
+        Ok(Some((
+            userhash,
+            Profile {
+                userdata,
+                playlists,
+            },
+        )))
+    }
+
+    async fn change_password(
+        &self,
+        userhash: u64,
+        old_password: &str,
+        new_password: &str,
+    ) -> anyhow::Result<()> {
+        let updated =
+            sqlx::query("UPDATE auth SET password = ?3 WHERE username = ?1 AND password = ?2")
+                .bind(int(userhash))
+                .bind(int(hash(old_password.as_bytes())))
+                .bind(int(hash(new_password.as_bytes())))
+                .execute(&self.database)
+                .timed()
+                .await?
+                .rows_affected();
+        match updated {
+            0 => Err(anyhow!("InvalidPassword")),
+            _ => Ok(()),
+        }
+    }
+
+    /*
+     * Every table refers to the user by the hash of their username, so renaming moves every row
+     * over to the hash of the new name. Returns the new hash
+     */
+    async fn change_username(
+        &self,
+        userhash: u64,
+        password: &str,
+        new_username: &str,
+    ) -> anyhow::Result<u64> {
+        let new_hash = hash(new_username.as_bytes());
+        if self.check_if_username_exists_in_auth(new_username).await? {
+            return Err(anyhow!("UsernameTaken"));
+        }
+        let (old, new) = (int(userhash), int(new_hash));
+
+        let mut tx = self.database.begin().await?;
+        let updated =
+            sqlx::query("UPDATE auth SET username = ?2 WHERE username = ?1 AND password = ?3")
+                .bind(old)
+                .bind(new)
+                .bind(int(hash(password.as_bytes())))
+                .execute(&mut tx)
+                .timed()
+                .await?
+                .rows_affected();
+        if updated == 0 {
+            return Err(anyhow!("InvalidPassword"));
+        }
+
+        let columns = [
+            ("playlist", "username"),
+            ("playlistdata", "username"),
+            ("plays", "username"),
+            ("activity", "username"),
+            ("follows", "follower"),
+            ("follows", "followee"),
+            ("follow_requests", "requester"),
+            ("follow_requests", "target"),
+            ("blocks", "blocker"),
+            ("blocks", "blocked"),
+            ("mutes", "muter"),
+            ("mutes", "muted"),
+            ("bans", "username"),
+            ("admin_log", "admin"),
+            ("invites", "created_by"),
+            ("invite_uses", "invited"),
+            ("invite_uses", "invited_by"),
+            ("song_edits", "edited_by"),
+        ];
+        for (table, column) in columns {
+            sqlx::query(&format!(
+                "UPDATE {table} SET {column} = ?2 WHERE {column} = ?1"
+            ))
+            .bind(old)
+            .bind(new)
+            .execute(&mut tx)
+            .timed()
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(new_hash)
+    }
+
+    // the admin log and the record of who the user invited are kept, song edits lose their editor
+    async fn delete_account(&self, userhash: u64, password: &str) -> anyhow::Result<()> {
+        let user = int(userhash);
+        let mut tx = self.database.begin().await?;
+        let deleted = sqlx::query("DELETE FROM auth WHERE username = ?1 AND password = ?2")
+            .bind(user)
+            .bind(int(hash(password.as_bytes())))
+            .execute(&mut tx)
+            .timed()
+            .await?
+            .rows_affected();
+        if deleted == 0 {
+            return Err(anyhow!("InvalidPassword"));
+        }
+
+        let queries = [
+            "DELETE FROM playlist WHERE username = ?1",
+            "DELETE FROM playlistdata WHERE username = ?1",
+            "DELETE FROM plays WHERE username = ?1",
+            "DELETE FROM activity WHERE username = ?1",
+            "DELETE FROM follows WHERE follower = ?1 OR followee = ?1",
+            "DELETE FROM follow_requests WHERE requester = ?1 OR target = ?1",
+            "DELETE FROM blocks WHERE blocker = ?1 OR blocked = ?1",
+            "DELETE FROM mutes WHERE muter = ?1 OR muted = ?1",
+            "DELETE FROM bans WHERE username = ?1",
+            "DELETE FROM invites WHERE created_by = ?1",
+            "DELETE FROM invite_uses WHERE invited = ?1",
+            "UPDATE song_edits SET edited_by = 0 WHERE edited_by = ?1",
+        ];
+        for query in queries {
+            sqlx::query(query)
+                .bind(user)
+                .execute(&mut tx)
+                .timed()
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn request_playlist(
+        &self,
+        userhash: u64,
+        name: &str,
+    ) -> anyhow::Result<Option<Playlist>> {
+        let playlist = sqlx::query_as::<_, (String, Option<String>, bool)>(
+            "SELECT name, description, public_playlist FROM playlist WHERE username = ?1 AND name = ?2",
+        )
+        .bind(int(userhash))
+        .bind(name)
+        .fetch_optional(&self.database)
+        .timed()
+        .await?;
+        Ok(
+            playlist.map(|(name, description, public_playlist)| Playlist {
+                name,
+                description,
+                public_playlist,
+            }),
+        )
+    }
+
+    async fn create_playlist(
+        &self,
+        username: u64,
+        name: &str,
+        public_playlist: &str,
+    ) -> anyhow::Result<bool> {
+        if self.request_playlist(username, name).await?.is_some() {
+            return Ok(false);
+        }
+        let public_playlist = match public_playlist.to_lowercase().as_str() {
+            "true" => true,
+            "false" => false,
+            _ => return Err(anyhow!("InvalidMessage")),
+        };
+
+        let timestamp = now();
+        sqlx::query(
+            r#"
+            INSERT INTO playlist(username, name, creation_timestamp, public_playlist, last_update)
+            VALUES(?1, ?2, ?3, ?4, ?5)
+            "#,
+        )
+        .bind(int(username))
+        .bind(name)
+        .bind(timestamp)
+        .bind(public_playlist)
+        .bind(timestamp)
+        .execute(&self.database)
+        .timed()
+        .await?;
+
+        if public_playlist {
+            let _ = self
+                .log_activity(
+                    username,
+                    Activity::CreatedPlaylist {
+                        playlist: name.to_string(),
+                    },
+                )
+                .await;
+        }
+        Ok(true)
+    }
+
+    async fn update_playlist(
+        &self,
+        username: u64,
+        playlist_name: &str,
+        data: Playlist,
+    ) -> anyhow::Result<()> {
+        let name = truncate(&data.name, 30);
+        sqlx::query(
+            r#"
+            UPDATE playlist
+            SET name = ?1, description = ?2, public_playlist = ?3, last_update = ?4
+            WHERE username = ?5 AND name = ?6
+            "#,
+        )
+        .bind(&name)
+        .bind(data.description.map(|x| truncate(&x, 200)))
+        .bind(data.public_playlist)
+        .bind(now())
+        .bind(int(username))
+        .bind(playlist_name)
+        .execute(&self.database)
+        .timed()
+        .await?;
+        Ok(())
+    }
+
+    async fn set_playlist_description(
+        &self,
+        username: u64,
+        name: &str,
+        description: &str,
+    ) -> anyhow::Result<()> {
+        sqlx::query("UPDATE playlist SET description = ?3 WHERE username = ?1 AND name = ?2")
+            .bind(int(username))
+            .bind(name)
+            .bind(description)
+            .execute(&self.database)
+            .timed()
+            .await?;
+        Ok(())
+    }
+
+    async fn rename_playlist(
+        &self,
+        username: u64,
+        name: &str,
+        new_name: &str,
+    ) -> anyhow::Result<()> {
+        sqlx::query("UPDATE playlist SET name = ?3 WHERE username = ?1 AND name = ?2")
+            .bind(int(username))
+            .bind(name)
+            .bind(new_name)
+            .execute(&self.database)
+            .timed()
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_playlist(&self, username: u64, playlist_name: &str) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM playlist WHERE username = ?1 AND name = ?2")
+            .bind(int(username))
+            .bind(playlist_name)
+            .execute(&self.database)
+            .timed()
+            .await?;
+        Ok(())
+    }
+
+    async fn append_song(
+        &self,
+        username: u64,
+        playlist_name: &str,
+        song_name: &str,
+        song_author: &str,
+        song_release: &str,
+    ) -> anyhow::Result<()> {
+        let song = self.find_song(song_name, song_author, song_release).await?;
+        self.insert_track(username, playlist_name, uint(song), song_name)
+            .await
+    }
+
+    async fn append_song_from_hash(
+        &self,
+        username: u64,
+        playlist_name: &str,
+        song_hash: u64,
+    ) -> anyhow::Result<()> {
+        let song = self.find_song_from_hash(song_hash).await?;
+        self.insert_track(username, playlist_name, song_hash, &song.title)
+            .await
+    }
+
+    async fn remove_song(
+        &self,
+        username: u64,
+        playlist_name: &str,
+        song_name: &str,
+        song_author: &str,
+        song_release: &str,
+    ) -> anyhow::Result<()> {
+        let song = self.find_song(song_name, song_author, song_release).await?;
+        self.remove_track(username, playlist_name, uint(song)).await
+    }
+
+    async fn remove_song_from_hash(
+        &self,
+        username: u64,
+        playlist_name: &str,
+        song_hash: u64,
+    ) -> anyhow::Result<()> {
+        self.remove_track(username, playlist_name, song_hash).await
+    }
+
+    async fn set_playlist_custom_image(
+        &self,
+        username: u64,
+        playlist_name: &str,
+        custom_image: bool,
+    ) -> anyhow::Result<()> {
+        sqlx::query("UPDATE playlist SET custom_image = ?3 WHERE username = ?1 AND name = ?2")
+            .bind(int(username))
+            .bind(playlist_name)
+            .bind(custom_image)
+            .execute(&self.database)
+            .timed()
+            .await?;
+        Ok(())
+    }
+
+    async fn playlist_cover_songs(
+        &self,
+        username: u64,
+        playlist_name: &str,
+    ) -> anyhow::Result<Option<Vec<(u64, Option<String>)>>> {
+        let custom_image = sqlx::query_scalar::<_, bool>(
+            "SELECT custom_image FROM playlist WHERE username = ?1 AND name = ?2 LIMIT 1",
+        )
+        .bind(int(username))
+        .bind(playlist_name)
+        .fetch_optional(&self.database)
+        .timed()
+        .await?;
+        if custom_image != Some(false) {
+            return Ok(None);
+        }
+        let rows = sqlx::query_as::<_, (i64, Option<String>)>(
+            r#"
+            SELECT playlistdata.song_hash, songs.album
+            FROM playlistdata
+            JOIN songs ON songs.id = playlistdata.song_hash
+            WHERE playlistdata.username = ?1 AND playlistdata.playlist_name = ?2
+                AND songs.thumbnail IS NOT NULL
+            ORDER BY playlistdata.date_added, playlistdata.rowid
+            "#,
+        )
+        .bind(int(username))
+        .bind(playlist_name)
+        .fetch_all(&self.database)
+        .timed()
+        .await?;
+        Ok(Some(rows.into_iter().map(|x| (uint(x.0), x.1)).collect()))
+    }
+
+    /*
+     * Following is a single row in follows, if the profile is private a follow request is created
+     * instead which has to be accepted
+     */
+    async fn follow_user(
+        &self,
+        userhash: u64,
+        name_to_follow: &str,
+    ) -> anyhow::Result<FollowStatus> {
+        let (target, public_profile) = match sqlx::query_as::<_, (i64, Option<bool>)>(
+            "SELECT username, public_profile FROM auth WHERE display_name = ?1 LIMIT 1",
+        )
+        .bind(name_to_follow)
+        .fetch_optional(&self.database)
+        .timed()
+        .await?
+        {
+            Some(v) => v,
+            None => return Err(anyhow!("no user of that name")),
+        };
+        let user = int(userhash);
+        if target == user {
+            return Err(anyhow!("CannotFollowSelf"));
+        }
+        if self.is_blocked(user, target).await? {
+            return Err(anyhow!("Blocked"));
+        }
+
+        if public_profile != Some(true) {
+            if self
+                .follows_of(userhash, "follower")
+                .await?
+                .contains(&uint(target))
+            {
+                return Ok(FollowStatus::Following);
+            }
+            let requested = sqlx::query(
+                r#"
+                INSERT OR IGNORE INTO follow_requests(requester, target, created_at)
+                VALUES(?1, ?2, ?3)
+                "#,
+            )
+            .bind(user)
+            .bind(target)
+            .bind(now())
+            .execute(&self.database)
+            .timed()
+            .await?
+            .rows_affected();
+            return Ok(FollowStatus::Requested {
+                target: uint(target),
+                new: requested > 0,
+            });
+        }
+
+        sqlx::query(
+            "INSERT OR IGNORE INTO follows(follower, followee, created_at) VALUES(?1, ?2, ?3)",
+        )
+        .bind(user)
+        .bind(target)
+        .bind(now())
+        .execute(&self.database)
+        .timed()
+        .await?;
+
+        let _ = self
+            .log_activity(
+                userhash,
+                Activity::Followed {
+                    display_name: name_to_follow.to_string(),
+                },
+            )
+            .await;
+        Ok(FollowStatus::Following)
+    }
+
+    async fn unfollow_user(&self, userhash: u64, name_to_unfollow: &str) -> anyhow::Result<()> {
+        let target = self.userhash(name_to_unfollow).await?;
+        let mut tx = self.database.begin().await?;
+        sqlx::query("DELETE FROM follows WHERE follower = ?1 AND followee = ?2")
+            .bind(int(userhash))
+            .bind(target)
+            .execute(&mut tx)
+            .timed()
+            .await?;
+        // unfollowing a private profile also cancels a pending request
+        sqlx::query("DELETE FROM follow_requests WHERE requester = ?1 AND target = ?2")
+            .bind(int(userhash))
+            .bind(target)
+            .execute(&mut tx)
+            .timed()
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn accept_follow(&self, userhash: u64, requester_name: &str) -> anyhow::Result<u64> {
+        let requester = uint(self.userhash(requester_name).await?);
+        let mut tx = self.database.begin().await?;
+        let removed =
+            sqlx::query("DELETE FROM follow_requests WHERE requester = ?1 AND target = ?2")
+                .bind(int(requester))
+                .bind(int(userhash))
+                .execute(&mut tx)
+                .timed()
+                .await?
+                .rows_affected();
+        if removed == 0 {
+            return Err(anyhow!("NoFollowRequest"));
+        }
+        sqlx::query(
+            "INSERT OR IGNORE INTO follows(follower, followee, created_at) VALUES(?1, ?2, ?3)",
+        )
+        .bind(int(requester))
+        .bind(int(userhash))
+        .bind(now())
+        .execute(&mut tx)
+        .timed()
+        .await?;
+        tx.commit().await?;
+
+        if let Some(display_name) = self
+            .get_user_data(userhash)
+            .await?
+            .and_then(|x| x.display_name)
+        {
+            let _ = self
+                .log_activity(requester, Activity::Followed { display_name })
+                .await;
+        }
+        Ok(requester)
+    }
+
+    async fn reject_follow(&self, userhash: u64, requester_name: &str) -> anyhow::Result<()> {
+        let requester = uint(self.userhash(requester_name).await?);
+        let removed =
+            sqlx::query("DELETE FROM follow_requests WHERE requester = ?1 AND target = ?2")
+                .bind(int(requester))
+                .bind(int(userhash))
+                .execute(&self.database)
+                .timed()
+                .await?
+                .rows_affected();
+        match removed {
+            0 => Err(anyhow!("NoFollowRequest")),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list_followers(&self, userhash: u64, cursor: i64) -> anyhow::Result<FollowList> {
+        self.follow_list(
+            r#"
+            SELECT a.display_name, f.created_at
+            FROM follows f INNER JOIN auth a ON a.username = f.follower
+            WHERE f.followee = ?1
+            ORDER BY f.created_at DESC, f.follower
+            LIMIT ?2 OFFSET ?3
+            "#,
+            userhash,
+            cursor,
+        )
+        .await
+    }
+
+    async fn list_following(&self, userhash: u64, cursor: i64) -> anyhow::Result<FollowList> {
+        self.follow_list(
+            r#"
+            SELECT a.display_name, f.created_at
+            FROM follows f INNER JOIN auth a ON a.username = f.followee
+            WHERE f.follower = ?1
+            ORDER BY f.created_at DESC, f.followee
+            LIMIT ?2 OFFSET ?3
+            "#,
+            userhash,
+            cursor,
+        )
+        .await
+    }
+
+    async fn list_follow_requests(&self, userhash: u64, cursor: i64) -> anyhow::Result<FollowList> {
+        self.follow_list(
+            r#"
+            SELECT a.display_name, r.created_at
+            FROM follow_requests r INNER JOIN auth a ON a.username = r.requester
+            WHERE r.target = ?1
+            ORDER BY r.created_at DESC, r.requester
+            LIMIT ?2 OFFSET ?3
+            "#,
+            userhash,
+            cursor,
+        )
+        .await
+    }
+
+    /*
+     * Blocking removes any follow or follow request between the two users, the rest (hiding
+     * profiles, activity and presence) is checked whenever those are read
+     */
+    async fn block_user(&self, userhash: u64, name_to_block: &str) -> anyhow::Result<()> {
+        let (user, target) = (int(userhash), self.userhash(name_to_block).await?);
+        if target == user {
+            return Err(anyhow!("CannotBlockSelf"));
+        }
+
+        let mut tx = self.database.begin().await?;
+        sqlx::query(
+            "INSERT OR IGNORE INTO blocks(blocker, blocked, created_at) VALUES(?1, ?2, ?3)",
+        )
+        .bind(user)
+        .bind(target)
+        .bind(now())
+        .execute(&mut tx)
+        .timed()
+        .await?;
+        let queries = [
+            r#"
+            DELETE FROM follows
+            WHERE (follower = ?1 AND followee = ?2) OR (follower = ?2 AND followee = ?1)
+            "#,
+            r#"
+            DELETE FROM follow_requests
+            WHERE (requester = ?1 AND target = ?2) OR (requester = ?2 AND target = ?1)
+            "#,
+        ];
+        for query in queries {
+            sqlx::query(query)
+                .bind(user)
+                .bind(target)
+                .execute(&mut tx)
+                .timed()
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn unblock_user(&self, userhash: u64, name_to_unblock: &str) -> anyhow::Result<()> {
+        let target = self.userhash(name_to_unblock).await?;
+        sqlx::query("DELETE FROM blocks WHERE blocker = ?1 AND blocked = ?2")
+            .bind(int(userhash))
+            .bind(target)
+            .execute(&self.database)
+            .timed()
+            .await?;
+        Ok(())
+    }
+
+    async fn mute_user(&self, userhash: u64, name_to_mute: &str) -> anyhow::Result<()> {
+        let target = self.userhash(name_to_mute).await?;
+        sqlx::query("INSERT OR IGNORE INTO mutes(muter, muted, created_at) VALUES(?1, ?2, ?3)")
+            .bind(int(userhash))
+            .bind(target)
+            .bind(now())
+            .execute(&self.database)
+            .timed()
+            .await?;
+        Ok(())
+    }
+
+    async fn unmute_user(&self, userhash: u64, name_to_unmute: &str) -> anyhow::Result<()> {
+        let target = self.userhash(name_to_unmute).await?;
+        sqlx::query("DELETE FROM mutes WHERE muter = ?1 AND muted = ?2")
+            .bind(int(userhash))
+            .bind(target)
+            .execute(&self.database)
+            .timed()
+            .await?;
+        Ok(())
+    }
+
+    async fn list_blocked(&self, userhash: u64) -> anyhow::Result<Vec<String>> {
+        self.names(
+            r#"
+            SELECT a.display_name
+            FROM blocks b INNER JOIN auth a ON a.username = b.blocked
+            WHERE b.blocker = ?1
+            ORDER BY b.created_at DESC
+            "#,
+            userhash,
+        )
+        .await
+    }
+
+    async fn list_muted(&self, userhash: u64) -> anyhow::Result<Vec<String>> {
+        self.names(
+            r#"
+            SELECT a.display_name
+            FROM mutes m INNER JOIN auth a ON a.username = m.muted
+            WHERE m.muter = ?1
+            ORDER BY m.created_at DESC
+            "#,
+            userhash,
+        )
+        .await
+    }
+
+    // followers that should be sent live events of the user, leaving out anyone that muted them
+    async fn audience_of(&self, userhash: u64) -> anyhow::Result<Vec<u64>> {
+        let audience = sqlx::query_scalar::<_, i64>(
+            r#"
+            SELECT f.follower
+            FROM follows f
+            WHERE
+                f.followee = ?1
+                AND NOT EXISTS(SELECT 1 FROM mutes m WHERE m.muter = f.follower AND m.muted = ?1)
+                AND NOT EXISTS(
+                    SELECT 1 FROM blocks b
+                    WHERE
+                        (b.blocker = f.follower AND b.blocked = ?1)
+                        OR (b.blocker = ?1 AND b.blocked = f.follower)
+                )
+            "#,
+        )
+        .bind(int(userhash))
+        .fetch_all(&self.database)
+        .timed()
+        .await?;
+        Ok(audience.into_iter().map(uint).collect())
+    }
+
+    // followed users whose live events the user wants to see, leaving out anyone they muted
+    async fn visible_following(&self, userhash: u64) -> anyhow::Result<Vec<u64>> {
+        let following = sqlx::query_scalar::<_, i64>(
+            r#"
+            SELECT f.followee
+            FROM follows f
+            WHERE
+                f.follower = ?1
+                AND NOT EXISTS(SELECT 1 FROM mutes m WHERE m.muter = ?1 AND m.muted = f.followee)
+                AND NOT EXISTS(
+                    SELECT 1 FROM blocks b
+                    WHERE
+                        (b.blocker = f.followee AND b.blocked = ?1)
+                        OR (b.blocker = ?1 AND b.blocked = f.followee)
+                )
+            "#,
+        )
+        .bind(int(userhash))
+        .fetch_all(&self.database)
+        .timed()
+        .await?;
+        Ok(following.into_iter().map(uint).collect())
+    }
+
+    // repeated reports of the same song inside of PLAY_DEDUP_SECONDS are ignored
+    async fn record_play(&self, userhash: u64, song_hash: u64) -> anyhow::Result<()> {
+        self.find_song_from_hash(song_hash).await?;
+        let now = now();
+        sqlx::query(
+            r#"
+            INSERT INTO plays(username, song_hash, played_at)
+            SELECT ?1, ?2, ?3
+            WHERE NOT EXISTS(
+                SELECT 1 FROM plays WHERE username = ?1 AND song_hash = ?2 AND played_at > ?4
+            )
+            "#,
+        )
+        .bind(int(userhash))
+        .bind(int(song_hash))
+        .bind(now)
+        .bind(now - PLAY_DEDUP_SECONDS as i64)
+        .execute(&self.database)
+        .timed()
+        .await?;
+        Ok(())
+    }
+
+    async fn top_songs(
+        &self,
+        userhash: u64,
+        from: u64,
+        to: u64,
+        limit: i64,
+    ) -> anyhow::Result<Vec<TopSong>> {
+        let songs = sqlx::query_as::<_, (i64, String, Option<String>, i64)>(
+            r#"
+            SELECT p.song_hash, s.title, COALESCE(s.artist, s.creator, s.uploader), COUNT(*)
+            FROM plays p INNER JOIN songs s ON s.id = p.song_hash
+            WHERE p.username = ?1 AND p.played_at >= ?2 AND p.played_at < ?3
+            GROUP BY p.song_hash, s.title, s.artist, s.creator, s.uploader
+            ORDER BY COUNT(*) DESC
+            LIMIT ?4
+            "#,
+        )
+        .bind(int(userhash))
+        .bind(int(from))
+        .bind(int(to))
+        .bind(limit.clamp(1, MAX_TOP_LIMIT))
+        .fetch_all(&self.database)
+        .timed()
+        .await?;
+
+        Ok(songs
+            .into_iter()
+            .map(|(id, title, artist, plays)| TopSong {
+                id: uint(id).to_string(),
+                title,
+                artist,
+                plays,
+            })
+            .collect())
+    }
+
+    async fn top_artists(
+        &self,
+        userhash: u64,
+        from: u64,
+        to: u64,
+        limit: i64,
+    ) -> anyhow::Result<Vec<TopArtist>> {
+        let artists = sqlx::query_as::<_, (String, i64)>(
+            r#"
+            SELECT COALESCE(s.artist, s.creator, s.uploader), COUNT(*)
+            FROM plays p INNER JOIN songs s ON s.id = p.song_hash
+            WHERE
+                p.username = ?1
+                AND p.played_at >= ?2
+                AND p.played_at < ?3
+                AND COALESCE(s.artist, s.creator, s.uploader) IS NOT NULL
+            GROUP BY 1
+            ORDER BY COUNT(*) DESC
+            LIMIT ?4
+            "#,
+        )
+        .bind(int(userhash))
+        .bind(int(from))
+        .bind(int(to))
+        .bind(limit.clamp(1, MAX_TOP_LIMIT))
+        .fetch_all(&self.database)
+        .timed()
+        .await?;
+
+        Ok(artists
+            .into_iter()
+            .map(|(artist, plays)| TopArtist { artist, plays })
+            .collect())
+    }
+
+    async fn listen_time(&self, userhash: u64, from: u64, to: u64) -> anyhow::Result<ListenTime> {
+        let (plays, unique_songs, seconds) = sqlx::query_as::<_, (i64, i64, i64)>(
+            r#"
+            SELECT COUNT(*), COUNT(DISTINCT p.song_hash), COALESCE(SUM(s.duration), 0)
+            FROM plays p LEFT JOIN songs s ON s.id = p.song_hash
+            WHERE p.username = ?1 AND p.played_at >= ?2 AND p.played_at < ?3
+            "#,
+        )
+        .bind(int(userhash))
+        .bind(int(from))
+        .bind(int(to))
+        .fetch_one(&self.database)
+        .timed()
+        .await?;
+
+        Ok(ListenTime {
+            plays,
+            unique_songs,
+            seconds: seconds.max(0) as u64,
+        })
+    }
+
+    async fn year_in_review(&self, userhash: u64, year: i64) -> anyhow::Result<YearInReview> {
+        if !(1970..=9999).contains(&year) {
+            return Err(anyhow!("InvalidYear"));
+        }
+        let from = year_start(year) as u64;
+        let to = year_start(year + 1) as u64;
+        let total = self.listen_time(userhash, from, to).await?;
+
+        Ok(YearInReview {
+            year,
+            plays: total.plays,
+            unique_songs: total.unique_songs,
+            seconds: total.seconds,
+            top_songs: self.top_songs(userhash, from, to, 5).await?,
+            top_artists: self.top_artists(userhash, from, to, 5).await?,
+        })
+    }
+
+    async fn log_activity(&self, userhash: u64, activity: Activity) -> anyhow::Result<()> {
+        let kind = activity.kind();
+        let (playlist, song_hash, detail) = match activity {
+            Activity::CreatedPlaylist { playlist } => (Some(playlist), None, None),
+            Activity::AddedSong {
+                playlist,
+                song_hash,
+            } => (Some(playlist), Some(int(song_hash)), None),
+            Activity::QueuedSong { url } => (None, None, Some(url)),
+            Activity::Followed { display_name } => (None, None, Some(display_name)),
+        };
+        sqlx::query(
+            r#"
+            INSERT INTO activity(username, kind, playlist, song_hash, detail, created_at)
+            VALUES(?1, ?2, ?3, ?4, ?5, ?6)
+            "#,
+        )
+        .bind(int(userhash))
+        .bind(kind)
+        .bind(playlist)
+        .bind(song_hash)
+        .bind(detail)
+        .bind(now())
+        .execute(&self.database)
+        .timed()
+        .await?;
+        Ok(())
+    }
+
+    async fn feed(&self, userhash: u64, cursor: Option<i64>) -> anyhow::Result<Feed> {
+        let items = sqlx::query_as::<_, FeedRow>(
+            r#"
+            SELECT a.id, u.display_name, a.kind, a.playlist, a.song_hash, a.detail, a.created_at
+            FROM
+                activity a
+                INNER JOIN auth u ON u.username = a.username
+                INNER JOIN follows f ON f.followee = a.username
+            WHERE
+                f.follower = ?1
+                AND u.public_profile = TRUE
+                AND NOT EXISTS(SELECT 1 FROM mutes m WHERE m.muter = ?1 AND m.muted = a.username)
+                AND NOT EXISTS(
+                    SELECT 1 FROM blocks b
+                    WHERE
+                        (b.blocker = a.username AND b.blocked = ?1)
+                        OR (b.blocker = ?1 AND b.blocked = a.username)
+                )
+                AND a.id < ?2
+            ORDER BY a.id DESC
+            LIMIT ?3
+            "#,
+        )
+        .bind(int(userhash))
+        .bind(cursor.unwrap_or(i64::MAX))
+        .bind(FEED_PAGE_SIZE)
+        .fetch_all(&self.database)
+        .timed()
+        .await?;
+
+        let cursor = match items.len() as i64 {
+            FEED_PAGE_SIZE => items.last().map(|x| x.0.to_string()),
+            _ => None,
+        };
+        Ok(Feed {
+            items: items.into_iter().map(feed_item).collect(),
+            cursor,
+        })
+    }
+
+    async fn is_admin(&self, username: u64) -> anyhow::Result<bool> {
+        Ok(sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM auth WHERE username = ?1 AND admin = TRUE)",
+        )
+        .bind(int(username))
+        .fetch_one(&self.database)
+        .timed()
+        .await?)
+    }
+
+    async fn set_admin(&self, userhash: u64, admin: bool) -> anyhow::Result<()> {
+        sqlx::query("UPDATE auth SET admin = ?2 WHERE username = ?1")
+            .bind(int(userhash))
+            .bind(admin)
+            .execute(&self.database)
+            .timed()
+            .await?;
+        Ok(())
+    }
+
+    // ban a user for an amount of seconds, or until they are unbanned if none is given
+    async fn ban_user(
+        &self,
+        userhash: u64,
+        banned_by: u64,
+        seconds: Option<u64>,
+    ) -> anyhow::Result<()> {
+        let now = now();
+        sqlx::query(
+            r#"
+            INSERT INTO bans(username, banned_by, expires_at, created_at)
+            VALUES(?1, ?2, ?3, ?4)
+            ON CONFLICT (username) DO UPDATE SET
+                banned_by = excluded.banned_by,
+                expires_at = excluded.expires_at,
+                created_at = excluded.created_at
+            "#,
+        )
+        .bind(int(userhash))
+        .bind(int(banned_by))
+        .bind(seconds.map(|x| now + x as i64))
+        .bind(now)
+        .execute(&self.database)
+        .timed()
+        .await?;
+        Ok(())
+    }
+
+    async fn unban_user(&self, userhash: u64) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM bans WHERE username = ?1")
+            .bind(int(userhash))
+            .execute(&self.database)
+            .timed()
+            .await?;
+        Ok(())
+    }
+
+    async fn is_banned(&self, userhash: u64) -> anyhow::Result<bool> {
+        Ok(sqlx::query_scalar::<_, bool>(
+            r#"
+            SELECT EXISTS(
+                SELECT 1 FROM bans
+                WHERE username = ?1 AND (expires_at IS NULL OR expires_at > ?2)
+            )
+            "#,
+        )
+        .bind(int(userhash))
+        .bind(now())
+        .fetch_one(&self.database)
+        .timed()
+        .await?)
+    }
+
+    async fn log_admin_action(
+        &self,
+        admin: u64,
+        command: &str,
+        args: &str,
+        response: &str,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO admin_log(admin, command, args, response, created_at)
+            VALUES(?1, ?2, ?3, ?4, ?5)
+            "#,
+        )
+        .bind(int(admin))
+        .bind(command)
+        .bind(args)
+        .bind(response)
+        .bind(now())
+        .execute(&self.database)
+        .timed()
+        .await?;
+        Ok(())
+    }
+
+    async fn admin_log(&self, cursor: Option<i64>) -> anyhow::Result<AdminLog> {
+        let items = sqlx::query_as::<_, (i64, Option<String>, String, String, String, i64)>(
+            r#"
+            SELECT l.id, a.display_name, l.command, l.args, l.response, l.created_at
+            FROM admin_log l LEFT JOIN auth a ON a.username = l.admin
+            WHERE l.id < ?1
+            ORDER BY l.id DESC
+            LIMIT ?2
+            "#,
+        )
+        .bind(cursor.unwrap_or(i64::MAX))
+        .bind(ADMIN_LOG_PAGE_SIZE)
+        .fetch_all(&self.database)
+        .timed()
+        .await?;
+
+        let cursor = match items.len() as i64 {
+            ADMIN_LOG_PAGE_SIZE => items.last().map(|x| x.0.to_string()),
+            _ => None,
+        };
+        Ok(AdminLog {
+            items: items
+                .into_iter()
+                .map(|x| AdminLogEntry {
+                    id: x.0.to_string(),
+                    admin: x.1,
+                    command: x.2,
+                    args: x.3,
+                    response: x.4,
+                    timestamp: uint(x.5),
+                })
+                .collect(),
+            cursor,
+        })
+    }
+
+    /*
+     * Create an invite code, users that aren't admins need enough invites left to cover every use
+     * of it
+     */
+    async fn create_invite(
+        &self,
+        userhash: u64,
+        max_uses: i32,
+        expires_in: Option<u64>,
+    ) -> anyhow::Result<String> {
+        let admin = self.is_admin(userhash).await?;
+        let mut tx = self.database.begin().await?;
+        if !admin {
+            let updated = sqlx::query(
+                r#"
+                UPDATE auth SET invites_left = invites_left - ?2
+                WHERE username = ?1 AND invites_left >= ?2
+                "#,
+            )
+            .bind(int(userhash))
+            .bind(max_uses)
+            .execute(&mut tx)
+            .timed()
+            .await?
+            .rows_affected();
+            if updated == 0 {
+                return Err(anyhow!("NoInvitesLeft"));
+            }
+        }
+
+        let now = now();
+        let code = generate_invite_code();
+        sqlx::query(
+            r#"
+            INSERT INTO invites(code, created_by, max_uses, expires_at, created_at)
+            VALUES(?1, ?2, ?3, ?4, ?5)
+            "#,
+        )
+        .bind(&code)
+        .bind(int(userhash))
+        .bind(max_uses)
+        .bind(expires_in.map(|x| now + x as i64))
+        .bind(now)
+        .execute(&mut tx)
+        .timed()
+        .await?;
+        tx.commit().await?;
+        Ok(code)
+    }
+
+    // the invites created by a user and who used them, newest first
+    async fn list_invites(&self, userhash: u64) -> anyhow::Result<InviteList> {
+        let invites_left =
+            sqlx::query_scalar::<_, i32>("SELECT invites_left FROM auth WHERE username = ?1")
+                .bind(int(userhash))
+                .fetch_optional(&self.database)
+                .timed()
+                .await?
+                .unwrap_or_default();
+
+        let invites = sqlx::query_as::<_, (String, i32, i32, Option<i64>, i64)>(
+            r#"
+            SELECT code, uses, max_uses, expires_at, created_at
+            FROM invites
+            WHERE created_by = ?1
+            ORDER BY created_at DESC
+            "#,
+        )
+        .bind(int(userhash))
+        .fetch_all(&self.database)
+        .timed()
+        .await?;
+
+        let used = sqlx::query_as::<_, (String, Option<String>)>(
+            r#"
+            SELECT u.code, a.display_name
+            FROM invite_uses u INNER JOIN auth a ON a.username = u.invited
+            WHERE u.invited_by = ?1
+            ORDER BY u.created_at
+            "#,
+        )
+        .bind(int(userhash))
+        .fetch_all(&self.database)
+        .timed()
+        .await?;
+
+        Ok(InviteList {
+            invites_left,
+            invites: invites
+                .into_iter()
+                .map(|(code, uses, max_uses, expires_at, created_at)| Invite {
+                    used_by: used
+                        .iter()
+                        .filter(|u| u.0 == code)
+                        .filter_map(|u| u.1.clone())
+                        .collect(),
+                    code,
+                    uses,
+                    max_uses,
+                    expires_at: expires_at.map(uint),
+                    created_at: uint(created_at),
+                })
+                .collect(),
+        })
+    }
+
+    // delete an unused or partly used invite, the uses left over go back to the allowance
+    async fn revoke_invite(&self, userhash: u64, code: &str) -> anyhow::Result<()> {
+        let mut tx = self.database.begin().await?;
+        let left_over = sqlx::query_scalar::<_, i32>(
+            "SELECT max_uses - uses FROM invites WHERE code = ?1 AND created_by = ?2",
+        )
+        .bind(code)
+        .bind(int(userhash))
+        .fetch_optional(&mut tx)
+        .timed()
+        .await?;
+        let left_over = match left_over {
+            Some(v) => v,
+            None => return Err(anyhow!("InvalidInvite")),
+        };
+
+        sqlx::query("DELETE FROM invites WHERE code = ?1")
+            .bind(code)
+            .execute(&mut tx)
+            .timed()
+            .await?;
+        sqlx::query(
+            "UPDATE auth SET invites_left = invites_left + ?2 WHERE username = ?1 AND admin = FALSE",
+        )
+        .bind(int(userhash))
+        .bind(left_over)
+        .execute(&mut tx)
+        .timed()
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn set_invites_left(&self, userhash: u64, invites_left: i32) -> anyhow::Result<()> {
+        sqlx::query("UPDATE auth SET invites_left = ?2 WHERE username = ?1")
+            .bind(int(userhash))
+            .bind(invites_left)
+            .execute(&self.database)
+            .timed()
+            .await?;
+        Ok(())
+    }
+
+    async fn export_playlists(&self, userhash: u64) -> anyhow::Result<Vec<ExportedPlaylist>> {
+        let playlists = sqlx::query_as::<_, (String, Option<String>, bool, i64, i64)>(
+            r#"
+            SELECT name, description, public_playlist, creation_timestamp, last_update
+            FROM playlist
+            WHERE username = ?1
+            ORDER BY creation_timestamp
+            "#,
+        )
+        .bind(int(userhash))
+        .fetch_all(&self.database)
+        .timed()
+        .await?;
+
+        let tracks = sqlx::query_as::<_, (String, i64, String, Option<String>, i64)>(
+            r#"
+            SELECT playlist_name, song_hash, song_name, custom_name, date_added
+            FROM playlistdata
+            WHERE username = ?1
+            ORDER BY date_added
+            "#,
+        )
+        .bind(int(userhash))
+        .fetch_all(&self.database)
+        .timed()
+        .await?;
+
+        Ok(playlists
+            .into_iter()
+            .map(
+                |(name, description, public_playlist, created_at, last_update)| ExportedPlaylist {
+                    tracks: tracks
+                        .iter()
+                        .filter(|t| t.0 == name)
+                        .map(|t| ExportedTrack {
+                            song: uint(t.1).to_string(),
+                            title: t.2.clone(),
+                            custom_name: t.3.clone(),
+                            date_added: uint(t.4),
+                        })
+                        .collect(),
+                    name,
+                    description,
+                    public_playlist,
+                    created_at: uint(created_at),
+                    last_update: uint(last_update),
+                    image: None,
+                },
+            )
+            .collect())
+    }
+
+    async fn export_plays(&self, userhash: u64) -> anyhow::Result<Vec<ExportedPlay>> {
+        let plays = sqlx::query_as::<_, (i64, Option<String>, i64)>(
+            r#"
+            SELECT
+                p.song_hash,
+                (SELECT s.title FROM songs s WHERE s.id = p.song_hash LIMIT 1),
+                p.played_at
+            FROM plays p
+            WHERE p.username = ?1
+            ORDER BY p.played_at
+            "#,
+        )
+        .bind(int(userhash))
+        .fetch_all(&self.database)
+        .timed()
+        .await?;
+
+        Ok(plays
+            .into_iter()
+            .map(|(song, title, played_at)| ExportedPlay {
+                song: uint(song).to_string(),
+                title,
+                played_at: uint(played_at),
+            })
+            .collect())
+    }
+
+    async fn export_activity(&self, userhash: u64) -> anyhow::Result<Vec<FeedItem>> {
+        let items = sqlx::query_as::<_, FeedRow>(
+            r#"
+            SELECT a.id, u.display_name, a.kind, a.playlist, a.song_hash, a.detail, a.created_at
+            FROM activity a INNER JOIN auth u ON u.username = a.username
+            WHERE a.username = ?1
+            ORDER BY a.id
+            "#,
+        )
+        .bind(int(userhash))
+        .fetch_all(&self.database)
+        .timed()
+        .await?;
+
+        Ok(items.into_iter().map(feed_item).collect())
+    }
+
+    fn pool_status(&self) -> Option<PoolStatus> {
+        Some(PoolStatus {
+            connections: self.database.size(),
+            idle: self.database.num_idle(),
+            closed: self.database.is_closed(),
+        })
+    }
+}
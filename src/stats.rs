@@ -0,0 +1,61 @@
+use serde::Serialize;
+
+// how many of the most recent plays are shown as recent_plays on the userdata
+pub(crate) const RECENT_PLAYS_LIMIT: i64 = 20;
+
+// if the same song is reported as played again within this many seconds it is treated as the same
+// play, streaming in chunks or sending NOW_PLAYING a few times should not inflate the stats
+pub(crate) const PLAY_DEDUP_SECONDS: u64 = 30;
+
+// default amount of entries returned by TOP_SONGS and TOP_ARTISTS
+pub(crate) const DEFAULT_TOP_LIMIT: i64 = 10;
+pub(crate) const MAX_TOP_LIMIT: i64 = 100;
+
+#[derive(Serialize)]
+pub(crate) struct TopSong {
+    pub id: String,
+    pub title: String,
+    pub artist: Option<String>,
+    pub plays: i64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct TopArtist {
+    pub artist: String,
+    pub plays: i64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ListenTime {
+    pub plays: i64,
+    pub unique_songs: i64,
+    pub seconds: u64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct YearInReview {
+    pub year: i64,
+    pub plays: i64,
+    pub unique_songs: i64,
+    pub seconds: u64,
+    pub top_songs: Vec<TopSong>,
+    pub top_artists: Vec<TopArtist>,
+}
+
+/*
+ * Seconds since unix epoch at the start of the given year (UTC), used to turn YEAR_IN_REVIEW into
+ * a time range
+ *
+ * This is the days from civil algorithm from http://howardhinnant.github.io/date_algorithms.html
+ * with the month and day fixed to january first, which saves pulling in a date crate for one
+ * calculation
+ */
+pub(crate) fn year_start(year: i64) -> i64 {
+    // the algorithm counts years as starting in march, so january belongs to the previous year
+    let y = year - 1;
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    // january first is day 306 of a march based year
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + 306;
+    (era * 146097 + doe - 719468) * 86400
+}
@@ -0,0 +1,161 @@
+use crate::activity::{Activity, Feed, FeedItem};
+use crate::admin::AdminLog;
+use crate::enrich::SongFields;
+use crate::export::{ExportedPlay, ExportedPlaylist};
+use crate::invites::InviteList;
+use crate::metadata::{SongEdit, SongHistory, SongMetadata, SongOverride};
+use crate::stats::{ListenTime, TopArtist, TopSong, YearInReview};
+use crate::user::{FollowList, FollowStatus, Playlist, Profile, UserData};
+use crate::{BigD, Database, PoolStatus, Song, SongDetails};
+use async_trait::async_trait;
+
+/*
+ * Everything the server stores goes through Storage so the backend can be swapped out, the
+ * postgres Database is used normally, SqliteStorage (see sqlite.rs) when database_url is sqlite:
+ * and MemoryStorage (see memory.rs) when it is memory://, the integration tests run against the
+ * last two
+ *
+ * The postgres methods already exist on Database with the same signatures so the trait is
+ * generated from this list along with an implementation that forwards to them, a new method has
+ * to be added here and to every backend
+ */
+macro_rules! storage {
+    ($($name:ident($($arg:ident: $ty:ty),*) -> $ret:ty;)*) => {
+        #[async_trait]
+        pub(crate) trait Storage: Send + Sync {
+            $(async fn $name(&self, $($arg: $ty),*) -> anyhow::Result<$ret>;)*
+
+            // connection pool numbers for the dashboard, None for backends without a pool
+            fn pool_status(&self) -> Option<PoolStatus> {
+                None
+            }
+        }
+
+        #[async_trait]
+        impl Storage for Database {
+            $(async fn $name(&self, $($arg: $ty),*) -> anyhow::Result<$ret> {
+                Database::$name(self, $($arg),*).await
+            })*
+
+            fn pool_status(&self) -> Option<PoolStatus> {
+                Some(PoolStatus {
+                    connections: self.database.size(),
+                    idle: self.database.num_idle(),
+                    closed: self.database.is_closed(),
+                })
+            }
+        }
+    };
+}
+
+storage! {
+    // songs
+    update_downloaded(hash: u64) -> ();
+    remove_duplicate_songs() -> ();
+    sync_library(timestamp: u64) -> String;
+    insert_song(song: Song) -> ();
+    find_song_from_details(song_name: &str, song_author: &str, song_release: &str) -> BigD;
+    find_song_from_hash(song_hash: u64) -> SongDetails;
+    song_from_hash(song_hash: u64) -> Option<Song>;
+    // every song with a thumbnail as (id, thumbnail), see artwork.rs
+    song_thumbnails() -> Vec<(u64, String)>;
+    delete_song(song_hash: u64) -> ();
+
+    // metadata corrections, see metadata.rs
+    song_metadata(song_hash: u64) -> Option<SongMetadata>;
+    edit_song(song_hash: u64, editor: u64, edit: &SongEdit) -> SongMetadata;
+    song_history(song_hash: u64, cursor: Option<i64>) -> SongHistory;
+    rename_song(userhash: u64, song_hash: u64, name: Option<&str>) -> ();
+    song_overrides(userhash: u64) -> Vec<SongOverride>;
+
+    // the enrichment pass, see enrich.rs
+    unenriched_songs(limit: i64) -> Vec<(u64, SongFields)>;
+    save_enrichment(song_hash: u64, old: &SongFields, new: &SongFields) -> ();
+
+    // accounts
+    new_user(username: &str, password: &str, invite: Option<&str>) -> ();
+    check_if_username_exists_in_auth(username: &str) -> bool;
+    check_if_user_exists_in_auth(username: &str, password: &str) -> (bool, Option<u64>);
+    update_login_timestamp(userhash: u64) -> ();
+    userhash_from_username(display_name: &str) -> BigD;
+    get_user_data(userhash: u64) -> Option<UserData>;
+    set_userdata(username: u64, new_data: UserData) -> ();
+    request_profile(viewer: u64, display_name: &str) -> Option<(u64, Profile)>;
+    change_password(userhash: u64, old_password: &str, new_password: &str) -> ();
+    change_username(userhash: u64, password: &str, new_username: &str) -> u64;
+    delete_account(userhash: u64, password: &str) -> ();
+
+    // playlists, create_playlist is false if the playlist already existed
+    request_playlist(userhash: u64, name: &str) -> Option<Playlist>;
+    create_playlist(username: u64, name: &str, public_playlist: &str) -> bool;
+    update_playlist(username: u64, playlist_name: &str, data: Playlist) -> ();
+    set_playlist_description(username: u64, name: &str, description: &str) -> ();
+    rename_playlist(username: u64, name: &str, new_name: &str) -> ();
+    delete_playlist(username: u64, playlist_name: &str) -> ();
+    append_song(
+        username: u64,
+        playlist_name: &str,
+        song_name: &str,
+        song_author: &str,
+        song_release: &str
+    ) -> ();
+    append_song_from_hash(username: u64, playlist_name: &str, song_hash: u64) -> ();
+    remove_song(
+        username: u64,
+        playlist_name: &str,
+        song_name: &str,
+        song_author: &str,
+        song_release: &str
+    ) -> ();
+    remove_song_from_hash(username: u64, playlist_name: &str, song_hash: u64) -> ();
+    // playlist covers, see pictures.rs. The songs with artwork in the order they were added as
+    // (id, album), None if the playlist has a custom image
+    set_playlist_custom_image(username: u64, playlist_name: &str, custom_image: bool) -> ();
+    playlist_cover_songs(username: u64, playlist_name: &str) -> Option<Vec<(u64, Option<String>)>>;
+
+    // follows, blocks and mutes
+    follow_user(userhash: u64, name_to_follow: &str) -> FollowStatus;
+    unfollow_user(userhash: u64, name_to_unfollow: &str) -> ();
+    accept_follow(userhash: u64, requester_name: &str) -> u64;
+    reject_follow(userhash: u64, requester_name: &str) -> ();
+    list_followers(userhash: u64, cursor: i64) -> FollowList;
+    list_following(userhash: u64, cursor: i64) -> FollowList;
+    list_follow_requests(userhash: u64, cursor: i64) -> FollowList;
+    block_user(userhash: u64, name_to_block: &str) -> ();
+    unblock_user(userhash: u64, name_to_unblock: &str) -> ();
+    mute_user(userhash: u64, name_to_mute: &str) -> ();
+    unmute_user(userhash: u64, name_to_unmute: &str) -> ();
+    list_blocked(userhash: u64) -> Vec<String>;
+    list_muted(userhash: u64) -> Vec<String>;
+    audience_of(userhash: u64) -> Vec<u64>;
+    visible_following(userhash: u64) -> Vec<u64>;
+
+    // plays and activity
+    record_play(userhash: u64, song_hash: u64) -> ();
+    top_songs(userhash: u64, from: u64, to: u64, limit: i64) -> Vec<TopSong>;
+    top_artists(userhash: u64, from: u64, to: u64, limit: i64) -> Vec<TopArtist>;
+    listen_time(userhash: u64, from: u64, to: u64) -> ListenTime;
+    year_in_review(userhash: u64, year: i64) -> YearInReview;
+    log_activity(userhash: u64, activity: Activity) -> ();
+    feed(userhash: u64, cursor: Option<i64>) -> Feed;
+
+    // admin
+    is_admin(username: u64) -> bool;
+    set_admin(userhash: u64, admin: bool) -> ();
+    ban_user(userhash: u64, banned_by: u64, seconds: Option<u64>) -> ();
+    unban_user(userhash: u64) -> ();
+    is_banned(userhash: u64) -> bool;
+    log_admin_action(admin: u64, command: &str, args: &str, response: &str) -> ();
+    admin_log(cursor: Option<i64>) -> AdminLog;
+
+    // invites
+    create_invite(userhash: u64, max_uses: i32, expires_in: Option<u64>) -> String;
+    list_invites(userhash: u64) -> InviteList;
+    revoke_invite(userhash: u64, code: &str) -> ();
+    set_invites_left(userhash: u64, invites_left: i32) -> ();
+
+    // EXPORT_MY_DATA
+    export_playlists(userhash: u64) -> Vec<ExportedPlaylist>;
+    export_plays(userhash: u64) -> Vec<ExportedPlay>;
+    export_activity(userhash: u64) -> Vec<FeedItem>;
+}
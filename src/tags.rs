@@ -0,0 +1,281 @@
+use crate::{cover, with_state, AppState, Bucket, FileStore, Song, Storage};
+use id3::frame::{Picture, PictureType};
+use id3::{Tag, TagLike, Timestamp, Version};
+use log::error;
+use std::io::Cursor;
+use warp::filters::BoxedFilter;
+use warp::http::header::{CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE};
+use warp::http::{HeaderValue, StatusCode};
+use warp::hyper::Body;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+/*
+ * ID3v2 tags for the songs in CACHE_DIR (or the songs bucket), so a song downloaded for offline use
+ * plays with its title, artist, album, album artist, year, genre and cover art anywhere
+ *
+ * Songs are tagged once they are stored, with the largest size of the artwork as the front cover
+ * (see artwork.rs). When the metadata of a song changes (EDIT_SONG or the enrichment pass) the
+ * tags are written again and the cover that is already in the file is kept.
+ * /<instance_key>-download/<id> serves the file as an attachment named "Artist - Title.mp3"
+ *
+ * yt-dlp gives whatever audio the video has, which is usually webm (opus) or m4a (aac) and only
+ * sometimes mp3. Only mp3s are tagged, the rest are stored as they were downloaded and served with
+ * the extension and content type of what they really are
+ */
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum AudioFormat {
+    Mp3,
+    WebM,
+    Mp4,
+    Ogg,
+    Flac,
+    Unknown,
+}
+
+impl AudioFormat {
+    // from the first bytes of the file, yt-dlp's extension isn't kept
+    pub fn detect(data: &[u8]) -> Self {
+        match data {
+            [b'I', b'D', b'3', ..] => Self::Mp3,
+            // an mpeg frame sync, the layer bits are 00 for aac in adts
+            [0xff, v, ..] if v & 0xe0 == 0xe0 && v & 0x06 != 0 => Self::Mp3,
+            [0x1a, 0x45, 0xdf, 0xa3, ..] => Self::WebM,
+            [_, _, _, _, b'f', b't', b'y', b'p', ..] => Self::Mp4,
+            [b'O', b'g', b'g', b'S', ..] => Self::Ogg,
+            [b'f', b'L', b'a', b'C', ..] => Self::Flac,
+            _ => Self::Unknown,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Mp3 => "audio/mpeg",
+            Self::WebM => "audio/webm",
+            Self::Mp4 => "audio/mp4",
+            Self::Ogg => "audio/ogg",
+            Self::Flac => "audio/flac",
+            Self::Unknown => "application/octet-stream",
+        }
+    }
+
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            Self::Mp3 => Some("mp3"),
+            Self::WebM => Some("webm"),
+            Self::Mp4 => Some("m4a"),
+            Self::Ogg => Some("ogg"),
+            Self::Flac => Some("flac"),
+            Self::Unknown => None,
+        }
+    }
+}
+
+// the upload date as YYYY-MM-DD, YYYYMMDD (before enrichment) or only a year
+fn recorded(upload_date: &str) -> Option<Timestamp> {
+    let digits: String = upload_date.chars().filter(|x| x != &'-').collect();
+    if !digits.chars().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+    let part = |at: usize| digits.get(at..at + 2).and_then(|x| x.parse::<u8>().ok());
+    Some(Timestamp {
+        year: digits.get(0..4)?.parse().ok()?,
+        month: part(4).filter(|x| (1..=12).contains(x)),
+        day: part(6).filter(|x| (1..=31).contains(x)),
+        hour: None,
+        minute: None,
+        second: None,
+    })
+}
+
+/*
+ * The file with its ID3 tag replaced by one made from the song, anything else in the old tag is
+ * dropped apart from the pictures, which are kept unless there is a new cover. Files that aren't
+ * mp3s are given back as they are
+ */
+pub(crate) fn write_tags(
+    data: Vec<u8>,
+    song: &Song,
+    cover: Option<Vec<u8>>,
+) -> anyhow::Result<Vec<u8>> {
+    if AudioFormat::detect(&data) != AudioFormat::Mp3 {
+        return Ok(data);
+    }
+    let old = Tag::read_from2(Cursor::new(&data)).unwrap_or_default();
+    let mut tag = Tag::new();
+    let fields = [
+        ("TIT2", &song.title),
+        ("TPE1", &song.artist),
+        ("TALB", &song.album),
+        ("TPE2", &song.album_artist),
+        ("TCON", &song.genre),
+    ];
+    for (id, value) in fields {
+        if let Some(v) = value.as_deref().map(str::trim).filter(|x| !x.is_empty()) {
+            tag.set_text(id, v);
+        }
+    }
+    if let Some(v) = song.upload_date.as_deref().and_then(recorded) {
+        tag.set_date_recorded(v);
+    }
+    match cover {
+        Some(data) => {
+            tag.add_frame(Picture {
+                mime_type: String::from("image/jpeg"),
+                picture_type: PictureType::CoverFront,
+                description: String::new(),
+                data,
+            });
+        }
+        None => {
+            for picture in old.pictures() {
+                tag.add_frame(picture.clone());
+            }
+        }
+    }
+
+    let mut file = Cursor::new(data);
+    tag.write_to_file(&mut file, Version::Id3v24)?;
+    Ok(file.into_inner())
+}
+
+// tag a song that is already stored, the file is read and put back whole
+pub(crate) async fn tag_stored_song(
+    files: &dyn FileStore,
+    song: &Song,
+    cover: Option<Vec<u8>>,
+) -> anyhow::Result<()> {
+    let name = match song.id {
+        Some(v) => v.to_string(),
+        None => return Ok(()),
+    };
+    let data = match files.get(Bucket::Songs, &name).await? {
+        Some(v) if AudioFormat::detect(&v) == AudioFormat::Mp3 => v,
+        _ => return Ok(()),
+    };
+    let song = song.clone();
+    let tagged = tokio::task::spawn_blocking(move || write_tags(data, &song, cover)).await??;
+    files.put(Bucket::Songs, &name, tagged).await
+}
+
+// the first tags of a downloaded song with its artwork as the cover, if tagging fails the file
+// is stored as it was downloaded
+pub(crate) async fn tag_new_song(data: Vec<u8>, song: &Song, files: &dyn FileStore) -> Vec<u8> {
+    let cover = match (song.id, &song.thumbnail) {
+        (Some(id), Some(_)) => cover(files, id).await,
+        _ => None,
+    };
+    let (song, original) = (song.clone(), data.clone());
+    let tagged = tokio::task::spawn_blocking(move || write_tags(data, &song, cover)).await;
+    match tagged.map_err(anyhow::Error::from).and_then(|x| x) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("failed to tag a downloaded song: {e}");
+            original
+        }
+    }
+}
+
+// write the tags of a song again after its metadata changed, songs not stored yet are skipped
+pub(crate) async fn retag_song(storage: &dyn Storage, files: &dyn FileStore, song_hash: u64) {
+    let result = match storage.song_from_hash(song_hash).await {
+        Ok(Some(song)) => tag_stored_song(files, &song, None).await,
+        Ok(None) => Ok(()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        error!("failed to tag song {song_hash}: {e}");
+    }
+}
+
+/*
+ * "Artist - Title.mp3" (or whatever the format really is) with the characters that aren't allowed
+ * in file names on some systems taken out
+ */
+pub(crate) fn download_filename(song: &Song, format: AudioFormat) -> String {
+    let title = song.title.clone().unwrap_or_default();
+    let name = match song.artist.as_deref().filter(|x| !x.trim().is_empty()) {
+        Some(artist) => format!("{artist} - {title}"),
+        None => title,
+    };
+    let name: String = name
+        .chars()
+        .filter(|x| {
+            !x.is_control() && !matches!(x, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|')
+        })
+        .collect();
+    let name = match name.trim().trim_matches('.') {
+        "" => "song",
+        v => v,
+    };
+    match format.extension() {
+        Some(extension) => format!("{name}.{extension}"),
+        None => name.to_string(),
+    }
+}
+
+// the characters besides letters and digits that can be left as they are in filename*=
+const ATTR_CHARS: &[u8] = b"!#$&+-.^_`|~";
+
+// filename= for older clients with anything that isn't ascii replaced, filename*= has all of it
+fn content_disposition(filename: &str) -> String {
+    let ascii: String = filename
+        .chars()
+        .map(|x| match x.is_ascii() {
+            true => x,
+            false => '_',
+        })
+        .collect();
+    let encoded: String = filename
+        .bytes()
+        .map(
+            |x| match x.is_ascii_alphanumeric() || ATTR_CHARS.contains(&x) {
+                true => String::from(x as char),
+                false => format!("%{x:02X}"),
+            },
+        )
+        .collect();
+    format!("attachment; filename=\"{ascii}\"; filename*=UTF-8''{encoded}")
+}
+
+async fn download_song(id: u64, state: AppState) -> Result<Response, Rejection> {
+    let song = match state.storage.song_from_hash(id).await {
+        Ok(Some(v)) => v,
+        Ok(None) => return Err(warp::reject::not_found()),
+        Err(e) => {
+            error!("failed to find song {id}: {e}");
+            return Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        }
+    };
+    let data = match state.files.get(Bucket::Songs, &id.to_string()).await {
+        Ok(Some(v)) => v,
+        Ok(None) => return Err(warp::reject::not_found()),
+        Err(e) => {
+            error!("failed to read song {id}: {e}");
+            return Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        }
+    };
+    let (length, format) = (data.len(), AudioFormat::detect(&data));
+    let mut response = Response::new(Body::from(data));
+    let headers = response.headers_mut();
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(format.content_type()),
+    );
+    headers.insert(CONTENT_LENGTH, length.into());
+    if let Ok(v) = content_disposition(&download_filename(&song, format)).parse() {
+        headers.insert(CONTENT_DISPOSITION, v);
+    }
+    Ok(response)
+}
+
+// /<id> under whatever path this is mounted at
+pub(crate) fn download_route(state: AppState) -> BoxedFilter<(Response,)> {
+    warp::get()
+        .and(warp::path::param::<u64>())
+        .and(warp::path::end())
+        .and(with_state(state))
+        .and_then(download_song)
+        .boxed()
+}
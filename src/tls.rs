@@ -0,0 +1,174 @@
+use crate::config;
+use anyhow::anyhow;
+use hyper::server::conn::Http;
+use log::{debug, error, info};
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::crypto::ring::sign::any_supported_type;
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+use warp::http::header::LOCATION;
+use warp::http::{Response, StatusCode};
+use warp::{Filter, Rejection, Reply};
+
+/*
+ * HTTPS for the websocket and file routes when tls_cert and tls_key are set
+ *
+ * warp's own tls server can't swap certificates without a restart, so connections are accepted
+ * here and handed to hyper with a resolver that always gives out the newest certificate. The
+ * files are checked every TLS_RELOAD_SECONDS and reloaded when they change, so renewing with
+ * certbot or similar doesn't need a restart. A bad renewal is logged and the old certificate is
+ * kept
+ *
+ * HTTP/2 is offered through ALPN, websockets still go over HTTP/1.1
+ */
+const TLS_RELOAD_SECONDS: u64 = 60;
+
+fn load_certified_key(cert: &str, key: &str) -> anyhow::Result<CertifiedKey> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert)?))
+        .collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(anyhow!("no certificates found in {cert}"));
+    }
+    let key = match rustls_pemfile::private_key(&mut BufReader::new(File::open(key)?))? {
+        Some(v) => v,
+        None => return Err(anyhow!("no private key found in {key}")),
+    };
+    let key = any_supported_type(&key).map_err(|e| anyhow!("unsupported private key: {e}"))?;
+    Ok(CertifiedKey::new(certs, key))
+}
+
+fn modified(cert: &str, key: &str) -> Option<(SystemTime, SystemTime)> {
+    let cert = std::fs::metadata(cert).and_then(|x| x.modified()).ok()?;
+    let key = std::fs::metadata(key).and_then(|x| x.modified()).ok()?;
+    Some((cert, key))
+}
+
+#[derive(Debug)]
+struct ReloadingCert {
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for ReloadingCert {
+    fn resolve(&self, _: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+// swap in the new certificate whenever either file changes
+async fn watch_cert(resolver: Arc<ReloadingCert>, cert: String, key: String) {
+    let mut last = modified(&cert, &key);
+    loop {
+        tokio::time::sleep(Duration::from_secs(TLS_RELOAD_SECONDS)).await;
+        let now = modified(&cert, &key);
+        if now.is_none() || now == last {
+            continue;
+        }
+        match load_certified_key(&cert, &key) {
+            Ok(v) => {
+                *resolver.current.write().unwrap() = Arc::new(v);
+                last = now;
+                info!("reloaded tls certificate from {cert}");
+            }
+            // the files might be half written, try again next time
+            Err(e) => error!("failed to reload tls certificate, keeping the old one: {e}"),
+        }
+    }
+}
+
+pub(crate) async fn serve_tls<F, R>(routes: F, addr: SocketAddr) -> anyhow::Result<()>
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    F::Future: Send,
+    R: Reply,
+{
+    let (cert, key) = match (&config().tls_cert, &config().tls_key) {
+        (Some(cert), Some(key)) => (cert.clone(), key.clone()),
+        _ => return Err(anyhow!("tls_cert and tls_key have to be set")),
+    };
+    let resolver = Arc::new(ReloadingCert {
+        current: RwLock::new(Arc::new(load_certified_key(&cert, &key)?)),
+    });
+    tokio::spawn(watch_cert(resolver.clone(), cert, key));
+
+    let mut tls = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    tls.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let acceptor = TlsAcceptor::from(Arc::new(tls));
+
+    let listener = TcpListener::bind(addr).await?;
+    info!("listening on https://{addr}");
+    let service = warp::service(routes);
+    loop {
+        let (stream, remote) = match listener.accept().await {
+            Ok(v) => v,
+            Err(e) => {
+                error!("failed to accept connection: {e}");
+                continue;
+            }
+        };
+        let (acceptor, service) = (acceptor.clone(), service.clone());
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(v) => v,
+                Err(e) => {
+                    debug!("tls handshake with {remote} failed: {e}");
+                    return;
+                }
+            };
+            if let Err(e) = Http::new()
+                .serve_connection(stream, service)
+                .with_upgrades()
+                .await
+            {
+                debug!("connection with {remote} closed: {e}");
+            }
+        });
+    }
+}
+
+// plain HTTP that sends everything over to the HTTPS port
+pub(crate) async fn redirect_to_https(addr: SocketAddr) {
+    let https_port = config().port;
+    let redirect = warp::header::optional::<String>("host")
+        .and(warp::path::full())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .map(
+            move |host: Option<String>, path: warp::path::FullPath, query: String| {
+                // there is nowhere to send a request that doesn't say which host it was for
+                let host = match host {
+                    Some(v) if !v.is_empty() => v,
+                    _ => return Response::builder().status(StatusCode::BAD_REQUEST).body(""),
+                };
+                // drop the port the redirect listener was reached on
+                let host = match host.rsplit_once(':') {
+                    Some((v, port)) if port.chars().all(|x| x.is_ascii_digit()) => v.to_string(),
+                    _ => host,
+                };
+                let port = match https_port {
+                    443 => String::new(),
+                    v => format!(":{v}"),
+                };
+                let query = match query.is_empty() {
+                    true => query,
+                    false => format!("?{query}"),
+                };
+                Response::builder()
+                    .status(StatusCode::PERMANENT_REDIRECT)
+                    .header(
+                        LOCATION,
+                        format!("https://{host}{port}{}{query}", path.as_str()),
+                    )
+                    .body("")
+            },
+        );
+    info!("redirecting http://{addr} to https");
+    warp::serve(redirect).run(addr).await;
+}
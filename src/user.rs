@@ -1,7 +1,10 @@
-use crate::BigD;
+use crate::{BigD, FollowResult};
 use num_traits::cast::ToPrimitive;
 use serde::{Deserialize, Serialize};
 
+// amount of users returned per page of LIST_FOLLOWERS and LIST_FOLLOWING
+pub(crate) const FOLLOW_PAGE_SIZE: i64 = 50;
+
 // To insert into postgres they must be of all optional type which is slightly inconvient
 
 #[derive(Default, Deserialize, Serialize)]
@@ -11,7 +14,7 @@ pub(crate) struct Playlist {
     pub public_playlist: bool,
 }
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub(crate) struct UserData {
     pub public_profile: Option<bool>,
     pub display_name: Option<String>, //limit to 30 char
@@ -19,10 +22,83 @@ pub(crate) struct UserData {
     pub now_playing: Option<String>, // keep under 50 char
     pub public_status: Option<String>,
     pub recent_plays: Option<Vec<String>>,
-    pub followers: Option<Vec<u64>>, // vec of display_name
+    pub followers: Option<Vec<u64>>, // filled in from the follows table
     pub following: Option<Vec<u64>>,
 }
 
+pub(crate) enum FollowStatus {
+    Following,
+    // the profile is private so a follow request was made, new is false if it was already pending
+    Requested { target: u64, new: bool },
+}
+
+// what other users see with REQUEST_PROFILE
+#[derive(Serialize)]
+pub(crate) struct Profile {
+    #[serde(flatten)]
+    pub userdata: UserData,
+    pub playlists: Vec<Playlist>, // only public playlists
+}
+
+impl Profile {
+    /*
+     * Take out what the viewer isn't allowed to see, what is being listened to and the recent
+     * plays are only there if the owner shares their status and the followers and following lists
+     * only for the owner and their followers
+     */
+    pub fn seen_by(mut self, owner: u64, viewer: u64) -> Self {
+        if owner == viewer {
+            return self;
+        }
+        if self.userdata.share_status != Some(true) {
+            self.userdata.now_playing = None;
+            self.userdata.recent_plays = None;
+        }
+        let follower = self
+            .userdata
+            .followers
+            .as_ref()
+            .is_some_and(|x| x.contains(&viewer));
+        if !follower {
+            self.userdata.followers = None;
+            self.userdata.following = None;
+        }
+        self
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct FollowEntry {
+    pub user: Option<String>, // display name
+    pub timestamp: u64,
+}
+
+// a page of followers or followed users, the cursor is null on the last page
+#[derive(Serialize)]
+pub(crate) struct FollowList {
+    pub items: Vec<FollowEntry>,
+    pub cursor: Option<String>,
+}
+
+impl FollowList {
+    pub fn from_page(entries: Vec<FollowResult>, offset: i64) -> Self {
+        let cursor = match entries.len() as i64 {
+            FOLLOW_PAGE_SIZE => Some((offset + FOLLOW_PAGE_SIZE).to_string()),
+            _ => None,
+        };
+        Self {
+            items: entries
+                .into_iter()
+                .map(|x| FollowEntry {
+                    user: x.display_name,
+                    timestamp: x.created_at.to_u64().unwrap_or_default(),
+                })
+                .collect(),
+            cursor,
+        }
+    }
+}
+
 pub(crate) struct UserDataBigD {
     pub public_profile: Option<bool>,
     pub display_name: Option<String>, //limit to 30 char
@@ -0,0 +1,417 @@
+use seanify::{routes, set_config, AppState, Config, MemoryDownloader};
+use sqlx::migrate::MigrateDatabase;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::{Arc, Once};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::timeout;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+use warp::test::WsClient;
+
+/*
+ * Runs the seanify binary for what happens outside of the websocket, loading the config,
+ * serving over TLS and the migrate, backup and restore subcommands so far. Every run gets a directory of its own as
+ * the working directory and an empty environment, so the .env or seanify.toml of whoever runs the
+ * tests isn't picked up
+ *
+ * backup and restore only work with postgres, they use the server in SEANIFY_TEST_POSTGRES like
+ * tests/websocket.rs and are skipped when it isn't set. The instance that is backed up is filled
+ * in through the websocket in this process, so it has a config of its own as well
+ *
+ * tests/tls has a self signed certificate for localhost that's good for 100 years, made with
+ *   openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:prime256v1 -nodes -days 36500
+ *     -subj /CN=localhost -addext subjectAltName=DNS:localhost,IP:127.0.0.1
+ *     -addext basicConstraints=critical,CA:FALSE
+ *     -keyout tests/tls/key.pem -out tests/tls/cert.pem
+ */
+const ADMIN_KEY: &str = "test-admin-key";
+
+static CONFIG: Once = Once::new();
+
+// where the files of the instance in this process are kept
+fn server_dir() -> PathBuf {
+    std::env::temp_dir().join("seanify-cli-tests-server")
+}
+
+// an empty directory for a test under the temp dir
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join("seanify-cli-tests").join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// the settings every run needs, the files are kept in the test directory
+fn flags(dir: &Path, database_url: &str) -> Vec<String> {
+    let dir = dir.display();
+    [
+        "--database-url",
+        database_url,
+        "--instance-key",
+        "songs",
+        "--cache-dir",
+        &format!("{dir}/cache"),
+        "--cdn-dir",
+        &format!("{dir}/cdn"),
+    ]
+    .iter()
+    .map(|x| x.to_string())
+    .collect()
+}
+
+fn seanify<S: AsRef<str>>(dir: &Path, args: &[S], env: &[(&str, &str)]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_seanify"))
+        .args(args.iter().map(|x| x.as_ref()))
+        .env_clear()
+        .envs(env.iter().copied())
+        .current_dir(dir)
+        .output()
+        .expect("seanify runs")
+}
+
+// stdout of a run that worked
+fn success(output: Output) -> String {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "seanify failed with {stderr}");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+// stderr of a run that didn't
+fn failure(output: Output) -> String {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success(), "seanify worked with {stdout}");
+    String::from_utf8(output.stderr).unwrap()
+}
+
+// the state of every migration from migrate status, by version
+fn migration_states(status: &str) -> Vec<(u32, String)> {
+    status
+        .lines()
+        .map(|x| {
+            let mut parts = x.split_whitespace();
+            let version = parts.next().unwrap().parse().unwrap();
+            (version, parts.next().unwrap().to_string())
+        })
+        .collect()
+}
+
+#[test]
+fn migrations_are_managed_by_the_binary() {
+    let dir = test_dir("migrate");
+    let url = format!("sqlite://{}/seanify.db", dir.display());
+    let migrate = |args: &[&str]| {
+        let mut flags = flags(&dir, &url);
+        flags.push(String::from("migrate"));
+        flags.extend(args.iter().map(|x| x.to_string()));
+        seanify(&dir, &flags, &[])
+    };
+    let migrations = std::fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/migrations/sqlite"))
+        .unwrap()
+        .filter(|x| {
+            let name = x.as_ref().unwrap().file_name();
+            name.to_string_lossy().ends_with(".up.sql")
+        })
+        .count();
+    let applied_up_to = |version: u32| {
+        let states = migration_states(&success(migrate(&["status"])));
+        assert_eq!(states.len(), migrations);
+        for (v, state) in states {
+            match v <= version {
+                true => assert_eq!(state, "applied", "{v:04}"),
+                false => assert_eq!(state, "pending", "{v:04}"),
+            }
+        }
+    };
+
+    applied_up_to(0);
+    assert!(success(migrate(&["up"])).contains("database is up to date"));
+    applied_up_to(u32::MAX);
+    assert!(success(migrate(&["down", "0005"])).contains("reverted to 0005"));
+    applied_up_to(5);
+    assert!(success(migrate(&["down"])).contains("reverted to 0004"));
+    applied_up_to(4);
+    success(migrate(&["up"]));
+    applied_up_to(u32::MAX);
+    let error = failure(migrate(&["sideways"]));
+    assert!(
+        error.contains("unknown migrate command sideways"),
+        "{error}"
+    );
+}
+
+async fn request(client: &mut WsClient, msg: &str) -> String {
+    client.send_text(msg).await;
+    let msg = timeout(Duration::from_secs(5), client.recv())
+        .await
+        .expect("no reply from the server")
+        .expect("connection closed");
+    msg.to_str().expect("text message").to_string()
+}
+
+#[tokio::test]
+async fn backups_restore_into_an_empty_database() {
+    let server = match std::env::var("SEANIFY_TEST_POSTGRES") {
+        Ok(v) => v,
+        Err(_) => {
+            eprintln!("SEANIFY_TEST_POSTGRES isn't set, skipping");
+            return;
+        }
+    };
+    let server = server.trim_end_matches('/');
+    CONFIG.call_once(|| {
+        let dir = server_dir();
+        let dir = dir.display();
+        let config = Config::from_flags(&[
+            "--database-url",
+            "memory://",
+            "--instance-key",
+            "songs",
+            "--admin-key",
+            ADMIN_KEY,
+            "--cache-dir",
+            &format!("{dir}/cache"),
+            "--cdn-dir",
+            &format!("{dir}/cdn"),
+        ])
+        .unwrap();
+        set_config(config).unwrap();
+    });
+
+    // an instance with an account, a song and a playlist
+    let downloader = MemoryDownloader::new();
+    let url = "https://example.com/watch?v=backup";
+    let id = downloader.add_song(url, "Backup", "Artist", "20220524");
+    let original = format!("{server}/seanify_test_backup");
+    let state = AppState::postgres(&original, downloader).await.unwrap();
+    let mut client = warp::test::ws()
+        .path("/seanify")
+        .handshake(routes(state.clone()))
+        .await
+        .expect("websocket handshake");
+    client
+        .send_text(format!("SIGN sean hunter2 {ADMIN_KEY}"))
+        .await;
+    client.send_text("AUTH sean hunter2").await;
+    assert_eq!(
+        request(&mut client, &format!("QUEUE {url}")).await,
+        "AddedSong"
+    );
+    state.cycle_queue().await;
+    assert_eq!(request(&mut client, "CREATE_PLAYLIST mix true").await, "OK");
+    let add = format!("ADD_SONG_HASH mix {id} _");
+    assert_eq!(request(&mut client, &add).await, "OK");
+    drop((client, state));
+
+    let dir = test_dir("backup");
+    let archive = dir.join("seanify.tar.gz").display().to_string();
+    let mut args = flags(&server_dir(), &original);
+    args.extend([String::from("backup"), archive.clone()]);
+    assert!(success(seanify(&dir, &args, &[])).starts_with("backed up "));
+
+    // the restore goes to another database and other directories
+    let restored = format!("{server}/seanify_test_restore");
+    sqlx::Postgres::drop_database(&restored).await.unwrap();
+    sqlx::Postgres::create_database(&restored).await.unwrap();
+    let restore = || {
+        let mut flags = flags(&dir, &restored);
+        flags.extend([String::from("restore"), archive.clone()]);
+        seanify(&dir, &flags, &[])
+    };
+    assert!(success(restore()).starts_with("restored "));
+    assert!(dir.join(format!("cache/{id}")).is_file());
+    let pool = sqlx::PgPool::connect(&restored).await.unwrap();
+    for table in ["auth", "songs", "playlist", "playlistdata"] {
+        let rows: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {table}"))
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(rows, 1, "{table}");
+    }
+    pool.close().await;
+
+    // there is something in the database now so it can't be restored into again
+    let error = failure(restore());
+    assert!(error.contains("restore needs an empty database"), "{error}");
+}
+
+#[test]
+fn config_is_layered_file_env_flags() {
+    let dir = test_dir("config");
+    let path = |name: &str| dir.join(name).display().to_string();
+    let file = |database: &str, port: u16| {
+        format!(
+            "database_url = \"sqlite://{}\"\ninstance_key = \"songs\"\ncache_dir = \"{}\"\n\
+             cdn_dir = \"{}\"\nport = {port}\n",
+            path(database),
+            path("cache"),
+            path("cdn"),
+        )
+    };
+    let status = |args: &[&str], env: &[(&str, &str)]| {
+        let mut args = args.to_vec();
+        args.extend(["migrate", "status"]);
+        seanify(&dir, &args, env)
+    };
+    let bad_port = "port has to be more than 0";
+
+    // seanify.toml is picked up from the working directory, its port of 0 is what fails
+    std::fs::write(dir.join("seanify.toml"), file("file.db", 0)).unwrap();
+    let error = failure(status(&[], &[]));
+    assert!(error.contains(bad_port), "{error}");
+    // env goes over the file and flags over env
+    success(status(&[], &[("PORT", "9000")]));
+    let error = failure(status(&[], &[("PORT", "0")]));
+    assert!(error.contains(bad_port), "{error}");
+    success(status(&["--port", "9000"], &[("PORT", "0")]));
+    success(status(&["--port=9000"], &[("PORT", "0")]));
+
+    // the database that gets migrated shows which database_url won, an empty env var is unset
+    let mut args = vec!["--port", "9000", "migrate", "up"];
+    success(seanify(&dir, &args, &[("DATABASE_URL", "")]));
+    assert!(dir.join("file.db").is_file());
+    let env = format!("sqlite://{}", path("env.db"));
+    success(seanify(&dir, &args, &[("DATABASE_URL", &env)]));
+    assert!(dir.join("env.db").is_file());
+    let flag = format!("sqlite://{}", path("flag.db"));
+    args.splice(0..0, ["--database-url", &flag]);
+    success(seanify(&dir, &args, &[("DATABASE_URL", &env)]));
+    assert!(dir.join("flag.db").is_file());
+
+    // --config and SEANIFY_CONFIG use another file instead of seanify.toml, its port is fine
+    std::fs::write(dir.join("other.toml"), file("other.db", 9000)).unwrap();
+    let other = path("other.toml");
+    success(status(&["--config", &other], &[]));
+    success(status(&[], &[("SEANIFY_CONFIG", &other)]));
+    let error = failure(status(&["--config", &path("missing.toml")], &[]));
+    assert!(error.contains("failed to read"), "{error}");
+
+    // everything that is missing is reported at once
+    std::fs::remove_file(dir.join("seanify.toml")).unwrap();
+    let error = failure(status(&[], &[]));
+    for name in ["database_url", "instance_key", "cache_dir", "cdn_dir"] {
+        assert!(error.contains(&format!("{name} has to be set")), "{error}");
+    }
+    let error = failure(status(&["--colour", "blue"], &[]));
+    assert!(error.contains("unknown flag --colour"), "{error}");
+}
+
+// a seanify server that is stopped when the test is over, even when it fails
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+// a port nothing is listening on right now
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn wait_for_port(server: &mut Server, port: u16) {
+    for _ in 0..100 {
+        if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+            return;
+        }
+        if let Some(status) = server.0.try_wait().unwrap() {
+            panic!("seanify exited with {status}");
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    panic!("nothing is listening on {port}");
+}
+
+// a plain HTTP request, the whole response comes back
+fn http(port: u16, request: &str) -> String {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[tokio::test]
+async fn tls_is_served_with_a_redirect_from_http() {
+    let dir = test_dir("tls");
+    let fixture = |name: &str| format!("{}/tests/tls/{name}", env!("CARGO_MANIFEST_DIR"));
+    let (port, redirect) = (free_port(), free_port());
+    let mut args = flags(&dir, &format!("sqlite://{}/seanify.db", dir.display()));
+    for (flag, value) in [
+        ("--tls-cert", fixture("cert.pem")),
+        ("--tls-key", fixture("key.pem")),
+        ("--port", port.to_string()),
+        ("--http-redirect-port", redirect.to_string()),
+    ] {
+        args.extend([flag.to_string(), value]);
+    }
+    let mut server = Server(
+        Command::new(env!("CARGO_BIN_EXE_seanify"))
+            .args(&args)
+            .env_clear()
+            .current_dir(&dir)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("seanify runs"),
+    );
+    wait_for_port(&mut server, port);
+    wait_for_port(&mut server, redirect);
+
+    // the path and query are kept, the port is swapped for the HTTPS one
+    let response = http(
+        redirect,
+        &format!(
+            "GET /seanify?a=1 HTTP/1.1\r\nHost: localhost:{redirect}\r\nConnection: close\r\n\r\n"
+        ),
+    );
+    assert!(response.starts_with("HTTP/1.1 308"), "{response}");
+    let location = format!("location: https://localhost:{port}/seanify?a=1\r\n");
+    assert!(response.to_lowercase().contains(&location), "{response}");
+    let response = http(redirect, "GET / HTTP/1.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.0 400"), "{response}");
+
+    let mut roots = RootCertStore::empty();
+    let cert = std::fs::read(fixture("cert.pem")).unwrap();
+    for cert in rustls_pemfile::certs(&mut cert.as_slice()) {
+        roots.add(cert.unwrap()).unwrap();
+    }
+    let connect = |alpn: &[&[u8]]| {
+        let mut tls = ClientConfig::builder()
+            .with_root_certificates(roots.clone())
+            .with_no_client_auth();
+        tls.alpn_protocols = alpn.iter().map(|x| x.to_vec()).collect();
+        async move {
+            let stream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+                .await
+                .unwrap();
+            let name = ServerName::try_from("localhost").unwrap();
+            TlsConnector::from(Arc::new(tls))
+                .connect(name, stream)
+                .await
+                .expect("tls handshake")
+        }
+    };
+
+    // HTTP/2 is picked when the client offers it
+    let stream = connect(&[b"h2", b"http/1.1"]).await;
+    assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+    // and HTTP/1.1 still works for everything else, like websockets
+    let mut stream = connect(&[b"http/1.1"]).await;
+    assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"http/1.1"[..]));
+    let request = "GET /seanify HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 "), "{response}");
+}
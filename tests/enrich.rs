@@ -0,0 +1,152 @@
+use seanify::{EnrichRules, SongFields};
+
+/*
+ * The rules of the enrichment pass one at a time, then all of them together on titles like the
+ * ones yt-dlp gives
+ */
+fn song(title: &str) -> SongFields {
+    SongFields {
+        title: title.to_string(),
+        ..Default::default()
+    }
+}
+
+fn only(rule: &str) -> EnrichRules {
+    EnrichRules::parse(Some(rule), None).unwrap()
+}
+
+#[test]
+fn album_from_title() {
+    let rules = only("album_from_title");
+    let enriched = rules.enrich(&song("Let It Go (From \"Frozen\")"));
+    assert_eq!(enriched.title, "Let It Go");
+    assert_eq!(enriched.album.as_deref(), Some("Frozen"));
+
+    // an album that is already set is kept along with the title
+    let mut with_album = song("Let It Go (From \"Frozen\")");
+    with_album.album = Some(String::from("Frozen (Original Soundtrack)"));
+    assert_eq!(rules.enrich(&with_album), with_album);
+}
+
+#[test]
+fn noise() {
+    let rules = only("noise");
+    for (title, expected) in [
+        ("Dark Red (Lyrics)", "Dark Red"),
+        ("Dark Red [HD]", "Dark Red"),
+        ("Dark Red (Official Music Video) [4K]", "Dark Red"),
+        ("Dark Red - Official Audio", "Dark Red"),
+        ("Dark Red | Official Lyric Video", "Dark Red"),
+        ("Dark Red (Audio) official video", "Dark Red"),
+        ("Dark Red (Live at the BBC)", "Dark Red (Live at the BBC)"),
+        ("Video Games", "Video Games"),
+        ("Audio", "Audio"),
+        ("(Official Video)", "(Official Video)"),
+    ] {
+        assert_eq!(rules.enrich(&song(title)).title, expected, "{title}");
+    }
+
+    // a custom list replaces the default one
+    let rules = EnrichRules::parse(Some("noise"), Some("Slowed, Reverb")).unwrap();
+    assert_eq!(
+        rules.enrich(&song("Dark Red (Slowed + Reverb)")).title,
+        "Dark Red"
+    );
+    assert_eq!(
+        rules.enrich(&song("Dark Red (Lyrics)")).title,
+        "Dark Red (Lyrics)"
+    );
+}
+
+#[test]
+fn split_artist() {
+    let rules = only("split_artist");
+    let enriched = rules.enrich(&song("Steve Lacy - Dark Red"));
+    assert_eq!(enriched.title, "Dark Red");
+    assert_eq!(enriched.artist.as_deref(), Some("Steve Lacy"));
+
+    // the prefix is removed if it's the artist the song already has
+    let mut known = song("steve lacy – Dark Red");
+    known.artist = Some(String::from("Steve Lacy"));
+    assert_eq!(rules.enrich(&known).title, "Dark Red");
+
+    // and left alone if it's someone else
+    let mut other = song("Dark Red - Live");
+    other.artist = Some(String::from("Steve Lacy"));
+    assert_eq!(rules.enrich(&other), other);
+
+    assert_eq!(rules.enrich(&song("Anti-Hero")), song("Anti-Hero"));
+}
+
+#[test]
+fn channel_artist() {
+    let rules = only("channel_artist");
+    let mut creator = song("Dark Red");
+    creator.creator = Some(String::from("Steve Lacy"));
+    creator.uploader = Some(String::from("Some Channel"));
+    assert_eq!(rules.enrich(&creator).artist.as_deref(), Some("Steve Lacy"));
+
+    for (uploader, expected) in [
+        ("Steve Lacy - Topic", Some("Steve Lacy")),
+        ("SteveLacyVEVO", Some("SteveLacy")),
+        ("Some Channel", None),
+    ] {
+        let mut topic = song("Dark Red");
+        topic.uploader = Some(uploader.to_string());
+        assert_eq!(
+            rules.enrich(&topic).artist.as_deref(),
+            expected,
+            "{uploader}"
+        );
+    }
+}
+
+#[test]
+fn dates() {
+    let rules = only("dates");
+    for (date, expected) in [
+        ("20220524", "2022-05-24"),
+        ("2022-05-24", "2022-05-24"),
+        ("20221324", "20221324"),
+        ("2022052", "2022052"),
+    ] {
+        let mut dated = song("Dark Red");
+        dated.upload_date = Some(date.to_string());
+        assert_eq!(
+            rules.enrich(&dated).upload_date.as_deref(),
+            Some(expected),
+            "{date}"
+        );
+    }
+}
+
+#[test]
+fn every_rule() {
+    let rules = EnrichRules::default();
+    let mut raw = song("Steve Lacy - Dark Red (Official Video) [HD]");
+    raw.uploader = Some(String::from("Steve Lacy"));
+    raw.upload_date = Some(String::from("20170224"));
+    let enriched = rules.enrich(&raw);
+    assert_eq!(enriched.title, "Dark Red");
+    assert_eq!(enriched.artist.as_deref(), Some("Steve Lacy"));
+    assert_eq!(enriched.upload_date.as_deref(), Some("2017-02-24"));
+
+    // the enriched song doesn't change again
+    assert_eq!(rules.enrich(&enriched), enriched);
+}
+
+#[test]
+fn parsing_rules() {
+    assert!(EnrichRules::parse(Some("none"), None).unwrap().is_empty());
+    assert!(!EnrichRules::parse(None, None).unwrap().is_empty());
+    assert_eq!(
+        EnrichRules::parse(Some(" noise , dates "), None).unwrap(),
+        EnrichRules {
+            album_from_title: false,
+            split_artist: false,
+            channel_artist: false,
+            ..EnrichRules::default()
+        }
+    );
+    assert!(EnrichRules::parse(Some("noise,spelling"), None).is_err());
+}
@@ -0,0 +1,131 @@
+use seanify::{contrast_ratio, Identicon, BACKGROUND, MIN_CONTRAST, MIN_SIZE};
+use std::collections::HashSet;
+
+/*
+ * Identicons are compared against snapshots at the smallest size, where every cell is 2x2 pixels
+ * inside a one pixel margin. A snapshot only changes if the way identicons are made does, which
+ * would give every existing user a new default pfp
+ */
+fn ascii(identicon: &Identicon) -> String {
+    let image = identicon.render(MIN_SIZE);
+    image
+        .rows()
+        .map(|row| {
+            row.map(|x| match x.0 == identicon.foreground {
+                true => '#',
+                false => '.',
+            })
+            .collect::<String>()
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[test]
+fn snapshots() {
+    let snapshots = [
+        (
+            0,
+            [177, 54, 186],
+            "\
+............
+.##########.
+.##########.
+.##..##..##.
+.##..##..##.
+.####..####.
+.####..####.
+.####..####.
+.####..####.
+.....##.....
+.....##.....
+............",
+        ),
+        (
+            1,
+            [212, 73, 89],
+            "\
+............
+.##..##..##.
+.##..##..##.
+...######...
+...######...
+...######...
+...######...
+............
+............
+.....##.....
+.....##.....
+............",
+        ),
+        (
+            u64::MAX,
+            [190, 69, 201],
+            "\
+............
+...######...
+...######...
+.....##.....
+.....##.....
+............
+............
+.....##.....
+.....##.....
+............
+............
+............",
+        ),
+    ];
+    for (seed, foreground, expected) in snapshots {
+        let identicon = Identicon::new(seed);
+        assert_eq!(identicon.foreground, foreground, "{seed}");
+        assert_eq!(identicon.background, BACKGROUND, "{seed}");
+        assert_eq!(ascii(&identicon), expected, "{seed}");
+    }
+}
+
+#[test]
+fn same_hash_same_image() {
+    assert_eq!(Identicon::new(42), Identicon::new(42));
+    assert_eq!(Identicon::new(42).render(64), Identicon::new(42).render(64));
+    assert_ne!(Identicon::new(42), Identicon::new(43));
+}
+
+#[test]
+fn sizes() {
+    let identicon = Identicon::new(7);
+    for size in [MIN_SIZE, 64, 97, 128, 400] {
+        let image = identicon.render(size);
+        assert_eq!(image.dimensions(), (size, size));
+        for (x, y, pixel) in image.enumerate_pixels() {
+            assert_eq!(pixel, image.get_pixel(size - 1 - x, y), "{size} at {x},{y}");
+        }
+        assert!(
+            image.pixels().any(|x| x.0 == identicon.foreground),
+            "{size}"
+        );
+    }
+    assert_eq!(identicon.render(1).dimensions(), (MIN_SIZE, MIN_SIZE));
+}
+
+#[test]
+fn colors_have_contrast() {
+    assert!((contrast_ratio([0; 3], [255; 3]) - 21.0).abs() < 0.01);
+    assert!((contrast_ratio(BACKGROUND, BACKGROUND) - 1.0).abs() < 0.01);
+    for seed in 0..10_000 {
+        let identicon = Identicon::new(seed);
+        let contrast = contrast_ratio(identicon.foreground, identicon.background);
+        assert!(contrast >= MIN_CONTRAST, "{seed} has {contrast}");
+    }
+}
+
+#[test]
+fn hashes_close_together_look_different() {
+    let identicons: HashSet<_> = (0..10_000)
+        .map(|seed| {
+            let identicon = Identicon::new(seed);
+            (identicon.cells, identicon.foreground)
+        })
+        .collect();
+    assert_eq!(identicons.len(), 10_000);
+}
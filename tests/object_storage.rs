@@ -0,0 +1,284 @@
+use flate2::read::GzDecoder;
+use lazy_static::lazy_static;
+use seahash::hash;
+use seanify::{routes, set_config, AppState, Config, MemoryDownloader};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::timeout;
+use warp::http::header::CONTENT_ENCODING;
+use warp::http::StatusCode;
+use warp::hyper::body::Bytes;
+use warp::reply::Response;
+use warp::test::WsClient;
+use warp::{Filter, Reply};
+
+/*
+ * Songs and images kept in S3 instead of the local directories. The bucket is a stand in served
+ * with warp on another thread, it keeps the objects in memory and only checks that requests are
+ * signed, the signing itself is left to real S3 compatible services
+ *
+ * The config is global so every test in this file shares the stand in and the s3 settings
+ */
+const BUCKET: &str = "seanify";
+const ACCESS_KEY: &str = "test-access-key";
+const ADMIN_KEY: &str = "test-admin-key";
+const PASSWORD: &str = "hunter2";
+
+type Objects = Arc<Mutex<BTreeMap<String, Vec<u8>>>>;
+
+lazy_static! {
+    static ref OBJECTS: Objects = start_stand_in();
+}
+
+fn signed(authorization: Option<String>) -> bool {
+    let credential = format!("AWS4-HMAC-SHA256 Credential={ACCESS_KEY}/");
+    authorization.is_some_and(|x| x.starts_with(&credential))
+}
+
+fn status(status: StatusCode) -> Response {
+    warp::reply::with_status(warp::reply(), status).into_response()
+}
+
+// the part of a ListObjectsV2 response that is read, everything fits on one page
+fn list_xml(objects: &BTreeMap<String, Vec<u8>>, prefix: &str) -> String {
+    let contents: String = objects
+        .iter()
+        .filter(|(k, _)| k.starts_with(prefix))
+        .map(|(k, v)| {
+            format!(
+                "<Contents><Key>{k}</Key><Size>{}</Size></Contents>",
+                v.len()
+            )
+        })
+        .collect();
+    format!("<ListBucketResult><IsTruncated>false</IsTruncated>{contents}</ListBucketResult>")
+}
+
+// PUT (with x-amz-copy-source for copies), GET and DELETE of objects and ListObjectsV2
+fn stand_in(
+    objects: Objects,
+) -> impl Filter<Extract = (Response,), Error = warp::Rejection> + Clone {
+    let objects = warp::any().map(move || objects.clone());
+    let auth = warp::header::optional::<String>("authorization");
+
+    let list = warp::get()
+        .and(warp::path(BUCKET))
+        .and(warp::path::end())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(auth)
+        .and(objects.clone())
+        .map(
+            |query: HashMap<String, String>, authorization, objects: Objects| {
+                if !signed(authorization) || query.get("list-type").map(|x| x.as_str()) != Some("2")
+                {
+                    return status(StatusCode::FORBIDDEN);
+                }
+                let prefix = query.get("prefix").cloned().unwrap_or_default();
+                list_xml(&objects.lock().unwrap(), &prefix).into_response()
+            },
+        );
+
+    let put = warp::put()
+        .and(warp::path(BUCKET))
+        .and(warp::path::tail())
+        .and(warp::header::optional::<String>("x-amz-copy-source"))
+        .and(warp::body::bytes())
+        .and(auth)
+        .and(objects.clone())
+        .map(
+            |key: warp::path::Tail,
+             source: Option<String>,
+             body: Bytes,
+             authorization,
+             objects: Objects| {
+                if !signed(authorization) {
+                    return status(StatusCode::FORBIDDEN);
+                }
+                let mut objects = objects.lock().unwrap();
+                let data = match source {
+                    Some(v) => match objects.get(v.trim_start_matches(&format!("/{BUCKET}/"))) {
+                        Some(v) => v.clone(),
+                        None => return status(StatusCode::NOT_FOUND),
+                    },
+                    None => body.to_vec(),
+                };
+                objects.insert(key.as_str().to_string(), data);
+                status(StatusCode::OK)
+            },
+        );
+
+    let get = warp::get()
+        .and(warp::path(BUCKET))
+        .and(warp::path::tail())
+        .and(auth)
+        .and(objects.clone())
+        .map(|key: warp::path::Tail, authorization, objects: Objects| {
+            if !signed(authorization) {
+                return status(StatusCode::FORBIDDEN);
+            }
+            match objects.lock().unwrap().get(key.as_str()) {
+                Some(v) => v.clone().into_response(),
+                None => status(StatusCode::NOT_FOUND),
+            }
+        });
+
+    let delete = warp::delete()
+        .and(warp::path(BUCKET))
+        .and(warp::path::tail())
+        .and(auth)
+        .and(objects)
+        .map(|key: warp::path::Tail, authorization, objects: Objects| {
+            if !signed(authorization) {
+                return status(StatusCode::FORBIDDEN);
+            }
+            objects.lock().unwrap().remove(key.as_str());
+            status(StatusCode::NO_CONTENT)
+        });
+
+    list.or(put).unify().or(get).unify().or(delete).unify()
+}
+
+// the stand in gets a runtime of its own so it outlives the runtime of any one test
+fn start_stand_in() -> Objects {
+    let objects = Objects::default();
+    let (tx, rx) = std::sync::mpsc::channel();
+    let filter = stand_in(objects.clone());
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async move {
+            let (addr, server) = warp::serve(filter).bind_ephemeral(([127, 0, 0, 1], 0));
+            tx.send(addr).unwrap();
+            server.await
+        });
+    });
+    let addr: SocketAddr = rx.recv().unwrap();
+
+    let config = Config::from_flags(&[
+        "--database-url",
+        "memory://",
+        "--instance-key",
+        "songs",
+        "--admin-key",
+        ADMIN_KEY,
+        "--s3-endpoint",
+        &format!("http://{addr}"),
+        "--s3-bucket",
+        BUCKET,
+        "--s3-access-key",
+        ACCESS_KEY,
+        "--s3-secret-key",
+        "test-secret-key",
+    ])
+    .unwrap();
+    set_config(config).unwrap();
+    objects
+}
+
+fn object(key: &str) -> Option<Vec<u8>> {
+    OBJECTS.lock().unwrap().get(key).cloned()
+}
+
+async fn recv(client: &mut WsClient) -> String {
+    let msg = timeout(Duration::from_secs(5), client.recv())
+        .await
+        .expect("no reply from the server")
+        .expect("connection closed");
+    msg.to_str().expect("text message").to_string()
+}
+
+async fn request(client: &mut WsClient, msg: &str) -> String {
+    client.send_text(msg).await;
+    recv(client).await
+}
+
+// signing up with the ADMIN_KEY doesn't need an invite
+async fn sign_up(state: &AppState, username: &str) -> WsClient {
+    let mut client = warp::test::ws()
+        .path("/seanify")
+        .handshake(routes(state.clone()))
+        .await
+        .expect("websocket handshake");
+    client
+        .send_text(format!("SIGN {username} {PASSWORD} {ADMIN_KEY}"))
+        .await;
+    client
+        .send_text(format!("AUTH {username} {PASSWORD}"))
+        .await;
+    assert_eq!(request(&mut client, "PING ").await, "PONG");
+    client
+}
+
+// the music and cdn routes are gzipped, the body is given back as it was stored
+async fn get(state: &AppState, path: &str) -> (StatusCode, Vec<u8>) {
+    let response = warp::test::request()
+        .path(path)
+        .reply(&routes(state.clone()))
+        .await;
+    let mut body = Vec::new();
+    match response.headers().get(CONTENT_ENCODING) {
+        Some(v) if v == "gzip" => {
+            GzDecoder::new(response.body().as_ref())
+                .read_to_end(&mut body)
+                .expect("gzipped body");
+        }
+        _ => body.extend_from_slice(response.body()),
+    }
+    (response.status(), body)
+}
+
+#[tokio::test]
+async fn downloaded_songs_are_uploaded_and_proxied() {
+    lazy_static::initialize(&OBJECTS);
+    let downloader = MemoryDownloader::new();
+    let url = "https://example.com/watch?v=s3";
+    let id = downloader.add_song(url, "Song", "Artist", "20220524");
+    let state = AppState::in_memory(downloader);
+    let mut client = sign_up(&state, "sean").await;
+
+    assert_eq!(
+        request(&mut client, &format!("QUEUE {url}")).await,
+        "AddedSong"
+    );
+    state.cycle_queue().await;
+    // the title stands in for the audio, it's stored with the tags in front
+    let data = object(&format!("songs/{id}")).expect("song uploaded");
+    assert!(data.starts_with(b"ID3") && data.ends_with(b"Song"));
+
+    let (status, body) = get(&state, &format!("/songs/{id}")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, data);
+    assert_eq!(get(&state, "/songs/1").await.0, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn images_follow_the_account() {
+    lazy_static::initialize(&OBJECTS);
+    let state = AppState::in_memory(MemoryDownloader::new());
+    let mut client = sign_up(&state, "ray").await;
+
+    // a default pfp is made on sign up
+    let pfp = format!("{}.png", hash(b"ray"));
+    let data = object(&format!("images/{pfp}")).expect("pfp uploaded");
+    assert!(data.starts_with(b"\x89PNG"));
+    let (status, body) = get(&state, &format!("/songs-cdn/{pfp}")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, data);
+
+    // renaming copies the images over to the new hash
+    assert_eq!(request(&mut client, "CREATE_PLAYLIST mix true").await, "OK");
+    assert_eq!(
+        request(&mut client, &format!("CHANGE_USERNAME {PASSWORD} ray2")).await,
+        "OK"
+    );
+    let images = OBJECTS
+        .lock()
+        .unwrap()
+        .keys()
+        .filter(|x| x.starts_with(&format!("images/{}", hash(b"ray2"))))
+        .count();
+    assert_eq!(images, 2);
+    assert_eq!(object(&format!("images/{pfp}")), None);
+}
@@ -0,0 +1,1452 @@
+use flate2::read::GzDecoder;
+use id3::{Tag, TagLike};
+use seahash::hash;
+use seanify::{routes, set_config, AppState, Config, MemoryDownloader};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::io::{Cursor, Read};
+use std::sync::Once;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::timeout;
+use warp::test::WsClient;
+use warp::Filter;
+
+/*
+ * Drives the websocket protocol end to end, nothing here needs yt-dlp. Every test runs against
+ * MemoryStorage, an in-memory sqlite database and postgres (see the backends macro at the bottom)
+ * so every backend is held to the same behaviour. The postgres runs need a server in
+ * SEANIFY_TEST_POSTGRES (ex: postgresql://localhost:5432), each test gets a database of its own
+ * there named after it, they are skipped when it isn't set
+ *
+ * The config is global so it is set once for every test in this file, from flags only so the .env
+ * of whoever runs the tests doesn't change anything
+ */
+const ADMIN_KEY: &str = "test-admin-key";
+const PASSWORD: &str = "hunter2";
+
+static CONFIG: Once = Once::new();
+
+#[derive(Clone, Copy, Debug)]
+enum Backend {
+    Memory,
+    Sqlite,
+    // the name of the test, which is also the name of its database
+    Postgres(&'static str),
+}
+
+// songs and playlists are named after the backend where they would clash in the shared cache dir
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Backend::Memory => write!(f, "Memory"),
+            Backend::Sqlite => write!(f, "Sqlite"),
+            Backend::Postgres(_) => write!(f, "Postgres"),
+        }
+    }
+}
+
+async fn state(backend: Backend, downloader: MemoryDownloader) -> AppState {
+    CONFIG.call_once(|| {
+        let dir = std::env::temp_dir().join("seanify-tests");
+        let dir = dir.to_string_lossy();
+        let config = Config::from_flags(&[
+            "--database-url",
+            "memory://",
+            "--instance-key",
+            "songs",
+            "--admin-key",
+            ADMIN_KEY,
+            "--cache-dir",
+            &format!("{dir}/cache"),
+            "--cdn-dir",
+            &format!("{dir}/cdn"),
+        ])
+        .unwrap();
+        set_config(config).unwrap();
+    });
+    match backend {
+        Backend::Memory => AppState::in_memory(downloader),
+        Backend::Sqlite => AppState::sqlite("sqlite::memory:", downloader)
+            .await
+            .expect("sqlite storage"),
+        Backend::Postgres(test) => {
+            let server = std::env::var("SEANIFY_TEST_POSTGRES").unwrap();
+            let url = format!("{}/seanify_test_{test}", server.trim_end_matches('/'));
+            AppState::postgres(&url, downloader)
+                .await
+                .expect("postgres storage")
+        }
+    }
+}
+
+async fn connect(state: &AppState) -> WsClient {
+    warp::test::ws()
+        .path("/seanify")
+        .handshake(routes(state.clone()))
+        .await
+        .expect("websocket handshake")
+}
+
+async fn recv(client: &mut WsClient) -> String {
+    let msg = timeout(Duration::from_secs(5), client.recv())
+        .await
+        .expect("no reply from the server")
+        .expect("connection closed");
+    msg.to_str().expect("text message").to_string()
+}
+
+// send a command and wait for the reply
+async fn request(client: &mut WsClient, msg: &str) -> String {
+    client.send_text(msg).await;
+    recv(client).await
+}
+
+async fn request_json(client: &mut WsClient, msg: &str) -> Value {
+    let reply = request(client, msg).await;
+    serde_json::from_str(&reply).unwrap_or_else(|_| panic!("{msg} replied with {reply}"))
+}
+
+// nothing comes back, a closed connection counts as nothing
+async fn no_reply(client: &mut WsClient) {
+    if let Ok(Ok(msg)) = timeout(Duration::from_millis(300), client.recv()).await {
+        assert!(msg.is_close(), "unexpected reply {msg:?}");
+    }
+}
+
+// SIGN and AUTH don't reply, a PING afterwards makes sure both went through
+async fn sign_up(state: &AppState, username: &str, invite: &str, admin: bool) -> WsClient {
+    let mut client = connect(state).await;
+    client
+        .send_text(format!("SIGN {username} {PASSWORD} {invite}"))
+        .await;
+    match admin {
+        true => client.send_text(format!("AUTH {username} {PASSWORD} {ADMIN_KEY}")),
+        false => client.send_text(format!("AUTH {username} {PASSWORD}")),
+    }
+    .await;
+    assert_eq!(request(&mut client, "PING ").await, "PONG");
+    client
+}
+
+// SIGN with the invite doesn't make an account, so the AUTH after it closes the connection
+async fn cannot_sign_up(state: &AppState, username: &str, invite: &str) {
+    let mut client = connect(state).await;
+    client
+        .send_text(format!("SIGN {username} {PASSWORD} {invite}"))
+        .await;
+    client
+        .send_text(format!("AUTH {username} {PASSWORD}"))
+        .await;
+    client.send_text("PING ").await;
+    no_reply(&mut client).await;
+}
+
+// every account after the first needs an invite, the inviter is an admin so it has enough
+async fn invited(state: &AppState, inviter: &mut WsClient, username: &str) -> WsClient {
+    let invite = request(inviter, "CREATE_INVITE 1").await;
+    sign_up(state, username, &invite, false).await
+}
+
+async fn log_in(state: &AppState, username: &str) -> WsClient {
+    let mut client = connect(state).await;
+    client
+        .send_text(format!("AUTH {username} {PASSWORD}"))
+        .await;
+    assert_eq!(request(&mut client, "PING ").await, "PONG");
+    client
+}
+
+// a song from the download route along with its Content-Disposition
+async fn download(state: &AppState, id: u64) -> (Vec<u8>, String) {
+    let response = warp::test::request()
+        .path(&format!("/songs-download/{id}"))
+        .reply(&routes(state.clone()))
+        .await;
+    assert_eq!(response.status(), 200);
+    let disposition = response.headers()["content-disposition"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    (response.body().to_vec(), disposition)
+}
+
+async fn set_profile(client: &mut WsClient, display_name: &str, public: bool) {
+    let msg = format!(
+        r#"UPDATE_USERDATA {{"public_profile": {public}, "display_name": "{display_name}"}}"#
+    );
+    assert_eq!(request(client, &msg).await, "OK");
+}
+
+async fn sign_up_and_authenticate(backend: Backend) {
+    let state = state(backend, MemoryDownloader::new()).await;
+    let mut client = sign_up(&state, "sean", ADMIN_KEY, false).await;
+    set_profile(&mut client, "sean", true).await;
+
+    let data = request_json(&mut client, "REQUEST_USERDATA ").await;
+    assert_eq!(data["display_name"], "sean");
+    assert_eq!(data["public_profile"], true);
+    assert_eq!(data["followers"], Value::Array(Vec::new()));
+}
+
+async fn unauthenticated_clients_get_no_replies(backend: Backend) {
+    let state = state(backend, MemoryDownloader::new()).await;
+    sign_up(&state, "sean", ADMIN_KEY, false).await;
+
+    let mut client = connect(&state).await;
+    client.send_text("PING ").await;
+    no_reply(&mut client).await;
+
+    let mut client = connect(&state).await;
+    client.send_text("AUTH sean wrong-password").await;
+    client.send_text("PING ").await;
+    no_reply(&mut client).await;
+}
+
+async fn queued_songs_can_be_added_to_playlists(backend: Backend) {
+    let downloader = MemoryDownloader::new();
+    let url = "https://example.com/watch?v=1";
+    let id = downloader.add_song(url, "Song", "Artist", "20220524");
+    let state = state(backend, downloader).await;
+    let mut client = sign_up(&state, "sean", ADMIN_KEY, false).await;
+
+    assert_eq!(request(&mut client, &format!("QUEUE {url}")).await, "AddedSong");
+    assert_eq!(request(&mut client, "QUEUE_LIST ").await, format!("{url} "));
+    state.cycle_queue().await;
+    assert_eq!(request(&mut client, "QUEUE_LIST ").await, "");
+
+    let library = request_json(&mut client, "SYNC_LIB 0").await;
+    assert_eq!(library[0]["id"], id.to_string());
+    assert_eq!(library[0]["title"], "Song");
+    assert_eq!(
+        request(&mut client, "FIND_SONG Song Artist 20220524").await,
+        id.to_string()
+    );
+
+    assert_eq!(request(&mut client, "CREATE_PLAYLIST mix true").await, "OK");
+    assert_eq!(
+        request(&mut client, &format!("ADD_SONG_HASH mix {id} _")).await,
+        "OK"
+    );
+    assert_eq!(
+        request(&mut client, "ADD_SONG_HASH mix 1 _").await,
+        "InvalidHash"
+    );
+    let playlist = request_json(&mut client, "REQUEST_PLAYLIST mix").await;
+    assert_eq!(playlist["name"], "mix");
+    assert_eq!(playlist["public_playlist"], true);
+}
+
+async fn songs_can_be_corrected_and_renamed(backend: Backend) {
+    let downloader = MemoryDownloader::new();
+    let url = "https://example.com/watch?v=2";
+    let id = downloader.add_song(url, "Artist - Song (Lyrics)", "Artist", "20220524");
+    let state = state(backend, downloader).await;
+    let mut admin = sign_up(&state, "sean", ADMIN_KEY, true).await;
+    set_profile(&mut admin, "sean", true).await;
+    assert_eq!(request(&mut admin, &format!("QUEUE {url}")).await, "AddedSong");
+    state.cycle_queue().await;
+
+    let since = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let edit = format!(r#"EDIT_SONG {id} {{"title": " Song ", "album": "Album"}}"#);
+    assert_eq!(request(&mut admin, &edit).await, "OK");
+    let edit = format!(r#"EDIT_SONG {id} {{"title": null}}"#);
+    assert_eq!(request(&mut admin, &edit).await, "InvalidEdit");
+    assert_eq!(
+        request(&mut admin, r#"EDIT_SONG 1 {"title": "Song"}"#).await,
+        "InvalidHash"
+    );
+
+    // edited songs are synced again
+    let library = request_json(&mut admin, &format!("SYNC_LIB {since}")).await;
+    assert_eq!(library[0]["title"], "Song");
+    assert_eq!(library[0]["album"], "Album");
+    assert_eq!(library[0]["artist"], "Artist");
+    let history = request_json(&mut admin, &format!("SONG_HISTORY {id}")).await;
+    assert_eq!(history["items"][0]["editor"], "sean");
+    assert_eq!(history["items"][0]["old"]["title"], "Artist - Song (Lyrics)");
+    assert_eq!(history["items"][0]["new"]["title"], "Song");
+
+    // renaming only works on songs in a playlist of the user
+    let mut user = invited(&state, &mut admin, "ray").await;
+    let rename = format!("RENAME_SONG {id} My%Song");
+    assert_eq!(request(&mut user, &rename).await, "NotInPlaylist");
+    assert_eq!(request(&mut user, "CREATE_PLAYLIST mix true").await, "OK");
+    assert_eq!(
+        request(&mut user, &format!("ADD_SONG_HASH mix {id} _")).await,
+        "OK"
+    );
+    assert_eq!(request(&mut user, &rename).await, "OK");
+    let overrides = request_json(&mut user, "SONG_OVERRIDES ").await;
+    assert_eq!(overrides[0]["id"], id.to_string());
+    assert_eq!(overrides[0]["name"], "My Song");
+    assert_eq!(request(&mut user, &format!("RENAME_SONG {id}")).await, "OK");
+    assert_eq!(
+        request_json(&mut user, "SONG_OVERRIDES ").await,
+        Value::Array(Vec::new())
+    );
+    assert_eq!(request(&mut user, &edit).await, "Unauthorized");
+}
+
+async fn new_songs_are_enriched(backend: Backend) {
+    let downloader = MemoryDownloader::new();
+    let noisy = "https://example.com/watch?v=3";
+    let id = downloader.add_song(
+        noisy,
+        "Artist - Song (Official Video) [HD]",
+        "Artist",
+        "20220524",
+    );
+    let edited = "https://example.com/watch?v=4";
+    let edited_id = downloader.add_song(edited, "Other (Lyrics)", "Artist", "20220524");
+    let dated = "https://example.com/watch?v=5";
+    let dated_id = downloader.add_song(dated, "Dated", "Artist", "20220524");
+    let state = state(backend, downloader).await;
+    let mut admin = sign_up(&state, "sean", ADMIN_KEY, true).await;
+    for url in [noisy, edited, dated] {
+        assert_eq!(request(&mut admin, &format!("QUEUE {url}")).await, "AddedSong");
+        state.cycle_queue().await;
+    }
+
+    // songs an admin already corrected are left alone, every song is only looked at once
+    let edit = format!(r#"EDIT_SONG {edited_id} {{"album": "Album"}}"#);
+    assert_eq!(request(&mut admin, &edit).await, "OK");
+    assert_eq!(state.enrich_songs().await, 2);
+    assert_eq!(state.enrich_songs().await, 0);
+
+    let library = request_json(&mut admin, "SYNC_LIB 0").await;
+    let song = library
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|x| x["id"] == id.to_string().as_str())
+        .expect("song in library");
+    assert_eq!(song["title"], "Song");
+    assert_eq!(song["artist"], "Artist");
+    assert_eq!(song["upload_date"], "2022-05-24");
+    let history = request_json(&mut admin, &format!("SONG_HISTORY {id}")).await;
+    assert_eq!(history["items"][0]["editor"], Value::Null);
+    assert_eq!(history["items"][0]["new"]["title"], "Song");
+    let history = request_json(&mut admin, &format!("SONG_HISTORY {edited_id}")).await;
+    assert_eq!(history["items"][0]["new"]["title"], "Other (Lyrics)");
+
+    // the song is still found by what yt-dlp gave it as well as by what it is now
+    for date in ["20220524", "2022-05-24"] {
+        assert_eq!(
+            request(&mut admin, &format!("FIND_SONG Dated Artist {date}")).await,
+            dated_id.to_string()
+        );
+    }
+    assert_eq!(request(&mut admin, "CREATE_PLAYLIST mix true").await, "OK");
+    assert_eq!(request(&mut admin, "ADD_SONG mix Dated Artist 20220524").await, "OK");
+    assert_eq!(request(&mut admin, "REMOVE_SONG mix Dated Artist 20220524").await, "OK");
+    assert_ne!(request(&mut admin, "REMOVE_SONG mix Other Artist 20220524").await, "OK");
+}
+
+// the backends share the cache dir so each gets a song of its own
+async fn downloaded_songs_are_tagged(backend: Backend) {
+    let downloader = MemoryDownloader::new();
+    let url = format!("https://example.com/watch?v=tags-{backend}");
+    let title = format!("{backend} Song");
+    let id = downloader.add_song(&url, &title, "Artist", "20220524");
+    let state = state(backend, downloader).await;
+    let mut admin = sign_up(&state, "sean", ADMIN_KEY, true).await;
+    assert_eq!(request(&mut admin, &format!("QUEUE {url}")).await, "AddedSong");
+    state.cycle_queue().await;
+
+    let (song, disposition) = download(&state, id).await;
+    let tag = Tag::read_from2(Cursor::new(&song)).expect("song is tagged");
+    assert_eq!(tag.title(), Some(title.as_str()));
+    assert_eq!(tag.artist(), Some("Artist"));
+    assert_eq!(tag.date_recorded().map(|x| x.year), Some(2022));
+    assert!(song.ends_with(title.as_bytes()));
+    let filename = format!(r#"filename="Artist - {title}.mp3""#);
+    assert!(disposition.contains(&filename), "{disposition}");
+
+    // edits are written to the file, the audio is left as it was
+    let edit = format!(r#"EDIT_SONG {id} {{"title": "Tagged", "album": "Album"}}"#);
+    assert_eq!(request(&mut admin, &edit).await, "OK");
+    let (song, disposition) = download(&state, id).await;
+    let tag = Tag::read_from2(Cursor::new(&song)).expect("song is tagged");
+    assert_eq!(tag.title(), Some("Tagged"));
+    assert_eq!(tag.album(), Some("Album"));
+    assert!(song.ends_with(title.as_bytes()));
+    assert!(disposition.contains("Artist - Tagged.mp3"), "{disposition}");
+
+    let missing = warp::test::request()
+        .path("/songs-download/1")
+        .reply(&routes(state.clone()))
+        .await;
+    assert_eq!(missing.status(), 404);
+}
+
+// yt-dlp mostly gives webm or m4a, those are stored and served as they are
+async fn other_formats_are_not_tagged(backend: Backend) {
+    let downloader = MemoryDownloader::new();
+    let url = format!("https://example.com/watch?v=webm-{backend}");
+    let title = format!("{backend} Webm");
+    let id = downloader.add_song(&url, &title, "Artist", "20220524");
+    let webm = [&[0x1a, 0x45, 0xdf, 0xa3][..], title.as_bytes()].concat();
+    downloader.set_audio(&url, &webm);
+    let state = state(backend, downloader).await;
+    let mut admin = sign_up(&state, "sean", ADMIN_KEY, true).await;
+    assert_eq!(request(&mut admin, &format!("QUEUE {url}")).await, "AddedSong");
+    state.cycle_queue().await;
+
+    let edit = format!(r#"EDIT_SONG {id} {{"title": "Edited"}}"#);
+    assert_eq!(request(&mut admin, &edit).await, "OK");
+    let response = warp::test::request()
+        .path(&format!("/songs-download/{id}"))
+        .reply(&routes(state.clone()))
+        .await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "audio/webm");
+    let disposition = response.headers()["content-disposition"].to_str().unwrap();
+    assert!(disposition.contains(r#"filename="Artist - Edited.webm""#), "{disposition}");
+    assert_eq!(response.body().as_ref(), webm.as_slice());
+}
+
+// a file from the cdn route, which is always gzipped
+async fn cdn(state: &AppState, path: &str) -> Vec<u8> {
+    let response = warp::test::request()
+        .path(path)
+        .reply(&routes(state.clone()))
+        .await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-encoding"], "gzip");
+    let mut body = Vec::new();
+    GzDecoder::new(response.body().as_ref())
+        .read_to_end(&mut body)
+        .expect("gzipped body");
+    body
+}
+
+// a 320x180 png like a youtube thumbnail, red on the sides and the color in the middle square
+async fn serve_thumbnail(color: [u8; 3]) -> String {
+    let image = image::RgbImage::from_fn(320, 180, |x, _| match (70..250).contains(&x) {
+        true => image::Rgb(color),
+        false => image::Rgb([255, 0, 0]),
+    });
+    let mut png = Vec::new();
+    image::DynamicImage::ImageRgb8(image)
+        .write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)
+        .unwrap();
+    let route = warp::path("thumbnail.png").map(move || png.clone());
+    let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    format!("http://{addr}/thumbnail.png")
+}
+
+async fn thumbnails_are_served_locally(backend: Backend) {
+    let downloader = MemoryDownloader::new();
+    let url = format!("https://example.com/watch?v=art-{backend}");
+    let id = downloader.add_song(&url, &format!("{backend} Art"), "Artist", "20220524");
+    downloader.set_thumbnail(&url, &serve_thumbnail([0, 0, 255]).await);
+    let missing = format!("https://example.com/watch?v=no-art-{backend}");
+    downloader.add_song(&missing, &format!("{backend} No Art"), "Artist", "20220524");
+    downloader.set_thumbnail(&missing, "http://127.0.0.1:1/thumbnail.png");
+    let state = state(backend, downloader).await;
+    let mut admin = sign_up(&state, "sean", ADMIN_KEY, true).await;
+    assert_eq!(request(&mut admin, &format!("QUEUE {url}")).await, "AddedSong");
+    state.cycle_queue().await;
+    assert_eq!(request(&mut admin, &format!("QUEUE {missing}")).await, "AddedSong");
+    state.cycle_queue().await;
+
+    let library = request_json(&mut admin, "SYNC_LIB 0").await;
+    let songs = library.as_array().unwrap();
+    let song = songs.iter().find(|x| x["id"] == id.to_string().as_str()).unwrap();
+    assert_eq!(song["thumbnail"], format!("/songs-cdn/art-{id}-512.jpg"));
+    assert_eq!(song["thumbnails"]["96"], format!("/songs-cdn/art-{id}-96.jpg"));
+    let other = songs.iter().find(|x| x["id"] != id.to_string().as_str()).unwrap();
+    assert!(other["thumbnail"].is_null());
+    assert!(other["thumbnails"].is_null());
+
+    // the middle of the thumbnail is kept as a square
+    for size in [96, 256, 512] {
+        let path = song["thumbnails"][size.to_string()].as_str().unwrap();
+        let art = image::load_from_memory(&cdn(&state, path).await).unwrap().to_rgb8();
+        assert_eq!(art.dimensions(), (size, size));
+        let corner = art.get_pixel(2, 2);
+        assert!(corner[2] > 200 && corner[0] < 50, "{corner:?}");
+    }
+
+    // and is the cover of the song
+    let (song, _) = download(&state, id).await;
+    let tag = Tag::read_from2(Cursor::new(&song)).expect("song is tagged");
+    let cover = tag.pictures().next().expect("song has a cover");
+    assert_eq!(cover.mime_type, "image/jpeg");
+    let cover = image::load_from_memory(&cover.data).unwrap().to_rgb8();
+    assert_eq!(cover.dimensions(), (512, 512));
+}
+
+async fn image(state: &AppState, path: &str) -> image::RgbImage {
+    image::load_from_memory(&cdn(state, path).await).unwrap().to_rgb8()
+}
+
+// the color at the middle of every quarter of a cover, or the middle of the cover with one
+fn quarters(cover: &image::RgbImage, tiles: usize) -> Vec<[u8; 3]> {
+    let points: &[(u32, u32)] = match tiles {
+        1 => &[(200, 200)],
+        _ => &[(100, 100), (300, 100), (100, 300), (300, 300)],
+    };
+    points.iter().map(|(x, y)| cover.get_pixel(*x, *y).0).collect()
+}
+
+fn assert_colors(cover: &image::RgbImage, expected: &[[u8; 3]]) {
+    for (got, expected) in quarters(cover, expected.len()).iter().zip(expected) {
+        let close = got.iter().zip(expected).all(|(a, b)| a.abs_diff(*b) < 40);
+        assert!(close, "{got:?} isn't {expected:?}");
+    }
+}
+
+async fn playlist_covers_are_made_from_artwork(backend: Backend) {
+    let colors = [
+        [0, 0, 255],
+        [0, 255, 0],
+        [255, 255, 0],
+        [0, 255, 255],
+        [255, 0, 255],
+    ];
+    let downloader = MemoryDownloader::new();
+    let mut songs = Vec::new();
+    for (i, color) in colors.iter().enumerate() {
+        let url = format!("https://example.com/watch?v=cover-{backend}-{i}");
+        let title = format!("{backend} Cover {i}");
+        songs.push((url.clone(), downloader.add_song(&url, &title, "Artist", "20220524")));
+        downloader.set_thumbnail(&url, &serve_thumbnail(*color).await);
+    }
+    let state = state(backend, downloader).await;
+    let mut client = sign_up(&state, "sean", ADMIN_KEY, true).await;
+    for (url, _) in &songs {
+        assert_eq!(request(&mut client, &format!("QUEUE {url}")).await, "AddedSong");
+        state.cycle_queue().await;
+    }
+
+    let name = format!("{backend}%covers");
+    let path = format!(
+        "/songs-cdn/{}-{}.png",
+        hash(b"sean"),
+        hash(name.replace('%', " ").as_bytes())
+    );
+    let add = |i: usize| format!("ADD_SONG_HASH {name} {} _", songs[i].1);
+    assert_eq!(request(&mut client, &format!("CREATE_PLAYLIST {name} true")).await, "OK");
+
+    // one song fills the cover, four are a grid and the fifth isn't on it
+    assert_eq!(request(&mut client, &add(0)).await, "OK");
+    assert_colors(&image(&state, &path).await, &colors[..1]);
+    for i in 1..5 {
+        assert_eq!(request(&mut client, &add(i)).await, "OK");
+    }
+    assert_colors(&image(&state, &path).await, &colors[..4]);
+
+    let remove = format!("REMOVE_SONG_HASH {name} {} _", songs[1].1);
+    assert_eq!(request(&mut client, &remove).await, "OK");
+    let expected = [colors[0], colors[2], colors[3], colors[4]];
+    assert_colors(&image(&state, &path).await, &expected);
+
+    // a custom image is kept until it's removed
+    let mut png = Vec::new();
+    image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(400, 400, image::Rgb([0; 3])))
+        .write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)
+        .unwrap();
+    let set = format!("SET_PLAYLIST_IMAGE {name} {}", base64::encode(&png));
+    assert_eq!(request(&mut client, &set).await, "OK");
+    assert_eq!(request(&mut client, &add(1)).await, "OK");
+    assert_colors(&image(&state, &path).await, &[[0; 3]; 4]);
+    assert_eq!(request(&mut client, &format!("REMOVE_PLAYLIST_IMAGE {name}")).await, "OK");
+    assert_colors(&image(&state, &path).await, &expected);
+
+    // an empty playlist goes back to the default image, which only has two colors
+    for (_, id) in &songs {
+        let remove = format!("REMOVE_SONG_HASH {name} {id} _");
+        assert_eq!(request(&mut client, &remove).await, "OK");
+    }
+    let empty = image(&state, &path).await;
+    assert!(empty.pixels().collect::<HashSet<_>>().len() <= 2);
+}
+
+async fn private_profiles_have_to_accept_follows(backend: Backend) {
+    let state = state(backend, MemoryDownloader::new()).await;
+    let mut sean = sign_up(&state, "sean", ADMIN_KEY, false).await;
+    set_profile(&mut sean, "sean", true).await;
+    let mut ray = invited(&state, &mut sean, "ray").await;
+    set_profile(&mut ray, "ray", false).await;
+
+    assert_eq!(request(&mut ray, "FOLLOW sean").await, "OK");
+    assert_eq!(request(&mut sean, "FOLLOW ray").await, "REQUESTED");
+    let notification = recv(&mut ray).await;
+    assert!(notification.starts_with("FOLLOW_REQUEST "), "{notification}");
+    assert!(notification.contains(r#""user":"sean""#), "{notification}");
+
+    let requests = request_json(&mut ray, "LIST_FOLLOW_REQUESTS ").await;
+    assert_eq!(requests["items"][0]["user"], "sean");
+    assert_eq!(request(&mut ray, "ACCEPT_FOLLOW sean").await, "OK");
+    assert!(recv(&mut sean).await.starts_with("FOLLOW_ACCEPTED "));
+
+    let following = request_json(&mut sean, "LIST_FOLLOWING ").await;
+    assert_eq!(following["items"][0]["user"], "ray");
+    let followers = request_json(&mut sean, "LIST_FOLLOWERS ").await;
+    assert_eq!(followers["items"][0]["user"], "ray");
+    assert_eq!(request(&mut ray, "ACCEPT_FOLLOW sean").await, "NoFollowRequest");
+}
+
+async fn invites_create_accounts_without_admin(backend: Backend) {
+    let state = state(backend, MemoryDownloader::new()).await;
+    let mut admin = sign_up(&state, "sean", ADMIN_KEY, true).await;
+    set_profile(&mut admin, "sean", true).await;
+
+    let invite = request(&mut admin, "CREATE_INVITE 1").await;
+    let mut user = sign_up(&state, "ray", &invite, false).await;
+    set_profile(&mut user, "ray", true).await;
+
+    let invites = request_json(&mut admin, "LIST_INVITES ").await;
+    assert_eq!(invites["invites"][0]["code"], invite.as_str());
+    assert_eq!(invites["invites"][0]["used_by"][0], "ray");
+
+    // the invite is used up so a second account can't be made with it
+    cannot_sign_up(&state, "other", &invite).await;
+
+    // invited accounts aren't admins even with the ADMIN_KEY
+    let mut client = connect(&state).await;
+    client
+        .send_text(format!("AUTH ray {PASSWORD} {ADMIN_KEY}"))
+        .await;
+    assert_eq!(request(&mut client, "ADMIN_LIST_CLIENTS ").await, "Unauthorized");
+    assert_eq!(request(&mut user, "CREATE_INVITE 1").await, "NoInvitesLeft");
+}
+
+// the play is recorded in the background so the listening time is checked until it shows up
+async fn plays(client: &mut WsClient, expected: u64) -> Value {
+    for _ in 0..50 {
+        let time = request_json(client, "LISTEN_TIME 0 99999999999").await;
+        if time["plays"] == expected {
+            return time;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("never got to {expected} plays");
+}
+
+async fn streams_are_counted_with_the_stream_token(backend: Backend) {
+    let downloader = MemoryDownloader::new();
+    let url = format!("https://example.com/watch?v=stream-{backend}");
+    let id = downloader.add_song(&url, &format!("{backend} Stream"), "Artist", "20220524");
+    let state = state(backend, downloader).await;
+    let mut admin = sign_up(&state, "sean", ADMIN_KEY, true).await;
+    let mut user = invited(&state, &mut admin, "ray").await;
+    assert_eq!(request(&mut admin, &format!("QUEUE {url}")).await, "AddedSong");
+    state.cycle_queue().await;
+
+    // every connection gets a token of its own
+    let token = request(&mut user, "STREAM_TOKEN ").await;
+    assert_eq!(token.len(), 32);
+    assert_ne!(request(&mut admin, "STREAM_TOKEN ").await, token);
+    for query in ["", "?token=nope", &format!("?token={token}")] {
+        let response = warp::test::request()
+            .path(&format!("/songs/{id}{query}"))
+            .reply(&routes(state.clone()))
+            .await;
+        assert_eq!(response.status(), 200);
+    }
+    plays(&mut user, 1).await;
+    assert_eq!(plays(&mut admin, 0).await["plays"], 0);
+
+    // and it stops working once the connection is gone
+    drop(user);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let response = warp::test::request()
+        .path(&format!("/songs/{id}?token={token}"))
+        .reply(&routes(state.clone()))
+        .await;
+    assert_eq!(response.status(), 200);
+    let mut user = log_in(&state, "ray").await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    plays(&mut user, 1).await;
+}
+
+async fn profiles_only_show_what_is_shared(backend: Backend) {
+    let downloader = MemoryDownloader::new();
+    let url = format!("https://example.com/watch?v=profile-{backend}");
+    let id = downloader.add_song(&url, &format!("{backend} Profile"), "Artist", "20220524");
+    let state = state(backend, downloader).await;
+    let mut sean = sign_up(&state, "sean", ADMIN_KEY, false).await;
+    set_profile(&mut sean, "sean", true).await;
+    let mut ray = invited(&state, &mut sean, "ray").await;
+    set_profile(&mut ray, "ray", true).await;
+    let mut nate = invited(&state, &mut sean, "nate").await;
+    let msg = r#"UPDATE_USERDATA {"public_profile": true, "display_name": "nate", "share_status": true}"#;
+    assert_eq!(request(&mut nate, msg).await, "OK");
+    assert_eq!(request(&mut sean, &format!("QUEUE {url}")).await, "AddedSong");
+    state.cycle_queue().await;
+    assert_eq!(request(&mut ray, &format!("NOW_PLAYING {id}")).await, "OK");
+    assert_eq!(request(&mut nate, &format!("NOW_PLAYING {id}")).await, "OK");
+
+    let profile = request_json(&mut sean, "REQUEST_PROFILE ray").await;
+    assert_eq!(profile["display_name"], "ray");
+    assert!(profile["recent_plays"].is_null());
+    assert!(profile["now_playing"].is_null());
+    assert!(profile["followers"].is_null());
+    assert!(profile["following"].is_null());
+    let own = request_json(&mut ray, "REQUEST_USERDATA ").await;
+    assert_eq!(own["recent_plays"][0], id.to_string());
+
+    let profile = request_json(&mut sean, "REQUEST_PROFILE nate").await;
+    assert_eq!(profile["recent_plays"][0], id.to_string());
+    assert_eq!(profile["now_playing"], id.to_string());
+    assert!(profile["followers"].is_null());
+
+    // followers see who else follows
+    assert_eq!(request(&mut sean, "FOLLOW nate").await, "OK");
+    let profile = request_json(&mut sean, "REQUEST_PROFILE nate").await;
+    assert_eq!(profile["followers"], serde_json::json!([hash(b"sean")]));
+    assert_eq!(profile["following"], serde_json::json!([]));
+}
+
+async fn connected_clients_are_listed_without_tokens(backend: Backend) {
+    let state = state(backend, MemoryDownloader::new()).await;
+    let mut admin = sign_up(&state, "sean", ADMIN_KEY, true).await;
+    set_profile(&mut admin, "sean", true).await;
+    let mut user = invited(&state, &mut admin, "ray").await;
+    let token = request(&mut user, "STREAM_TOKEN ").await;
+
+    let reply = request(&mut admin, "ADMIN_LIST_CLIENTS ").await;
+    assert!(!reply.contains(&token), "{reply}");
+    let clients: Value = serde_json::from_str(&reply).unwrap();
+    let clients = clients.as_array().unwrap();
+    assert_eq!(clients.len(), 2);
+    assert!(clients.iter().all(|x| x.get("id").is_none()));
+    let sean = clients.iter().find(|x| x["user"] == "sean").unwrap();
+    assert_eq!(sean["admin"], true);
+    assert_eq!(sean["username_hash"], hash(b"sean").to_string());
+}
+
+async fn song_edits_follow_the_editor_account(backend: Backend) {
+    let downloader = MemoryDownloader::new();
+    let url = format!("https://example.com/watch?v=editor-{backend}");
+    let id = downloader.add_song(&url, &format!("{backend} Editor"), "Artist", "20220524");
+    let state = state(backend, downloader).await;
+    let mut sean = sign_up(&state, "sean", ADMIN_KEY, true).await;
+    set_profile(&mut sean, "sean", true).await;
+    let mut ray = invited(&state, &mut sean, "ray").await;
+    set_profile(&mut ray, "ray", true).await;
+    assert_eq!(request(&mut sean, "ADMIN_PROMOTE ray").await, "OK");
+    let mut ray = connect(&state).await;
+    ray.send_text(format!("AUTH ray {PASSWORD} {ADMIN_KEY}")).await;
+    assert_eq!(request(&mut sean, &format!("QUEUE {url}")).await, "AddedSong");
+    state.cycle_queue().await;
+    let edit = format!(r#"EDIT_SONG {id} {{"album": "Album"}}"#);
+    assert_eq!(request(&mut sean, &edit).await, "OK");
+
+    let renamed = format!("CHANGE_USERNAME {PASSWORD} sean2");
+    assert_eq!(request(&mut sean, &renamed).await, "OK");
+    let history = request_json(&mut ray, &format!("SONG_HISTORY {id}")).await;
+    assert_eq!(history["items"][0]["editor"], "sean");
+
+    // someone signing up with the name later isn't the editor
+    sean.send_text(format!("DELETE_ACCOUNT {PASSWORD}")).await;
+    no_reply(&mut sean).await;
+    let mut other = invited(&state, &mut ray, "sean2").await;
+    set_profile(&mut other, "sean", true).await;
+    let history = request_json(&mut ray, &format!("SONG_HISTORY {id}")).await;
+    assert_eq!(history["items"][0]["editor"], Value::Null);
+    assert_eq!(history["items"][0]["new"]["album"], "Album");
+}
+
+async fn admin_key_only_makes_the_first_admin(backend: Backend) {
+    let state = state(backend, MemoryDownloader::new()).await;
+    let mut admin = sign_up(&state, "sean", ADMIN_KEY, true).await;
+
+    // there is an admin already so the ADMIN_KEY doesn't make an account
+    cannot_sign_up(&state, "other", ADMIN_KEY).await;
+
+    // later admins are promoted
+    let mut user = invited(&state, &mut admin, "ray").await;
+    set_profile(&mut user, "ray", true).await;
+    assert_eq!(request(&mut admin, "ADMIN_PROMOTE ray").await, "OK");
+    let mut client = connect(&state).await;
+    client
+        .send_text(format!("AUTH ray {PASSWORD} {ADMIN_KEY}"))
+        .await;
+    let log = request_json(&mut client, "ADMIN_LOG ").await;
+    assert_eq!(log["items"][0]["command"], "ADMIN_PROMOTE");
+}
+
+async fn banned_users_are_kicked_and_cannot_authenticate(backend: Backend) {
+    let state = state(backend, MemoryDownloader::new()).await;
+    let mut admin = sign_up(&state, "sean", ADMIN_KEY, true).await;
+    let invite = request(&mut admin, "CREATE_INVITE 1").await;
+    let mut user = sign_up(&state, "ray", &invite, false).await;
+    set_profile(&mut user, "ray", true).await;
+
+    assert_eq!(request(&mut admin, "ADMIN_BAN ray").await, "OK");
+    no_reply(&mut user).await;
+
+    let mut client = connect(&state).await;
+    client
+        .send_text(format!("AUTH ray {PASSWORD}"))
+        .await;
+    client.send_text("PING ").await;
+    no_reply(&mut client).await;
+
+    let log = request_json(&mut admin, "ADMIN_LOG ").await;
+    assert_eq!(log["items"][0]["command"], "ADMIN_BAN");
+    assert_eq!(log["items"][0]["response"], "OK");
+}
+
+async fn client_commands_are_relayed_to_the_same_user(backend: Backend) {
+    let state = state(backend, MemoryDownloader::new()).await;
+    let mut phone = sign_up(&state, "sean", ADMIN_KEY, false).await;
+    let mut desktop = log_in(&state, "sean").await;
+    let mut other = invited(&state, &mut phone, "ray").await;
+
+    phone.send_text("VOL_SET 50").await;
+    assert_eq!(recv(&mut desktop).await, "VOL_SET 50");
+    assert_eq!(recv(&mut phone).await, "VOL_SET 50");
+    no_reply(&mut other).await;
+}
+
+async fn plays_make_up_the_listening_stats(backend: Backend) {
+    let downloader = MemoryDownloader::new();
+    let first = format!("https://example.com/watch?v=plays-{backend}");
+    let second = format!("https://example.com/watch?v=plays-other-{backend}");
+    let third = format!("https://example.com/watch?v=plays-third-{backend}");
+    let a = downloader.add_song(&first, &format!("{backend} Played"), "Artist", "20220524");
+    let b = downloader.add_song(&second, &format!("{backend} Other"), "Other", "20220524");
+    let c = downloader.add_song(&third, &format!("{backend} Third"), "Artist", "20220524");
+    let state = state(backend, downloader).await;
+    let mut client = sign_up(&state, "sean", ADMIN_KEY, true).await;
+    for url in [&first, &second, &third] {
+        assert_eq!(request(&mut client, &format!("QUEUE {url}")).await, "AddedSong");
+        state.cycle_queue().await;
+    }
+
+    // moving forward in a song isn't a new play, and neither is starting it again this soon
+    for (song, position) in [(a, 0), (a, 30), (a, 10), (b, 0), (c, 0)] {
+        let msg = format!("NOW_PLAYING {song} {position}");
+        assert_eq!(request(&mut client, &msg).await, "OK");
+    }
+    assert_eq!(request(&mut client, "NOW_PLAYING 1").await, "InvalidHash");
+    assert_eq!(plays(&mut client, 3).await["unique_songs"], 3);
+    let data = request_json(&mut client, "REQUEST_USERDATA ").await;
+    let recent = data["recent_plays"].as_array().unwrap();
+    assert_eq!(recent.len(), 3);
+    assert!(recent.contains(&Value::from(c.to_string())));
+
+    let top = request_json(&mut client, "TOP_SONGS 0 99999999999").await;
+    assert_eq!(top.as_array().unwrap().len(), 3);
+    assert!(top.as_array().unwrap().iter().all(|x| x["plays"] == 1));
+    let top = request_json(&mut client, "TOP_SONGS 0 99999999999 1").await;
+    assert_eq!(top.as_array().unwrap().len(), 1);
+    let artists = request_json(&mut client, "TOP_ARTISTS 0 99999999999").await;
+    assert_eq!(
+        artists,
+        serde_json::json!([{"artist": "Artist", "plays": 2}, {"artist": "Other", "plays": 1}])
+    );
+    assert_eq!(request_json(&mut client, "LISTEN_TIME 0 1").await["plays"], 0);
+
+    // every play is in this year, whichever side of new year's the clock is on
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let year = 1970 + now / 31_556_952;
+    let mut years = Vec::new();
+    for year in year - 1..=year + 1 {
+        let review = request_json(&mut client, &format!("YEAR_IN_REVIEW {year}")).await;
+        assert_eq!(review["year"], year);
+        years.push(review["plays"].as_u64().unwrap());
+        if review["plays"] == 3 {
+            assert_eq!(review["top_songs"].as_array().unwrap().len(), 3);
+            assert_eq!(review["top_artists"][0]["artist"], "Artist");
+        }
+    }
+    assert_eq!(years.iter().filter(|x| **x == 3).count(), 1);
+    assert_eq!(years.iter().sum::<u64>(), 3);
+    assert_eq!(request(&mut client, "YEAR_IN_REVIEW soon").await, "InvalidYear");
+}
+
+async fn presence_is_sent_to_followers(backend: Backend) {
+    let downloader = MemoryDownloader::new();
+    let url = format!("https://example.com/watch?v=presence-{backend}");
+    let id = downloader.add_song(&url, &format!("{backend} Presence"), "Artist", "20220524");
+    let state = state(backend, downloader).await;
+    let mut sean = sign_up(&state, "sean", ADMIN_KEY, true).await;
+    let msg = r#"UPDATE_USERDATA {"public_profile": true, "display_name": "sean", "share_status": true}"#;
+    assert_eq!(request(&mut sean, msg).await, "OK");
+    let mut ray = invited(&state, &mut sean, "ray").await;
+    set_profile(&mut ray, "ray", true).await;
+    let mut nate = invited(&state, &mut sean, "nate").await;
+    set_profile(&mut nate, "nate", true).await;
+    assert_eq!(request(&mut ray, "FOLLOW sean").await, "OK");
+    assert_eq!(request(&mut sean, &format!("QUEUE {url}")).await, "AddedSong");
+    state.cycle_queue().await;
+
+    // only followers hear about it
+    assert_eq!(request(&mut sean, &format!("NOW_PLAYING {id} 42")).await, "OK");
+    let presence = recv(&mut ray).await;
+    let presence: Value =
+        serde_json::from_str(presence.strip_prefix("PRESENCE ").unwrap()).unwrap();
+    assert_eq!(presence["user"], "sean");
+    assert_eq!(presence["song"], id.to_string());
+    assert_eq!(presence["position"], 42);
+    no_reply(&mut nate).await;
+    let list = request_json(&mut ray, "LIST_PRESENCE ").await;
+    assert_eq!(list[0]["song"], id.to_string());
+    assert_eq!(request_json(&mut nate, "LIST_PRESENCE ").await, serde_json::json!([]));
+
+    // once every connection is gone the song is cleared
+    drop(sean);
+    let presence = recv(&mut ray).await;
+    assert!(presence.contains(r#""song":null"#), "{presence}");
+    assert_eq!(request_json(&mut ray, "LIST_PRESENCE ").await, serde_json::json!([]));
+
+    // and nothing is sent without share_status
+    let mut ray_again = log_in(&state, "ray").await;
+    assert_eq!(request(&mut nate, "FOLLOW ray").await, "OK");
+    assert_eq!(request(&mut ray_again, &format!("NOW_PLAYING {id}")).await, "OK");
+    no_reply(&mut nate).await;
+    assert_eq!(request_json(&mut nate, "LIST_PRESENCE ").await, serde_json::json!([]));
+}
+
+async fn feed_shows_what_followed_users_do(backend: Backend) {
+    let downloader = MemoryDownloader::new();
+    let url = format!("https://example.com/watch?v=feed-{backend}");
+    let id = downloader.add_song(&url, &format!("{backend} Feed"), "Artist", "20220524");
+    let state = state(backend, downloader).await;
+    let mut sean = sign_up(&state, "sean", ADMIN_KEY, true).await;
+    set_profile(&mut sean, "sean", true).await;
+    let mut ray = invited(&state, &mut sean, "ray").await;
+    set_profile(&mut ray, "ray", true).await;
+    let mut nate = invited(&state, &mut sean, "nate").await;
+    set_profile(&mut nate, "nate", false).await;
+    assert_eq!(request(&mut ray, "FOLLOW sean").await, "OK");
+    assert_eq!(request(&mut ray, "FOLLOW nate").await, "REQUESTED");
+    assert!(recv(&mut nate).await.starts_with("FOLLOW_REQUEST "));
+    assert_eq!(request(&mut nate, "ACCEPT_FOLLOW ray").await, "OK");
+    assert!(recv(&mut ray).await.starts_with("FOLLOW_ACCEPTED "));
+
+    assert_eq!(request(&mut sean, &format!("QUEUE {url}")).await, "AddedSong");
+    state.cycle_queue().await;
+    assert_eq!(request(&mut sean, "CREATE_PLAYLIST mix true").await, "OK");
+    assert_eq!(request(&mut sean, &format!("ADD_SONG_HASH mix {id} _")).await, "OK");
+    assert_eq!(request(&mut sean, "CREATE_PLAYLIST hidden false").await, "OK");
+    assert_eq!(request(&mut sean, &format!("ADD_SONG_HASH hidden {id} _")).await, "OK");
+    assert_eq!(request(&mut sean, "FOLLOW ray").await, "OK");
+    // nate's profile is private so none of it shows up
+    assert_eq!(request(&mut nate, "CREATE_PLAYLIST other true").await, "OK");
+
+    let feed = request_json(&mut ray, "FEED ").await;
+    let items = feed["items"].as_array().unwrap();
+    let kinds: Vec<&str> = items.iter().map(|x| x["kind"].as_str().unwrap()).collect();
+    assert_eq!(kinds, ["Followed", "AddedSong", "CreatedPlaylist", "QueuedSong"]);
+    assert!(items.iter().all(|x| x["user"] == "sean"));
+    assert_eq!(items[0]["detail"], "ray");
+    assert_eq!(items[1]["playlist"], "mix");
+    assert_eq!(items[1]["song"], id.to_string());
+    assert_eq!(feed["cursor"], Value::Null);
+
+    // pages are 50 items long
+    for i in 0..50 {
+        let msg = format!("QUEUE https://example.com/watch?v=page-{i}");
+        assert_eq!(request(&mut sean, &msg).await, "AddedSong");
+    }
+    let feed = request_json(&mut ray, "FEED ").await;
+    assert_eq!(feed["items"].as_array().unwrap().len(), 50);
+    assert_eq!(feed["items"][0]["kind"], "QueuedSong");
+    let cursor = feed["cursor"].as_str().expect("cursor for the next page");
+    let feed = request_json(&mut ray, &format!("FEED {cursor}")).await;
+    assert_eq!(feed["items"].as_array().unwrap().len(), 4);
+    assert_eq!(feed["cursor"], Value::Null);
+    assert_eq!(request(&mut ray, "FEED next").await, "InvalidCursor");
+}
+
+async fn follows_can_be_listed_and_undone(backend: Backend) {
+    let state = state(backend, MemoryDownloader::new()).await;
+    let mut sean = sign_up(&state, "sean", ADMIN_KEY, true).await;
+    set_profile(&mut sean, "sean", true).await;
+    let mut ray = invited(&state, &mut sean, "ray").await;
+    set_profile(&mut ray, "ray", true).await;
+    let mut nate = invited(&state, &mut sean, "nate").await;
+    set_profile(&mut nate, "nate", true).await;
+
+    // following twice is still one follow
+    assert_eq!(request(&mut ray, "FOLLOW sean").await, "OK");
+    assert_eq!(request(&mut nate, "FOLLOW sean").await, "OK");
+    assert_eq!(request(&mut ray, "FOLLOW sean").await, "OK");
+    let followers = request_json(&mut sean, "LIST_FOLLOWERS ").await;
+    let mut users: Vec<&str> = followers["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|x| x["user"].as_str().unwrap())
+        .collect();
+    users.sort_unstable();
+    assert_eq!(users, ["nate", "ray"]);
+    assert_eq!(followers["cursor"], Value::Null);
+    let data = request_json(&mut sean, "REQUEST_USERDATA ").await;
+    assert_eq!(data["followers"].as_array().unwrap().len(), 2);
+    let following = request_json(&mut ray, "LIST_FOLLOWING ").await;
+    assert_eq!(following["items"][0]["user"], "sean");
+
+    assert_eq!(request(&mut ray, "UNFOLLOW sean").await, "OK");
+    let followers = request_json(&mut sean, "LIST_FOLLOWERS ").await;
+    assert_eq!(followers["items"].as_array().unwrap().len(), 1);
+    assert_eq!(followers["items"][0]["user"], "nate");
+    let following = request_json(&mut ray, "LIST_FOLLOWING ").await;
+    assert_eq!(following["items"], serde_json::json!([]));
+    let data = request_json(&mut ray, "REQUEST_USERDATA ").await;
+    assert_eq!(data["following"], serde_json::json!([]));
+
+    for cursor in ["-1", "next"] {
+        let msg = format!("LIST_FOLLOWERS {cursor}");
+        assert_eq!(request(&mut sean, &msg).await, "InvalidCursor");
+    }
+    // there is nobody to follow
+    ray.send_text("FOLLOW nobody").await;
+    no_reply(&mut ray).await;
+}
+
+async fn blocks_and_mutes_hide_users(backend: Backend) {
+    let downloader = MemoryDownloader::new();
+    let url = format!("https://example.com/watch?v=mute-{backend}");
+    let id = downloader.add_song(&url, &format!("{backend} Mute"), "Artist", "20220524");
+    let state = state(backend, downloader).await;
+    let mut sean = sign_up(&state, "sean", ADMIN_KEY, true).await;
+    let msg = r#"UPDATE_USERDATA {"public_profile": true, "display_name": "sean", "share_status": true}"#;
+    assert_eq!(request(&mut sean, msg).await, "OK");
+    let mut ray = invited(&state, &mut sean, "ray").await;
+    set_profile(&mut ray, "ray", true).await;
+    let mut nate = invited(&state, &mut sean, "nate").await;
+    set_profile(&mut nate, "nate", true).await;
+    assert_eq!(request(&mut ray, "FOLLOW sean").await, "OK");
+    assert_eq!(request(&mut nate, "FOLLOW sean").await, "OK");
+    assert_eq!(request(&mut sean, "FOLLOW ray").await, "OK");
+
+    // blocking drops the follows both ways and keeps them from coming back
+    assert_eq!(request(&mut sean, "BLOCK ray").await, "OK");
+    assert_eq!(request(&mut sean, "BLOCK nobody").await, "CouldNotBeFound");
+    assert_eq!(request_json(&mut sean, "LIST_BLOCKED ").await, serde_json::json!(["ray"]));
+    let following = request_json(&mut sean, "LIST_FOLLOWING ").await;
+    assert_eq!(following["items"], serde_json::json!([]));
+    let followers = request_json(&mut sean, "LIST_FOLLOWERS ").await;
+    assert_eq!(followers["items"][0]["user"], "nate");
+    assert_eq!(followers["items"].as_array().unwrap().len(), 1);
+    ray.send_text("FOLLOW sean").await;
+    no_reply(&mut ray).await;
+    ray.send_text("REQUEST_PROFILE sean").await;
+    no_reply(&mut ray).await;
+
+    // muting keeps the follow but nothing comes through
+    assert_eq!(request(&mut nate, "MUTE sean").await, "OK");
+    assert_eq!(request_json(&mut nate, "LIST_MUTED ").await, serde_json::json!(["sean"]));
+    assert_eq!(request(&mut sean, &format!("QUEUE {url}")).await, "AddedSong");
+    state.cycle_queue().await;
+    assert_eq!(request(&mut sean, &format!("NOW_PLAYING {id}")).await, "OK");
+    no_reply(&mut nate).await;
+    assert_eq!(request_json(&mut nate, "FEED ").await["items"], serde_json::json!([]));
+    assert_eq!(request_json(&mut nate, "LIST_PRESENCE ").await, serde_json::json!([]));
+    let following = request_json(&mut nate, "LIST_FOLLOWING ").await;
+    assert_eq!(following["items"][0]["user"], "sean");
+
+    assert_eq!(request(&mut nate, "UNMUTE sean").await, "OK");
+    assert_eq!(request_json(&mut nate, "LIST_MUTED ").await, serde_json::json!([]));
+    let feed = request_json(&mut nate, "FEED ").await;
+    assert_eq!(feed["items"][0]["kind"], "QueuedSong");
+    assert_eq!(request_json(&mut nate, "LIST_PRESENCE ").await[0]["user"], "sean");
+
+    assert_eq!(request(&mut sean, "UNBLOCK ray").await, "OK");
+    assert_eq!(request_json(&mut sean, "LIST_BLOCKED ").await, serde_json::json!([]));
+    assert_eq!(request(&mut ray, "FOLLOW sean").await, "OK");
+}
+
+async fn follow_requests_can_be_rejected_or_withdrawn(backend: Backend) {
+    let state = state(backend, MemoryDownloader::new()).await;
+    let mut sean = sign_up(&state, "sean", ADMIN_KEY, true).await;
+    set_profile(&mut sean, "sean", true).await;
+    let mut ray = invited(&state, &mut sean, "ray").await;
+    set_profile(&mut ray, "ray", false).await;
+
+    assert_eq!(request(&mut sean, "FOLLOW ray").await, "REQUESTED");
+    assert!(recv(&mut ray).await.starts_with("FOLLOW_REQUEST "));
+    // asking again doesn't send another notification
+    assert_eq!(request(&mut sean, "FOLLOW ray").await, "REQUESTED");
+    no_reply(&mut ray).await;
+    assert_eq!(request(&mut ray, "REJECT_FOLLOW sean").await, "OK");
+    no_reply(&mut sean).await;
+    let requests = request_json(&mut ray, "LIST_FOLLOW_REQUESTS ").await;
+    assert_eq!(requests["items"], serde_json::json!([]));
+    assert_eq!(request(&mut ray, "REJECT_FOLLOW sean").await, "NoFollowRequest");
+
+    // unfollowing takes back a pending request
+    assert_eq!(request(&mut sean, "FOLLOW ray").await, "REQUESTED");
+    assert!(recv(&mut ray).await.starts_with("FOLLOW_REQUEST "));
+    assert_eq!(request(&mut sean, "UNFOLLOW ray").await, "OK");
+    assert_eq!(request(&mut ray, "ACCEPT_FOLLOW sean").await, "NoFollowRequest");
+    let following = request_json(&mut sean, "LIST_FOLLOWING ").await;
+    assert_eq!(following["items"], serde_json::json!([]));
+}
+
+async fn admins_manage_the_queue_library_and_users(backend: Backend) {
+    let downloader = MemoryDownloader::new();
+    let url = format!("https://example.com/watch?v=admin-{backend}");
+    let id = downloader.add_song(&url, &format!("{backend} Admin"), "Artist", "20220524");
+    let state = state(backend, downloader).await;
+    let mut admin = sign_up(&state, "sean", ADMIN_KEY, true).await;
+    set_profile(&mut admin, "sean", true).await;
+    let mut ray = invited(&state, &mut admin, "ray").await;
+    set_profile(&mut ray, "ray", true).await;
+    assert_eq!(request(&mut ray, "ADMIN_CLEAR_QUEUE ").await, "Unauthorized");
+
+    for i in 0..3 {
+        let msg = format!("QUEUE https://example.com/watch?v={i}");
+        assert_eq!(request(&mut admin, &msg).await, "AddedSong");
+    }
+    let queue = |urls: &[usize]| -> String {
+        urls.iter()
+            .map(|x| format!("https://example.com/watch?v={x} "))
+            .collect()
+    };
+    assert_eq!(request(&mut admin, "ADMIN_MOVE_QUEUE 2 0").await, "OK");
+    assert_eq!(request(&mut admin, "QUEUE_LIST ").await, queue(&[2, 0, 1]));
+    assert_eq!(request(&mut admin, "ADMIN_MOVE_QUEUE 3 0").await, "InvalidPosition");
+    assert_eq!(request(&mut admin, "ADMIN_REMOVE_QUEUE 1").await, "OK");
+    assert_eq!(request(&mut admin, "QUEUE_LIST ").await, queue(&[2, 1]));
+    assert_eq!(request(&mut admin, "ADMIN_REMOVE_QUEUE 2").await, "InvalidPosition");
+    assert_eq!(request(&mut admin, "ADMIN_CLEAR_QUEUE ").await, "OK");
+    assert_eq!(request(&mut admin, "QUEUE_LIST ").await, "");
+
+    // a deleted song is gone from the library, the playlists and the cache
+    assert_eq!(request(&mut admin, &format!("QUEUE {url}")).await, "AddedSong");
+    state.cycle_queue().await;
+    assert_eq!(request(&mut admin, "CREATE_PLAYLIST mix true").await, "OK");
+    assert_eq!(request(&mut admin, &format!("ADD_SONG_HASH mix {id} _")).await, "OK");
+    assert_eq!(request(&mut admin, &format!("ADMIN_DELETE_SONG {id}")).await, "OK");
+    assert_eq!(request(&mut admin, &format!("ADMIN_DELETE_SONG {id}")).await, "InvalidHash");
+    assert_eq!(request(&mut admin, "ADMIN_DELETE_SONG song").await, "ExpectedHash");
+    assert_eq!(request_json(&mut admin, "SYNC_LIB 0").await, serde_json::json!([]));
+    let response = warp::test::request()
+        .path(&format!("/songs/{id}"))
+        .reply(&routes(state.clone()))
+        .await;
+    assert_eq!(response.status(), 404);
+
+    // kicking closes every connection of the user
+    let mut ray_again = log_in(&state, "ray").await;
+    assert_eq!(request(&mut admin, "ADMIN_KICK ray").await, "2");
+    no_reply(&mut ray).await;
+    no_reply(&mut ray_again).await;
+    assert_eq!(request(&mut admin, "ADMIN_KICK nobody").await, "CouldNotBeFound");
+
+    assert_eq!(request(&mut admin, "ADMIN_BAN sean").await, "CannotBanSelf");
+    assert_eq!(request(&mut admin, "ADMIN_BAN ray soon").await, "InvalidDuration");
+    assert_eq!(request(&mut admin, "ADMIN_BAN ray 3600").await, "OK");
+    assert_eq!(request(&mut admin, "ADMIN_UNBAN ray").await, "OK");
+    let mut ray = log_in(&state, "ray").await;
+
+    // demoted admins lose admin on the connections they have open
+    assert_eq!(request(&mut admin, "ADMIN_DEMOTE sean").await, "CannotDemoteSelf");
+    assert_eq!(request(&mut admin, "ADMIN_PROMOTE ray").await, "OK");
+    let mut ray_admin = connect(&state).await;
+    ray_admin
+        .send_text(format!("AUTH ray {PASSWORD} {ADMIN_KEY}"))
+        .await;
+    assert_eq!(request(&mut ray_admin, "ADMIN_CLEAR_QUEUE ").await, "OK");
+    assert_eq!(request(&mut admin, "ADMIN_DEMOTE ray").await, "OK");
+    assert_eq!(request(&mut ray_admin, "ADMIN_CLEAR_QUEUE ").await, "Unauthorized");
+
+    // everyone else can only hand out the invites they are given
+    assert_eq!(request(&mut admin, "ADMIN_SET_INVITES ray -1").await, "InvalidCount");
+    assert_eq!(request(&mut admin, "ADMIN_SET_INVITES ray 2").await, "OK");
+    assert_eq!(request(&mut ray, "CREATE_INVITE 2").await.len(), 12);
+    assert_eq!(request(&mut ray, "CREATE_INVITE 1").await, "NoInvitesLeft");
+
+    let log = request_json(&mut admin, "ADMIN_LOG ").await;
+    assert_eq!(log["items"][0]["command"], "ADMIN_SET_INVITES");
+    assert_eq!(log["items"][0]["args"], "ray 2");
+    assert_eq!(log["items"][1]["response"], "InvalidCount");
+    assert_eq!(log["items"][2]["admin"], "sean");
+}
+
+// a GET with the token sent as a bearer token
+async fn get_with_token(state: &AppState, path: &str, token: Option<&str>) -> (u16, String) {
+    let mut request = warp::test::request().path(path);
+    if let Some(token) = token {
+        request = request.header("authorization", format!("Bearer {token}"));
+    }
+    let response = request.reply(&routes(state.clone())).await;
+    let body = String::from_utf8(response.body().to_vec()).unwrap();
+    (response.status().as_u16(), body)
+}
+
+async fn instance_status_needs_the_admin_key(backend: Backend) {
+    let state = state(backend, MemoryDownloader::new()).await;
+    let mut sean = sign_up(&state, "sean", ADMIN_KEY, true).await;
+    let _ray = invited(&state, &mut sean, "ray").await;
+    let _sean_again = log_in(&state, "sean").await;
+    let _anonymous = connect(&state).await;
+    let url = "https://example.com/watch?v=status";
+    assert_eq!(request(&mut sean, &format!("QUEUE {url}")).await, "AddedSong");
+
+    for token in [None, Some("nope"), Some("songs")] {
+        let (status, _) = get_with_token(&state, "/admin/status", token).await;
+        assert_eq!(status, 401);
+    }
+    let (status, body) = get_with_token(&state, "/admin/status", Some(ADMIN_KEY)).await;
+    assert_eq!(status, 200);
+    let status: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(
+        status["clients"],
+        serde_json::json!({"connected": 4, "authenticated": 3, "unique_users": 2})
+    );
+    assert_eq!(status["song_manager"]["queue"], serde_json::json!([url]));
+    assert_eq!(status["song_manager"]["queue_limit"], 50);
+    assert_eq!(status["database"].is_null(), matches!(backend, Backend::Memory));
+    assert_eq!(status["rate_limited"], serde_json::json!([]));
+}
+
+async fn metrics_need_the_admin_key(backend: Backend) {
+    let state = state(backend, MemoryDownloader::new()).await;
+    let _sean = sign_up(&state, "sean", ADMIN_KEY, true).await;
+    let _anonymous = connect(&state).await;
+    let mut failed = connect(&state).await;
+    // a failed AUTH closes the connection
+    failed.send_text("AUTH sean wrong").await;
+    no_reply(&mut failed).await;
+
+    let (status, _) = get_with_token(&state, "/metrics", None).await;
+    assert_eq!(status, 401);
+    let (status, body) = get_with_token(&state, "/metrics", Some(ADMIN_KEY)).await;
+    assert_eq!(status, 200);
+    assert!(body.contains("\nseanify_websocket_connections 2\n"), "{body}");
+    for metric in [
+        "seanify_websocket_connections_total ",
+        "seanify_commands_total{command=\"PING\"} ",
+        "seanify_command_duration_seconds_bucket{command=\"PING\",le=\"+Inf\"} ",
+        "seanify_auth_failures_total ",
+        "seanify_db_query_duration_seconds_count ",
+    ] {
+        assert!(body.contains(metric), "{metric} is missing from\n{body}");
+    }
+}
+
+async fn invites_can_be_shared_revoked_and_expire(backend: Backend) {
+    let state = state(backend, MemoryDownloader::new()).await;
+    let mut admin = sign_up(&state, "sean", ADMIN_KEY, true).await;
+    let mut ray = invited(&state, &mut admin, "ray").await;
+    set_profile(&mut ray, "ray", true).await;
+    assert_eq!(request(&mut admin, "ADMIN_SET_INVITES ray 3").await, "OK");
+    for uses in ["0", "101", "some"] {
+        let msg = format!("CREATE_INVITE {uses}");
+        assert_eq!(request(&mut ray, &msg).await, "InvalidMessage");
+    }
+
+    // an invite can be used as many times as it was made for
+    let shared = request(&mut ray, "CREATE_INVITE 2").await;
+    assert_eq!(request(&mut ray, "CREATE_INVITE 2").await, "NoInvitesLeft");
+    for name in ["nate", "tom"] {
+        let mut client = sign_up(&state, name, &shared, false).await;
+        set_profile(&mut client, name, true).await;
+    }
+    cannot_sign_up(&state, "other", &shared).await;
+    let invites = request_json(&mut ray, "LIST_INVITES ").await;
+    assert_eq!(invites["invites"][0]["code"], shared.as_str());
+    let mut used_by: Vec<&str> = invites["invites"][0]["used_by"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|x| x.as_str().unwrap())
+        .collect();
+    used_by.sort_unstable();
+    assert_eq!(used_by, ["nate", "tom"]);
+
+    // revoking gives back the uses that were left
+    let unused = request(&mut ray, "CREATE_INVITE 1").await;
+    assert_eq!(request(&mut ray, "CREATE_INVITE 1").await, "NoInvitesLeft");
+    assert_eq!(request(&mut ray, &format!("REVOKE_INVITE {unused}")).await, "OK");
+    assert_eq!(request(&mut ray, &format!("REVOKE_INVITE {unused}")).await, "InvalidInvite");
+    cannot_sign_up(&state, "other", &unused).await;
+    assert_eq!(request(&mut ray, "CREATE_INVITE 1").await.len(), 12);
+
+    let expired = request(&mut admin, "CREATE_INVITE 1 0").await;
+    cannot_sign_up(&state, "other", &expired).await;
+    let lasting = request(&mut admin, "CREATE_INVITE 1 3600").await;
+    sign_up(&state, "other", &lasting, false).await;
+}
+
+// AUTH with the wrong details closes the connection
+async fn cannot_log_in(state: &AppState, username: &str, password: &str) {
+    let mut client = connect(state).await;
+    client
+        .send_text(format!("AUTH {username} {password}"))
+        .await;
+    client.send_text("PING ").await;
+    no_reply(&mut client).await;
+}
+
+async fn accounts_can_be_changed_and_deleted(backend: Backend) {
+    let state = state(backend, MemoryDownloader::new()).await;
+    let mut sean = sign_up(&state, "sean", ADMIN_KEY, true).await;
+    set_profile(&mut sean, "sean", true).await;
+    let mut ray = invited(&state, &mut sean, "ray").await;
+    set_profile(&mut ray, "ray", true).await;
+    assert_eq!(request(&mut ray, "FOLLOW sean").await, "OK");
+    assert_eq!(request(&mut ray, "CREATE_PLAYLIST mix true").await, "OK");
+
+    assert_eq!(request(&mut ray, "CHANGE_PASSWORD wrong new").await, "InvalidPassword");
+    let msg = format!("CHANGE_PASSWORD {PASSWORD} hunter3");
+    assert_eq!(request(&mut ray, &msg).await, "OK");
+    cannot_log_in(&state, "ray", PASSWORD).await;
+
+    // open connections stay logged in under the new name
+    let mut ray_again = connect(&state).await;
+    ray_again.send_text("AUTH ray hunter3").await;
+    assert_eq!(request(&mut ray, "CHANGE_USERNAME wrong ray2").await, "InvalidPassword");
+    assert_eq!(request(&mut ray, "CHANGE_USERNAME hunter3 sean").await, "UsernameTaken");
+    assert_eq!(request(&mut ray, "CHANGE_USERNAME hunter3 ray2").await, "OK");
+    cannot_log_in(&state, "ray", "hunter3").await;
+    for client in [&mut ray, &mut ray_again] {
+        assert_eq!(request_json(client, "REQUEST_PLAYLIST mix").await["name"], "mix");
+    }
+    let followers = request_json(&mut sean, "LIST_FOLLOWERS ").await;
+    assert_eq!(followers["items"][0]["user"], "ray");
+    let mut renamed = connect(&state).await;
+    renamed.send_text("AUTH ray2 hunter3").await;
+    assert_eq!(request(&mut renamed, "PING ").await, "PONG");
+
+    // deleting closes every connection and leaves nothing behind
+    assert_eq!(request(&mut ray, "DELETE_ACCOUNT wrong").await, "InvalidPassword");
+    ray.send_text("DELETE_ACCOUNT hunter3").await;
+    for client in [&mut ray, &mut ray_again, &mut renamed] {
+        no_reply(client).await;
+    }
+    cannot_log_in(&state, "ray2", "hunter3").await;
+    let followers = request_json(&mut sean, "LIST_FOLLOWERS ").await;
+    assert_eq!(followers["items"], serde_json::json!([]));
+    let mut ray = invited(&state, &mut sean, "ray2").await;
+    set_profile(&mut ray, "ray", true).await;
+    assert_eq!(request(&mut ray, "REQUEST_PLAYLIST mix").await, "null");
+    let following = request_json(&mut ray, "LIST_FOLLOWING ").await;
+    assert_eq!(following["items"], serde_json::json!([]));
+}
+
+async fn personal_data_can_be_exported_once(backend: Backend) {
+    let downloader = MemoryDownloader::new();
+    let url = format!("https://example.com/watch?v=export-{backend}");
+    let id = downloader.add_song(&url, &format!("{backend} Export"), "Artist", "20220524");
+    let state = state(backend, downloader).await;
+    let mut sean = sign_up(&state, "sean", ADMIN_KEY, true).await;
+    set_profile(&mut sean, "sean", true).await;
+    let mut ray = invited(&state, &mut sean, "ray").await;
+    set_profile(&mut ray, "ray", true).await;
+    assert_eq!(request(&mut ray, "FOLLOW sean").await, "OK");
+    assert_eq!(request(&mut ray, "MUTE sean").await, "OK");
+    assert_eq!(request(&mut ray, &format!("QUEUE {url}")).await, "AddedSong");
+    state.cycle_queue().await;
+    assert_eq!(request(&mut ray, "CREATE_PLAYLIST mix true").await, "OK");
+    assert_eq!(request(&mut ray, &format!("ADD_SONG_HASH mix {id} _")).await, "OK");
+    assert_eq!(request(&mut ray, &format!("NOW_PLAYING {id}")).await, "OK");
+
+    // asking again replaces the link
+    let replaced = request(&mut ray, "EXPORT_MY_DATA ").await;
+    let link = request(&mut ray, "EXPORT_MY_DATA ").await;
+    assert!(link.starts_with("/export/"), "{link}");
+    let get = |path: String| {
+        let state = state.clone();
+        async move {
+            warp::test::request()
+                .path(&path)
+                .reply(&routes(state))
+                .await
+        }
+    };
+    assert_eq!(get(replaced).await.status(), 404);
+    let response = get(link.clone()).await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(get(link).await.status(), 404);
+
+    let mut files = std::collections::HashMap::new();
+    let mut archive = tar::Archive::new(GzDecoder::new(response.body().as_ref()));
+    for entry in archive.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let name = entry.path().unwrap().to_string_lossy().to_string();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).unwrap();
+        files.insert(name, data);
+    }
+    let pfp = image::load_from_memory(&files["seanify-export/images/pfp.png"]);
+    assert!(pfp.is_ok());
+    let data: Value = serde_json::from_slice(&files["seanify-export/data.json"]).unwrap();
+    assert_eq!(data["userdata"]["display_name"], "ray");
+    assert_eq!(data["playlists"][0]["name"], "mix");
+    assert_eq!(data["playlists"][0]["tracks"][0]["song"], id.to_string());
+    let image = data["playlists"][0]["image"].as_str().unwrap();
+    assert!(files.contains_key(&format!("seanify-export/{image}")), "{image}");
+    assert_eq!(data["following"][0]["user"], "sean");
+    assert_eq!(data["muted"], serde_json::json!(["sean"]));
+    assert_eq!(data["plays"][0]["song"], id.to_string());
+    assert_eq!(data["queued_songs"][0]["url"], url.as_str());
+    assert_eq!(data["activity"].as_array().unwrap().len(), 4);
+    assert_eq!(data["invites"]["invites"], serde_json::json!([]));
+}
+
+// a #[tokio::test] for every backend, in a module named after it
+macro_rules! backends {
+    ($($test:ident),* $(,)?) => {
+        mod memory {
+            $(#[tokio::test]
+            async fn $test() {
+                super::$test(super::Backend::Memory).await
+            })*
+        }
+
+        mod sqlite {
+            $(#[tokio::test]
+            async fn $test() {
+                super::$test(super::Backend::Sqlite).await
+            })*
+        }
+
+        mod postgres {
+            $(#[tokio::test]
+            async fn $test() {
+                if std::env::var("SEANIFY_TEST_POSTGRES").is_err() {
+                    eprintln!("SEANIFY_TEST_POSTGRES isn't set, skipping");
+                    return;
+                }
+                super::$test(super::Backend::Postgres(stringify!($test))).await
+            })*
+        }
+    };
+}
+
+backends! {
+    sign_up_and_authenticate,
+    unauthenticated_clients_get_no_replies,
+    queued_songs_can_be_added_to_playlists,
+    songs_can_be_corrected_and_renamed,
+    new_songs_are_enriched,
+    downloaded_songs_are_tagged,
+    other_formats_are_not_tagged,
+    thumbnails_are_served_locally,
+    playlist_covers_are_made_from_artwork,
+    private_profiles_have_to_accept_follows,
+    invites_create_accounts_without_admin,
+    admin_key_only_makes_the_first_admin,
+    streams_are_counted_with_the_stream_token,
+    profiles_only_show_what_is_shared,
+    connected_clients_are_listed_without_tokens,
+    song_edits_follow_the_editor_account,
+    banned_users_are_kicked_and_cannot_authenticate,
+    client_commands_are_relayed_to_the_same_user,
+    plays_make_up_the_listening_stats,
+    presence_is_sent_to_followers,
+    feed_shows_what_followed_users_do,
+    follows_can_be_listed_and_undone,
+    blocks_and_mutes_hide_users,
+    follow_requests_can_be_rejected_or_withdrawn,
+    admins_manage_the_queue_library_and_users,
+    instance_status_needs_the_admin_key,
+    metrics_need_the_admin_key,
+    invites_can_be_shared_revoked_and_expire,
+    accounts_can_be_changed_and_deleted,
+    personal_data_can_be_exported_once,
+}